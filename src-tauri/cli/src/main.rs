@@ -0,0 +1,636 @@
+use mp4_converter_core::converter::{
+    check_ffmpeg, convert_video, ConformDuration, ConversionMode, ConversionOptions,
+    ConversionProgress, ConversionResult, ConversionStatus, HwDecode,
+};
+use mp4_converter_core::aspect::{parse_ratio, AspectFit};
+use mp4_converter_core::devices::DeviceProfile;
+use mp4_converter_core::error::ConvertError;
+use mp4_converter_core::hls::OutputFormat;
+use mp4_converter_core::output_dir::{resolve_output_dir, OutputDirMode};
+use mp4_converter_core::paths::validate_output_dir;
+use mp4_converter_core::presets::{builtin_preset, BUILTIN_PRESETS};
+use mp4_converter_core::renditions::RenditionSpec;
+use mp4_converter_core::resolver::FfmpegResolver;
+use mp4_converter_core::segments::SegmentSpec;
+use mp4_converter_core::settings::Settings;
+use mp4_converter_core::task_dir::sweep_stale_task_dirs;
+use mp4_converter_core::task_log::TaskLog;
+use mp4_converter_core::verify::Verification;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Every file converted
+const EXIT_OK: u8 = 0;
+/// At least one file failed or was interrupted
+const EXIT_SOME_FAILED: u8 = 1;
+/// Nothing was attempted: bad arguments, no ffmpeg, unusable output dir
+const EXIT_SETUP: u8 = 2;
+
+const USAGE: &str = "\
+Usage: mp4-converter-cli convert <files...> [--out <dir>] [options]
+
+Options:
+  --out <dir>        Directory for converted files; default: each file's own
+                     folder, or --fallback-out when that is read-only
+  --fallback-out <dir>
+                     Directory for files whose own folder is read-only
+  --preset <name>    Conversion preset (phone, streaming); default: phone
+  --jobs <n>         Files to convert at once; default: 1
+  --max-duration <s> Split each output into parts of at most this many seconds
+  --max-size <MB>    Split each output into parts of at most this size
+  --format <fmt>     Output format (mp4, hls, mkv); hls writes a playlist folder
+                     per video, mkv keeps all subtitles, fonts and audio
+                     tracks. Default: mp4
+  --hls-time <s>     Target HLS segment length; default: 6
+  --aspect <w:h>     Reframe to an aspect ratio, e.g. 9:16, 1:1, 4:5
+  --fit <mode>       How to reach --aspect (crop, pad, blur_pad); default: crop
+  --metadata <k=v>   Set an output tag such as title=...; repeatable
+  --cover <image>    Embed a poster image, or `auto` for a frame from the video
+  --fix-channel-balance
+                     Copy one-sided stereo audio to both channels
+  --keep-data-streams
+                     Try to keep timecode/telemetry tracks instead of dropping them
+  --rendition <suffix:height[:crf]>
+                     Also write a rendition scaled to at most this height, e.g.
+                     _720:720:23; repeatable
+  --fix-audio-only   Only convert the audio; fail rather than re-encode the video
+  --replace-original With --fix-audio-only, replace MP4 sources with the result
+  --no-hw-decode     Always decode in software when re-encoding
+  --fade-in <secs>   Fade in from black and silence
+  --trim-silence     Cut silence at the start and end of each input
+  --progress-file <path>
+                     Keep the latest progress as a JSON line in this file;
+                     needs a single input
+  --fade-out <secs>  Fade out to black and silence at the end
+  --target <device>  Re-encode H.264 that modern_phone, old_tv, web or
+                     strict_device players can't handle instead of copying it;
+                     strict_device also converts audio to 44.1 kHz stereo
+  --sample-rate <Hz> Resample the audio, e.g. 44100 or 48000
+  --channels <n>     Mix the audio to this many channels, e.g. 2
+  --video-stream <n> Convert the n-th video stream (0:v:n) instead of the
+                     main one
+  --program <id>     Convert one program of a DVD/Blu-ray rip or broadcast
+                     stream: its first video and audio stream
+  --audio-stream <n> Convert the n-th audio track (0:a:n) instead of the one
+                     picked by language and the default flag
+  --lang <codes>     Languages to pick the audio track and forced subtitles
+                     by, most wanted first, e.g. ja,eng
+  --no-forced-subs   Don't burn the source's forced subtitles in
+  --primary-audio-only
+                     Write only the chosen audio track of a MOV/MP4 whose
+                     other AAC tracks would be copied along
+  --stage-locally    Copy each input to local disk before converting it
+  --wait-for-stable  Wait for inputs that are still being written to finish
+  --hw-encode-only   Fail instead of letting VideoToolbox encode in software
+  --abort-if-larger  Stop an encode once its output is bigger than the input
+  --hash             Record the SHA-256 of each input and output
+  --max-decode-errors <n>
+                     Decoding errors a source may have before its output is
+                     flagged for review (default 10)
+  --out-dir-template <template>
+                     Folder under --out for each output, e.g.
+                     {output_root}/{year}/{month} by the source's date
+  --conform-duration <mode>
+                     When the source's audio and video end apart: keep
+                     (default), shortest, pad_video or pad_audio
+  --verify <level>   Check each output after encoding: off, probe (default) or
+                     decode, which also decodes its last two seconds
+  --json             Print line-delimited JSON progress instead of a progress bar
+  --ffmpeg <path>    Use this ffmpeg binary
+  --ffprobe <path>   Use this ffprobe binary
+
+Exit codes: 0 all succeeded, 1 some failed, 2 setup error";
+
+struct Args {
+    files: Vec<String>,
+    /// None writes next to each source
+    out_dir: Option<String>,
+    fallback_out_dir: Option<String>,
+    options: ConversionOptions,
+    jobs: usize,
+    json: bool,
+    ffmpeg_path: Option<String>,
+    ffprobe_path: Option<String>,
+}
+
+/// `suffix:height[:crf]`
+fn parse_rendition(value: &str) -> Result<RenditionSpec, String> {
+    let mut parts = value.split(':');
+    let suffix = parts.next().unwrap_or_default().to_string();
+    let max_height = parts
+        .next()
+        .and_then(|height| height.parse().ok())
+        .ok_or("--rendition needs suffix:height[:crf]")?;
+    let crf = match parts.next() {
+        Some(crf) => Some(crf.parse().map_err(|_| "--rendition crf must be a whole number")?),
+        None => None,
+    };
+    Ok(RenditionSpec { suffix, max_height: Some(max_height), crf })
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    match args.next().as_deref() {
+        Some("convert") => {}
+        Some(other) => return Err(format!("Unknown command: {}", other)),
+        None => return Err("Missing command".to_string()),
+    }
+
+    let mut files = Vec::new();
+    let mut out_dir = None;
+    let mut fallback_out_dir = None;
+    let mut preset = "phone".to_string();
+    let mut jobs = 1;
+    let mut json = false;
+    let mut ffmpeg_path = None;
+    let mut ffprobe_path = None;
+    let mut segment = None;
+    let mut aspect = None;
+    let mut aspect_fit = AspectFit::Crop;
+    let mut stage_locally = false;
+    let mut metadata = BTreeMap::new();
+    let mut cover_image = None;
+    let mut fix_channel_balance = false;
+    let mut keep_data_streams = false;
+    let mut renditions = Vec::new();
+    let mut output_format = OutputFormat::Mp4;
+    let mut mode = ConversionMode::Auto;
+    let mut replace_original = false;
+    let mut hw_decode = HwDecode::Auto;
+    let mut target_profile = None;
+    let mut fade_in_seconds = None;
+    let mut progress_file = None;
+    let mut wait_for_stable = false;
+    let mut verify_output = Verification::Probe;
+    let mut auto_trim_silence = false;
+    let mut abort_if_larger_than_input = false;
+    let mut compute_hashes = false;
+    let mut hw_encode_only = false;
+    let mut audio_sample_rate = None;
+    let mut audio_channels = None;
+    let mut video_stream = None;
+    let mut program_id = None;
+    let mut audio_stream = None;
+    let mut preferred_languages = Vec::new();
+    let mut ignore_forced_subtitles = false;
+    let mut primary_audio_only = false;
+    let mut max_decode_errors = None;
+    let mut conform_duration = ConformDuration::Keep;
+    let mut output_dir_template = None;
+    let mut fade_out_seconds = None;
+    let mut hls_segment_seconds = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--out" => out_dir = Some(value("--out")?),
+            "--fallback-out" => fallback_out_dir = Some(value("--fallback-out")?),
+            "--preset" => preset = value("--preset")?,
+            "--jobs" => {
+                jobs = value("--jobs")?
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or("--jobs must be a positive number")?
+            }
+            "--max-duration" => {
+                let seconds = value("--max-duration")?
+                    .parse()
+                    .map_err(|_| "--max-duration must be a number of seconds")?;
+                segment = Some(SegmentSpec::MaxDurationSeconds(seconds));
+            }
+            "--max-size" => {
+                let mb = value("--max-size")?
+                    .parse()
+                    .map_err(|_| "--max-size must be a whole number of MB")?;
+                segment = Some(SegmentSpec::MaxSizeMb(mb));
+            }
+            "--format" => {
+                output_format = match value("--format")?.as_str() {
+                    "mp4" => OutputFormat::Mp4,
+                    "hls" => OutputFormat::Hls,
+                    "mkv" => OutputFormat::Mkv,
+                    other => return Err(format!("Unknown output format: {}", other)),
+                }
+            }
+            "--hls-time" => {
+                let seconds = value("--hls-time")?
+                    .parse()
+                    .map_err(|_| "--hls-time must be a number of seconds")?;
+                hls_segment_seconds = Some(seconds);
+            }
+            "--fade-in" => {
+                let seconds = value("--fade-in")?
+                    .parse()
+                    .map_err(|_| "--fade-in must be a number of seconds")?;
+                fade_in_seconds = Some(seconds);
+            }
+            "--fade-out" => {
+                let seconds = value("--fade-out")?
+                    .parse()
+                    .map_err(|_| "--fade-out must be a number of seconds")?;
+                fade_out_seconds = Some(seconds);
+            }
+            "--aspect" => aspect = Some(value("--aspect")?),
+            "--fit" => {
+                aspect_fit = match value("--fit")?.as_str() {
+                    "crop" => AspectFit::Crop,
+                    "pad" => AspectFit::Pad,
+                    "blur_pad" => AspectFit::BlurPad,
+                    other => return Err(format!("Unknown fit mode: {}", other)),
+                }
+            }
+            "--metadata" => {
+                let entry = value("--metadata")?;
+                let (key, tag) = entry.split_once('=').ok_or("--metadata needs key=value")?;
+                metadata.insert(key.to_string(), tag.to_string());
+            }
+            "--cover" => cover_image = Some(value("--cover")?),
+            "--fix-channel-balance" => fix_channel_balance = true,
+            "--keep-data-streams" => keep_data_streams = true,
+            "--rendition" => renditions.push(parse_rendition(&value("--rendition")?)?),
+            "--fix-audio-only" => mode = ConversionMode::AudioOnlyFix,
+            "--replace-original" => replace_original = true,
+            "--no-hw-decode" => hw_decode = HwDecode::Off,
+            "--target" => {
+                target_profile = Some(match value("--target")?.as_str() {
+                    "modern_phone" => DeviceProfile::ModernPhone,
+                    "old_tv" => DeviceProfile::OldTv,
+                    "web" => DeviceProfile::Web,
+                    "strict_device" => DeviceProfile::StrictDevice,
+                    other => return Err(format!("Unknown target device: {}", other)),
+                })
+            }
+            "--sample-rate" => {
+                let rate = value("--sample-rate")?
+                    .parse()
+                    .map_err(|_| "--sample-rate must be a whole number of Hz")?;
+                audio_sample_rate = Some(rate);
+            }
+            "--channels" => {
+                let channels = value("--channels")?
+                    .parse()
+                    .map_err(|_| "--channels must be a whole number")?;
+                audio_channels = Some(channels);
+            }
+            "--video-stream" => {
+                let index = value("--video-stream")?
+                    .parse()
+                    .map_err(|_| "--video-stream must be a stream number")?;
+                video_stream = Some(index);
+            }
+            "--program" => {
+                let id = value("--program")?
+                    .parse()
+                    .map_err(|_| "--program must be a program id")?;
+                program_id = Some(id);
+            }
+            "--audio-stream" => {
+                let index = value("--audio-stream")?
+                    .parse()
+                    .map_err(|_| "--audio-stream must be a stream number")?;
+                audio_stream = Some(index);
+            }
+            "--lang" => {
+                preferred_languages =
+                    value("--lang")?.split(',').map(|code| code.trim().to_string()).collect();
+            }
+            "--no-forced-subs" => ignore_forced_subtitles = true,
+            "--primary-audio-only" => primary_audio_only = true,
+            "--out-dir-template" => output_dir_template = Some(value("--out-dir-template")?),
+            "--conform-duration" => {
+                conform_duration = match value("--conform-duration")?.as_str() {
+                    "keep" => ConformDuration::Keep,
+                    "shortest" => ConformDuration::Shortest,
+                    "pad_video" => ConformDuration::PadVideo,
+                    "pad_audio" => ConformDuration::PadAudio,
+                    other => return Err(format!("Unknown duration mode: {}", other)),
+                }
+            }
+            "--max-decode-errors" => {
+                let count = value("--max-decode-errors")?
+                    .parse()
+                    .map_err(|_| "--max-decode-errors must be a number")?;
+                max_decode_errors = Some(count);
+            }
+            "--progress-file" => progress_file = Some(value("--progress-file")?),
+            "--stage-locally" => stage_locally = true,
+            "--wait-for-stable" => wait_for_stable = true,
+            "--trim-silence" => auto_trim_silence = true,
+            "--abort-if-larger" => abort_if_larger_than_input = true,
+            "--hash" => compute_hashes = true,
+            "--hw-encode-only" => hw_encode_only = true,
+            "--verify" => {
+                verify_output = match value("--verify")?.as_str() {
+                    "off" => Verification::Off,
+                    "probe" => Verification::Probe,
+                    "decode" => Verification::Decode,
+                    other => return Err(format!("Unknown verification level: {}", other)),
+                }
+            }
+            "--json" => json = true,
+            "--ffmpeg" => ffmpeg_path = Some(value("--ffmpeg")?),
+            "--ffprobe" => ffprobe_path = Some(value("--ffprobe")?),
+            "--" => files.extend(args.by_ref()),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return Err("No input files given".to_string());
+    }
+    if progress_file.is_some() && files.len() > 1 {
+        return Err("--progress-file needs a single input file".to_string());
+    }
+    let mut options = builtin_preset(&preset).ok_or_else(|| {
+        format!("Unknown preset '{}' (available: {})", preset, BUILTIN_PRESETS.join(", "))
+    })?;
+    if let Some(spec) = segment {
+        spec.validate()?;
+        options.segment = Some(spec);
+    }
+    options.stage_locally = stage_locally;
+    options.cover_image = cover_image;
+    options.fix_channel_balance = fix_channel_balance;
+    options.keep_data_streams = keep_data_streams;
+    options.renditions = renditions;
+    options.output_format = output_format;
+    options.hls_segment_seconds = hls_segment_seconds;
+    options.mode = mode;
+    options.replace_original = replace_original;
+    options.hw_decode = hw_decode;
+    options.target_profile = target_profile;
+    options.fade_in_seconds = fade_in_seconds;
+    options.fade_out_seconds = fade_out_seconds;
+    options.progress_file = progress_file;
+    options.wait_for_stable = wait_for_stable;
+    options.verify_output = verify_output;
+    options.auto_trim_silence = auto_trim_silence;
+    options.abort_if_larger_than_input = abort_if_larger_than_input;
+    options.compute_hashes = compute_hashes;
+    options.hw_encode_only = hw_encode_only;
+    options.audio_sample_rate = audio_sample_rate;
+    options.audio_channels = audio_channels;
+    options.video_stream = video_stream;
+    options.program_id = program_id;
+    options.audio_stream = audio_stream;
+    options.preferred_languages = preferred_languages;
+    options.ignore_forced_subtitles = ignore_forced_subtitles;
+    options.primary_audio_only = primary_audio_only;
+    options.max_decode_errors = max_decode_errors;
+    options.conform_duration = conform_duration;
+    options.output_dir_template = output_dir_template;
+    if !metadata.is_empty() {
+        options.metadata = Some(metadata);
+    }
+    if let Some(ratio) = aspect {
+        parse_ratio(&ratio)?;
+        options.aspect = Some(ratio);
+        options.aspect_fit = aspect_fit;
+    }
+
+    Ok(Args {
+        files,
+        out_dir,
+        fallback_out_dir,
+        options,
+        jobs,
+        json,
+        ffmpeg_path,
+        ffprobe_path,
+    })
+}
+
+/// Renders progress either as a single redrawn status line on stderr or as
+/// JSON lines on stdout
+struct Reporter {
+    json: bool,
+    total: usize,
+    state: Mutex<ReporterState>,
+}
+
+#[derive(Default)]
+struct ReporterState {
+    finished: usize,
+    /// Running files by task id: display name and percentage
+    active: BTreeMap<String, (String, f64)>,
+}
+
+impl Reporter {
+    fn progress(&self, file: &str, progress: &ConversionProgress) {
+        if self.json {
+            if let Ok(serde_json::Value::Object(mut line)) = serde_json::to_value(progress) {
+                line.insert("file".to_string(), file.into());
+                println!("{}", serde_json::Value::Object(line));
+            }
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if !progress.status.is_terminal() {
+            let name = display_name(file);
+            state.active.insert(progress.task_id.clone(), (name, progress.progress));
+            self.redraw(&state);
+        }
+    }
+
+    fn finished(&self, task_id: &str, file: &str, result: &Result<ConversionResult, ConvertError>) {
+        let mut state = self.state.lock().unwrap();
+        state.finished += 1;
+        state.active.remove(task_id);
+        if self.json {
+            // One summary line per file, including failures that happen
+            // before ffmpeg starts and so never produce a progress event
+            let line = match result {
+                Ok(done) => serde_json::json!({
+                    "file": file, "task_id": task_id, "result": "ok",
+                    "output_path": done.output_path, "output_size": done.output_size,
+                    "input_bytes": done.input_bytes, "output_bytes": done.output_bytes,
+                    "streams": done.streams, "status": done.status,
+                    "decode_error_count": done.decode_error_count,
+                    "input_sha256": done.input_sha256, "output_sha256": done.output_sha256,
+                    "output_dir": done.output_dir,
+                }),
+                Err(e) => serde_json::json!({
+                    "file": file, "task_id": task_id, "result": "error", "error": e,
+                }),
+            };
+            println!("{}", line);
+            return;
+        }
+        eprint!("\r\x1b[K");
+        match result {
+            Ok(done) => {
+                let mut details = Vec::new();
+                if let Some((width, height)) = done.output_size {
+                    details.push(format!("{}x{}", width, height));
+                }
+                match done.size_reduction_percent() {
+                    Some(reduction) if reduction >= 0.0 => {
+                        details.push(format!("{:.0}% smaller", reduction))
+                    }
+                    Some(reduction) => details.push(format!("{:.0}% larger", -reduction)),
+                    None => {}
+                }
+                if done.status == ConversionStatus::CompletedWithErrors {
+                    details.push(format!("{} decoding errors, review it", done.decode_error_count));
+                }
+                if details.is_empty() {
+                    eprintln!("done    {} -> {}", file, done.output_path);
+                } else {
+                    eprintln!("done    {} -> {} ({})", file, done.output_path, details.join(", "));
+                }
+            }
+            Err(ConvertError::Cancelled) => eprintln!("stopped {}", file),
+            Err(e) => eprintln!("failed  {}: {}", file, e),
+        }
+        self.redraw(&state);
+    }
+
+    fn redraw(&self, state: &ReporterState) {
+        let running: Vec<String> = state
+            .active
+            .values()
+            .map(|(name, percent)| format!("{} {:.0}%", name, percent))
+            .collect();
+        eprint!("\r\x1b[K[{}/{}] {}", state.finished, self.total, running.join(" | "));
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// The core only accepts absolute paths; resolve relative ones against the
+/// current directory like any other command-line tool would
+fn absolute(path: &str) -> String {
+    std::path::absolute(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+fn display_name(file: &str) -> String {
+    Path::new(file)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| file.to_string())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_SETUP);
+        }
+    };
+
+    let resolver = Arc::new(FfmpegResolver::new(args.ffmpeg_path, args.ffprobe_path));
+    if !check_ffmpeg(&resolver).await.unwrap_or(false) {
+        eprintln!("No working ffmpeg binary found; install ffmpeg or pass --ffmpeg");
+        return ExitCode::from(EXIT_SETUP);
+    }
+    // Explicit folders are checked once up front; per-source ones as each
+    // file comes up, so one read-only source doesn't stop the rest
+    let out_dir = args.out_dir.as_deref().map(absolute);
+    for dir in out_dir.iter().chain(args.fallback_out_dir.as_ref()) {
+        if let Err(e) = validate_output_dir(&absolute(dir)) {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_SETUP);
+        }
+    }
+    let settings = Arc::new(Settings {
+        output_dir_mode: OutputDirMode::SameAsInput,
+        fallback_output_dir: args.fallback_out_dir.as_deref().map(absolute),
+        ..Settings::default()
+    });
+
+    sweep_stale_task_dirs(None);
+
+    // Ctrl-C cancels every task; each one kills its ffmpeg and removes its
+    // partial output before returning
+    let cancel = CancellationToken::new();
+    let on_interrupt = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            on_interrupt.cancel();
+        }
+    });
+
+    let reporter = Arc::new(Reporter {
+        json: args.json,
+        total: args.files.len(),
+        state: Mutex::new(ReporterState::default()),
+    });
+    let slots = Arc::new(Semaphore::new(args.jobs));
+    let options = Arc::new(args.options);
+    let mut tasks = JoinSet::new();
+
+    for (index, file) in args.files.into_iter().enumerate() {
+        let task_id = format!("cli-{}-{}", std::process::id(), index);
+        let resolver = Arc::clone(&resolver);
+        let reporter = Arc::clone(&reporter);
+        let slots = Arc::clone(&slots);
+        let options = Arc::clone(&options);
+        let out_dir = out_dir.clone();
+        let settings = Arc::clone(&settings);
+        let cancel = cancel.child_token();
+
+        tasks.spawn(async move {
+            let input_path = absolute(&file);
+            let result = match slots.acquire().await {
+                Ok(_permit) if !cancel.is_cancelled() => {
+                    match resolve_output_dir(&input_path, out_dir.as_deref(), &settings) {
+                        Ok(resolved) => {
+                            let progress_reporter = Arc::clone(&reporter);
+                            let progress_file = file.clone();
+                            convert_video(
+                                &resolver,
+                                &input_path,
+                                &resolved.dir,
+                                &task_id,
+                                &options,
+                                None,
+                                &TaskLog::default(),
+                                &cancel,
+                                move |progress| {
+                                    progress_reporter.progress(&progress_file, &progress)
+                                },
+                            )
+                            .await
+                            .map(|mut done| {
+                                done.output_dir = Some(resolved);
+                                done
+                            })
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                _ => Err(ConvertError::Cancelled),
+            };
+            reporter.finished(&task_id, &file, &result);
+            result.is_ok()
+        });
+    }
+
+    let mut failed = 0;
+    while let Some(outcome) = tasks.join_next().await {
+        if !matches!(outcome, Ok(true)) {
+            failed += 1;
+        }
+    }
+
+    if !reporter.json {
+        eprint!("\r\x1b[K");
+        eprintln!("{} of {} files converted", reporter.total - failed, reporter.total);
+    }
+    if failed == 0 {
+        ExitCode::from(EXIT_OK)
+    } else {
+        ExitCode::from(EXIT_SOME_FAILED)
+    }
+}
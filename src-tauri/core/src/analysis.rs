@@ -0,0 +1,629 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::VideoInfo;
+use crate::error::ConvertError;
+use crate::paths::ffmpeg_path_arg;
+use crate::process::{self, output_cancellable};
+use crate::resolver::FfmpegResolver;
+
+/// A crop rectangle in source pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl CropRect {
+    /// Round to even values, which yuv420p encoding requires
+    pub fn to_even(self) -> CropRect {
+        CropRect {
+            x: self.x & !1,
+            y: self.y & !1,
+            w: self.w & !1,
+            h: self.h & !1,
+        }
+    }
+
+    pub fn filter(self) -> String {
+        let rect = self.to_even();
+        format!("crop={}:{}:{}:{}", rect.w, rect.h, rect.x, rect.y)
+    }
+
+    /// Check the rectangle fits inside a frame of the given size
+    pub fn validate(self, width: u32, height: u32) -> Result<(), String> {
+        let rect = self.to_even();
+        if rect.w == 0 || rect.h == 0 {
+            return Err("Crop width and height must be at least 2 pixels".to_string());
+        }
+        if rect.x + rect.w > width || rect.y + rect.h > height {
+            return Err(format!(
+                "Crop {}x{}+{}+{} does not fit the {}x{} frame",
+                rect.w, rect.h, rect.x, rect.y, width, height
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CropDetection {
+    /// Suggested crop, or None when the picture already fills the frame
+    pub crop: Option<CropRect>,
+    /// Share of sampled points that agree with the suggestion (0.0-1.0)
+    pub confidence: f64,
+    pub samples: usize,
+}
+
+/// Where in the file to sample, as fractions of the duration. Several points
+/// keep a fade-from-black intro or a dark scene from deciding the result.
+const SAMPLE_POINTS: &[f64] = &[0.15, 0.35, 0.55, 0.75];
+const SAMPLE_SECONDS: &str = "2";
+
+/// Crops that keep at least this share of each dimension aren't worth doing
+const FULL_FRAME_RATIO: f64 = 0.98;
+
+/// Tolerance in pixels when checking whether two samples agree
+const AGREEMENT_PIXELS: u32 = 4;
+
+/// Run `cropdetect` at several points in the file and merge the results.
+///
+/// The suggestion is the bounding box of all samples, so content visible in
+/// any sample is never cropped away.
+pub async fn detect_crop(
+    resolver: &FfmpegResolver,
+    info: &VideoInfo,
+) -> Result<CropDetection, ConvertError> {
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let input = ffmpeg_path_arg(Path::new(&info.path));
+
+    let starts: Vec<f64> = if info.duration > 0.0 {
+        SAMPLE_POINTS.iter().map(|p| info.duration * p).collect()
+    } else {
+        vec![0.0]
+    };
+
+    let mut rects = Vec::new();
+    for start in starts {
+        let mut cmd = Command::new(&ffmpeg_path);
+        cmd.args(["-hide_banner", "-nostdin", "-ss"])
+            .arg(format!("{:.3}", start))
+            .arg("-i")
+            .arg(&input)
+            .args(["-t", SAMPLE_SECONDS, "-vf", "cropdetect=limit=24:round=2:reset=0"])
+            .args(["-an", "-sn", "-f", "null", "-"]);
+        let output = process::output(resolver.runner(), &mut cmd)
+            .await
+            .map_err(|e| format!("Failed to run cropdetect: {}", e))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if let Some(rect) = stderr.lines().rev().find_map(parse_cropdetect_line) {
+            rects.push(rect);
+        }
+    }
+
+    if rects.is_empty() {
+        return Err("Crop detection found no usable frames".into());
+    }
+
+    let left = rects.iter().map(|r| r.x).min().unwrap_or(0);
+    let top = rects.iter().map(|r| r.y).min().unwrap_or(0);
+    let right = rects.iter().map(|r| r.x + r.w).max().unwrap_or(info.width);
+    let bottom = rects.iter().map(|r| r.y + r.h).max().unwrap_or(info.height);
+    let merged = CropRect {
+        x: left,
+        y: top,
+        w: right - left,
+        h: bottom - top,
+    }
+    .to_even();
+
+    let close = |a: u32, b: u32| a.abs_diff(b) <= AGREEMENT_PIXELS;
+    let agreeing = rects
+        .iter()
+        .filter(|r| {
+            close(r.x, merged.x) && close(r.y, merged.y) && close(r.w, merged.w) && close(r.h, merged.h)
+        })
+        .count();
+    let confidence = agreeing as f64 / rects.len() as f64;
+
+    let is_full_frame = merged.w as f64 >= info.width as f64 * FULL_FRAME_RATIO
+        && merged.h as f64 >= info.height as f64 * FULL_FRAME_RATIO;
+
+    Ok(CropDetection {
+        crop: if is_full_frame { None } else { Some(merged) },
+        confidence,
+        samples: rects.len(),
+    })
+}
+
+/// Parse the `crop=w:h:x:y` suggestion from a cropdetect log line.
+/// Fully black frames produce negative or empty sizes and are skipped.
+fn parse_cropdetect_line(line: &str) -> Option<CropRect> {
+    let value = line.split("crop=").nth(1)?.split_whitespace().next()?;
+    let parts: Vec<i64> = value.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let [w, h, x, y] = parts.as_slice() else {
+        return None;
+    };
+    if *w <= 0 || *h <= 0 || *x < 0 || *y < 0 {
+        return None;
+    }
+    Some(CropRect {
+        x: *x as u32,
+        y: *y as u32,
+        w: *w as u32,
+        h: *h as u32,
+    })
+}
+
+/// A grid of thumbnails tiled into a single JPEG
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactSheet {
+    pub path: String,
+    /// Source time of each tile in seconds, left to right, top to bottom
+    pub timestamps: Vec<f64>,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+const MAX_SHEET_CELLS_PER_SIDE: u32 = 10;
+
+/// Extract `columns * rows` evenly spaced frames and tile them into one JPEG
+/// in `output_dir`.
+///
+/// Videos with fewer frames than cells get a smaller grid rather than
+/// repeated tiles. Each frame is read with its own fast `-ss` seek, so long
+/// files don't have to be decoded end to end.
+pub async fn generate_contact_sheet(
+    resolver: &FfmpegResolver,
+    info: &VideoInfo,
+    columns: u32,
+    rows: u32,
+    width: u32,
+    output_dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<ContactSheet, ConvertError> {
+    if !(1..=MAX_SHEET_CELLS_PER_SIDE).contains(&columns)
+        || !(1..=MAX_SHEET_CELLS_PER_SIDE).contains(&rows)
+    {
+        return Err(format!(
+            "Columns and rows must be between 1 and {}",
+            MAX_SHEET_CELLS_PER_SIDE
+        )
+        .into());
+    }
+    if info.duration <= 0.0 {
+        return Err("Cannot build a contact sheet for a file without a duration".into());
+    }
+
+    let (columns, rows) = fit_grid(columns, rows, available_frames(info));
+    let count = columns * rows;
+    let tile_width = (width / columns) & !1;
+    if tile_width < 16 {
+        return Err(format!("Sheet width {} is too small for {} columns", width, columns).into());
+    }
+
+    let timestamps: Vec<f64> = (0..count)
+        .map(|i| info.duration * (i as f64 + 0.5) / count as f64)
+        .collect();
+
+    std::fs::create_dir_all(output_dir)
+        .map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+    let stem = Path::new(&info.filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "sheet".to_string());
+    let output_path: PathBuf =
+        output_dir.join(format!("{}-{}.jpg", stem, uuid::Uuid::new_v4().simple()));
+
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let input = ffmpeg_path_arg(Path::new(&info.path));
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin", "-y"]);
+    for t in &timestamps {
+        cmd.arg("-ss").arg(format!("{:.3}", t)).arg("-i").arg(&input);
+    }
+
+    let mut graph = String::new();
+    for i in 0..count {
+        graph.push_str(&format!(
+            "[{i}:v:0]trim=end_frame=1,scale={w}:-2,setsar=1,setpts=PTS-STARTPTS[v{i}];",
+            i = i,
+            w = tile_width
+        ));
+    }
+    for i in 0..count {
+        graph.push_str(&format!("[v{}]", i));
+    }
+    graph.push_str(&format!("concat=n={}:v=1:a=0,tile={}x{}[out]", count, columns, rows));
+
+    cmd.arg("-filter_complex")
+        .arg(graph)
+        .args(["-map", "[out]", "-frames:v", "1", "-q:v", "3"])
+        .arg(ffmpeg_path_arg(&output_path));
+
+    let output = match output_cancellable(resolver.runner(), &mut cmd, cancel).await {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = std::fs::remove_file(&output_path);
+            return Err(e);
+        }
+    };
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&output_path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Err(format!("Failed to generate contact sheet: {}", reason).into());
+    }
+
+    Ok(ContactSheet {
+        path: output_path.to_string_lossy().to_string(),
+        timestamps,
+        columns,
+        rows,
+    })
+}
+
+/// Rough frame count from duration and frame rate, or None if unknown
+fn available_frames(info: &VideoInfo) -> Option<u32> {
+    if info.frame_rate > 0.0 {
+        Some((info.duration * info.frame_rate).floor().max(1.0) as u32)
+    } else {
+        None
+    }
+}
+
+/// Shrink the grid until it has no more cells than there are frames,
+/// dropping rows before columns so the sheet keeps its width
+fn fit_grid(mut columns: u32, mut rows: u32, frames: Option<u32>) -> (u32, u32) {
+    let Some(frames) = frames else {
+        return (columns, rows);
+    };
+    while columns * rows > frames {
+        if rows > 1 {
+            rows -= 1;
+        } else {
+            columns -= 1;
+        }
+    }
+    (columns.max(1), rows)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityMetric {
+    Ssim,
+    Psnr,
+}
+
+impl QualityMetric {
+    fn filter(self) -> &'static str {
+        match self {
+            QualityMetric::Ssim => "ssim",
+            QualityMetric::Psnr => "psnr",
+        }
+    }
+
+    /// Name of the summary line in ffmpeg's log and of its overall score
+    fn log_keys(self) -> (&'static str, &'static str) {
+        match self {
+            QualityMetric::Ssim => ("SSIM", "All"),
+            QualityMetric::Psnr => ("PSNR", "average"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub metric: QualityMetric,
+    /// Overall score: SSIM 0.0-1.0, PSNR in dB (higher is better for both)
+    pub average: f64,
+    /// Every value ffmpeg reported, e.g. per-plane `Y`/`U`/`V` scores
+    pub details: BTreeMap<String, f64>,
+    /// Where the comparison started, in source seconds
+    pub sample_start: f64,
+    /// How much was compared; None means the whole file
+    pub sample_seconds: Option<f64>,
+}
+
+/// Files longer than this are compared over a sample from the middle
+const FULL_COMPARE_SECONDS: f64 = 120.0;
+const SAMPLE_COMPARE_SECONDS: f64 = 60.0;
+
+/// Score `converted` against `original` with the `ssim` or `psnr` filter.
+///
+/// The converted video is scaled to the original's size first, since both
+/// filters need matching frames.
+pub async fn compare_quality(
+    resolver: &FfmpegResolver,
+    original: &VideoInfo,
+    converted: &VideoInfo,
+    metric: QualityMetric,
+    cancel: &CancellationToken,
+) -> Result<QualityReport, ConvertError> {
+    if original.width == 0 || original.height == 0 {
+        return Err("Original video has no known resolution".into());
+    }
+
+    let (sample_start, sample_seconds) = if original.duration > FULL_COMPARE_SECONDS {
+        let start = (original.duration - SAMPLE_COMPARE_SECONDS) / 2.0;
+        (start, Some(SAMPLE_COMPARE_SECONDS))
+    } else {
+        (0.0, None)
+    };
+
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin"]);
+    for info in [converted, original] {
+        if let Some(seconds) = sample_seconds {
+            cmd.arg("-ss")
+                .arg(format!("{:.3}", sample_start))
+                .arg("-t")
+                .arg(format!("{:.3}", seconds));
+        }
+        cmd.arg("-i").arg(ffmpeg_path_arg(Path::new(&info.path)));
+    }
+
+    let graph = format!(
+        "[0:v]scale={}:{}:flags=bicubic,setsar=1[main];[1:v]setsar=1[ref];[main][ref]{}",
+        original.width,
+        original.height,
+        metric.filter()
+    );
+    cmd.arg("-lavfi").arg(graph).args(["-an", "-sn", "-f", "null", "-"]);
+
+    let output = output_cancellable(resolver.runner(), &mut cmd, cancel).await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Err(format!("Failed to compare quality: {}", reason).into());
+    }
+
+    let (label, average_key) = metric.log_keys();
+    let details = stderr
+        .lines()
+        .rev()
+        .find_map(|line| parse_metric_line(line, label))
+        .ok_or("ffmpeg did not report a quality score")?;
+    let average = *details
+        .get(average_key)
+        .ok_or("ffmpeg did not report an overall quality score")?;
+
+    Ok(QualityReport {
+        metric,
+        average,
+        details,
+        sample_start,
+        sample_seconds,
+    })
+}
+
+/// Parse `key:value` pairs from the summary line, e.g.
+/// `[Parsed_ssim_4 @ 0x..] SSIM Y:0.98 (17.1) U:0.99 (19.8) V:0.99 (20.1) All:0.98 (17.9)`
+fn parse_metric_line(line: &str, label: &str) -> Option<BTreeMap<String, f64>> {
+    let rest = line.split_once(&format!("] {} ", label))?.1;
+    let values: BTreeMap<String, f64> = rest
+        .split_whitespace()
+        .filter_map(|token| {
+            let (key, value) = token.split_once(':')?;
+            Some((key.to_string(), value.parse::<f64>().ok()?))
+        })
+        .collect();
+    (!values.is_empty()).then_some(values)
+}
+
+/// Which side of a stereo track carries the sound when the other is silent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveChannel {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelBalance {
+    /// Loudest sampled RMS level per channel in dB; digital silence is
+    /// reported as -120
+    pub left_rms_db: f64,
+    pub right_rms_db: f64,
+    /// Sound in one channel only, which plays on one earbud
+    pub unbalanced_audio: bool,
+    pub live_channel: Option<LiveChannel>,
+}
+
+impl ChannelBalance {
+    /// `pan` filter that copies the live channel to both sides
+    pub fn fix_filter(&self) -> Option<&'static str> {
+        match self.live_channel? {
+            LiveChannel::Left => Some("pan=stereo|c0=c0|c1=c0"),
+            LiveChannel::Right => Some("pan=stereo|c0=c1|c1=c1"),
+        }
+    }
+}
+
+const BALANCE_SAMPLE_SECONDS: &str = "10";
+/// A channel this quiet is treated as empty...
+const SILENT_CHANNEL_DB: f64 = -70.0;
+/// ...but only when the other one is at least this loud, so quiet but real
+/// stereo content is never flagged
+const LIVE_CHANNEL_DB: f64 = -50.0;
+const SILENCE_FLOOR_DB: f64 = -120.0;
+
+/// Measure both channels of the described audio track with `astats` at the same
+/// points crop detection samples. Only stereo tracks are judged; anything
+/// else comes back balanced.
+pub async fn detect_channel_balance(
+    resolver: &FfmpegResolver,
+    info: &VideoInfo,
+) -> Result<ChannelBalance, ConvertError> {
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let input = ffmpeg_path_arg(Path::new(&info.path));
+
+    let starts: Vec<f64> = if info.duration > 0.0 {
+        SAMPLE_POINTS.iter().map(|p| info.duration * p).collect()
+    } else {
+        vec![0.0]
+    };
+
+    let mut levels: Option<[f64; 2]> = None;
+    for start in starts {
+        let mut cmd = Command::new(&ffmpeg_path);
+        cmd.args(["-hide_banner", "-nostdin", "-ss"])
+            .arg(format!("{:.3}", start))
+            .arg("-i")
+            .arg(&input)
+            .args(["-t", BALANCE_SAMPLE_SECONDS, "-map"])
+            .arg(format!("0:a:{}", info.audio_stream_index))
+            .args(["-af", "astats"])
+            .args(["-vn", "-sn", "-f", "null", "-"]);
+        let output = process::output(resolver.runner(), &mut cmd)
+            .await
+            .map_err(|e| format!("Failed to run astats: {}", e))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let channels = parse_astats_rms(&stderr);
+        let [left, right] = channels.as_slice() else {
+            continue;
+        };
+        // The loudest sample decides, so a quiet scene doesn't look silent
+        levels = Some(match levels {
+            Some([l, r]) => [l.max(*left), r.max(*right)],
+            None => [*left, *right],
+        });
+    }
+
+    let [left, right] = levels.unwrap_or([SILENCE_FLOOR_DB; 2]);
+    let live_channel = if left <= SILENT_CHANNEL_DB && right >= LIVE_CHANNEL_DB {
+        Some(LiveChannel::Right)
+    } else if right <= SILENT_CHANNEL_DB && left >= LIVE_CHANNEL_DB {
+        Some(LiveChannel::Left)
+    } else {
+        None
+    };
+    Ok(ChannelBalance {
+        left_rms_db: left,
+        right_rms_db: right,
+        unbalanced_audio: live_channel.is_some(),
+        live_channel,
+    })
+}
+
+/// Per-channel `RMS level dB` values from astats' summary, in channel
+/// order. The `Overall` block that follows the channels is left out.
+fn parse_astats_rms(log: &str) -> Vec<f64> {
+    let mut levels = Vec::new();
+    let mut in_channel = false;
+    for line in log.lines() {
+        let Some((_, text)) = line.split_once("] ") else {
+            continue;
+        };
+        if text.starts_with("Channel:") {
+            in_channel = true;
+        } else if text.starts_with("Overall") {
+            in_channel = false;
+        } else if let Some(value) = text.strip_prefix("RMS level dB:").filter(|_| in_channel) {
+            // Silence is printed as `-inf`
+            let db = value.trim().parse::<f64>().unwrap_or(f64::NEG_INFINITY);
+            levels.push(db.max(SILENCE_FLOOR_DB));
+        }
+    }
+    levels
+}
+
+/// A stretch of audio below the silence threshold, in source seconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SilentInterval {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// The part of the source a conversion keeps, in source seconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrimRange {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+}
+
+/// Level and length that count as dead air for `auto_trim_silence`
+pub const TRIM_SILENCE_DB: f64 = -50.0;
+pub const TRIM_SILENCE_SECONDS: f64 = 2.0;
+/// Silence left in place next to the kept part, so the first and last
+/// words aren't clipped
+const TRIM_MARGIN_SECONDS: f64 = 0.5;
+/// How close to the start or end a silence has to reach to count as an edge
+const EDGE_SECONDS: f64 = 0.1;
+
+/// Run `silencedetect` over the described audio track and list every silence
+/// at least `min_duration` seconds long that stays under `threshold_db`
+pub async fn detect_silence(
+    resolver: &FfmpegResolver,
+    info: &VideoInfo,
+    threshold_db: f64,
+    min_duration: f64,
+    cancel: &CancellationToken,
+) -> Result<Vec<SilentInterval>, ConvertError> {
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin", "-i"])
+        .arg(ffmpeg_path_arg(Path::new(&info.path)))
+        .arg("-map")
+        .arg(format!("0:a:{}", info.audio_stream_index))
+        .arg("-af")
+        .arg(format!("silencedetect=noise={}dB:d={}", threshold_db, min_duration))
+        .args(["-vn", "-sn", "-f", "null", "-"]);
+    let output = output_cancellable(resolver.runner(), &mut cmd, cancel).await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Err(format!("Failed to detect silence: {}", reason).into());
+    }
+    Ok(parse_silences(&stderr, info.duration))
+}
+
+/// Pair up silencedetect's `silence_start`/`silence_end` lines. A silence
+/// still running when the audio ends gets no end line, so it ends with the
+/// file.
+fn parse_silences(log: &str, duration: f64) -> Vec<SilentInterval> {
+    let mut intervals = Vec::new();
+    let mut open = None;
+    for line in log.lines().filter(|line| line.contains("silencedetect")) {
+        let value = |key: &str| {
+            let rest = &line[line.find(key)? + key.len()..];
+            rest.split_whitespace().next()?.parse::<f64>().ok()
+        };
+        if let Some(start) = value("silence_start:") {
+            open = Some(start.max(0.0));
+        } else if let (Some(start), Some(end)) = (open, value("silence_end:")) {
+            intervals.push(SilentInterval { start, end });
+            open = None;
+        }
+    }
+    if let Some(start) = open {
+        intervals.push(SilentInterval { start, end: duration.max(start) });
+    }
+    intervals
+}
+
+/// The range left after cutting silence that touches the start or the end.
+/// Interior silences are never cut; None when there is nothing at the edges
+/// or the whole file is silent.
+pub fn edge_trim(intervals: &[SilentInterval], duration: f64) -> Option<TrimRange> {
+    let mut start = 0.0;
+    let mut end = duration;
+    if let Some(first) = intervals.first().filter(|s| s.start <= EDGE_SECONDS) {
+        start = (first.end - TRIM_MARGIN_SECONDS).max(0.0);
+    }
+    if let Some(last) = intervals.last().filter(|s| s.end >= duration - EDGE_SECONDS) {
+        end = (last.start + TRIM_MARGIN_SECONDS).min(duration);
+    }
+    (end > start && (start > 0.0 || end < duration)).then_some(TrimRange {
+        start_seconds: start,
+        end_seconds: end,
+    })
+}
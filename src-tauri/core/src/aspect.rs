@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// How a frame is brought to a target aspect ratio
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AspectFit {
+    /// Center-crop away whatever doesn't fit
+    #[default]
+    Crop,
+    /// Keep the whole frame and fill the rest with a solid color
+    Pad,
+    /// Keep the whole frame over a blurred, enlarged copy of itself
+    BlurPad,
+}
+
+/// Parse a ratio like `9:16`, `1.91:1` or `4/5`
+pub fn parse_ratio(ratio: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid aspect ratio '{}', expected e.g. 9:16", ratio);
+    let (w, h) = ratio.split_once([':', '/']).ok_or_else(invalid)?;
+    let w: f64 = w.trim().parse().map_err(|_| invalid())?;
+    let h: f64 = h.trim().parse().map_err(|_| invalid())?;
+    let value = w / h;
+    if w <= 0.0 || h <= 0.0 || !(0.1..=10.0).contains(&value) {
+        return Err(invalid());
+    }
+    Ok(value)
+}
+
+/// Largest even value not above `x`, at least 2
+fn even(x: f64) -> u32 {
+    ((x / 2.0).floor() as u32 * 2).max(2)
+}
+
+/// Filters that bring a `width`x`height` picture to `ratio`, and the
+/// resulting frame size
+pub fn aspect_filter(
+    ratio: f64,
+    fit: AspectFit,
+    color: &str,
+    width: u32,
+    height: u32,
+) -> (String, (u32, u32)) {
+    let (w, h) = (width as f64, height as f64);
+    let wider = w / h > ratio;
+    match fit {
+        AspectFit::Crop => {
+            let (out_w, out_h) = if wider {
+                (even(h * ratio), even(h))
+            } else {
+                (even(w), even(w / ratio))
+            };
+            (format!("crop={}:{}", out_w, out_h), (out_w, out_h))
+        }
+        AspectFit::Pad | AspectFit::BlurPad => {
+            let (out_w, out_h) = if wider {
+                (even(w), even(w / ratio))
+            } else {
+                (even(h * ratio), even(h))
+            };
+            // Scaling to even input sizes keeps the centered offsets whole
+            let fitted = format!(
+                "scale={}:{}:force_original_aspect_ratio=decrease:force_divisible_by=2",
+                out_w, out_h
+            );
+            let filter = if fit == AspectFit::Pad {
+                format!(
+                    "{},pad={}:{}:(ow-iw)/2:(oh-ih)/2:color={}",
+                    fitted, out_w, out_h, color
+                )
+            } else {
+                format!(
+                    "split[aspect_fg][aspect_bg];\
+                     [aspect_bg]scale={w}:{h}:force_original_aspect_ratio=increase,\
+                     crop={w}:{h},boxblur=20:2[aspect_blur];\
+                     [aspect_fg]{fitted}[aspect_front];\
+                     [aspect_blur][aspect_front]overlay=(W-w)/2:(H-h)/2,setsar=1",
+                    w = out_w,
+                    h = out_h,
+                    fitted = fitted
+                )
+            };
+            (filter, (out_w, out_h))
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Opt-in audit trail: one JSON line per conversion lifecycle event,
+//! appended to a file of the user's choosing, independent of the history
+//! the UI shows.
+//!
+//! Events go through a channel to a writer thread, so recording one never
+//! waits on the disk. Write failures are reported on stderr and the next
+//! event tries again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::ConversionOptions;
+use crate::error::ConvertError;
+use crate::hashing::sha256_file_blocking;
+use crate::history::utc_timestamp;
+
+/// Size at which the log is moved aside to `<name>.1`, replacing an older
+/// one there
+pub const AUDIT_ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Most lines `tail` returns
+pub const MAX_TAIL_LINES: usize = 10_000;
+
+/// One lifecycle event of a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// Submitted; it may still wait for a paused queue
+    Enqueued { task_id: String, input_path: String },
+    /// Conversion began with these settings
+    Started {
+        task_id: String,
+        input_path: String,
+        output_dir: String,
+        options: Box<ConversionOptions>,
+    },
+    /// An ffmpeg or ffprobe command line the task ran, program first
+    Command { task_id: String, argv: Vec<String> },
+    /// `output_sha256` is filled in by the writer, off the conversion's time,
+    /// unless the conversion hashed its output already
+    Completed {
+        task_id: String,
+        output_path: String,
+        input_bytes: u64,
+        output_bytes: u64,
+        output_sha256: Option<String>,
+    },
+    Failed {
+        task_id: String,
+        /// The `kind` of the `ConvertError`, e.g. `input_unavailable`
+        error_code: String,
+        message: String,
+    },
+    Cancelled { task_id: String },
+}
+
+impl AuditEvent {
+    /// `Failed` or `Cancelled` for an error
+    pub fn from_error(task_id: &str, error: &ConvertError) -> Self {
+        if *error == ConvertError::Cancelled {
+            return AuditEvent::Cancelled { task_id: task_id.to_string() };
+        }
+        AuditEvent::Failed {
+            task_id: task_id.to_string(),
+            error_code: error.code(),
+            message: error.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    /// UTC, `YYYY-MM-DDTHH:MM:SSZ`
+    at: String,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// Handle to the audit writer; the default one records nothing. Clones
+/// share the writer.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    sender: Option<Sender<AuditEvent>>,
+}
+
+impl AuditLog {
+    /// Start a writer appending to `path`. It stops once every handle is
+    /// dropped.
+    pub fn open(path: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel::<AuditEvent>();
+        std::thread::spawn(move || {
+            for mut event in receiver {
+                if let AuditEvent::Completed { output_path, output_sha256, .. } = &mut event {
+                    if output_sha256.is_none() {
+                        *output_sha256 =
+                            sha256_file_blocking(Path::new(output_path), &CancellationToken::new())
+                                .ok();
+                    }
+                }
+                if let Err(e) = append(&path, &event) {
+                    eprintln!("Failed to write audit log {}: {}", path.display(), e);
+                }
+            }
+        });
+        AuditLog { sender: Some(sender) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Queue an event for writing; never blocks
+    pub fn record(&self, event: AuditEvent) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event);
+        }
+    }
+}
+
+fn append(path: &Path, event: &AuditEvent) -> std::io::Result<()> {
+    let record = AuditRecord { at: utc_timestamp(), event };
+    let line = serde_json::to_string(&record)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let size = std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+    if size > 0 && size + line.len() as u64 + 1 > AUDIT_ROTATE_BYTES {
+        std::fs::rename(path, rotated_path(path))?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".1");
+    path.with_file_name(name)
+}
+
+/// The last `lines` lines of the log at `path`, oldest first; empty when
+/// there is no log yet
+pub fn tail(path: &Path, lines: usize) -> Result<Vec<String>, String> {
+    let lines = lines.min(MAX_TAIL_LINES);
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read audit log: {}", e)),
+    };
+    let mut last = VecDeque::with_capacity(lines);
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read audit log: {}", e))?;
+        if last.len() == lines {
+            last.pop_front();
+        }
+        if lines > 0 {
+            last.push_back(line);
+        }
+    }
+    Ok(last.into())
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::VideoInfo;
+use crate::error::ConvertError;
+use crate::paths::ffmpeg_path_arg;
+use crate::process::{output_cancellable, TokioRunner};
+use crate::resolver::h264_encoders;
+
+const TEST_WIDTH: u32 = 1920;
+const TEST_HEIGHT: u32 = 1080;
+const TEST_RATE: u32 = 30;
+
+/// Measured throughput of one encoder on the synthetic 1080p source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncoderBenchmark {
+    pub encoder: String,
+    /// Encoded frames per second
+    pub fps: Option<f64>,
+    /// Media seconds encoded per wall-clock second
+    pub speed: Option<f64>,
+    /// Why the encoder couldn't be measured (e.g. compiled in but no GPU)
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Benchmark {
+    /// Identifies the machine and ffmpeg build the numbers belong to
+    pub fingerprint: String,
+    pub duration_seconds: f64,
+    pub results: Vec<EncoderBenchmark>,
+}
+
+impl Benchmark {
+    /// Estimated encode time for a source using `encoder`
+    pub fn estimate_seconds(&self, info: &VideoInfo, encoder: &str) -> Option<f64> {
+        if info.duration <= 0.0 {
+            return None;
+        }
+        Some(info.duration / self.expected_speed(info, encoder)?)
+    }
+
+    /// Speed `encoder` should reach on a source, scaling the measured 1080p
+    /// speed by pixel count
+    pub fn expected_speed(&self, info: &VideoInfo, encoder: &str) -> Option<f64> {
+        let speed = self
+            .results
+            .iter()
+            .find(|r| r.encoder == encoder)
+            .and_then(|r| r.speed)?;
+        if speed <= 0.0 {
+            return None;
+        }
+        let pixels = (info.width as f64 * info.height as f64).max(1.0);
+        Some(speed * (TEST_WIDTH as f64 * TEST_HEIGHT as f64) / pixels)
+    }
+}
+
+/// A result is reusable while the CPU count, platform and ffmpeg build match
+pub fn hardware_fingerprint(ffmpeg_version: &str) -> String {
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(0);
+    format!(
+        "{}-{}-{}cores-{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        cores,
+        ffmpeg_version
+    )
+}
+
+/// Encode a synthetic `testsrc2` clip with every available H.264 encoder.
+///
+/// Each run writes a temp mp4 so muxing is included in the timing; the file
+/// is removed whether the run succeeds, fails or is cancelled.
+pub async fn run_benchmark(
+    ffmpeg_path: &str,
+    fingerprint: String,
+    duration_seconds: f64,
+    cancel: &CancellationToken,
+) -> Result<Benchmark, ConvertError> {
+    if !(1.0..=60.0).contains(&duration_seconds) {
+        return Err("Benchmark duration must be between 1 and 60 seconds".into());
+    }
+
+    let mut results = Vec::new();
+    for encoder in h264_encoders(ffmpeg_path).await? {
+        let output_path = std::env::temp_dir().join(format!(
+            "mp4-converter-benchmark-{}.mp4",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let mut cmd = Command::new(ffmpeg_path);
+        cmd.args(["-hide_banner", "-nostdin", "-y", "-f", "lavfi", "-i"])
+            .arg(format!(
+                "testsrc2=size={}x{}:rate={}",
+                TEST_WIDTH, TEST_HEIGHT, TEST_RATE
+            ))
+            .arg("-t")
+            .arg(duration_seconds.to_string())
+            .args(encoder_args(&encoder))
+            .arg(ffmpeg_path_arg(&output_path));
+
+        let started = Instant::now();
+        let output = output_cancellable(&TokioRunner, &mut cmd, cancel).await;
+        let elapsed = started.elapsed().as_secs_f64();
+        let _ = std::fs::remove_file(&output_path);
+
+        let result = match output {
+            Ok(output) if output.status.success() && elapsed > 0.0 => EncoderBenchmark {
+                encoder,
+                fps: Some(duration_seconds * TEST_RATE as f64 / elapsed),
+                speed: Some(duration_seconds / elapsed),
+                error: None,
+            },
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                EncoderBenchmark {
+                    encoder,
+                    fps: None,
+                    speed: None,
+                    error: Some(stderr.lines().last().unwrap_or("Encoder failed").to_string()),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        results.push(result);
+    }
+
+    Ok(Benchmark {
+        fingerprint,
+        duration_seconds,
+        results,
+    })
+}
+
+/// Settings matching what `convert_video` uses for each encoder
+fn encoder_args(encoder: &str) -> Vec<String> {
+    let args: &[&str] = match encoder {
+        "libx264" => &["-preset", "fast", "-crf", "23", "-pix_fmt", "yuv420p"],
+        "h264_videotoolbox" => &["-q:v", "65", "-pix_fmt", "yuv420p"],
+        // VAAPI needs frames uploaded to the GPU instead of a pixel format
+        "h264_vaapi" => &["-vaapi_device", "/dev/dri/renderD128", "-vf", "format=nv12,hwupload"],
+        _ => &["-pix_fmt", "yuv420p"],
+    };
+    ["-c:v", encoder]
+        .iter()
+        .chain(args)
+        .map(|a| a.to_string())
+        .collect()
+}
@@ -0,0 +1,249 @@
+//! Size cap for what the app keeps in its cache dir: contact sheets,
+//! previews, troubleshooting samples and staged inputs.
+//!
+//! Each file is registered with its size and last use in a small index
+//! (`cache-index.json` in the cache dir), and the least recently used ones
+//! go once the total is over the cap. Files of running tasks are never
+//! evicted. Files can disappear behind the index's back (the OS cleaning
+//! caches, the user, a task dir being removed); they just drop out of it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+pub const DEFAULT_CACHE_CAP_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+const INDEX_FILE: &str = "cache-index.json";
+
+/// What a cached file is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    ContactSheet,
+    Preview,
+    Sample,
+    /// Local copy of a network input, inside its task dir
+    Staged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    kind: CacheKind,
+    bytes: u64,
+    /// Milliseconds since the Unix epoch
+    last_used: u64,
+    /// The task that wrote it; its files stay while it runs
+    task_id: Option<String>,
+}
+
+/// How much of the cap is in use
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheUsage {
+    pub total_bytes: u64,
+    pub cap_bytes: u64,
+    pub files: usize,
+    pub bytes_by_kind: BTreeMap<CacheKind, u64>,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    /// Where the index is saved; None keeps it in memory only
+    index_path: Option<PathBuf>,
+    cap_bytes: u64,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+/// The cache index, shared by everything that writes to the cache
+#[derive(Debug)]
+pub struct CacheManager {
+    state: Mutex<CacheState>,
+    /// Woken when a registration takes the cache over its cap
+    over_cap: Notify,
+}
+
+impl Default for CacheManager {
+    fn default() -> Self {
+        CacheManager {
+            state: Mutex::new(CacheState {
+                index_path: None,
+                cap_bytes: DEFAULT_CACHE_CAP_BYTES,
+                entries: HashMap::new(),
+            }),
+            over_cap: Notify::new(),
+        }
+    }
+}
+
+impl CacheManager {
+    /// Load the index kept in `cache_dir` and save to it from now on;
+    /// entries whose files are gone are dropped
+    pub fn open(&self, cache_dir: &Path) {
+        let index_path = cache_dir.join(INDEX_FILE);
+        let mut entries: HashMap<PathBuf, CacheEntry> = std::fs::read_to_string(&index_path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        entries.retain(|path, _| path.is_file());
+        let mut state = self.state.lock().unwrap();
+        entries.extend(state.entries.drain());
+        state.entries = entries;
+        state.index_path = Some(index_path);
+        save(&state);
+    }
+
+    pub fn set_cap(&self, bytes: u64) {
+        self.state.lock().unwrap().cap_bytes = bytes;
+        self.over_cap.notify_one();
+    }
+
+    /// Record a file just written, or rewritten, as used now
+    pub fn register(&self, path: &Path, kind: CacheKind, task_id: Option<&str>) {
+        let Ok(meta) = std::fs::metadata(path) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.entries.insert(
+            path.to_path_buf(),
+            CacheEntry {
+                kind,
+                bytes: meta.len(),
+                last_used: now(),
+                task_id: task_id.map(str::to_string),
+            },
+        );
+        save(&state);
+        if total_bytes(&state) > state.cap_bytes {
+            self.over_cap.notify_one();
+        }
+    }
+
+    /// Mark a cached file as just used, so it goes last
+    pub fn touch(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.get_mut(path) {
+            entry.last_used = now();
+            save(&state);
+        }
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let mut state = self.state.lock().unwrap();
+        forget_missing(&mut state);
+        let mut bytes_by_kind = BTreeMap::new();
+        for entry in state.entries.values() {
+            *bytes_by_kind.entry(entry.kind).or_default() += entry.bytes;
+        }
+        CacheUsage {
+            total_bytes: total_bytes(&state),
+            cap_bytes: state.cap_bytes,
+            files: state.entries.len(),
+            bytes_by_kind,
+        }
+    }
+
+    /// Wait until a registration (or a lowered cap) may have taken the
+    /// cache over its cap
+    pub async fn over_cap(&self) {
+        self.over_cap.notified().await;
+    }
+
+    /// Delete least recently used files until the cache fits its cap,
+    /// sparing those of `running` tasks; the bytes freed
+    pub fn evict(&self, running: &[String]) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        forget_missing(&mut state);
+        let mut total = total_bytes(&state);
+        if total <= state.cap_bytes {
+            return 0;
+        }
+        let mut candidates: Vec<(PathBuf, u64, u64)> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| !is_running(entry, running))
+            .map(|(path, entry)| (path.clone(), entry.last_used, entry.bytes))
+            .collect();
+        candidates.sort_by_key(|(_, last_used, _)| *last_used);
+        let mut freed = 0;
+        for (path, _, bytes) in candidates {
+            if total <= state.cap_bytes {
+                break;
+            }
+            if remove(&path) {
+                state.entries.remove(&path);
+                total -= bytes;
+                freed += bytes;
+            }
+        }
+        save(&state);
+        freed
+    }
+
+    /// Delete every cached file except those of `running` tasks; the bytes
+    /// freed
+    pub fn clear(&self, running: &[String]) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let mut freed = 0;
+        let paths: Vec<PathBuf> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| !is_running(entry, running))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in paths {
+            if remove(&path) {
+                freed += state.entries.remove(&path).map_or(0, |entry| entry.bytes);
+            }
+        }
+        save(&state);
+        freed
+    }
+}
+
+fn is_running(entry: &CacheEntry, running: &[String]) -> bool {
+    entry.task_id.as_ref().is_some_and(|id| running.contains(id))
+}
+
+/// Delete a cached file; one that is already gone counts as deleted
+fn remove(path: &Path) -> bool {
+    match std::fs::remove_file(path) {
+        Ok(()) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => {
+            eprintln!("Failed to remove cached file {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+fn forget_missing(state: &mut CacheState) {
+    state.entries.retain(|path, _| path.is_file());
+}
+
+fn total_bytes(state: &CacheState) -> u64 {
+    state.entries.values().map(|entry| entry.bytes).sum()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Best effort, like the other caches: a failed save only costs the
+/// last-use times of this session
+fn save(state: &CacheState) {
+    let Some(path) = &state.index_path else {
+        return;
+    };
+    let Ok(json) = serde_json::to_string(&state.entries) else {
+        return;
+    };
+    let temp_path = path.with_extension("json.tmp");
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if std::fs::write(&temp_path, json).is_ok() {
+        let _ = std::fs::rename(&temp_path, path);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A chapter marker, in seconds on the source timeline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// Read the `chapters` array of ffprobe's `-show_chapters` JSON output
+pub fn parse_chapters(json: &serde_json::Value) -> Vec<Chapter> {
+    let Some(chapters) = json["chapters"].as_array() else {
+        return Vec::new();
+    };
+    chapters
+        .iter()
+        .filter_map(|chapter| {
+            let start = chapter["start_time"].as_str()?.parse::<f64>().ok()?;
+            let end = chapter["end_time"].as_str()?.parse::<f64>().ok()?;
+            let title = chapter["tags"]["title"].as_str().map(|t| t.to_string());
+            Some(Chapter { start, end, title })
+        })
+        .collect()
+}
+
+/// Fit chapters to an output that keeps `start..end` of the source and plays
+/// at `speed`: chapters outside the range are dropped, the rest are clipped
+/// and shifted so the first kept moment is 0.
+pub fn retime_chapters(
+    chapters: &[Chapter],
+    start: f64,
+    end: Option<f64>,
+    speed: f64,
+) -> Vec<Chapter> {
+    let end = end.unwrap_or(f64::INFINITY);
+    chapters
+        .iter()
+        .filter(|c| c.end > start && c.start < end)
+        .map(|c| Chapter {
+            start: (c.start.max(start) - start) / speed,
+            end: (c.end.min(end) - start) / speed,
+            title: c.title.clone(),
+        })
+        .collect()
+}
+
+/// An ffmetadata file carrying chapters, removed when dropped
+#[derive(Debug)]
+pub struct ChapterFile {
+    pub path: PathBuf,
+}
+
+impl Drop for ChapterFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Write chapters as an ffmetadata file in `work_dir` for `-map_chapters`
+pub fn write_chapter_file(chapters: &[Chapter], work_dir: &Path) -> Result<ChapterFile, String> {
+    let mut text = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        text.push_str("[CHAPTER]\nTIMEBASE=1/1000\n");
+        text.push_str(&format!("START={}\n", (chapter.start * 1000.0).round() as i64));
+        text.push_str(&format!("END={}\n", (chapter.end * 1000.0).round() as i64));
+        if let Some(title) = &chapter.title {
+            text.push_str(&format!("title={}\n", escape_metadata(title)));
+        }
+    }
+
+    let path = work_dir.join("chapters.txt");
+    std::fs::write(&path, text).map_err(|e| format!("Failed to write chapter file: {}", e))?;
+    Ok(ChapterFile { path })
+}
+
+/// Backslash-escape the characters ffmetadata treats specially
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// ffprobe's `-show_chapters` JSON for three 10 second chapters
+    fn three_chapters() -> serde_json::Value {
+        let chapter = |id: u32, title: &str| {
+            json!({
+                "id": id, "time_base": "1/1000",
+                "start_time": format!("{}.000000", id * 10),
+                "end_time": format!("{}.000000", id * 10 + 10),
+                "tags": {"title": title}
+            })
+        };
+        json!({"chapters": [chapter(0, "Intro"), chapter(1, "Act 1; = #2"), chapter(2, "Credits")]})
+    }
+
+    /// Chapters back from an ffmetadata file, the way ffmpeg reads them
+    fn read_ffmetadata(text: &str) -> Vec<Chapter> {
+        let mut chapters = Vec::new();
+        for block in text.split("[CHAPTER]\n").skip(1) {
+            let field = |name: &str| {
+                block.lines().find_map(|line| line.strip_prefix(name).map(str::to_string))
+            };
+            let millis = |name| field(name).unwrap().parse::<f64>().unwrap() / 1000.0;
+            let title = field("title=").map(|title| {
+                let mut unescaped = String::new();
+                let mut chars = title.chars();
+                while let Some(c) = chars.next() {
+                    unescaped.push(if c == '\\' { chars.next().unwrap() } else { c });
+                }
+                unescaped
+            });
+            chapters.push(Chapter { start: millis("START="), end: millis("END="), title });
+        }
+        chapters
+    }
+
+    #[test]
+    fn parses_probed_chapters() {
+        let chapters = parse_chapters(&three_chapters());
+        assert_eq!(chapters.len(), 3);
+        assert_eq!(chapters[1], Chapter {
+            start: 10.0,
+            end: 20.0,
+            title: Some("Act 1; = #2".to_string()),
+        });
+        assert_eq!(parse_chapters(&json!({})), []);
+    }
+
+    #[test]
+    fn retimes_to_the_kept_range_and_speed() {
+        let chapters = parse_chapters(&three_chapters());
+        let kept = retime_chapters(&chapters, 5.0, Some(25.0), 2.0);
+        let times: Vec<(f64, f64)> = kept.iter().map(|c| (c.start, c.end)).collect();
+        assert_eq!(times, [(0.0, 2.5), (2.5, 7.5), (7.5, 10.0)]);
+        // Chapters wholly outside the range are dropped
+        let kept = retime_chapters(&chapters, 20.0, None, 1.0);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].title.as_deref(), Some("Credits"));
+    }
+
+    #[test]
+    fn three_chapters_round_trip_through_ffmetadata() {
+        let dir = std::env::temp_dir().join(format!("chapters-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let chapters = retime_chapters(&parse_chapters(&three_chapters()), 0.0, None, 2.0);
+        let file = write_chapter_file(&chapters, &dir).unwrap();
+        let text = std::fs::read_to_string(&file.path).unwrap();
+        assert!(text.starts_with(";FFMETADATA1\n"));
+        assert!(text.contains("title=Act 1\\; \\= \\#2\n"), "{}", text);
+
+        let read = read_ffmetadata(&text);
+        assert_eq!(read.len(), 3);
+        let titles: Vec<&str> = read.iter().filter_map(|c| c.title.as_deref()).collect();
+        assert_eq!(titles, ["Intro", "Act 1; = #2", "Credits"]);
+        assert_eq!(read, chapters);
+
+        let path = file.path.clone();
+        drop(file);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
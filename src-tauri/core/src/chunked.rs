@@ -0,0 +1,361 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::command::{FfmpegCommandBuilder, Section, NO_OPTIONS};
+use crate::converter::{
+    parse_time_to_seconds, video_encoder_args, ConversionProgress, ConversionStatus, RateLimit,
+    VideoInfo,
+};
+use crate::error::ConvertError;
+use crate::paths::ffmpeg_path_arg;
+use crate::process::{output_cancellable, ProcessRunner};
+use crate::task_log::TaskLog;
+use crate::telemetry::ProcessTable;
+
+/// libx264 stops scaling at around this many threads, so each segment job
+/// gets roughly this many cores
+const THREADS_PER_JOB: usize = 8;
+const MAX_JOBS: usize = 8;
+/// Segments shorter than this cost more in startup than they save
+const MIN_SEGMENT_SECONDS: f64 = 30.0;
+/// Thread count of the copy-only split and join runs: ffmpeg's own choice
+const AUTO_THREADS: &str = "0";
+
+/// Everything a chunked encode needs, already validated by `convert_video`
+pub struct ChunkedJob<'a> {
+    pub runner: &'a dyn ProcessRunner,
+    /// Where the segment encodes are registered for usage telemetry
+    pub processes: &'a Arc<ProcessTable>,
+    pub ffmpeg_path: &'a str,
+    pub ffprobe_path: &'a str,
+    pub info: &'a VideoInfo,
+    pub output_path: &'a Path,
+    pub video_filters: &'a [String],
+    pub extra_video_args: &'a [String],
+    pub rate_limit: Option<RateLimit>,
+    pub crf: u32,
+    /// `-allow_sw` for VideoToolbox
+    pub allow_sw: bool,
+    /// Color tags copied from the source onto each encoded segment
+    pub color_args: &'a [String],
+    /// Audio codec, filter and extra args for the final mux
+    pub audio_args: &'a [String],
+    pub extra_output_args: &'a [String],
+    /// Replaces copying the source metadata when not empty
+    pub metadata_args: &'a [String],
+    /// Retimed ffmetadata chapters; without one the source chapters are kept
+    pub chapter_file: Option<&'a Path>,
+    /// Output duration, for progress
+    pub duration: f64,
+    pub task_id: &'a str,
+    /// The task's private directory; segments go in a subfolder and ffmpeg
+    /// runs from it
+    pub work_dir: &'a Path,
+    pub log: &'a TaskLog,
+    pub cancel: &'a CancellationToken,
+}
+
+pub enum ChunkOutcome {
+    Done,
+    /// The source can't be split safely; the reason is logged and the caller
+    /// falls back to a single ffmpeg process
+    Unsupported(String),
+}
+
+/// Temp directory for segments, removed with everything in it when dropped
+struct WorkDir(PathBuf);
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Encode the video in parallel segments and join them losslessly.
+///
+/// The source video is split at keyframes with `-f segment -c copy`, every
+/// segment is encoded by its own ffmpeg process, and the parts are joined
+/// with the concat demuxer while the audio is taken from the original file.
+pub async fn encode_chunked<F>(
+    job: &ChunkedJob<'_>,
+    callback: Arc<F>,
+) -> Result<ChunkOutcome, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let jobs = (cores / THREADS_PER_JOB)
+        .clamp(2, MAX_JOBS)
+        .min((job.info.duration / MIN_SEGMENT_SECONDS) as usize);
+    if jobs < 2 {
+        return Ok(ChunkOutcome::Unsupported("file is too short to split".to_string()));
+    }
+    if let Some(reason) = check_constant_frame_rate(job).await? {
+        return Ok(ChunkOutcome::Unsupported(reason));
+    }
+
+    let dir = job.work_dir.join("chunks");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let work_dir = WorkDir(dir);
+
+    let segments = split(job, &work_dir.0, jobs).await?;
+    if segments.len() != jobs {
+        return Ok(ChunkOutcome::Unsupported(format!(
+            "keyframes are too far apart to split into {} parts",
+            jobs
+        )));
+    }
+
+    let threads = (cores / jobs).max(1);
+    let encoded = encode_segments(job, &work_dir.0, &segments, threads, callback.clone()).await?;
+
+    // Splitting at an open-GOP keyframe drops the leading frames that
+    // reference the previous segment, which shows up as missing time
+    let encoded_seconds: f64 = encoded.iter().sum();
+    let frame = if job.info.frame_rate > 0.0 { 1.0 / job.info.frame_rate } else { 0.05 };
+    let tolerance = 0.5 + frame * jobs as f64;
+    if (encoded_seconds - job.duration).abs() > tolerance {
+        return Ok(ChunkOutcome::Unsupported(format!(
+            "segments add up to {:.2}s instead of {:.2}s",
+            encoded_seconds, job.duration
+        )));
+    }
+
+    callback(ConversionProgress {
+        indeterminate: true,
+        ..ConversionProgress::update(job.task_id, 100.0, ConversionStatus::Finalizing)
+    });
+    concat(job, &work_dir.0, segments.len()).await?;
+    Ok(ChunkOutcome::Done)
+}
+
+/// Refuse variable frame rate sources, whose segment boundaries don't line
+/// up cleanly after re-encoding
+async fn check_constant_frame_rate(job: &ChunkedJob<'_>) -> Result<Option<String>, ConvertError> {
+    let mut cmd = Command::new(job.ffprobe_path);
+    cmd.args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=r_frame_rate,avg_frame_rate", "-of", "csv=p=0"])
+        .arg(ffmpeg_path_arg(Path::new(&job.info.path)));
+    let output = output_cancellable(job.runner, &mut cmd, job.cancel).await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rates: Vec<f64> = stdout
+        .trim()
+        .split(',')
+        .filter_map(|rate| {
+            let (num, den) = rate.split_once('/')?;
+            let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+            (den != 0.0).then(|| num / den)
+        })
+        .collect();
+
+    match rates.as_slice() {
+        [real, average] if *average > 0.0 && (real - average).abs() / average <= 0.01 => Ok(None),
+        _ => Ok(Some("variable or unknown frame rate".to_string())),
+    }
+}
+
+/// Split the video stream into roughly equal keyframe-aligned segments and
+/// return their file names
+async fn split(job: &ChunkedJob<'_>, dir: &Path, jobs: usize) -> Result<Vec<String>, ConvertError> {
+    let times: Vec<String> = (1..jobs)
+        .map(|i| format!("{:.3}", job.info.duration * i as f64 / jobs as f64))
+        .collect();
+    let list_path = dir.join("segments.csv");
+
+    let mut args = FfmpegCommandBuilder::new(AUTO_THREADS);
+    args.input(NO_OPTIONS, ffmpeg_path_arg(Path::new(&job.info.path)));
+    args.map(format!("0:v:{}", job.info.video_stream_index))
+        .push(Section::Video, ["-c", "copy"])
+        .push(Section::OutputFlags, ["-f", "segment", "-segment_times"])
+        .push(Section::OutputFlags, [times.join(","), "-segment_list".to_string()])
+        .push(Section::OutputFlags, [ffmpeg_path_arg(&list_path)])
+        .push(Section::OutputFlags, ["-segment_list_type", "csv", "-reset_timestamps", "1"])
+        .output(ffmpeg_path_arg(&dir.join("source_%03d.mkv")));
+    let mut cmd = ffmpeg_command(job, &args);
+
+    let output = output_cancellable(job.runner, &mut cmd, job.cancel).await?;
+    if !output.status.success() {
+        return Err(format!("Failed to split video: ffmpeg exited with status: {}", output.status)
+            .into());
+    }
+
+    let list = std::fs::read_to_string(&list_path)
+        .map_err(|e| format!("Failed to read segment list: {}", e))?;
+    Ok(list
+        .lines()
+        .filter_map(|line| line.split(',').next())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect())
+}
+
+/// Encode every segment at once, returning the encoded duration of each
+async fn encode_segments<F>(
+    job: &ChunkedJob<'_>,
+    dir: &Path,
+    segments: &[String],
+    threads: usize,
+    callback: Arc<F>,
+) -> Result<Vec<f64>, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let done = Arc::new(Mutex::new(vec![0.0; segments.len()]));
+    let (encoder_args, _) = video_encoder_args(&threads.to_string(), job.rate_limit, job.crf, job.allow_sw);
+    let mut tasks = JoinSet::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+        let mut args = FfmpegCommandBuilder::new(&threads.to_string());
+        args.input(NO_OPTIONS, ffmpeg_path_arg(&dir.join(segment)));
+        if !job.video_filters.is_empty() {
+            args.push(Section::Filters, ["-vf".to_string(), job.video_filters.join(",")]);
+        }
+        args.push(Section::Video, encoder_args.iter().chain(job.color_args))
+            .push(Section::Video, job.extra_video_args)
+            .push(Section::Video, ["-pix_fmt", "yuv420p"])
+            .push(Section::Audio, ["-an"])
+            .push(Section::OutputFlags, ["-progress", "pipe:1"])
+            .output(ffmpeg_path_arg(&dir.join(format!("encoded_{:03}.mkv", index))));
+        let mut cmd = ffmpeg_command(job, &args);
+
+        let done = Arc::clone(&done);
+        let callback = Arc::clone(&callback);
+        let task_id = job.task_id.to_string();
+        let duration = job.duration;
+        let spawned = job.runner.spawn(cmd.stdin(Stdio::null()));
+        let registered = spawned
+            .as_ref()
+            .ok()
+            .and_then(|child| child.id())
+            .map(|pid| job.processes.register(job.task_id, pid));
+        tasks.spawn(async move {
+            let _registered = registered;
+            let mut child = spawned.map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+            let stdout = child.take_stdout().ok_or("Failed to capture stdout")?;
+            // Nothing on stderr is used, but it must keep flowing
+            if let Some(mut stderr) = child.take_stderr() {
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await;
+                });
+            }
+            let mut reader = BufReader::new(stdout).lines();
+
+            while let Ok(Some(line)) = reader.next_line().await {
+                let Some(time) = line.strip_prefix("out_time=") else {
+                    continue;
+                };
+                let total: f64 = {
+                    let mut done = done.lock().unwrap();
+                    done[index] = parse_time_to_seconds(time);
+                    done.iter().sum()
+                };
+                let percent = if duration > 0.0 {
+                    (total / duration * 100.0).min(100.0)
+                } else {
+                    0.0
+                };
+                callback(ConversionProgress::update(&task_id, percent, ConversionStatus::Converting));
+            }
+
+            let status = child.wait().await.map_err(|e| format!("FFmpeg process error: {}", e))?;
+            if !status.success() {
+                return Err(format!(
+                    "Segment {} failed: ffmpeg exited with status: {}",
+                    index, status
+                ));
+            }
+            Ok(())
+        });
+    }
+
+    loop {
+        let next = tokio::select! {
+            next = tasks.join_next() => next,
+            _ = job.cancel.cancelled() => {
+                // Aborting drops each task's child, and kill_on_drop stops it
+                tasks.shutdown().await;
+                return Err(ConvertError::Cancelled);
+            }
+        };
+        match next {
+            None => break,
+            Some(Ok(Ok(()))) => {}
+            Some(Ok(Err(e))) => {
+                tasks.shutdown().await;
+                return Err(e.into());
+            }
+            Some(Err(e)) => {
+                tasks.shutdown().await;
+                return Err(format!("Segment encode task failed: {}", e).into());
+            }
+        }
+    }
+
+    let done = done.lock().unwrap().clone();
+    Ok(done)
+}
+
+/// Join the encoded segments and mux the original audio back in
+async fn concat(job: &ChunkedJob<'_>, dir: &Path, count: usize) -> Result<(), ConvertError> {
+    let list_path = dir.join("parts.txt");
+    let list: String = (0..count)
+        .map(|i| format!("file 'encoded_{:03}.mkv'\n", i))
+        .collect();
+    std::fs::write(&list_path, list).map_err(|e| format!("Failed to write concat list: {}", e))?;
+
+    let mut args = FfmpegCommandBuilder::new(AUTO_THREADS);
+    args.input(["-f", "concat"], ffmpeg_path_arg(&list_path));
+    let source = args.input(NO_OPTIONS, ffmpeg_path_arg(Path::new(&job.info.path)));
+    let chapters_from = match job.chapter_file {
+        Some(path) => args.input(NO_OPTIONS, ffmpeg_path_arg(path)),
+        None => source,
+    };
+    args.map("0:v:0")
+        .map(format!("{}:a:{}?", source, job.info.audio_stream_index))
+        .push(Section::Mapping, ["-map_chapters".to_string(), chapters_from.to_string()])
+        .push(Section::Video, ["-c:v", "copy"]);
+    // Tags come from the original file unless they are being stripped
+    if !job.metadata_args.iter().any(|arg| arg == "-map_metadata") {
+        args.push(Section::Metadata, ["-map_metadata".to_string(), source.to_string()]);
+    }
+    args.push(Section::Metadata, job.metadata_args)
+        .push(Section::Audio, job.audio_args)
+        .push(Section::OutputFlags, ["-movflags", "+faststart"])
+        .push(Section::OutputFlags, job.extra_output_args)
+        .output(ffmpeg_path_arg(job.output_path));
+    let mut cmd = ffmpeg_command(job, &args);
+
+    let result = output_cancellable(job.runner, &mut cmd, job.cancel).await;
+    let failure = match &result {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => Some(ConvertError::Failed(format!(
+            "Failed to join segments: ffmpeg exited with status: {}",
+            output.status
+        ))),
+        Err(e) => Some(e.clone()),
+    };
+    match failure {
+        None => Ok(()),
+        Some(e) => {
+            let _ = std::fs::remove_file(job.output_path);
+            Err(e)
+        }
+    }
+}
+
+/// The ffmpeg process for `args`, run from the task's folder and logged
+fn ffmpeg_command(job: &ChunkedJob<'_>, args: &FfmpegCommandBuilder) -> Command {
+    let mut args = args.clone();
+    args.push(Section::Global, ["-hide_banner"]);
+    let args = args.build();
+    job.log.command(job.ffmpeg_path, &args);
+    let mut cmd = Command::new(job.ffmpeg_path);
+    cmd.current_dir(job.work_dir).args(args);
+    cmd
+}
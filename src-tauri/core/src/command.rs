@@ -0,0 +1,163 @@
+//! The ffmpeg command line of a conversion, assembled in fixed sections.
+//!
+//! `convert_video` decides what goes into each section in whatever order
+//! suits it; the argv always comes out in the order below, so a new option
+//! can't land before an input it belongs after, or after the output file.
+
+/// Part of the command line, in argv order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// Options that apply to the whole run
+    Global,
+    /// Every input with the options in front of it
+    Inputs,
+    /// `-map` and `-map_chapters`, plus the codecs of streams that are
+    /// mapped wholesale (data, attachments)
+    Mapping,
+    Video,
+    /// `-vf`, `-af` and per-stream filters
+    Filters,
+    Audio,
+    Subtitles,
+    Metadata,
+    /// Muxer flags, length limits and the progress pipe
+    OutputFlags,
+}
+
+const SECTIONS: usize = Section::OutputFlags as usize + 1;
+
+/// For `input` when nothing goes in front of it
+pub const NO_OPTIONS: [&str; 0] = [];
+
+/// Builder of one ffmpeg invocation
+#[derive(Debug, Clone)]
+pub struct FfmpegCommandBuilder {
+    sections: [Vec<String>; SECTIONS],
+    inputs: usize,
+    threads: String,
+    output: Option<String>,
+}
+
+impl FfmpegCommandBuilder {
+    /// Never prompt, overwrite the output, and use `threads` for decoding;
+    /// encoding gets the same count unless the encoder settings name one
+    pub fn new(threads: &str) -> Self {
+        let mut builder = FfmpegCommandBuilder {
+            sections: Default::default(),
+            inputs: 0,
+            threads: threads.to_string(),
+            output: None,
+        };
+        builder.push(Section::Global, ["-nostdin", "-threads", threads, "-y"]);
+        builder
+    }
+
+    /// Let ffmpeg read keys from stdin, so `q` can stop it where signals
+    /// can't be sent (Windows); the process must then get a piped stdin
+    pub fn accept_keys(&mut self) -> &mut Self {
+        self.sections[Section::Global as usize].retain(|arg| arg != "-nostdin");
+        self
+    }
+
+    /// Append arguments to a section
+    pub fn push<I, S>(&mut self, section: Section, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.sections[section as usize].extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Add an input after the options that apply to it; the input's index,
+    /// as `-map` counts them
+    pub fn input<I, S>(&mut self, options: I, path: impl Into<String>) -> usize
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.push(Section::Inputs, options);
+        self.push(Section::Inputs, ["-i".to_string(), path.into()]);
+        self.inputs += 1;
+        self.inputs - 1
+    }
+
+    /// Map a stream, e.g. `0:v:0` or `1:a?`
+    pub fn map(&mut self, spec: impl Into<String>) -> &mut Self {
+        self.push(Section::Mapping, ["-map".to_string(), spec.into()])
+    }
+
+    pub fn output(&mut self, path: impl Into<String>) -> &mut Self {
+        self.output = Some(path.into());
+        self
+    }
+
+    /// The argv, without the program
+    pub fn build(&self) -> Vec<String> {
+        let mut args = self.sections.concat();
+        // One encoder thread count: the encoder settings' own, or the global one
+        let output_sections = &self.sections[Section::Mapping as usize..];
+        if !output_sections.iter().flatten().any(|arg| arg == "-threads") {
+            args.extend(["-threads".to_string(), self.threads.clone()]);
+        }
+        args.extend(self.output.iter().cloned());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn sections_come_out_in_argv_order() {
+        let mut cmd = FfmpegCommandBuilder::new("4");
+        cmd.output("out.mp4");
+        cmd.push(Section::OutputFlags, ["-progress", "pipe:1"]);
+        cmd.push(Section::Metadata, ["-metadata", "title=Clip"]);
+        cmd.push(Section::Subtitles, ["-sn"]);
+        cmd.push(Section::Audio, ["-c:a", "copy"]);
+        cmd.push(Section::Filters, ["-vf", "scale=-2:720"]);
+        cmd.push(Section::Video, ["-c:v", "copy"]);
+        cmd.map("0:v:0");
+        cmd.input(["-ss", "10.000"], "in.mov");
+        let expected = argv(&[
+            "-nostdin", "-threads", "4", "-y", "-ss", "10.000", "-i", "in.mov", "-map", "0:v:0",
+            "-c:v", "copy", "-vf", "scale=-2:720", "-c:a", "copy", "-sn", "-metadata",
+            "title=Clip", "-progress", "pipe:1", "-threads", "4", "out.mp4",
+        ]);
+        assert_eq!(cmd.build(), expected);
+    }
+
+    #[test]
+    fn encoder_threads_replace_the_global_count() {
+        let mut cmd = FfmpegCommandBuilder::new("4");
+        cmd.input(NO_OPTIONS, "in.mov");
+        cmd.push(Section::Video, ["-c:v", "libx264", "-threads", "2"]);
+        cmd.output("out.mp4");
+        let expected = argv(&[
+            "-nostdin", "-threads", "4", "-y", "-i", "in.mov", "-c:v", "libx264", "-threads", "2",
+            "out.mp4",
+        ]);
+        assert_eq!(cmd.build(), expected);
+    }
+
+    #[test]
+    fn inputs_are_counted_as_map_indexes() {
+        let mut cmd = FfmpegCommandBuilder::new("2");
+        cmd.accept_keys();
+        assert_eq!(cmd.input(NO_OPTIONS, "in.mkv"), 0);
+        assert_eq!(cmd.input(["-itsoffset", "0.500"], "in.mkv"), 1);
+        cmd.map("0:v:0").map("1:a:0?");
+        cmd.output("out.mp4");
+        let expected = argv(&[
+            "-threads", "2", "-y", "-i", "in.mkv", "-itsoffset", "0.500", "-i", "in.mkv", "-map",
+            "0:v:0", "-map", "1:a:0?", "-threads", "2", "out.mp4",
+        ]);
+        assert_eq!(cmd.build(), expected);
+    }
+}
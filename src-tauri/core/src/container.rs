@@ -0,0 +1,98 @@
+//! Telling real MP4 files apart from the rest of the ISO/QuickTime family.
+//! ffprobe's `mov` demuxer reports `mov,mp4,m4a,3gp,3g2,mj2` for all of
+//! them, so the `ftyp` brand and the extension decide.
+
+use std::path::Path;
+
+/// `ftyp` brands of MP4-family files phones and apps open, lowercase
+const MP4_BRANDS: &[&str] = &[
+    "isom", "iso2", "iso3", "iso4", "iso5", "iso6", "mp41", "mp42", "avc1", "m4v", "m4vh",
+    "m4vp", "m4a", "mmp4", "msnv", "dash", "3gp4", "3gp5", "3gp6", "3g2a",
+];
+/// Brands of QuickTime and Motion JPEG 2000 files, which several Android
+/// apps refuse even with H.264/AAC inside
+const NON_MP4_BRANDS: &[&str] = &["qt", "mjp2"];
+/// Used when the file has no brand, as old QuickTime files don't
+const MP4_EXTENSIONS: &[&str] = &["mp4", "m4v", "m4a", "3gp", "3g2"];
+
+/// Whether a file is an MP4 that mobile apps accept, from ffprobe's
+/// `format_name`, the major brand if there is one, and the extension.
+/// An unknown major brand defers to the compatible brands, which ffprobe
+/// reports run together (`isomiso2avc1mp41`).
+pub fn is_mp4_family(
+    format_name: &str,
+    major_brand: Option<&str>,
+    compatible_brands: Option<&str>,
+    path: &Path,
+) -> bool {
+    if !format_name.split(',').any(|name| name == "mp4") {
+        return false;
+    }
+    if let Some(brand) = major_brand.map(|brand| brand.trim().to_lowercase()) {
+        if NON_MP4_BRANDS.contains(&brand.as_str()) {
+            return false;
+        }
+        if MP4_BRANDS.contains(&brand.as_str()) {
+            return true;
+        }
+        let compatible = compatible_brands.unwrap_or_default().to_lowercase();
+        let mut chunks = compatible.as_bytes().chunks(4);
+        if chunks.any(|chunk| {
+            std::str::from_utf8(chunk).is_ok_and(|chunk| MP4_BRANDS.contains(&chunk.trim()))
+        }) {
+            return true;
+        }
+    }
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    MP4_EXTENSIONS.contains(&extension.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOV_FORMAT: &str = "mov,mp4,m4a,3gp,3g2,mj2";
+
+    /// Format name, major brand, compatible brands, file name, expected
+    type Case = (&'static str, Option<&'static str>, Option<&'static str>, &'static str, bool);
+
+    #[test]
+    fn brand_and_extension_decide() {
+        let cases: &[Case] = &[
+            (MOV_FORMAT, Some("isom"), None, "a.mp4", true),
+            (MOV_FORMAT, Some("mp41"), None, "a.mp4", true),
+            (MOV_FORMAT, Some("mp42"), None, "a.mov", true),
+            (MOV_FORMAT, Some("M4V "), None, "a.m4v", true),
+            (MOV_FORMAT, Some("M4A "), None, "a.m4a", true),
+            (MOV_FORMAT, Some("3gp4"), None, "a.3gp", true),
+            (MOV_FORMAT, Some("qt  "), None, "a.mov", false),
+            // A QuickTime brand wins over an MP4 extension
+            (MOV_FORMAT, Some("qt  "), Some("qt  isom"), "a.mp4", false),
+            (MOV_FORMAT, Some("mjp2"), None, "a.mp4", false),
+            (MOV_FORMAT, Some("dash"), Some("iso6mp41"), "a.mp4", true),
+            (MOV_FORMAT, Some("avc1"), None, "a.mp4", true),
+            (MOV_FORMAT, Some("xxxx"), Some("xxxxisomavc1"), "a.mov", true),
+            (MOV_FORMAT, Some("xxxx"), Some("xxxxyyyy"), "a.mov", false),
+            (MOV_FORMAT, Some("xxxx"), None, "a.mp4", true),
+            // Old QuickTime files have no brand at all
+            (MOV_FORMAT, None, None, "a.mov", false),
+            (MOV_FORMAT, None, None, "A.MP4", true),
+            ("matroska,webm", None, None, "a.mp4", false),
+            ("avi", Some("isom"), None, "a.mp4", false),
+        ];
+        for (format, major, compatible, name, expected) in cases {
+            assert_eq!(
+                is_mp4_family(format, *major, *compatible, Path::new(name)),
+                *expected,
+                "{} {:?} {:?} {}",
+                format,
+                major,
+                compatible,
+                name
+            );
+        }
+    }
+}
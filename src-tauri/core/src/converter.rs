@@ -0,0 +1,4603 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::analysis::{
+    detect_channel_balance, detect_crop, detect_silence, edge_trim, CropRect, LiveChannel,
+    TrimRange, TRIM_SILENCE_DB, TRIM_SILENCE_SECONDS,
+};
+use crate::aspect::{aspect_filter, parse_ratio, AspectFit};
+use crate::cache_manager::CacheKind;
+use crate::chapters::{parse_chapters, retime_chapters, write_chapter_file, Chapter};
+use crate::chunked::{encode_chunked, ChunkOutcome, ChunkedJob};
+use crate::container::is_mp4_family;
+use crate::command::{FfmpegCommandBuilder, Section, NO_OPTIONS};
+use crate::cover::prepare_cover;
+use crate::devices::{
+    audio_targets, compatibility_issues, compatibility_warnings, AudioTargets, DeviceProfile,
+};
+use crate::drm;
+use crate::error::ConvertError;
+use crate::external_audio::{probe_audio_file, AudioMuxMode, ExternalAudio};
+use crate::faststart::{is_faststart, is_fragmented};
+use crate::growing::wait_until_stable;
+use crate::hashing::sha256_file;
+use crate::hls::{
+    count_playlist_segments, hls_args, hls_dir_bytes, playlist_path, remove_hls_output,
+    resolve_hls_dir, HlsSegmentType, OutputFormat, DEFAULT_HLS_SECONDS,
+};
+use crate::paths::{
+    create_output_subdir, ffmpeg_path_arg, input_unavailable, validate_deletable,
+    validate_input_path, validate_output_dir,
+};
+use crate::probe_cache::FileStamp;
+use crate::probe_sanity;
+use crate::process::{output_with_timeout, ProcessPipe, RunningProcess};
+use crate::preview::sample_range;
+use crate::progress_file::ProgressFile;
+use crate::resolver::FfmpegResolver;
+use crate::sandbox::{denied_error, denied_in_output, ScopedAccess};
+use crate::segments::{
+    enforce_size_limit, existing_segments, remove_segments, resolve_segment_base, segment_args,
+    segment_pattern, SegmentSpec,
+};
+use crate::staging::{check_staging_space, should_stage, stage_input};
+use crate::output_dir::ResolvedOutputDir;
+use crate::naming::{
+    expand_dir_template, expand_template, fit_output_name, resolve_output_path, sanitize_file_stem,
+    CollisionPolicy, DEFAULT_OUTPUT_TEMPLATE,
+};
+use crate::subtitles::{escape_filter_path, prepare_subtitle, SubtitleMode};
+use crate::renditions::{convert_renditions, RenditionSpec};
+use crate::task_dir::TaskDir;
+use crate::task_log::TaskLog;
+use crate::tracks::{
+    companion_audio_tracks, select_audio, select_streams, tracks_of, StreamSelection, TrackInfo,
+};
+use crate::validation::{check_options, validate_for_input};
+use crate::verify::{verify_output, ExpectedOutput, Verification};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoInfo {
+    pub path: String,
+    pub filename: String,
+    pub codec: String,
+    pub audio_codec: String,
+    /// Sample rate of the first audio stream in Hz
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u32>,
+    pub container: String,
+    /// `ftyp` major brand, e.g. `isom` or `qt  ` for QuickTime
+    pub major_brand: Option<String>,
+    /// A real MP4 (or M4V/3GP) rather than a QuickTime file, going by the
+    /// brand and the extension
+    pub is_mp4_family: bool,
+    pub duration: f64,
+    pub width: u32,
+    pub height: u32,
+    /// Display rotation in degrees (phones record portrait as rotated
+    /// landscape); ffmpeg applies it when decoding
+    pub rotation: i32,
+    /// Pixel shape as `w:h`, e.g. `32:27` for anamorphic PAL DVD; None
+    /// when unknown
+    pub sample_aspect_ratio: Option<String>,
+    /// Picture shape the source asks to be shown at, e.g. `16:9`
+    pub display_aspect_ratio: Option<String>,
+    /// Average frames per second, 0.0 when unknown
+    pub frame_rate: f64,
+    pub bitrate: u64,
+    /// Video stream bitrate in bits/s; estimated from the file size when
+    /// the container doesn't say, 0 when that can't be done either
+    pub video_bitrate: u64,
+    /// Bitrate of the audio track described, 0 when the container doesn't say
+    pub audio_bitrate: u64,
+    /// Codec profile as ffprobe names it, e.g. `High`
+    pub profile: Option<String>,
+    /// Codec level times ten (H.264 4.1 is 41)
+    pub level: Option<u32>,
+    pub ref_frames: Option<u32>,
+    pub needs_conversion: bool,
+    /// MP4/MOV with the `moov` index ahead of the media data, so it can
+    /// play while still downloading
+    pub is_faststart: bool,
+    /// MP4 split into `moof` fragments (screen recorders, some cameras);
+    /// it won't seek on several devices, so it counts as needing conversion
+    pub is_fragmented: bool,
+    /// Container `creation_time` tag, if the source has one
+    pub creation_time: Option<String>,
+    pub chapters: Vec<Chapter>,
+    /// Pixel format of the video stream, e.g. `yuv422p10le`
+    pub pix_fmt: String,
+    /// Transparent video: an alpha pix_fmt, or VP8/VP9 with a WebM alpha layer
+    pub has_alpha: bool,
+    pub color_range: Option<String>,
+    pub color_space: Option<String>,
+    pub color_transfer: Option<String>,
+    pub color_primaries: Option<String>,
+    pub streams: StreamCounts,
+    /// The stream everything above describes and conversions map, as
+    /// `0:v:<n>` counts them
+    pub video_stream_index: usize,
+    /// Every video stream of the source, cover art included
+    pub video_streams: Vec<VideoStream>,
+    /// The audio stream described and mapped, as `0:a:<n>` counts them
+    pub audio_stream_index: usize,
+    /// Every audio track, with its language and disposition flags
+    pub audio_tracks: Vec<TrackInfo>,
+    /// Every subtitle track, with its language and disposition flags
+    pub subtitle_tracks: Vec<TrackInfo>,
+    /// The program the streams above come from, when one was chosen
+    pub program_id: Option<u32>,
+    /// Programs (titles) of a DVD/Blu-ray rip or broadcast stream; empty
+    /// for files without any
+    pub programs: Vec<ProgramInfo>,
+    /// ffprobe gave a negative or implausibly long duration; `duration` is
+    /// then taken from the streams, or 0 when none of them is believable
+    pub duration_suspect: bool,
+    /// `bitrate` is size over duration because none was reported or the
+    /// reported one was far off
+    pub bitrate_estimated: bool,
+    /// `video_bitrate` is what the audio tracks leave of the file's size
+    /// over duration, as Matroska files don't give one
+    pub video_bitrate_estimated: bool,
+    /// The picture has no size, a sign of damage; such sources get their
+    /// output decode-checked
+    pub needs_integrity_check: bool,
+    /// How much longer the audio runs than the video, negative when it
+    /// stops first; None within `AV_MISMATCH_THRESHOLD_SECS` or when a
+    /// stream doesn't say
+    pub av_duration_mismatch_seconds: Option<f64>,
+    /// Reasons copied video may not play on the device profile it was
+    /// checked against; advisory, so `needs_conversion` ignores them
+    pub compatibility_warnings: Vec<String>,
+    /// An animated WebP or APNG (stickers and the like): no audio, often
+    /// odd sizes and little or no timing; always re-encoded
+    pub is_animated_image: bool,
+}
+
+/// One video stream of a source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoStream {
+    /// Position among the file's video streams, as in `-map 0:v:<n>`
+    pub index: usize,
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    /// Bits/s, 0 when the container doesn't say
+    pub bitrate: u64,
+    pub is_default: bool,
+    /// Embedded cover art rather than a picture stream
+    pub is_attached_pic: bool,
+}
+
+impl VideoStream {
+    fn from_json(index: usize, stream: &serde_json::Value) -> Self {
+        VideoStream {
+            index,
+            codec: stream["codec_name"].as_str().unwrap_or("unknown").to_string(),
+            width: stream["width"].as_u64().unwrap_or(0) as u32,
+            height: stream["height"].as_u64().unwrap_or(0) as u32,
+            bitrate: stream_bitrate(stream).unwrap_or(0),
+            is_default: stream["disposition"]["default"] == 1,
+            is_attached_pic: stream["disposition"]["attached_pic"] == 1,
+        }
+    }
+}
+
+/// One program of a multi-program source, e.g. a title of a DVD rip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramInfo {
+    pub program_id: u32,
+    /// `service_name` tag, if the muxer wrote one
+    pub title: Option<String>,
+    /// Longest stream of the program in seconds; None when unknown
+    pub duration: Option<f64>,
+    pub video_streams: usize,
+    pub audio_streams: usize,
+    pub subtitle_streams: usize,
+    /// `0:v:<n>` of its first picture stream
+    pub video_stream: Option<usize>,
+    /// `0:a:<n>` of its first audio stream
+    pub audio_stream: Option<usize>,
+}
+
+/// Programs from `-show_programs`, with their streams located among all of
+/// the file's streams
+fn parse_programs(json: &serde_json::Value, all_streams: &[serde_json::Value]) -> Vec<ProgramInfo> {
+    // Position of a stream among the file's streams of its type
+    let ordinal = |stream: &serde_json::Value| {
+        let index = stream["index"].as_u64()?;
+        let kind = &stream["codec_type"];
+        Some(
+            all_streams
+                .iter()
+                .filter(|s| s["codec_type"] == *kind && s["index"].as_u64() < Some(index))
+                .count(),
+        )
+    };
+    let programs = json["programs"].as_array().map(Vec::as_slice).unwrap_or_default();
+    programs
+        .iter()
+        .filter_map(|program| {
+            let streams = program["streams"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let of_kind = |kind: &'static str| {
+                streams.iter().filter(move |s| {
+                    s["codec_type"] == kind && s["disposition"]["attached_pic"] != 1
+                })
+            };
+            let duration = streams
+                .iter()
+                .filter_map(|s| s["duration"].as_str().and_then(|d| d.parse::<f64>().ok()))
+                .fold(None, |longest: Option<f64>, d| Some(longest.map_or(d, |l| l.max(d))));
+            Some(ProgramInfo {
+                program_id: program["program_id"].as_u64()? as u32,
+                title: program["tags"]["service_name"].as_str().map(str::to_string),
+                duration,
+                video_streams: of_kind("video").count(),
+                audio_streams: of_kind("audio").count(),
+                subtitle_streams: of_kind("subtitle").count(),
+                video_stream: of_kind("video").next().and_then(ordinal),
+                audio_stream: of_kind("audio").next().and_then(ordinal),
+            })
+        })
+        .collect()
+}
+
+/// The stream to convert when there are several, like ffmpeg's own pick:
+/// cover art never, a default-disposition stream first, then the largest
+/// picture and the highest bitrate. Files with a preview or second angle
+/// as stream 0 get their main picture this way.
+fn main_video_stream(streams: &[VideoStream]) -> Option<usize> {
+    streams
+        .iter()
+        .filter(|s| !s.is_attached_pic)
+        .max_by_key(|s| {
+            let pixels = s.width as u64 * s.height as u64;
+            // The earliest stream wins a tie
+            (s.is_default, pixels, s.bitrate, std::cmp::Reverse(s.index))
+        })
+        .map(|s| s.index)
+}
+
+/// How many streams of each kind a file has
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamCounts {
+    /// Picture streams, not counting embedded cover art
+    pub video: usize,
+    pub audio: usize,
+    pub subtitle: usize,
+    /// Timecode and telemetry tracks (GoPro `gpmd`, `tmcd`...), which the MP4
+    /// muxer often can't write
+    pub data: usize,
+    /// Fonts and cover art
+    pub attachment: usize,
+    /// Codec or tag name of each data stream, e.g. `gpmd`
+    pub data_codecs: Vec<String>,
+}
+
+impl VideoInfo {
+    pub fn has_audio(&self) -> bool {
+        self.audio_codec != "unknown"
+    }
+
+    /// Describe audio track `index` instead of the probed one
+    pub fn use_audio_track(&mut self, index: usize) {
+        let Some(track) = self.audio_tracks.get(index) else {
+            return;
+        };
+        self.audio_stream_index = index;
+        self.audio_codec = track.codec.clone();
+        self.audio_sample_rate = track.sample_rate;
+        self.audio_channels = track.channels;
+    }
+
+    /// Compatible streams in a fragmented file, which a plain
+    /// `-c copy -movflags +faststart` remux fixes
+    pub fn only_needs_defragment(&self) -> bool {
+        self.is_fragmented
+            && self.codec == "h264"
+            && self.audio_codec == "aac"
+            && self.is_mp4_family
+    }
+
+    /// `needs_conversion`, counting a back-loaded index as a problem too in
+    /// strict streaming mode
+    pub fn needs_conversion_for(&self, strict_streaming: bool) -> bool {
+        self.needs_conversion || (strict_streaming && !self.is_faststart)
+    }
+
+    /// Width and height as displayed, with non-square pixels stretched
+    /// out and the rotation applied
+    pub fn display_size(&self) -> (u32, u32) {
+        let (width, height) = self.square_pixel_size(self.width, self.height);
+        if self.rotation.rem_euclid(180) == 90 {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Sample aspect ratio when pixels aren't square
+    pub fn non_square_sar(&self) -> Option<(u32, u32)> {
+        let (num, den) = self.sample_aspect_ratio.as_deref()?.split_once(':')?;
+        let (num, den) = (num.parse::<u32>().ok()?, den.parse::<u32>().ok()?);
+        (num > 0 && den > 0 && num != den).then_some((num, den))
+    }
+
+    /// A stored picture size once widened to square pixels, as the
+    /// re-encode's `setsar=1` scale does
+    fn square_pixel_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.non_square_sar() {
+            Some((num, den)) => {
+                let width = (width as u64 * num as u64 / den as u64) as u32;
+                (width - width % 2, height)
+            }
+            None => (width, height),
+        }
+    }
+
+    /// Bits per component, read from the pix_fmt name (`yuv420p10le` is 10)
+    pub fn bit_depth(&self) -> u32 {
+        let name = self.pix_fmt.trim_end_matches("le").trim_end_matches("be");
+        let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let (head, depth) = name.split_at(name.len() - digits);
+        // Only `...p10`, `p010` style suffixes are depths; `nv12` or `yuyv422` aren't
+        match depth.parse::<u32>() {
+            Ok(depth) if head.ends_with('p') => depth,
+            _ => 8,
+        }
+    }
+
+    /// Whether chroma is stored at more than 4:2:0 resolution
+    pub fn has_full_chroma(&self) -> bool {
+        let name = self.pix_fmt.as_str();
+        name.contains("422") || name.contains("444") || name.starts_with("gbr")
+    }
+
+    /// ffmpeg's native VP8/VP9 decoders drop the WebM alpha layer, which
+    /// only libvpx reads
+    fn alpha_decoder(&self) -> Option<&'static str> {
+        if !self.has_alpha || pix_fmt_has_alpha(&self.pix_fmt) {
+            return None;
+        }
+        match self.codec.as_str() {
+            "vp8" => Some("libvpx"),
+            "vp9" => Some("libvpx-vp9"),
+            _ => None,
+        }
+    }
+
+    /// Mastering-grade sources (ProRes, DNxHD...) that band visibly at the
+    /// default quality once squeezed into 8-bit 4:2:0
+    pub fn is_high_fidelity(&self) -> bool {
+        self.bit_depth() > 8 || self.has_full_chroma()
+    }
+
+    /// Color tags to carry over to an encoded output
+    fn color_args(&self) -> Vec<String> {
+        [
+            ("-color_primaries", &self.color_primaries),
+            ("-color_trc", &self.color_transfer),
+            ("-colorspace", &self.color_space),
+            ("-color_range", &self.color_range),
+        ]
+        .into_iter()
+        // An RGB matrix tag would be wrong once the output is YUV
+        .filter(|(flag, value)| !(*flag == "-colorspace" && value.as_deref() == Some("gbr")))
+        .filter_map(|(flag, value)| Some([flag.to_string(), value.clone()?]))
+        .flatten()
+        .collect()
+    }
+}
+
+fn pix_fmt_has_alpha(pix_fmt: &str) -> bool {
+    ["yuva", "gbrap", "rgba", "bgra", "argb", "abgr", "ya8", "ya16"]
+        .iter()
+        .any(|prefix| pix_fmt.starts_with(prefix))
+}
+
+/// Filters that flatten a transparent picture onto a solid color
+fn alpha_composite_filter(background: &str) -> String {
+    format!(
+        "format=yuva420p,split[fg][base];\
+         [base]drawbox=c={}@1:replace=1:t=fill[bg];[bg][fg]overlay=format=auto",
+        background
+    )
+}
+
+/// Background colors accepted for alpha compositing: an ffmpeg color name
+/// or `#RRGGBB`, nothing that could escape into the filter graph
+pub(crate) fn is_valid_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => !color.is_empty() && color.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+/// Where a task is in its lifecycle; serialized as the plain lowercase string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionStatus {
+    /// Waiting in the app's queue; the event carries the queue position and
+    /// a start estimate
+    Queued,
+    /// Scanning the input before converting (silence detection)
+    Analyzing,
+    Starting,
+    /// The input is still being written (a recording or download in
+    /// progress); the conversion starts once its size holds still
+    WaitingForFile,
+    /// Every session of the hardware encoder is taken by other conversions;
+    /// this one starts when one of them finishes
+    WaitingForEncoder,
+    /// Copying a network input to local disk before converting it
+    Staging,
+    Converting,
+    /// Converting in audio-only fix mode, with the video copied as-is, which
+    /// usually runs far faster than realtime
+    FixingAudio,
+    /// Encoding is done and ffmpeg is writing the index (`+faststart`)
+    Finalizing,
+    Completed,
+    /// Finished, but ffmpeg reported more decoding errors than allowed; the
+    /// output may have damaged stretches and should be reviewed before the
+    /// original goes
+    CompletedWithErrors,
+    Error,
+    /// The input went away mid-conversion (drive or share disconnected)
+    InputUnavailable,
+    /// Stopped by `abort_if_larger_than_input` once the output outgrew the
+    /// source; nothing is kept
+    OutputTooLarge,
+    Cancelled,
+    /// Cancelled with `CancelMode::KeepPartial`: ffmpeg finished the file
+    /// at the point it was stopped and the output was kept
+    CancelledPartial,
+}
+
+impl ConversionStatus {
+    /// Whether the task is over and no further updates will follow
+    pub fn is_terminal(self) -> bool {
+        match self {
+            ConversionStatus::Queued
+            | ConversionStatus::Analyzing
+            | ConversionStatus::Starting
+            | ConversionStatus::WaitingForFile
+            | ConversionStatus::WaitingForEncoder
+            | ConversionStatus::Staging
+            | ConversionStatus::Converting
+            | ConversionStatus::FixingAudio
+            | ConversionStatus::Finalizing => false,
+            ConversionStatus::Completed
+            | ConversionStatus::CompletedWithErrors
+            | ConversionStatus::Error
+            | ConversionStatus::InputUnavailable
+            | ConversionStatus::OutputTooLarge
+            | ConversionStatus::Cancelled
+            | ConversionStatus::CancelledPartial => true,
+        }
+    }
+
+    /// Whether the task finished with an output, damaged or not
+    pub fn is_completed(self) -> bool {
+        matches!(self, ConversionStatus::Completed | ConversionStatus::CompletedWithErrors)
+    }
+}
+
+/// What cancelling a conversion does with the output written so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelMode {
+    /// Kill ffmpeg and delete the output
+    #[default]
+    Discard,
+    /// Interrupt ffmpeg so it finishes the file where it is, and keep it.
+    /// For MP4 that includes the `+faststart` rewrite, so stopping a long
+    /// encode can take a while; ffmpeg gets `PARTIAL_STOP_GRACE_SECS` before
+    /// it is killed and the output discarded after all. Segmented, HLS and
+    /// chunked encodes are always discarded.
+    KeepPartial,
+}
+
+/// How long an interrupted ffmpeg may take to finish a partial output
+pub const PARTIAL_STOP_GRACE_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionProgress {
+    pub task_id: String,
+    pub progress: f64,
+    pub status: ConversionStatus,
+    pub output_path: Option<String>,
+    pub error: Option<String>,
+    pub video_action: Option<StreamAction>,
+    pub audio_action: Option<StreamAction>,
+    /// Set while the status has no meaningful percentage (e.g. finalizing)
+    pub indeterminate: bool,
+    /// Encoding speed as a multiple of realtime, from ffmpeg's `speed=`
+    pub speed: Option<f64>,
+    /// Estimated seconds left at the current speed
+    pub eta_seconds: Option<f64>,
+    /// On the completed event, the result's warnings, including problems
+    /// ffmpeg reported while decoding. While converting, a warning when the
+    /// output is on track to end up larger than the source.
+    pub warnings: Vec<String>,
+    /// Size of the output so far, from ffmpeg's `total_size=`
+    pub bytes_written: Option<u64>,
+    /// The hardware encoder seems to have fallen back to software: it runs
+    /// far below the benchmarked speed, or said so. Stays set once raised;
+    /// libx264 settings are the better choice for such a machine.
+    pub software_fallback: bool,
+    /// Place in the queue while queued, 1 being next
+    pub queue_position: Option<usize>,
+    /// While queued, a rough guess of the seconds until the task starts;
+    /// recomputed whenever the queue or the running tasks change
+    pub estimated_start_seconds: Option<f64>,
+}
+
+impl ConversionProgress {
+    /// A plain status update with no output or stream details
+    pub fn update(task_id: &str, progress: f64, status: ConversionStatus) -> Self {
+        ConversionProgress {
+            task_id: task_id.to_string(),
+            progress,
+            status,
+            output_path: None,
+            error: None,
+            video_action: None,
+            audio_action: None,
+            indeterminate: false,
+            speed: None,
+            eta_seconds: None,
+            warnings: Vec::new(),
+            bytes_written: None,
+            software_fallback: false,
+            queue_position: None,
+            estimated_start_seconds: None,
+        }
+    }
+}
+
+/// What happened to a stream during conversion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", content = "encoder", rename_all = "snake_case")]
+pub enum StreamAction {
+    Copied,
+    Encoded(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionResult {
+    pub output_path: String,
+    pub video_action: StreamAction,
+    pub audio_action: StreamAction,
+    /// Output duration in seconds (differs from the source when speed changes)
+    pub duration: f64,
+    /// Overall bitrate of the written file in bits/s, measured after encoding
+    pub output_bitrate: Option<u64>,
+    /// Things the user should know about how the options were applied
+    pub warnings: Vec<String>,
+    /// Displayed frame size of the written file, measured after encoding
+    pub output_size: Option<(u32, u32)>,
+    /// Every file written, in order, when the output was split into
+    /// segments; `output_path` is then the first of them
+    pub segment_paths: Vec<String>,
+    /// Every output of a multi-rendition job, in spec order; `output_path`
+    /// is then the first of them
+    pub rendition_paths: Vec<String>,
+    /// Segments listed in the playlist of an HLS output, which is then
+    /// `output_path`; 0 for MP4 output
+    pub hls_segment_count: usize,
+    /// `-hwaccel` method the source was decoded with, if any
+    pub hw_decoder: Option<String>,
+    /// Part of the source that was kept after `auto_trim_silence`
+    pub trimmed: Option<TrimRange>,
+    /// Size of the source file
+    pub input_bytes: u64,
+    /// Size of everything written, all segments included
+    pub output_bytes: u64,
+    /// The source tracks that were converted, and why
+    pub streams: StreamSelection,
+    /// Lines of ffmpeg's output reporting source frames it couldn't decode
+    pub decode_error_count: usize,
+    /// `Completed`, `CompletedWithErrors` when `decode_error_count` is over
+    /// the limit, or `CancelledPartial` for an output that was cut short
+    pub status: ConversionStatus,
+    /// SHA-256 of the source as read for the conversion, with
+    /// `compute_hashes`
+    pub input_sha256: Option<String>,
+    /// SHA-256 of the output, with `compute_hashes`; None for segmented,
+    /// HLS and multi-rendition outputs, which are several files
+    pub output_sha256: Option<String>,
+    /// The folder the output went to and why it was picked; filled in by
+    /// callers that resolve it
+    pub output_dir: Option<ResolvedOutputDir>,
+    /// The failed task this conversion retried; filled in by the caller
+    pub retry_of: Option<String>,
+}
+
+impl ConversionResult {
+    /// How much smaller the output is than the source, in percent; negative
+    /// when it grew
+    pub fn size_reduction_percent(&self) -> Option<f64> {
+        (self.input_bytes > 0).then(|| {
+            (1.0 - self.output_bytes as f64 / self.input_bytes as f64) * 100.0
+        })
+    }
+}
+
+/// User-tunable conversion options; every field defaults to today's behavior
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConversionOptions {
+    /// Extra arguments appended after the video codec settings
+    pub extra_video_args: Vec<String>,
+    /// Extra arguments appended after the audio codec settings
+    pub extra_audio_args: Vec<String>,
+    /// Extra arguments appended right before the output path
+    pub extra_output_args: Vec<String>,
+    /// External subtitle file (.srt, .vtt, .ass) to mux or burn in
+    pub subtitle_file: Option<String>,
+    pub subtitle_mode: SubtitleMode,
+    /// Detect black bars and crop them away when found
+    pub auto_crop: bool,
+    /// Explicit crop rectangle; takes precedence over `auto_crop`
+    pub crop: Option<CropRect>,
+    /// Playback speed factor (2.0 = twice as fast); forces re-encoding
+    pub speed: Option<f64>,
+    /// Output file name template, e.g. `{stem}_{height}p`; the output
+    /// format's extension is appended
+    pub output_template: Option<String>,
+    /// Folder under the output dir to write to, e.g.
+    /// `{output_root}/{year}/{month}` by the source's creation date; created
+    /// as needed
+    pub output_dir_template: Option<String>,
+    pub collision_policy: CollisionPolicy,
+    /// Encode long files as parallel segments to use more cores; falls back
+    /// to a single process when the source can't be split safely
+    pub chunked_encode: bool,
+    /// Peak video bitrate cap for streaming targets
+    pub max_bitrate_kbps: Option<u32>,
+    /// Rate control buffer; defaults to twice the cap
+    pub buffer_size_kbps: Option<u32>,
+    /// Color transparent sources are flattened onto (name or `#RRGGBB`);
+    /// defaults to white
+    pub alpha_background: Option<String>,
+    /// Split the output into duration- or size-limited `_000.mp4` parts
+    pub segment: Option<SegmentSpec>,
+    /// Drop all container and stream metadata (GPS location, device model
+    /// and serial, software tags) instead of copying it
+    pub strip_metadata: bool,
+    /// Keep the source's `creation_time` even when metadata is stripped
+    pub preserve_timestamps: bool,
+    /// Target aspect ratio such as `9:16`, `1:1` or `4:5`
+    pub aspect: Option<String>,
+    pub aspect_fit: AspectFit,
+    /// Fill color for `AspectFit::Pad` (name or `#RRGGBB`); defaults to black
+    pub pad_color: Option<String>,
+    /// Shift the audio against the video; positive values delay it,
+    /// negative ones make it play earlier
+    pub audio_delay_ms: Option<i64>,
+    /// Audio gain in dB, limited to -60..+30; forces the audio to be encoded
+    pub volume_db: Option<f64>,
+    /// Copy the input to a local temp file first; large inputs on network
+    /// shares are staged even without it
+    pub stage_locally: bool,
+    /// Raise the frame rate to this many fps (at most 120); forces re-encoding
+    pub interpolate_fps: Option<u32>,
+    /// Fade picture and sound in from black and silence over this many
+    /// seconds; forces re-encoding of both
+    pub fade_in_seconds: Option<f64>,
+    /// Fade out to black and silence over the last this many seconds of the
+    /// output
+    pub fade_out_seconds: Option<f64>,
+    pub interpolation_quality: InterpolationQuality,
+    /// Drop repeated frames (screen recordings) with `mpdecimate`; the
+    /// output then has a variable frame rate
+    pub dedup_frames: bool,
+    /// Noise reduction before encoding, so grain doesn't eat the bitrate
+    pub denoise: Denoise,
+    /// Mild unsharp mask after scaling
+    pub sharpen: bool,
+    /// Separately recorded audio to replace or add to the video's own
+    pub external_audio: Option<ExternalAudio>,
+    /// Lengthen shorter outputs to this many seconds; longer ones are left
+    /// alone
+    pub min_duration_seconds: Option<f64>,
+    pub extend_mode: ExtendMode,
+    /// What to do when the source's audio and video end apart; see
+    /// `VideoInfo::av_duration_mismatch_seconds`
+    pub conform_duration: ConformDuration,
+    /// Container tags to set on the output, e.g. `title` or `comment`
+    pub metadata: Option<BTreeMap<String, String>>,
+    /// Poster image embedded as cover art: an image path, or `auto` for the
+    /// frame 10% into the video
+    pub cover_image: Option<String>,
+    /// Copy the audio to both sides when a stereo track has sound in one
+    /// channel only; balanced tracks are left alone
+    pub fix_channel_balance: bool,
+    /// Try to carry data streams (timecode, telemetry) over with
+    /// `-copy_unknown` instead of dropping them; the muxer may still refuse
+    pub keep_data_streams: bool,
+    /// Scale down to at most this many lines, keeping the aspect ratio;
+    /// forces re-encoding when the source is taller
+    pub max_height: Option<u32>,
+    /// Constant quality on the libx264 CRF scale (0-51, lower is better);
+    /// VideoToolbox gets the matching `-q:v`. Forces re-encoding.
+    pub crf: Option<u32>,
+    /// Write one output per spec instead of a single file
+    pub renditions: Vec<RenditionSpec>,
+    /// One MP4 file, or an HLS playlist with segments in a per-video folder
+    pub output_format: OutputFormat,
+    pub hls_segment_type: HlsSegmentType,
+    /// Target HLS segment length; defaults to 6 seconds
+    pub hls_segment_seconds: Option<f64>,
+    /// Keep the latest progress in this file as one line of JSON, rewritten
+    /// at most once a second; the app checks it with `validate_progress_file`
+    pub progress_file: Option<String>,
+    /// Hold off while the input is still growing instead of converting the
+    /// part written so far
+    pub wait_for_stable: bool,
+    /// How a finished single-file output is checked before the task counts
+    /// as completed
+    pub verify_output: Verification,
+    /// Cut silence at the very start and end of the source; silences in
+    /// between are kept
+    pub auto_trim_silence: bool,
+    /// Convert only this part of the source, cut like the silence trim;
+    /// replaces `auto_trim_silence`. Previews use it.
+    pub clip: Option<TrimRange>,
+    /// Stop the encode as soon as the output is bigger than the source,
+    /// as when the chosen quality inflates an already compact file
+    pub abort_if_larger_than_input: bool,
+    /// Fail when VideoToolbox can't get a hardware session instead of
+    /// letting it fall back to its much slower software encoder
+    /// (`-allow_sw 0`); no effect with other encoders
+    pub hw_encode_only: bool,
+    /// Encode with libx264 instead of waiting when the hardware encoder has
+    /// no free session (see `EncoderSlots`); needs an ffmpeg built with it
+    pub software_when_encoder_busy: bool,
+    /// Encoding speed the benchmark predicts for this source (see
+    /// `Benchmark::expected_speed`); an encode running far below it is
+    /// flagged as a likely software fallback
+    pub expected_encode_speed: Option<f64>,
+    /// Resample the audio to this rate in Hz; forces an audio encode
+    pub audio_sample_rate: Option<u32>,
+    /// Mix the audio to this many channels; forces an audio encode
+    pub audio_channels: Option<u32>,
+    /// Convert the source's `0:v:<n>` instead of the stream picked as the
+    /// main one (see `VideoInfo::video_streams`)
+    pub video_stream: Option<usize>,
+    /// Convert this program of a multi-program source (see
+    /// `VideoInfo::programs`): its first picture and audio stream
+    pub program_id: Option<u32>,
+    /// Convert the source's `0:a:<n>` instead of the track picked by
+    /// language and disposition (see `VideoInfo::audio_tracks`)
+    pub audio_stream: Option<usize>,
+    /// Language codes (`eng`, `ja`...) to pick the audio track and forced
+    /// subtitles by, most wanted first
+    pub preferred_languages: Vec<String>,
+    /// Leave forced subtitle tracks of the source out instead of burning
+    /// the one for the chosen audio into the picture
+    pub ignore_forced_subtitles: bool,
+    /// Write just the chosen audio track of a MOV/MP4 with several AAC
+    /// tracks, instead of copying the others along
+    pub primary_audio_only: bool,
+    /// Decoding errors allowed before the result is `CompletedWithErrors`;
+    /// None uses `DEFAULT_MAX_DECODE_ERRORS`
+    pub max_decode_errors: Option<usize>,
+    /// Run ffmpeg with `-v verbose` and write all of its output to the task
+    /// log, for troubleshooting
+    pub verbose_log: bool,
+    pub mode: ConversionMode,
+    pub hw_decode: HwDecode,
+    /// Device profile whose fixable problems (H.264 profile, level,
+    /// reference frames) force copied video to be re-encoded
+    pub target_profile: Option<DeviceProfile>,
+    /// Replace the source file with the fixed one once it's written;
+    /// audio-only fixes of MP4 sources only
+    pub replace_original: bool,
+    /// Hash the input and the finished output with SHA-256 into the
+    /// result; off by default, as it reads both files once more
+    pub compute_hashes: bool,
+}
+
+/// What a conversion is allowed to touch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionMode {
+    /// Copy or encode each stream as needed
+    #[default]
+    Auto,
+    /// Only the audio is converted; fails rather than encode the video
+    AudioOnlyFix,
+}
+
+/// How a clip shorter than `min_duration_seconds` is lengthened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtendMode {
+    /// Play the clip again from the start
+    #[default]
+    Loop,
+    /// Hold the last frame, with silence
+    Freeze,
+}
+
+/// How a source whose audio and video end apart is evened out. Clips,
+/// minimum durations, audio delays and added audio files set the length
+/// themselves, so they leave it as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConformDuration {
+    /// Leave both streams as they are
+    #[default]
+    Keep,
+    /// End the output with the shorter stream (`-shortest`)
+    Shortest,
+    /// Hold the last frame until a longer audio track ends (`tpad`); the
+    /// video is then re-encoded
+    PadVideo,
+    /// Add silence until a longer video ends (`apad`); the audio is then
+    /// re-encoded
+    PadAudio,
+}
+
+/// Slack allowed when checking an extended output's length
+const DURATION_TOLERANCE: f64 = 0.1;
+
+/// Whether re-encodes may decode the source on the GPU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HwDecode {
+    /// Use the platform's hardware decoder, falling back to software when
+    /// it fails
+    #[default]
+    Auto,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Denoise {
+    #[default]
+    Off,
+    Light,
+    Medium,
+    /// Non-local means; much slower than the others
+    Strong,
+}
+
+impl Denoise {
+    fn filter(self) -> Option<&'static str> {
+        match self {
+            Denoise::Off => None,
+            Denoise::Light => Some("hqdn3d=2:1.5:3:2.25"),
+            Denoise::Medium => Some("hqdn3d=4:3:6:4.5"),
+            Denoise::Strong => Some("nlmeans=s=3:p=7:r=15"),
+        }
+    }
+}
+
+const SHARPEN_FILTER: &str = "unsharp=5:5:0.6:3:3:0.0";
+
+/// How frames are made up when raising the frame rate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationQuality {
+    /// Repeat frames; fast but still choppy
+    Duplicate,
+    /// Crossfade neighbouring frames
+    Blend,
+    /// Motion-compensated interpolation; smoothest, and many times slower
+    /// than realtime
+    #[default]
+    Motion,
+}
+
+/// Highest frame rate interpolation may produce
+pub(crate) const MAX_INTERPOLATE_FPS: u32 = 120;
+
+fn interpolation_filter(fps: u32, quality: InterpolationQuality) -> String {
+    match quality {
+        InterpolationQuality::Duplicate => format!("fps={}", fps),
+        InterpolationQuality::Blend => format!("minterpolate=fps={}:mi_mode=blend", fps),
+        InterpolationQuality::Motion => format!("minterpolate=fps={}:mi_mode=mci", fps),
+    }
+}
+
+/// A `-maxrate`/`-bufsize` style video bitrate cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max_kbps: u32,
+    pub buffer_kbps: u32,
+}
+
+impl ConversionOptions {
+    /// Check everything that can be judged without the input file, so a bad
+    /// option set (typed in, or imported from a preset file) is refused
+    /// before any work starts
+    pub fn validate(&self) -> Result<(), String> {
+        match check_options(self).into_iter().next() {
+            Some(error) => Err(error.message),
+            None => Ok(()),
+        }
+    }
+
+    fn burns_subtitle(&self) -> bool {
+        self.subtitle_file.is_some() && self.subtitle_mode == SubtitleMode::Burn
+    }
+
+    fn changes_speed(&self) -> bool {
+        self.speed.is_some_and(|speed| speed != 1.0)
+    }
+
+    fn changes_volume(&self) -> bool {
+        self.volume_db.is_some_and(|db| db != 0.0)
+    }
+
+    fn fades(&self) -> bool {
+        [self.fade_in_seconds, self.fade_out_seconds].iter().any(|s| s.is_some_and(|s| s > 0.0))
+    }
+
+    /// Whether the options need filters that rule out copying the video stream
+    pub(crate) fn forces_video_encode(&self) -> bool {
+        self.burns_subtitle()
+            || self.changes_speed()
+            || self.crop.is_some()
+            || self.aspect.is_some()
+            || self.interpolate_fps.is_some()
+            || self.dedup_frames
+            || self.denoise != Denoise::Off
+            || self.sharpen
+            || self.fades()
+    }
+
+    /// Whether a source's video stream is expected to be copied rather than
+    /// encoded (auto-crop can still force an encode once bars are found)
+    pub fn copies_video(&self, info: &VideoInfo) -> bool {
+        // 10-bit or 4:2:2 H.264 won't play on phones, so it's encoded too
+        info.codec == "h264"
+            && !info.is_high_fidelity()
+            && !self.forces_video_encode()
+            && !self.exceeds_rate_limit(info)
+            && self.crf.is_none()
+            && self.max_height.is_none_or(|height| info.display_size().1 <= height)
+            && self.device_fixes(info).is_empty()
+    }
+
+    /// Problems with the source for `target_profile` that re-encoding fixes
+    fn device_fixes(&self, info: &VideoInfo) -> Vec<String> {
+        let Some(device) = self.target_profile else {
+            return Vec::new();
+        };
+        compatibility_issues(info, device)
+            .into_iter()
+            .filter(|issue| issue.kind.fixed_by_reencode())
+            .map(|issue| issue.message)
+            .collect()
+    }
+
+    /// Sample rate and channel count the audio is converted to: the
+    /// explicit ones, else whatever `target_profile` needs for this source
+    fn audio_conformance(&self, info: &VideoInfo) -> AudioTargets {
+        let device = self
+            .target_profile
+            .map(|device| audio_targets(info, device))
+            .unwrap_or_default();
+        AudioTargets {
+            sample_rate: self.audio_sample_rate.or(device.sample_rate),
+            channels: self.audio_channels.or(device.channels),
+        }
+    }
+
+    /// CRF the video is encoded at: the explicit one, or the default for
+    /// the source
+    fn crf_for(&self, info: &VideoInfo) -> u32 {
+        self.crf.unwrap_or(if info.is_high_fidelity() { CRF_HIGH } else { CRF })
+    }
+
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.max_bitrate_kbps.map(|max_kbps| RateLimit {
+            max_kbps,
+            buffer_kbps: self.buffer_size_kbps.unwrap_or(max_kbps.saturating_mul(2)),
+        })
+    }
+
+    /// Output arguments for `strip_metadata` and explicit `metadata`
+    /// entries; empty when metadata is simply copied
+    fn metadata_args(&self, info: &VideoInfo) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        if self.strip_metadata {
+            args.extend(
+                ["-map_metadata", "-1", "-map_metadata:s:v", "-1", "-map_metadata:s:a", "-1"]
+                    .iter()
+                    .map(|a| a.to_string()),
+            );
+            // The mov muxer can write location tags from its own options, so
+            // they are blanked explicitly as well
+            for key in LOCATION_TAGS {
+                args.push("-metadata".to_string());
+                args.push(format!("{}=", key));
+            }
+            if let Some(time) = info.creation_time.as_ref().filter(|_| self.preserve_timestamps) {
+                args.push("-metadata".to_string());
+                args.push(format!("creation_time={}", time));
+            }
+        }
+        // Loop counts and encoder tags of stickers mean nothing in MP4,
+        // which doesn't loop
+        if info.is_animated_image && !self.strip_metadata {
+            args.extend(["-map_metadata".to_string(), "-1".to_string()]);
+        }
+        // Later -metadata options win, so explicit entries override both
+        // copied and blanked tags
+        for (key, value) in self.metadata.iter().flatten() {
+            args.push("-metadata".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        args
+    }
+
+    /// Whether the source's average video bitrate is already over the cap,
+    /// so copying it can't meet the constraint
+    fn exceeds_rate_limit(&self, info: &VideoInfo) -> bool {
+        let Some(limit) = self.rate_limit() else {
+            return false;
+        };
+        let source = if info.video_bitrate > 0 { info.video_bitrate } else { info.bitrate };
+        source > limit.max_kbps as u64 * 1000
+    }
+}
+
+/// Location tags phones write, removed by `strip_metadata`
+const LOCATION_TAGS: &[&str] = &[
+    "location",
+    "location-eng",
+    "com.apple.quicktime.location.ISO6709",
+];
+
+/// Label for the `{quality}` template token, matching the encoder settings below
+fn quality_label(copies_video: bool, crf: u32) -> String {
+    if copies_video {
+        "copy".to_string()
+    } else if cfg!(target_os = "macos") {
+        format!("q{}", vt_quality(crf))
+    } else {
+        format!("crf{}", crf)
+    }
+}
+
+/// Expand the output file name for a probed source without converting it
+pub fn output_file_name(
+    info: &VideoInfo,
+    template: Option<&str>,
+    options: &ConversionOptions,
+) -> Result<String, String> {
+    let template = template
+        .or(options.output_template.as_deref())
+        .unwrap_or(DEFAULT_OUTPUT_TEMPLATE);
+    let quality = quality_label(options.copies_video(info), options.crf_for(info));
+    expand_template(template, info, &quality, options.output_format.extension())
+}
+
+/// The file a single-file conversion of a probed source would write under
+/// `output_dir`, folder template and collision policy applied; nothing is
+/// created
+pub fn output_destination(
+    info: &VideoInfo,
+    output_dir: &str,
+    options: &ConversionOptions,
+) -> Result<PathBuf, ConvertError> {
+    let root = validate_output_dir(output_dir)?;
+    let dir = match &options.output_dir_template {
+        Some(template) => root.join(expand_dir_template(template, info)?),
+        None => root,
+    };
+    let file_name = fit_output_name(&dir, &output_file_name(info, None, options)?);
+    Ok(resolve_output_path(&dir, &file_name, options.collision_policy))
+}
+
+/// Refuse metadata that could be read as an ffmpeg option or can't survive
+/// the command line
+pub(crate) fn validate_metadata(metadata: &BTreeMap<String, String>) -> Result<(), String> {
+    for (key, value) in metadata {
+        let key_ok = !key.is_empty()
+            && !key.starts_with('-')
+            && !key.contains(|c: char| c == '=' || c.is_whitespace() || c.is_control());
+        if !key_ok {
+            return Err(format!("Invalid metadata key: {:?}", key));
+        }
+        if value.contains('\0') {
+            return Err(format!("Metadata value for {} contains a NUL character", key));
+        }
+    }
+    Ok(())
+}
+
+/// Options the converter manages itself. Letting extra args override them
+/// would add inputs, break the progress pipe, or change overwrite behavior.
+const RESERVED_ARGS: &[&str] = &["-i", "-y", "-n", "-progress"];
+
+/// Options that read or write files of their own choosing
+const FILE_ARGS: &[&str] = &[
+    "-passlogfile",
+    "-vstats",
+    "-vstats_file",
+    "-report",
+    "-attach",
+    "-dump_attachment",
+    "-filter_script",
+    "-filter_complex_script",
+    "-sdp_file",
+    "-fpre",
+];
+
+/// Filter options: a second one replaces the chain the converter builds,
+/// and `movie=`/`amovie=` sources in it can read any file
+const FILTER_ARGS: &[&str] = &["-vf", "-af", "-filter", "-filter_complex", "-lavfi"];
+
+/// Muxers that write to outputs named in their own options
+const FILE_FORMATS: &[&str] = &["tee"];
+
+/// Options known to take a value, by name without a stream specifier.
+/// Any other option is taken to be a flag, so whatever bare value follows
+/// it would be read by ffmpeg as another output file.
+const VALUE_ARGS: &[&str] = &[
+    "-c", "-codec", "-vcodec", "-acodec", "-b", "-crf", "-cq", "-qp", "-q", "-qscale", "-qmin",
+    "-qmax", "-preset", "-tune", "-profile", "-level", "-tier", "-pix_fmt", "-r", "-s",
+    "-aspect", "-g", "-bf", "-refs", "-keyint_min", "-sc_threshold", "-force_key_frames",
+    "-maxrate", "-minrate", "-bufsize", "-rc", "-rc-lookahead", "-spatial-aq", "-temporal-aq",
+    "-aq-mode", "-aq-strength", "-b_ref_mode", "-x264-params", "-x264opts", "-x265-params",
+    "-svtav1-params", "-color_primaries", "-color_trc", "-colorspace", "-color_range",
+    "-fps_mode", "-vsync", "-ar", "-ac", "-sample_fmt", "-channel_layout", "-ch_layout",
+    "-metadata", "-map_metadata", "-map_chapters", "-disposition", "-tag", "-bsf", "-movflags",
+    "-brand", "-frag_duration", "-min_frag_duration", "-video_track_timescale", "-use_editlist",
+    "-write_tmcd", "-timecode", "-avoid_negative_ts", "-max_muxing_queue_size",
+    "-max_interleave_delta", "-muxdelay", "-muxpreload", "-fflags", "-flags", "-strict",
+    "-threads", "-frames", "-vframes", "-aframes", "-fs", "-t", "-to", "-ss", "-f",
+];
+
+/// Check user-supplied extra args before they are appended to the command.
+///
+/// A bare value must follow an option from `VALUE_ARGS`; anywhere else
+/// ffmpeg would take it as an extra output file, so it is rejected.
+pub(crate) fn validate_extra_args(field: &str, args: &[String]) -> Result<(), String> {
+    let mut expects_value: Option<&str> = None;
+    for arg in args {
+        let is_option = arg.len() > 1 && arg.starts_with('-') && arg.parse::<f64>().is_err();
+        if let Some(option) = expects_value.take().filter(|_| !is_option) {
+            if option == "-f" && FILE_FORMATS.contains(&arg.as_str()) {
+                return Err(format!("{}: '-f {}' can write other files", field, arg));
+            }
+            continue;
+        }
+        if !is_option {
+            return Err(format!(
+                "{}: unexpected argument '{}' (extra arguments cannot add inputs or outputs)",
+                field, arg
+            ));
+        }
+        let name = arg.split(':').next().unwrap_or(arg);
+        if RESERVED_ARGS.contains(&name) {
+            return Err(format!(
+                "{}: '{}' is managed by the converter and cannot be set",
+                field, arg
+            ));
+        }
+        // `-/name` reads the option's value from a file
+        if FILE_ARGS.contains(&name) || arg.starts_with("-/") {
+            return Err(format!("{}: '{}' reads or writes other files", field, arg));
+        }
+        if FILTER_ARGS.contains(&name) {
+            return Err(format!("{}: '{}' would replace the converter's filters", field, arg));
+        }
+        expects_value = VALUE_ARGS.contains(&name).then_some(name);
+    }
+    Ok(())
+}
+
+pub async fn check_ffmpeg(resolver: &FfmpegResolver) -> Result<bool, String> {
+    Ok(resolver.ffmpeg().await.is_ok())
+}
+
+/// Probe a file, reusing the resolver's cached result while the file's
+/// size and modification time are unchanged
+pub async fn get_video_info(
+    resolver: &FfmpegResolver,
+    path: &str,
+) -> Result<VideoInfo, ConvertError> {
+    let canonical = validate_input_path(path)?;
+    let stamp = FileStamp::of(&canonical);
+    if let Some(info) = stamp.and_then(|stamp| resolver.probe_cache().get(&canonical, stamp)) {
+        return Ok(info);
+    }
+
+    let _access = ScopedAccess::start([canonical.as_path()]);
+    let info = probe_video(resolver, &canonical, StreamChoice::default()).await?;
+    if let Some(stamp) = stamp {
+        resolver.probe_cache().insert(canonical, stamp, info.clone());
+    }
+    Ok(info)
+}
+
+/// Streams a probe describes instead of the file's main ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamChoice {
+    /// The source's `0:v:<n>`
+    pub video: Option<usize>,
+    /// The first picture and audio stream of this program
+    pub program: Option<u32>,
+}
+
+impl StreamChoice {
+    /// The choice a set of options makes
+    pub fn from_options(options: &ConversionOptions) -> Self {
+        StreamChoice { video: options.video_stream, program: options.program_id }
+    }
+}
+
+/// Whether `info` (the default probe) has the streams `choice` asks for
+fn choice_exists(info: &VideoInfo, choice: StreamChoice) -> bool {
+    match (choice.video, choice.program) {
+        (Some(index), None) => info.video_streams.get(index).is_some_and(|s| !s.is_attached_pic),
+        (None, Some(id)) => info
+            .programs
+            .iter()
+            .any(|program| program.program_id == id && program.video_stream.is_some()),
+        _ => false,
+    }
+}
+
+/// The probe a conversion with `options` works from, describing the
+/// streams it converts, and the source tracks it reads
+pub async fn probe_for_conversion(
+    resolver: &FfmpegResolver,
+    path: &str,
+    options: &ConversionOptions,
+) -> Result<(VideoInfo, StreamSelection), ConvertError> {
+    let info = get_video_info(resolver, path).await?;
+    // Other streams than the main ones describe themselves differently; a
+    // choice that doesn't exist is left to validation to explain
+    let choice = StreamChoice::from_options(options);
+    let mut info = if choice != StreamChoice::default() && choice_exists(&info, choice) {
+        get_video_info_with(resolver, path, choice).await?
+    } else {
+        info
+    };
+    let selection = select_streams(&info, options);
+    if let Some(index) = selection.audio_stream {
+        info.use_audio_track(index);
+    }
+    Ok((info, selection))
+}
+
+/// `get_video_info` describing other streams than the main ones; not cached
+pub async fn get_video_info_with(
+    resolver: &FfmpegResolver,
+    path: &str,
+    choice: StreamChoice,
+) -> Result<VideoInfo, ConvertError> {
+    let canonical = validate_input_path(path)?;
+    let _access = ScopedAccess::start([canonical.as_path()]);
+    probe_video(resolver, &canonical, choice).await
+}
+
+/// ffprobe's JSON for a file exactly as it reported it, before the checks
+/// `get_video_info` applies
+pub async fn get_raw_probe(
+    resolver: &FfmpegResolver,
+    path: &str,
+) -> Result<serde_json::Value, ConvertError> {
+    let canonical = validate_input_path(path)?;
+    let _access = ScopedAccess::start([canonical.as_path()]);
+    run_probe(resolver, &canonical).await
+}
+
+async fn run_probe(
+    resolver: &FfmpegResolver,
+    canonical: &Path,
+) -> Result<serde_json::Value, ConvertError> {
+    let probe_input = ffmpeg_path_arg(canonical);
+    let path = canonical.to_string_lossy().to_string();
+    let path = path.as_str();
+    let ffprobe_path = resolver.ffprobe().await?;
+
+    // The error text tells a refused or protected file apart
+    let mut cmd = Command::new(&ffprobe_path);
+    cmd.args([
+        "-v",
+        "error",
+        "-print_format",
+        "json",
+        "-show_format",
+        "-show_streams",
+        "-show_chapters",
+        "-show_programs",
+        &probe_input,
+    ]);
+    let output = output_with_timeout(resolver.runner(), &mut cmd, resolver.probe_timeout())
+        .await
+        .map_err(|e| format!("Failed to get video info: {}", e))?
+        .ok_or_else(|| ConvertError::ProbeTimeout(path.to_string()))?;
+
+    if !output.status.success() {
+        let stderr: Vec<String> =
+            String::from_utf8_lossy(&output.stderr).lines().map(str::to_string).collect();
+        if denied_in_output(&stderr) {
+            return Err(denied_error(canonical, false));
+        }
+        if drm::failure_suggests_fairplay(canonical, &stderr.join("\n")) {
+            return Err(ConvertError::DrmProtected(path.to_string()));
+        }
+        return Err("Failed to probe video file".into());
+    }
+
+    let json_str = String::from_utf8_lossy(&output.stdout);
+    let json: serde_json::Value =
+        serde_json::from_str(&json_str).map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+    if drm::probe_shows_protection(&json) {
+        return Err(ConvertError::DrmProtected(path.to_string()));
+    }
+    Ok(json)
+}
+
+async fn probe_video(
+    resolver: &FfmpegResolver,
+    canonical: &Path,
+    choice: StreamChoice,
+) -> Result<VideoInfo, ConvertError> {
+    let json = run_probe(resolver, canonical).await?;
+    video_info_from_probe(canonical, &json, choice, resolver.max_probe_duration())
+}
+
+/// Describe `canonical` from ffprobe's JSON for it; durations past
+/// `max_probe_duration` are taken as bogus
+pub(crate) fn video_info_from_probe(
+    canonical: &Path,
+    json: &serde_json::Value,
+    choice: StreamChoice,
+    max_probe_duration: std::time::Duration,
+) -> Result<VideoInfo, ConvertError> {
+    let path = canonical.to_string_lossy().to_string();
+    let path = path.as_str();
+
+    let all_streams = json["streams"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let video_json: Vec<&serde_json::Value> =
+        all_streams.iter().filter(|s| s["codec_type"] == "video").collect();
+    let video_streams: Vec<VideoStream> =
+        video_json.iter().enumerate().map(|(index, s)| VideoStream::from_json(index, s)).collect();
+    let programs = parse_programs(json, all_streams);
+    let program = match choice.program {
+        Some(id) => Some(
+            programs
+                .iter()
+                .find(|program| program.program_id == id)
+                .ok_or_else(|| format!("The source has no program {}", id))?,
+        ),
+        None => None,
+    };
+    let video_stream_index = match choice.video.or(program.and_then(|p| p.video_stream)) {
+        Some(index) if video_streams.get(index).is_some_and(|s| !s.is_attached_pic) => index,
+        Some(index) => return Err(format!("The source has no video stream {}", index).into()),
+        None if program.is_some() => return Err("The program has no video stream".into()),
+        None => main_video_stream(&video_streams).ok_or("No video stream found")?,
+    };
+    let video_stream = video_json[video_stream_index];
+
+    let audio_tracks = tracks_of(all_streams, "audio");
+    let subtitle_tracks = tracks_of(all_streams, "subtitle");
+    let audio_stream_index = match program {
+        Some(program) => program.audio_stream.unwrap_or(0),
+        None => select_audio(&audio_tracks, None, &[]).map_or(0, |(index, _)| index),
+    };
+    let audio_track = audio_tracks
+        .get(audio_stream_index)
+        // A program without audio describes none
+        .filter(|_| program.is_none_or(|p| p.audio_stream.is_some()));
+    let streams = count_streams(all_streams);
+    let audio_duration = audio_track
+        .and_then(|_| {
+            all_streams.iter().filter(|s| s["codec_type"] == "audio").nth(audio_stream_index)
+        })
+        .and_then(stream_duration);
+
+    let codec = video_stream["codec_name"]
+        .as_str()
+        .unwrap_or("unknown")
+        .to_string();
+
+    let audio_codec = audio_track.map_or("unknown", |track| track.codec.as_str()).to_string();
+    let audio_sample_rate = audio_track.and_then(|track| track.sample_rate);
+    let audio_channels = audio_track.and_then(|track| track.channels);
+
+    let video_bitrate = stream_bitrate(video_stream).unwrap_or(0);
+    let audio_bitrate = audio_track.and_then(|track| track.bitrate).unwrap_or(0);
+
+    let stream_str = |key: &str| {
+        video_stream[key]
+            .as_str()
+            .filter(|v| !v.is_empty() && *v != "unknown")
+            .map(|v| v.to_string())
+    };
+    // `0:1` is ffprobe's way of saying it doesn't know
+    let aspect_ratio = |key: &str| stream_str(key).filter(|ratio| !ratio.starts_with("0:"));
+    let pix_fmt = stream_str("pix_fmt").unwrap_or_default();
+    // WebM keeps VP8/VP9 alpha in a side layer that ffprobe only reports as a tag
+    let has_alpha = pix_fmt_has_alpha(&pix_fmt)
+        || matches!(video_stream["tags"]["alpha_mode"].as_str(), Some("1"));
+
+    let width = video_stream["width"].as_u64().unwrap_or(0) as u32;
+    let height = video_stream["height"].as_u64().unwrap_or(0) as u32;
+    // Newer ffprobe reports a display matrix, older ones a `rotate` tag
+    let rotation = video_stream["side_data_list"]
+        .as_array()
+        .and_then(|list| list.iter().find_map(|data| data["rotation"].as_f64()))
+        .or_else(|| video_stream["tags"]["rotate"].as_str().and_then(|r| r.parse().ok()))
+        .map(|degrees: f64| degrees.round() as i32)
+        .unwrap_or(0);
+    let frame_rate = video_stream["avg_frame_rate"]
+        .as_str()
+        .and_then(parse_rational)
+        .unwrap_or(0.0);
+    let is_animated_image = ANIMATED_IMAGE_CODECS.contains(&codec.as_str());
+
+    let format = &json["format"];
+    // A program can be much shorter than the whole file (menus, extras)
+    let duration = program.and_then(|p| p.duration).unwrap_or_else(|| {
+        format["duration"]
+            .as_str()
+            .and_then(|d| d.parse::<f64>().ok())
+            .unwrap_or(0.0)
+    });
+    // Piped WebP gives no duration, only frames at the rate it assumes
+    let duration = match video_stream["nb_frames"].as_str().and_then(|n| n.parse::<f64>().ok()) {
+        Some(frames) if is_animated_image && duration <= 0.0 => {
+            frames / animation_frame_rate(frame_rate)
+        }
+        _ => duration,
+    };
+    let program_id = program.map(|p| p.program_id);
+
+    let bitrate = format["bit_rate"]
+        .as_str()
+        .and_then(|b| b.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    let creation_time = format["tags"]["creation_time"].as_str().map(|t| t.to_string());
+
+    let container = format["format_name"]
+        .as_str()
+        .unwrap_or("unknown")
+        .to_string();
+
+    let filename = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+
+    let major_brand = format["tags"]["major_brand"]
+        .as_str()
+        .filter(|brand| !brand.trim().is_empty())
+        .map(|brand| brand.to_string());
+    let compatible_brands = format["tags"]["compatible_brands"].as_str();
+    let is_mp4_family =
+        is_mp4_family(&container, major_brand.as_deref(), compatible_brands, canonical);
+
+    // Check if needs conversion: must be H.264+AAC in MP4 container for
+    // mobile compatibility; a compatible .mov only needs a remux
+    let is_mobile_compatible = codec == "h264" && audio_codec == "aac" && is_mp4_family;
+    let is_faststart = container.contains("mp4") && is_faststart(canonical);
+    let is_fragmented = container.contains("mp4") && is_fragmented(canonical);
+
+    let mut info = VideoInfo {
+        path: path.to_string(),
+        filename,
+        codec,
+        audio_codec,
+        audio_sample_rate,
+        audio_channels,
+        container,
+        major_brand,
+        is_mp4_family,
+        duration,
+        width,
+        height,
+        rotation,
+        sample_aspect_ratio: aspect_ratio("sample_aspect_ratio"),
+        display_aspect_ratio: aspect_ratio("display_aspect_ratio"),
+        frame_rate,
+        bitrate,
+        video_bitrate,
+        audio_bitrate,
+        profile: stream_str("profile"),
+        level: video_stream["level"].as_u64().filter(|level| *level > 0).map(|level| level as u32),
+        ref_frames: video_stream["refs"].as_u64().map(|refs| refs as u32),
+        needs_conversion: !is_mobile_compatible || is_fragmented,
+        is_faststart,
+        is_fragmented,
+        creation_time,
+        chapters: parse_chapters(json),
+        pix_fmt,
+        has_alpha,
+        color_range: stream_str("color_range"),
+        color_space: stream_str("color_space"),
+        color_transfer: stream_str("color_transfer"),
+        color_primaries: stream_str("color_primaries"),
+        streams,
+        video_stream_index,
+        video_streams,
+        audio_stream_index,
+        audio_tracks,
+        subtitle_tracks,
+        program_id,
+        programs,
+        duration_suspect: false,
+        bitrate_estimated: false,
+        video_bitrate_estimated: false,
+        needs_integrity_check: false,
+        av_duration_mismatch_seconds: None,
+        compatibility_warnings: Vec::new(),
+        is_animated_image,
+    };
+    info.av_duration_mismatch_seconds = match (stream_duration(video_stream), audio_duration) {
+        (Some(video), Some(audio)) => {
+            Some(audio - video).filter(|gap| gap.abs() > AV_MISMATCH_THRESHOLD_SECS)
+        }
+        _ => None,
+    };
+    let stream_durations: Vec<f64> = all_streams
+        .iter()
+        .filter_map(|s| s["duration"].as_str().and_then(|d| d.parse().ok()))
+        .collect();
+    probe_sanity::sanitize(
+        &mut info,
+        &stream_durations,
+        file_size(path),
+        max_probe_duration.as_secs_f64(),
+    );
+    info.compatibility_warnings = compatibility_warnings(&info, DeviceProfile::default());
+    Ok(info)
+}
+
+/// Video codecs of animated images, as ffprobe names them
+const ANIMATED_IMAGE_CODECS: &[&str] = &["webp", "apng"];
+/// Rate animated images are encoded at when they don't give a usable one;
+/// frames are repeated to keep their own timing
+const DEFAULT_ANIMATION_FPS: f64 = 25.0;
+/// Above this an animated image's reported rate is taken as a timebase,
+/// not a frame rate
+const MAX_ANIMATION_FPS: f64 = 60.0;
+
+/// The constant rate an animated image is encoded at
+fn animation_frame_rate(reported: f64) -> f64 {
+    if (1.0..=MAX_ANIMATION_FPS).contains(&reported) {
+        reported
+    } else {
+        DEFAULT_ANIMATION_FPS
+    }
+}
+
+/// A stream's own length: MP4 and TS give it as seconds, Matroska only as
+/// a `DURATION` tag
+fn stream_duration(stream: &serde_json::Value) -> Option<f64> {
+    stream["duration"]
+        .as_str()
+        .and_then(|d| d.parse::<f64>().ok())
+        .or_else(|| stream["tags"]["DURATION"].as_str().map(parse_time_to_seconds))
+        .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+}
+
+fn count_streams(streams: &[serde_json::Value]) -> StreamCounts {
+    let mut counts = StreamCounts::default();
+    for stream in streams {
+        match stream["codec_type"].as_str() {
+            Some("video") if stream["disposition"]["attached_pic"] == 1 => counts.attachment += 1,
+            Some("video") => counts.video += 1,
+            Some("audio") => counts.audio += 1,
+            Some("subtitle") => counts.subtitle += 1,
+            Some("attachment") => counts.attachment += 1,
+            Some("data") => {
+                counts.data += 1;
+                // Telemetry is generic `bin_data` to ffmpeg; the tag says what
+                // it is. Tags ffprobe can't print come out as `[0][0][0][0]`.
+                let name = stream["codec_tag_string"]
+                    .as_str()
+                    .filter(|tag| !tag.starts_with('['))
+                    .or_else(|| stream["codec_name"].as_str())
+                    .unwrap_or("unknown");
+                counts.data_codecs.push(name.to_string());
+            }
+            _ => {}
+        }
+    }
+    counts
+}
+
+/// A stream's bitrate in bits/s: `bit_rate`, else the `BPS` statistics tag
+/// mkvmerge writes, as Matroska has no bitrate field of its own
+pub(crate) fn stream_bitrate(stream: &serde_json::Value) -> Option<u64> {
+    let tags = &stream["tags"];
+    [&stream["bit_rate"], &tags["BPS"], &tags["BPS-eng"]]
+        .into_iter()
+        .find_map(|value| value.as_str().and_then(|b| b.parse::<u64>().ok()))
+        .filter(|bitrate| *bitrate > 0)
+}
+
+/// Parse an ffprobe rational like "30000/1001"; "0/0" gives None
+fn parse_rational(value: &str) -> Option<f64> {
+    let (num, den) = value.split_once('/')?;
+    let num: f64 = num.parse().ok()?;
+    let den: f64 = den.parse().ok()?;
+    if den == 0.0 {
+        None
+    } else {
+        Some(num / den)
+    }
+}
+
+/// Parse time string like "00:01:23.45" to seconds
+pub fn parse_time_to_seconds(time_str: &str) -> f64 {
+    let parts: Vec<&str> = time_str.split(':').collect();
+    if parts.len() == 3 {
+        let hours: f64 = parts[0].parse().unwrap_or(0.0);
+        let minutes: f64 = parts[1].parse().unwrap_or(0.0);
+        let seconds: f64 = parts[2].parse().unwrap_or(0.0);
+        hours * 3600.0 + minutes * 60.0 + seconds
+    } else {
+        0.0
+    }
+}
+
+/// Range manual gain is limited to
+const VOLUME_DB_RANGE: std::ops::RangeInclusive<f64> = -60.0..=30.0;
+
+/// Audio files further off the video's length than this are reported
+const MAX_AUDIO_MISMATCH_SECS: f64 = 2.0;
+
+/// A source's audio and video ending further apart than this is reported
+/// in `av_duration_mismatch_seconds`; AAC framing alone leaves a fraction
+/// of a second
+pub const AV_MISMATCH_THRESHOLD_SECS: f64 = 1.0;
+
+/// Larger audio shifts are almost certainly a typo
+pub(crate) const MAX_AUDIO_DELAY_MS: i64 = 30_000;
+
+/// Sample rates and channel counts the AAC encoder takes
+pub(crate) const AUDIO_SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+pub(crate) const AUDIO_CHANNELS: std::ops::RangeInclusive<u32> = 1..=8;
+
+/// Input options that shift a second read of the source, so the audio can be
+/// taken from it and still be copied
+fn audio_delay_input_args(delay_ms: i64) -> Vec<String> {
+    let seconds = format!("{:.3}", delay_ms.unsigned_abs() as f64 / 1000.0);
+    let option = if delay_ms > 0 { "-itsoffset" } else { "-ss" };
+    vec![option.to_string(), seconds]
+}
+
+/// Filter that shifts audio that is re-encoded anyway
+fn audio_delay_filter(delay_ms: i64) -> String {
+    if delay_ms > 0 {
+        format!("adelay={}:all=1", delay_ms)
+    } else {
+        format!(
+            "atrim=start={:.3},asetpts=PTS-STARTPTS",
+            delay_ms.unsigned_abs() as f64 / 1000.0
+        )
+    }
+}
+
+/// Build an `atempo` chain for a speed factor.
+///
+/// Each atempo instance is kept within 0.5-2.0, where it sounds best and
+/// which older ffmpeg builds require, so larger factors are split up.
+fn atempo_chain(speed: f64) -> Vec<String> {
+    let mut filters = Vec::new();
+    let mut remaining = speed;
+    while remaining > 2.0 {
+        filters.push("atempo=2.0".to_string());
+        remaining /= 2.0;
+    }
+    while remaining < 0.5 {
+        filters.push("atempo=0.5".to_string());
+        remaining /= 0.5;
+    }
+    filters.push(format!("atempo={}", remaining));
+    filters
+}
+
+/// Start of the error a failed hardware decoder ends an attempt with
+const HW_DECODE_FAILED: &str = "Hardware decoding failed";
+const HW_FALLBACK_WARNING: &str =
+    "Hardware decoding didn't work for this file, so it was decoded in software";
+/// What ffmpeg prints when a `-hwaccel` method can't be set up
+const HW_DECODE_ERRORS: &[&str] = &[
+    "Failed setup for format",
+    "hwaccel initialisation returned error",
+    "Device creation failed",
+    "No device available for decoder",
+    "Failed to initialise VAAPI connection",
+];
+
+/// `-hwaccel` method of the platform's usual hardware decoder. D3D11VA
+/// covers every GPU vendor on Windows, unlike CUDA.
+fn hw_decode_method() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        Some("videotoolbox")
+    } else if cfg!(target_os = "windows") {
+        Some("d3d11va")
+    } else if cfg!(target_os = "linux") {
+        Some("vaapi")
+    } else {
+        None
+    }
+}
+
+fn hw_decode_failed(stderr_tail: &[String]) -> bool {
+    stderr_tail.iter().any(|line| HW_DECODE_ERRORS.iter().any(|error| line.contains(error)))
+}
+
+/// Get the number of CPU cores for multi-threading
+fn get_thread_count() -> String {
+    std::thread::available_parallelism()
+        .map(|n| n.get().to_string())
+        .unwrap_or_else(|_| "4".to_string())
+}
+
+/// The H.264 encoder used when video has to be re-encoded
+pub const VIDEO_ENCODER: &str = if cfg!(target_os = "macos") {
+    "h264_videotoolbox"
+} else {
+    "libx264"
+};
+
+/// Quality targets; high-fidelity sources get more bits to avoid banding
+const CRF: u32 = 23;
+const CRF_HIGH: u32 = 18;
+pub(crate) const MAX_CRF: u32 = 51;
+/// Below this a `max_height` is more likely a typo than a wish
+pub(crate) const MIN_MAX_HEIGHT: u32 = 16;
+
+/// VideoToolbox `-q:v` for a CRF value, lined up so the default CRFs 23 and
+/// 18 give its usual 65 and 75
+fn vt_quality(crf: u32) -> u32 {
+    111u32.saturating_sub(2 * crf).clamp(1, 100)
+}
+
+/// Pin flag/value video settings to the first video stream (`-c:v` becomes
+/// `-c:v:0`, `-crf` becomes `-crf:v:0`)
+fn main_video_only(args: &[String]) -> Vec<String> {
+    args.chunks(2)
+        .flat_map(|pair| {
+            let flag = match pair[0].as_str() {
+                "-vf" => "-filter:v:0".to_string(),
+                flag if flag.ends_with(":v") => format!("{}:0", flag),
+                flag => format!("{}:v:0", flag),
+            };
+            std::iter::once(flag).chain(pair.get(1).cloned())
+        })
+        .collect()
+}
+
+/// Encoder settings used whenever the video has to be re-encoded
+pub fn video_encoder_args(
+    thread_count: &str,
+    rate_limit: Option<RateLimit>,
+    crf: u32,
+    allow_sw: bool,
+) -> (Vec<String>, StreamAction) {
+    #[cfg(target_os = "macos")]
+    {
+        let _ = thread_count;
+        // VideoToolbox ignores rate limits in constant-quality mode, so a cap
+        // replaces -q:v with a target bitrate (see rate_limit_args)
+        let mut args = vec!["-c:v".to_string(), "h264_videotoolbox".to_string()];
+        if rate_limit.is_none() {
+            args.extend(["-q:v".to_string(), vt_quality(crf).to_string()]);
+        }
+        args.extend(["-profile:v", "main", "-level", "4.0"].iter().map(|a| a.to_string()));
+        args.extend(["-allow_sw".to_string(), if allow_sw { "1" } else { "0" }.to_string()]);
+        if let Some(limit) = rate_limit {
+            args.extend(rate_limit_args(VIDEO_ENCODER, limit));
+        }
+        (args, StreamAction::Encoded(VIDEO_ENCODER.to_string()))
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = allow_sw;
+        software_encoder_args(thread_count, rate_limit, crf)
+    }
+}
+
+/// libx264 settings: the encoder everywhere but macOS, and the stand-in
+/// there when VideoToolbox has no free session
+pub fn software_encoder_args(
+    thread_count: &str,
+    rate_limit: Option<RateLimit>,
+    crf: u32,
+) -> (Vec<String>, StreamAction) {
+    let crf = crf.to_string();
+    let mut args: Vec<String> = [
+        "-c:v", "libx264", "-preset", "fast", "-crf", &crf, "-profile:v", "main", "-level", "4.0",
+        "-threads", thread_count,
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .collect();
+    if let Some(limit) = rate_limit {
+        args.extend(rate_limit_args("libx264", limit));
+    }
+    (args, StreamAction::Encoded("libx264".to_string()))
+}
+
+/// Bitrate cap arguments for an encoder
+fn rate_limit_args(encoder: &str, limit: RateLimit) -> Vec<String> {
+    let max = format!("{}k", limit.max_kbps);
+    let buffer = format!("{}k", limit.buffer_kbps);
+    let mut args = Vec::new();
+    match encoder {
+        // Target a little under the cap so the limiter rarely has to step in
+        "h264_videotoolbox" => {
+            args.push("-b:v".to_string());
+            args.push(format!("{}k", limit.max_kbps / 10 * 8));
+        }
+        // NVENC only honours -maxrate in VBR mode
+        "h264_nvenc" => {
+            args.extend(["-rc".to_string(), "vbr".to_string()]);
+        }
+        _ => {}
+    }
+    args.extend(["-maxrate".to_string(), max, "-bufsize".to_string(), buffer]);
+    args
+}
+
+/// Bits/s audio is encoded to AAC at
+const AAC_BITRATE: u64 = 128_000;
+
+/// Audio codec settings: copy AAC as-is, otherwise encode to AAC
+fn audio_codec_args(is_aac: bool, conform: AudioTargets) -> (Vec<String>, StreamAction) {
+    if is_aac {
+        (vec!["-c:a".to_string(), "copy".to_string()], StreamAction::Copied)
+    } else {
+        let mut args: Vec<String> =
+            ["-c:a", "aac", "-b:a", "128k"].iter().map(|a| a.to_string()).collect();
+        if let Some(rate) = conform.sample_rate {
+            args.extend(["-ar".to_string(), rate.to_string()]);
+        }
+        if let Some(channels) = conform.channels {
+            args.extend(["-ac".to_string(), channels.to_string()]);
+        }
+        (args, StreamAction::Encoded("aac".to_string()))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_video<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let Some(path) = &options.progress_file else {
+        return convert_checked(
+            resolver,
+            input_path,
+            output_dir,
+            task_id,
+            options,
+            cache_dir,
+            log,
+            cancel,
+            progress_callback,
+        )
+        .await;
+    };
+    let progress_file = Arc::new(ProgressFile::new(PathBuf::from(path), log.clone()));
+    let writer = Arc::clone(&progress_file);
+    let result = convert_checked(
+        resolver,
+        input_path,
+        output_dir,
+        task_id,
+        options,
+        cache_dir,
+        log,
+        cancel,
+        move |progress| {
+            writer.record(&progress);
+            progress_callback(progress);
+        },
+    )
+    .await;
+    // Errors found before ffmpeg started are returned without a callback
+    match &result {
+        Err(e) if !progress_file.is_finished() => {
+            progress_file.record(&failure_progress(task_id, e))
+        }
+        _ => {}
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn convert_checked<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let errors = check_options(options);
+    if !errors.is_empty() {
+        return Err(ConvertError::InvalidOptions(errors));
+    }
+    if options.wait_for_stable {
+        let waited = wait_until_stable(Path::new(input_path), cancel, || {
+            progress_callback(ConversionProgress::update(
+                task_id,
+                0.0,
+                ConversionStatus::WaitingForFile,
+            ))
+        })
+        .await;
+        if let Err(e) = waited {
+            progress_callback(match &e {
+                ConvertError::Cancelled => {
+                    ConversionProgress::update(task_id, 0.0, ConversionStatus::Cancelled)
+                }
+                _ => failure_progress(task_id, &e),
+            });
+            return Err(e);
+        }
+    }
+    if options.compute_hashes {
+        return convert_hashed(
+            resolver,
+            input_path,
+            output_dir,
+            task_id,
+            options,
+            cache_dir,
+            log,
+            cancel,
+            progress_callback,
+        )
+        .await;
+    }
+    convert_outputs(
+        resolver,
+        input_path,
+        output_dir,
+        task_id,
+        options,
+        cache_dir,
+        log,
+        cancel,
+        progress_callback,
+    )
+    .await
+}
+
+/// Convert with the input hashed alongside and the output after it; the
+/// completed event waits for both, so the task can be cancelled until then
+#[allow(clippy::too_many_arguments)]
+async fn convert_hashed<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let mut input_job = tokio::spawn(sha256_file(PathBuf::from(input_path), cancel.clone()));
+    // The source is swapped for the output at the end, so it must be read
+    // in full before that
+    let early_input_hash = match options.replace_original {
+        true => Some(join_hash(&mut input_job).await),
+        false => None,
+    };
+    let callback = Arc::new(progress_callback);
+    let held = Arc::new(std::sync::Mutex::new(None));
+    let (forward, hold) = (Arc::clone(&callback), Arc::clone(&held));
+    let result = convert_outputs(
+        resolver,
+        input_path,
+        output_dir,
+        task_id,
+        options,
+        cache_dir,
+        log,
+        cancel,
+        move |progress: ConversionProgress| {
+            if progress.status.is_completed() {
+                *hold.lock().unwrap() = Some(progress);
+            } else {
+                forward(progress);
+            }
+        },
+    )
+    .await;
+    if result.is_err() {
+        input_job.abort();
+        return result;
+    }
+    let input_hash = match early_input_hash {
+        Some(hash) => hash,
+        None => join_hash(&mut input_job).await,
+    };
+    let mut done = result?;
+    let Some(mut completed) = held.lock().unwrap().take() else {
+        // A partial output isn't worth a hash
+        return Ok(done);
+    };
+
+    callback(ConversionProgress {
+        indeterminate: true,
+        ..ConversionProgress::update(task_id, 100.0, ConversionStatus::Finalizing)
+    });
+    let mut warnings = Vec::new();
+    done.input_sha256 = hash_or_warn("input", input_hash, log, &mut warnings);
+    if done.segment_paths.is_empty() && done.rendition_paths.is_empty() && done.hls_segment_count == 0
+    {
+        let output_hash = sha256_file(PathBuf::from(&done.output_path), cancel.clone()).await;
+        done.output_sha256 = hash_or_warn("output", output_hash, log, &mut warnings);
+    }
+    done.warnings.extend(warnings.iter().cloned());
+    completed.warnings.extend(warnings);
+    callback(completed);
+    Ok(done)
+}
+
+async fn join_hash(
+    job: &mut tokio::task::JoinHandle<Result<String, ConvertError>>,
+) -> Result<String, ConvertError> {
+    job.await.map_err(|e| ConvertError::Failed(format!("Failed to hash the file: {}", e)))?
+}
+
+/// The hash, logged; a failure becomes a warning, since the output itself
+/// is fine (and may already have replaced the source), so even a cancel
+/// only stops the hashing
+fn hash_or_warn(
+    what: &str,
+    hash: Result<String, ConvertError>,
+    log: &TaskLog,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let warning = match hash {
+        Ok(hash) => {
+            log.line(&format!("SHA-256 of the {}: {}", what, hash));
+            return Some(hash);
+        }
+        Err(ConvertError::Cancelled) => format!("The {} wasn't hashed: cancelled", what),
+        Err(e) => format!("The {} wasn't hashed: {}", what, e),
+    };
+    log.line(&warning);
+    warnings.push(warning);
+    None
+}
+
+/// Convert to the output or, with `renditions`, to each of them
+#[allow(clippy::too_many_arguments)]
+async fn convert_outputs<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    if !options.renditions.is_empty() {
+        return convert_renditions(
+            resolver,
+            input_path,
+            output_dir,
+            task_id,
+            options,
+            cache_dir,
+            log,
+            cancel,
+            progress_callback,
+        )
+        .await;
+    }
+    convert_single(
+        resolver,
+        input_path,
+        output_dir,
+        task_id,
+        options,
+        cache_dir,
+        log,
+        cancel,
+        progress_callback,
+    )
+    .await
+}
+
+/// Convert to one output; `options` must already be validated. A failed
+/// hardware decoder is retried once in software.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn convert_single<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let callback = Arc::new(progress_callback);
+    let first = Arc::clone(&callback);
+    let result = convert_attempt(
+        resolver,
+        input_path,
+        output_dir,
+        task_id,
+        options,
+        cache_dir,
+        log,
+        cancel,
+        move |progress| first(progress),
+    )
+    .await;
+    match result {
+        Err(ConvertError::Failed(message)) if message.starts_with(HW_DECODE_FAILED) => {
+            log.line(&format!("{}; retrying with software decoding", message));
+            let software = ConversionOptions { hw_decode: HwDecode::Off, ..options.clone() };
+            let retry = Arc::clone(&callback);
+            let result = convert_attempt(
+                resolver,
+                input_path,
+                output_dir,
+                task_id,
+                &software,
+                cache_dir,
+                log,
+                cancel,
+                move |mut progress| {
+                    if progress.status.is_completed() {
+                        progress.warnings.push(HW_FALLBACK_WARNING.to_string());
+                    }
+                    retry(progress)
+                },
+            )
+            .await;
+            result.map(|mut result| {
+                result.warnings.push(HW_FALLBACK_WARNING.to_string());
+                result
+            })
+        }
+        result => result,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn convert_attempt<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    // Declared first so it is dropped last, after the temp files inside it
+    let work_dir = TaskDir::create(cache_dir, task_id)?;
+    // Every user-picked path ffmpeg reads or writes, for sandboxed builds
+    let picked = [
+        Some(input_path),
+        Some(output_dir),
+        options.subtitle_file.as_deref(),
+        options.external_audio.as_ref().map(|external| external.path.as_str()),
+        options.cover_image.as_deref(),
+    ];
+    let _access = ScopedAccess::start(picked.into_iter().flatten().map(Path::new));
+
+    // Subtitles are checked (and transcoded to UTF-8 if needed) up front so a
+    // bad file fails here rather than as an ffmpeg error
+    let subtitle = options
+        .subtitle_file
+        .as_deref()
+        .map(|path| -> Result<_, ConvertError> {
+            let path = validate_input_path(path)?;
+            Ok(prepare_subtitle(&path, work_dir.path())?)
+        })
+        .transpose()?;
+    let burn_subtitle = options.burns_subtitle();
+
+    let speed = options.speed.unwrap_or(1.0);
+    let changes_speed = options.changes_speed();
+    let audio_delay = options.audio_delay_ms.filter(|ms| *ms != 0);
+
+    // Get video info for progress calculation and smart conversion
+    let (info, mut selection) = probe_for_conversion(resolver, input_path, options).await?;
+    // Everything from here on sees the options as checked for this input
+    let normalized = validate_for_input(options, &info).map_err(ConvertError::InvalidOptions)?;
+    let options: &ConversionOptions = &normalized;
+    let output_dir = validate_output_dir(output_dir)?;
+    let output_dir = match &options.output_dir_template {
+        Some(template) => {
+            let relative = expand_dir_template(template, &info)?;
+            create_output_subdir(&output_dir, &relative)?
+        }
+        None => output_dir,
+    };
+    let input_bytes = file_size(&info.path);
+    // Staging swaps `info.path` for the local copy; a swap replaces this one
+    let original_path = PathBuf::from(&info.path);
+    let fixes_audio_only = options.mode == ConversionMode::AudioOnlyFix;
+
+    let crop = match options.crop {
+        Some(rect) => Some(rect),
+        None if options.auto_crop => detect_crop(resolver, &info).await?.crop,
+        None => None,
+    };
+    if let Some(rect) = crop {
+        rect.validate(info.width, info.height)?;
+    }
+    // The cut applies to the input, which subtitles, an added audio file or
+    // a shifted audio input would have to follow too
+    let trim_blocked = options.subtitle_file.is_some()
+        || options.external_audio.is_some()
+        || audio_delay.is_some()
+        || options.min_duration_seconds.is_some();
+    let trim = if let Some(clip) = options.clip {
+        Some(clip)
+    } else if options.auto_trim_silence && info.has_audio() && !trim_blocked {
+        progress_callback(ConversionProgress::update(task_id, 0.0, ConversionStatus::Analyzing));
+        let silences =
+            detect_silence(resolver, &info, TRIM_SILENCE_DB, TRIM_SILENCE_SECONDS, cancel).await;
+        match silences {
+            Ok(silences) => edge_trim(&silences, info.duration),
+            Err(e) => {
+                progress_callback(failure_progress(task_id, &e));
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+    let source_duration = trim.map_or(info.duration, |t| t.end_seconds - t.start_seconds);
+    // Progress is measured against the output timeline, which a minimum
+    // duration can lengthen
+    let natural_duration = source_duration / speed;
+    let extension = options
+        .min_duration_seconds
+        .filter(|min| natural_duration > 0.0 && natural_duration < *min);
+    let duration = extension.unwrap_or(natural_duration);
+    // How far the audio runs past the video on the output timeline, when
+    // the streams are to be evened out
+    let av_mismatch = info
+        .av_duration_mismatch_seconds
+        .filter(|_| {
+            options.conform_duration != ConformDuration::Keep
+                && trim.is_none()
+                && extension.is_none()
+                && audio_delay.is_none()
+                && options.external_audio.is_none()
+        })
+        .map(|gap| gap / speed);
+    let cuts_to_shortest =
+        av_mismatch.is_some() && options.conform_duration == ConformDuration::Shortest;
+    let video_padding = av_mismatch
+        .filter(|gap| *gap > 0.0 && options.conform_duration == ConformDuration::PadVideo);
+    let audio_padding = av_mismatch
+        .filter(|gap| *gap < 0.0 && options.conform_duration == ConformDuration::PadAudio)
+        .map(f64::abs);
+    // Padding only ever lengthens the shorter stream
+    let conforms_streams = cuts_to_shortest || video_padding.is_some() || audio_padding.is_some();
+    if let Some(gap) = av_mismatch.filter(|_| conforms_streams) {
+        log.line(&format!("The audio ends {:.3}s after the video; evening them out", gap));
+    }
+    // Padding lengthens the shorter stream to the longer one, which the
+    // probed duration already is
+    let duration = match av_mismatch {
+        Some(gap) if cuts_to_shortest => duration - gap.abs(),
+        _ => duration,
+    };
+    let burns_forced_subtitle = selection.burns_forced_subtitle;
+    let is_h264 = options.copies_video(&info)
+        && crop.is_none()
+        && extension.is_none()
+        && video_padding.is_none()
+        && !burns_forced_subtitle;
+    if info.only_needs_defragment() && is_h264 {
+        // Copying both streams into the plain mp4 muxer is the whole fix
+        log.line("The source is fragmented; remuxing it into a regular MP4");
+    }
+    let rate_limit = options.rate_limit();
+
+    let mut warnings = Vec::new();
+    let would_copy = info.codec == "h264" && !options.forces_video_encode();
+    if would_copy && options.exceeds_rate_limit(&info) {
+        warnings.push(format!(
+            "The source bitrate is above the {} kbit/s cap, so the video was re-encoded \
+             instead of copied",
+            options.max_bitrate_kbps.unwrap_or_default()
+        ));
+    }
+    if would_copy && video_padding.is_some() {
+        warnings.push(
+            "The video was held on its last frame until the audio ends, so it was re-encoded \
+             instead of copied"
+                .to_string(),
+        );
+    }
+    if would_copy && burns_forced_subtitle {
+        warnings.push(
+            "The source's forced subtitles were burned in, so the video was re-encoded instead \
+             of copied"
+                .to_string(),
+        );
+    }
+    if let Some(index) = selection.forced_subtitle.filter(|_| !burns_forced_subtitle) {
+        warnings.push(format!(
+            "Forced subtitle track {} is made of pictures, which can't be burned in, so it was \
+             left out",
+            index
+        ));
+    }
+    if would_copy {
+        for reason in options.device_fixes(&info) {
+            warnings.push(format!("{}, so the video was re-encoded instead of copied", reason));
+        }
+    }
+    if is_h264 {
+        // Audio issues are converted away below
+        warnings.extend(
+            compatibility_issues(&info, options.target_profile.unwrap_or_default())
+                .into_iter()
+                .filter(|issue| !issue.kind.is_audio())
+                .map(|issue| issue.message),
+        );
+    }
+
+    if options.auto_trim_silence && options.clip.is_none() && trim_blocked {
+        warnings.push(
+            "Silence wasn't trimmed; that can't be combined with subtitles, an added audio \
+             file, an audio delay or a minimum duration"
+                .to_string(),
+        );
+    }
+    if let Some(range) = trim {
+        log.line(&format!(
+            "{}: keeping {:.3}s to {:.3}s",
+            if options.clip.is_some() { "Clipping" } else { "Trimming silence" },
+            range.start_seconds,
+            range.end_seconds
+        ));
+    }
+
+    if options.dedup_frames {
+        warnings.push(
+            "Duplicate frames were dropped, so the output has a variable frame rate".to_string(),
+        );
+    }
+    if options.interpolate_fps.is_some()
+        && options.interpolation_quality != InterpolationQuality::Duplicate
+    {
+        warnings.push(
+            "Frame interpolation is slow; expect the conversion to run well below realtime"
+                .to_string(),
+        );
+    }
+
+    let is_hls = options.output_format == OutputFormat::Hls;
+    let is_mkv = options.output_format == OutputFormat::Mkv;
+    if is_mkv {
+        warnings.push(
+            "MKV output plays in desktop players and TVs, but most phones' built-in players \
+             won't open it"
+                .to_string(),
+        );
+    }
+    // The segment muxer writes every part from the same streams, so a
+    // one-frame cover would only make it into the first part
+    let cover_image =
+        options.cover_image.as_deref().filter(|_| options.segment.is_none() && !is_hls);
+    if options.cover_image.is_some() && cover_image.is_none() {
+        warnings.push("Split outputs don't get a cover image, so none was added".to_string());
+    }
+
+    let alpha_background = options.alpha_background.as_deref().unwrap_or("white");
+    if info.has_alpha {
+        warnings.push(format!(
+            "The source is transparent; MP4/H.264 can't store transparency, so it was \
+             placed on a {} background",
+            alpha_background
+        ));
+    }
+    let crf = options.crf_for(&info);
+
+    let expanded = output_file_name(&info, None, options)?;
+    let file_name = fit_output_name(&output_dir, &expanded);
+    let stem = Path::new(&info.path).file_stem().unwrap_or_default().to_string_lossy();
+    if sanitize_file_stem(&stem) != stem || file_name != expanded {
+        warnings.push(format!(
+            "The input name can't be used as-is in the output folder, so the output was \
+             named {}",
+            file_name
+        ));
+    }
+    let output_path =
+        resolve_output_path(&output_dir, &file_name, options.collision_policy);
+    // Segments are named after the output path, which itself isn't written
+    let output_path = match &options.segment {
+        Some(_) => resolve_segment_base(output_path, options.collision_policy),
+        None => output_path,
+    };
+    // HLS writes a playlist and its segments into a folder of their own,
+    // named after the output; an earlier playlist there is replaced whole
+    let output_path = if is_hls {
+        let hls_dir = resolve_hls_dir(&output_path, options.collision_policy);
+        if hls_dir.exists() {
+            let _ = std::fs::remove_dir_all(&hls_dir);
+        }
+        std::fs::create_dir_all(&hls_dir)
+            .map_err(|e| format!("Failed to create HLS folder {}: {}", hls_dir.display(), e))?;
+        playlist_path(&hls_dir)
+    } else {
+        output_path
+    };
+    let output_path_str = output_path.to_string_lossy().to_string();
+
+    // Measured only when asked for, and not for a track that gets replaced
+    let replaces_own_audio = options
+        .external_audio
+        .as_ref()
+        .is_some_and(|external| external.mode == AudioMuxMode::Replace);
+    let balance_filter = if options.fix_channel_balance && info.has_audio() && !replaces_own_audio {
+        let balance = detect_channel_balance(resolver, &info).await?;
+        if let Some(live) = balance.live_channel {
+            let side = match live {
+                LiveChannel::Left => "left",
+                LiveChannel::Right => "right",
+            };
+            warnings.push(format!(
+                "The audio was only in the {} channel, so it was copied to both",
+                side
+            ));
+        }
+        balance.fix_filter()
+    } else {
+        None
+    };
+
+    // A strict target's sample rate or channel limit only shows in the
+    // audio, so it's converted while the video may still be copied
+    let conform = options.audio_conformance(&info);
+    let conforms_audio = conform != AudioTargets::default();
+    for issue in compatibility_issues(&info, options.target_profile.unwrap_or_default()) {
+        if issue.kind.is_audio() {
+            warnings.push(format!("{}, so the audio was converted", issue.message));
+        }
+    }
+    let is_aac = info.audio_codec == "aac"
+        && !conforms_audio
+        && !changes_speed
+        && !options.changes_volume()
+        && balance_filter.is_none()
+        && extension.is_none()
+        && audio_padding.is_none()
+        && !options.fades();
+    let audio_delay = audio_delay.filter(|_| info.has_audio());
+    // AAC is shifted by reading it from a time-shifted second input, which
+    // keeps it copied; anything else is re-encoded with a delay filter
+    let delay_input = audio_delay.filter(|_| is_aac);
+
+    let external_audio = match &options.external_audio {
+        Some(external) => {
+            let path = validate_input_path(&external.path)?;
+            let probe = probe_audio_file(resolver, &path).await?;
+            if (probe.duration - info.duration).abs() > MAX_AUDIO_MISMATCH_SECS {
+                warnings.push(format!(
+                    "The audio file is {:.1}s long but the video is {:.1}s; it was padded or \
+                     cut to fit",
+                    probe.duration, info.duration
+                ));
+            }
+            Some((external, path, probe))
+        }
+        None => None,
+    };
+    // With no audio of its own, an added track is simply the audio
+    let replaces_audio = external_audio.as_ref().is_some_and(|(external, ..)| {
+        external.mode == AudioMuxMode::Replace || !info.has_audio()
+    });
+    // A replaced track isn't read, so it can't be shifted by input either
+    let delay_input = delay_input.filter(|_| !replaces_audio);
+    // Copied audio of an iPhone-style MOV keeps the other AAC tracks too,
+    // as they are, behind the chosen one; picking a track keeps just that
+    if is_aac
+        && !is_mkv
+        && !is_hls
+        && external_audio.is_none()
+        && options.audio_stream.is_none()
+        && !options.primary_audio_only
+    {
+        let (kept, left_out) = companion_audio_tracks(&info);
+        for track in left_out {
+            warnings.push(format!(
+                "Audio track {} ({}) can't be stored in MP4, so it was left out",
+                track.index, track.codec
+            ));
+        }
+        selection.kept_audio_streams = kept;
+    }
+    let expected = ExpectedOutput {
+        duration,
+        has_audio: info.has_audio() || external_audio.is_some(),
+        streams_conformed: conforms_streams,
+    };
+
+    // Chapters can be copied as-is unless the timeline changes; then they are
+    // rewritten into an ffmetadata file with the new times
+    let chapter_file = if (changes_speed || trim.is_some()) && !info.chapters.is_empty() {
+        let (start, end) = trim.map_or((0.0, None), |t| (t.start_seconds, Some(t.end_seconds)));
+        let chapters = retime_chapters(&info.chapters, start, end, speed);
+        Some(write_chapter_file(&chapters, work_dir.path())?)
+    } else {
+        None
+    };
+
+    // Send starting progress
+    progress_callback(ConversionProgress {
+        task_id: task_id.to_string(),
+        progress: 0.0,
+        status: ConversionStatus::Starting,
+        output_path: None,
+        error: None,
+        video_action: None,
+        audio_action: None,
+        indeterminate: false,
+        speed: None,
+        eta_seconds: None,
+        warnings: Vec::new(),
+        bytes_written: None,
+        software_fallback: false,
+        queue_position: None,
+        estimated_start_seconds: None,
+    });
+
+    // Reading a big file over the network while encoding is slow and prone
+    // to stalls, so it is copied to local disk first
+    let staged = if should_stage(options.stage_locally, &info) {
+        let source = Path::new(&info.path);
+        let source_bytes = std::fs::metadata(source).map(|m| m.len()).unwrap_or(0);
+        log.line(&format!("Copying {} to local disk before converting", info.path));
+        let staged = match check_staging_space(source_bytes, source_bytes, work_dir.path(), &output_dir)
+        {
+            Ok(()) => {
+                stage_input(source, work_dir.path(), cancel, |percent| {
+                    progress_callback(ConversionProgress::update(
+                        task_id,
+                        percent,
+                        ConversionStatus::Staging,
+                    ))
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match staged {
+            Ok(staged) => {
+                resolver.cache_manager().register(&staged.path, CacheKind::Staged, Some(task_id));
+                Some(staged)
+            }
+            Err(ConvertError::Cancelled) => {
+                progress_callback(ConversionProgress::update(task_id, 0.0, ConversionStatus::Cancelled));
+                return Err(ConvertError::Cancelled);
+            }
+            Err(e) => {
+                let e = unavailable_or(e, &info.path).await;
+                progress_callback(failure_progress(task_id, &e));
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+    // Everything from here on reads the local copy
+    let info = match &staged {
+        Some(staged) => VideoInfo { path: staged.path.to_string_lossy().to_string(), ..info },
+        None => info,
+    };
+
+    let cover = match cover_image {
+        Some(cover) => match prepare_cover(resolver, cover, &info, work_dir.path(), log, cancel).await {
+            Ok(cover) => Some(cover),
+            Err(ConvertError::Cancelled) => {
+                progress_callback(ConversionProgress::update(task_id, 0.0, ConversionStatus::Cancelled));
+                return Err(ConvertError::Cancelled);
+            }
+            Err(e) => {
+                progress_callback(failure_progress(task_id, &e));
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let task_id_owned = task_id.to_string();
+    let output_path_arg = match options.segment {
+        Some(_) => ffmpeg_path_arg(&segment_pattern(&output_path)),
+        None => ffmpeg_path_arg(&output_path),
+    };
+    let input_path_arg = ffmpeg_path_arg(Path::new(&info.path));
+    let thread_count = get_thread_count();
+
+    // Wrap callback in Arc for sharing
+    let callback = Arc::new(progress_callback);
+    let callback_clone = Arc::clone(&callback);
+
+    // Run ffmpeg conversion with optimizations
+    let mut cmd = FfmpegCommandBuilder::new(&thread_count);
+    if options.verbose_log {
+        cmd.push(Section::Global, ["-v", "verbose"]);
+    }
+    let mut input_options: Vec<String> = Vec::new();
+    if let Some(decoder) = info.alpha_decoder() {
+        input_options.extend(["-c:v".to_string(), decoder.to_string()]);
+    }
+    // Decoding dominates re-encodes of 4K HEVC; frames come back to system
+    // memory, so the filters and encoder are the same either way
+    let hw_decoder = hw_decode_method().filter(|_| {
+        options.hw_decode == HwDecode::Auto
+            && !is_h264
+            && info.alpha_decoder().is_none()
+            && !info.is_animated_image
+    });
+    if let Some(method) = hw_decoder {
+        input_options.extend(["-hwaccel".to_string(), method.to_string()]);
+    }
+    if let (Some(min), ExtendMode::Loop) = (extension, options.extend_mode) {
+        // Enough extra plays to pass the minimum; `-t` cuts the excess
+        let loops = (min / natural_duration).ceil() as u32 - 1;
+        input_options.extend(["-stream_loop".to_string(), loops.to_string()]);
+    }
+    if let Some(range) = trim {
+        input_options.extend([
+            "-ss".to_string(),
+            format!("{:.3}", range.start_seconds),
+            "-t".to_string(),
+            format!("{:.3}", range.end_seconds - range.start_seconds),
+        ]);
+    }
+    cmd.input(input_options, input_path_arg.as_str());
+
+    // Flatten transparency before anything else touches the picture, then
+    // crop so burned-in subtitles land inside the kept picture. Noise is
+    // removed at the source resolution, before any scaling smears it, and
+    // sharpening comes after scaling so it isn't scaled away.
+    let mut video_filters: Vec<String> = Vec::new();
+    if info.has_alpha {
+        video_filters.push(alpha_composite_filter(alpha_background));
+    }
+    // Animated images time each frame on its own, often sparsely or not at
+    // all; a constant rate keeps players and the encoder happy
+    if info.is_animated_image {
+        video_filters.push(format!("fps={}", animation_frame_rate(info.frame_rate)));
+    }
+    if let Some(filter) = options.denoise.filter() {
+        video_filters.push(filter.to_string());
+    }
+    if let Some(rect) = crop {
+        video_filters.push(rect.filter());
+    }
+    // Anamorphic sources (DVD rips, DivX AVIs) are widened to square pixels,
+    // since not every player honours the ratio stored in the stream; crops
+    // are measured in stored pixels, so this comes after
+    if !is_h264 && info.non_square_sar().is_some() {
+        video_filters.push("scale=trunc(iw*sar/2)*2:ih,setsar=1".to_string());
+    }
+    if let Some(ratio) = &options.aspect {
+        let ratio = parse_ratio(ratio)?;
+        let pad_color = options.pad_color.as_deref().unwrap_or("black");
+        // Filters see the picture upright, so a portrait phone clip is
+        // measured as portrait
+        let (width, height) = match crop {
+            Some(rect) => info.square_pixel_size(rect.to_even().w, rect.to_even().h),
+            None => info.display_size(),
+        };
+        let (filter, _) = aspect_filter(ratio, options.aspect_fit, pad_color, width, height);
+        video_filters.push(filter);
+    }
+    if let Some(height) = options.max_height {
+        // Never scales up; the comma is escaped inside the filter chain
+        video_filters.push(format!("scale=-2:min(ih\\,{})", height - height % 2));
+    }
+    if options.sharpen {
+        video_filters.push(SHARPEN_FILTER.to_string());
+    }
+    // Dropped frames keep their neighbours' timestamps, so the duration
+    // and time-based progress stay the same
+    if options.dedup_frames {
+        video_filters.push("mpdecimate".to_string());
+    }
+    // Stickers come in any size, and 4:2:0 needs both sides even
+    if info.is_animated_image {
+        video_filters.push("scale=trunc(iw/2)*2:trunc(ih/2)*2".to_string());
+    }
+
+    // HLS carries subtitles as separate WebVTT playlists, which this
+    // single-playlist output doesn't write
+    let muxes_subtitle = subtitle.is_some() && !burn_subtitle && !is_hls;
+    if subtitle.is_some() && !burn_subtitle && is_hls {
+        warnings.push(
+            "HLS output can't carry soft subtitles, so the subtitle file wasn't added; burn it \
+             in to keep it"
+                .to_string(),
+        );
+    }
+    let mut subtitle_input = None;
+    if let Some(sub) = &subtitle {
+        if burn_subtitle {
+            video_filters.push(format!("subtitles=filename={}", escape_filter_path(&sub.path)));
+        } else if muxes_subtitle {
+            subtitle_input = Some(cmd.input(NO_OPTIONS, ffmpeg_path_arg(&sub.path)));
+        }
+    }
+    if let Some(index) = selection.forced_subtitle.filter(|_| burns_forced_subtitle) {
+        let burn = format!(
+            "subtitles=filename={}:si={}",
+            escape_filter_path(Path::new(&info.path)),
+            index
+        );
+        // The filter reads the source from its start, so the frames of a
+        // cut are shifted back to source time for it
+        video_filters.push(match trim {
+            Some(range) => {
+                format!("setpts=PTS+{:.3}/TB,{},setpts=PTS-STARTPTS", range.start_seconds, burn)
+            }
+            None => burn,
+        });
+    }
+    let chapter_input = chapter_file
+        .as_ref()
+        .map(|file| cmd.input(NO_OPTIONS, ffmpeg_path_arg(&file.path)));
+    let audio_input = match delay_input {
+        Some(ms) => cmd.input(audio_delay_input_args(ms), input_path_arg.as_str()),
+        None => 0,
+    };
+    let external_input = external_audio
+        .as_ref()
+        .map(|(_, path, _)| cmd.input(NO_OPTIONS, ffmpeg_path_arg(path)));
+    let cover_input =
+        cover.as_ref().map(|cover| cmd.input(NO_OPTIONS, ffmpeg_path_arg(&cover.path)));
+
+    // Map explicitly, after every input: the codec decisions were made for
+    // these streams, and ffmpeg's own pick can be a different audio track
+    cmd.map(format!("0:v:{}", info.video_stream_index));
+    if let Some(input) = cover_input {
+        cmd.map(format!("{}:v:0", input));
+    }
+    // MKV keeps every soundtrack; a chosen program or an added track limits
+    // it to the one the audio settings are about
+    if is_mkv && external_input.is_none() && info.program_id.is_none() {
+        cmd.map(format!("{}:a?", audio_input));
+    } else if !replaces_audio {
+        cmd.map(format!("{}:a:{}?", audio_input, info.audio_stream_index));
+        for index in &selection.kept_audio_streams {
+            cmd.map(format!("{}:a:{}", audio_input, index));
+        }
+    }
+    // Players pick the default track, so only the chosen one is
+    if !selection.kept_audio_streams.is_empty() {
+        cmd.push(Section::Metadata, ["-disposition:a:0", "default"]);
+        for output_index in 1..=selection.kept_audio_streams.len() {
+            cmd.push(Section::Metadata, [format!("-disposition:a:{}", output_index), "0".to_string()]);
+        }
+    }
+    if let Some(input) = external_input {
+        cmd.map(format!("{}:a:0", input));
+    }
+    if let Some(input) = subtitle_input {
+        cmd.map(format!("{}:0", input));
+    }
+    // ...and its subtitles and attachments, untouched
+    if is_mkv {
+        cmd.map("0:s?").map("0:t?").push(Section::Mapping, ["-c:t", "copy"]);
+    }
+    // Data streams are left out by the explicit maps above unless asked for
+    let keeps_data = options.keep_data_streams && info.streams.data > 0 && !is_hls;
+    if keeps_data {
+        cmd.map("0:d?").push(Section::Mapping, ["-c:d", "copy", "-copy_unknown"]);
+    } else if info.streams.data > 0 {
+        warnings.push(format!(
+            "Dropped {} data stream(s) ({}) that MP4 files usually can't carry",
+            info.streams.data,
+            info.streams.data_codecs.join(", ")
+        ));
+    }
+    let chapters_from = chapter_input.unwrap_or(0);
+    cmd.push(Section::Mapping, ["-map_chapters".to_string(), chapters_from.to_string()]);
+
+    // The shift is on the source timeline, so it comes before any tempo change
+    let mut audio_filters: Vec<String> = Vec::new();
+    if let Some(ms) = audio_delay.filter(|_| delay_input.is_none()) {
+        audio_filters.push(audio_delay_filter(ms));
+    }
+    if let Some(filter) = balance_filter {
+        audio_filters.push(filter.to_string());
+    }
+    if changes_speed {
+        video_filters.push(format!("setpts=PTS/{}", speed));
+        audio_filters.extend(atempo_chain(speed));
+    }
+    if let Some(db) = options.volume_db.filter(|_| options.changes_volume()) {
+        let clamped = db.clamp(*VOLUME_DB_RANGE.start(), *VOLUME_DB_RANGE.end());
+        if clamped != db {
+            warnings.push(format!("Volume {} dB is out of range, {} dB was used", db, clamped));
+        }
+        audio_filters.push(format!("volume={}dB", clamped));
+    }
+    if let (Some(min), ExtendMode::Freeze) = (extension, options.extend_mode) {
+        video_filters.push(format!(
+            "tpad=stop_mode=clone:stop_duration={:.3}",
+            min - natural_duration
+        ));
+        audio_filters.push("apad".to_string());
+    }
+    if let Some(seconds) = video_padding {
+        video_filters.push(format!("tpad=stop_mode=clone:stop_duration={:.3}", seconds));
+    }
+    if let Some(seconds) = audio_padding {
+        audio_filters.push(format!("apad=pad_dur={:.3}", seconds));
+    }
+
+    if let Some(fps) = options.interpolate_fps {
+        video_filters.push(interpolation_filter(fps, options.interpolation_quality));
+    }
+
+    // After every geometry and timing filter, so the fades cover the final
+    // picture and end on the output's last frame, padding included
+    let half = duration / 2.0;
+    let mut fade_length = |seconds: Option<f64>, which: &str| {
+        let seconds = seconds.filter(|s| *s > 0.0)?;
+        if seconds > half {
+            warnings.push(format!(
+                "The {} fade of {:.2}s is longer than half the video, so {:.2}s was used",
+                which, seconds, half
+            ));
+        }
+        Some(seconds.min(half))
+    };
+    if let Some(seconds) = fade_length(options.fade_in_seconds, "opening") {
+        video_filters.push(format!("fade=t=in:st=0:d={:.3}", seconds));
+        audio_filters.push(format!("afade=t=in:st=0:d={:.3}", seconds));
+    }
+    if let Some(seconds) = fade_length(options.fade_out_seconds, "closing") {
+        let start = duration - seconds;
+        video_filters.push(format!("fade=t=out:st={:.3}:d={:.3}", start, seconds));
+        audio_filters.push(format!("afade=t=out:st={:.3}:d={:.3}", start, seconds));
+    }
+    // soxr resamples more cleanly than ffmpeg's own swr; `-ar` still sets
+    // the rate where the build lacks it
+    if let Some(rate) = conform.sample_rate {
+        if resolver.ffmpeg_has_library("soxr").await {
+            audio_filters.push(format!("aresample={}:resampler=soxr", rate));
+        }
+    }
+
+    // Dither when dropping to 8 bits so gradients don't band
+    if !is_h264 && info.bit_depth() > 8 {
+        video_filters.push("scale=sws_dither=ed".to_string());
+        video_filters.push("format=yuv420p".to_string());
+    }
+    let color_args = info.color_args();
+    let metadata_args = options.metadata_args(&info);
+    if !video_filters.is_empty() {
+        log.line(&format!("Video filters: {}", video_filters.join(",")));
+    }
+    if !audio_filters.is_empty() {
+        log.line(&format!("Audio filters: {}", audio_filters.join(",")));
+    }
+
+    // Burned subtitles and fades rely on whole-video timestamps, which
+    // segments reset, so only plain video re-encodes are split up. Segments are decoded without
+    // the alpha decoder, so transparent sources aren't either.
+    let chunkable = subtitle.is_none()
+        && !burns_forced_subtitle
+        && selection.kept_audio_streams.is_empty()
+        && external_audio.is_none()
+        && extension.is_none()
+        && !conforms_streams
+        && !info.has_alpha
+        && !info.is_animated_image
+        && options.segment.is_none()
+        && !is_hls
+        && !options.dedup_frames
+        && !options.fades()
+        && trim.is_none()
+        && cover.is_none()
+        && !keeps_data
+        && !is_mkv;
+    if options.chunked_encode && !is_h264 && chunkable {
+        let (_, video_action) =
+            video_encoder_args(&thread_count, rate_limit, crf, !options.hw_encode_only);
+        // The join reads audio from the source directly, so the shift is
+        // always a filter here
+        let mut audio_filters = audio_filters.clone();
+        if let Some(ms) = delay_input {
+            audio_filters.insert(0, audio_delay_filter(ms));
+        }
+        let (mut audio_args, audio_action) = audio_codec_args(is_aac && delay_input.is_none(), conform);
+        if !audio_filters.is_empty() {
+            audio_args.push("-af".to_string());
+            audio_args.push(audio_filters.join(","));
+        }
+        if audio_delay.is_some() {
+            audio_args.push("-t".to_string());
+            audio_args.push(format!("{:.3}", duration));
+        }
+        audio_args.extend(options.extra_audio_args.iter().cloned());
+        let ffprobe_path = resolver.ffprobe().await?;
+        let job = ChunkedJob {
+            runner: resolver.runner(),
+            processes: resolver.processes(),
+            ffmpeg_path: &ffmpeg_path,
+            ffprobe_path: &ffprobe_path,
+            info: &info,
+            output_path: &output_path,
+            video_filters: &video_filters,
+            extra_video_args: &options.extra_video_args,
+            rate_limit,
+            crf,
+            allow_sw: !options.hw_encode_only,
+            color_args: &color_args,
+            audio_args: &audio_args,
+            extra_output_args: &options.extra_output_args,
+            metadata_args: &metadata_args,
+            chapter_file: chapter_file.as_ref().map(|file| file.path.as_path()),
+            duration,
+            task_id,
+            work_dir: work_dir.path(),
+            log,
+            cancel,
+        };
+
+        match encode_chunked(&job, Arc::clone(&callback)).await {
+            Ok(ChunkOutcome::Done) => {
+                let checked = check_output(resolver, &output_path, &expected, options, log, cancel);
+                if let Err(e) = checked.await {
+                    callback(failure_progress(task_id, &e));
+                    return Err(e);
+                }
+                callback(ConversionProgress {
+                    output_path: Some(output_path_str.clone()),
+                    video_action: Some(video_action.clone()),
+                    audio_action: Some(audio_action.clone()),
+                    warnings: warnings.clone(),
+                    ..ConversionProgress::update(task_id, 100.0, ConversionStatus::Completed)
+                });
+                let (output_bitrate, output_size, _) =
+                    measure_output(resolver, &output_path_str).await;
+                let output_bytes = file_size(&output_path_str);
+                return Ok(ConversionResult {
+                    output_path: output_path_str,
+                    video_action,
+                    audio_action,
+                    duration,
+                    output_bitrate,
+                    warnings,
+                    output_size,
+                    segment_paths: Vec::new(),
+                    rendition_paths: Vec::new(),
+                    hls_segment_count: 0,
+                    hw_decoder: None,
+                    trimmed: trim,
+                    input_bytes,
+                    output_bytes,
+                    streams: selection,
+                    decode_error_count: 0,
+                    status: ConversionStatus::Completed,
+                    input_sha256: None,
+                    output_sha256: None,
+                    output_dir: None,
+                    retry_of: None,
+                });
+            }
+            Ok(ChunkOutcome::Unsupported(reason)) => {
+                log.line(&format!("Chunked encode not possible ({}), using one process", reason));
+            }
+            Err(ConvertError::Cancelled) => {
+                callback(ConversionProgress::update(task_id, 0.0, ConversionStatus::Cancelled));
+                return Err(ConvertError::Cancelled);
+            }
+            Err(e) => {
+                let e = unavailable_or(e, &info.path).await;
+                callback(failure_progress(task_id, &e));
+                return Err(e);
+            }
+        }
+    }
+
+    // Smart encoding: copy if already correct codec, otherwise re-encode.
+    // The chosen action is recorded here so the result reports what actually ran.
+    // With a cover the output has two video streams, so the picture's
+    // settings are pinned to the first one and the cover gets its own
+    let main_video = |args: Vec<String>| match cover {
+        Some(_) => main_video_only(&args),
+        None => args,
+    };
+    // A hardware encoder with every session taken would fail to open one:
+    // wait for another conversion to finish with it, or encode in software
+    let mut busy_fallback = false;
+    let _encoder_slot = if is_h264 {
+        None
+    } else {
+        let slots = resolver.encoder_slots();
+        match slots.try_acquire(VIDEO_ENCODER) {
+            Some(slot) => Some(slot),
+            None if options.software_when_encoder_busy
+                && (VIDEO_ENCODER == "libx264" || resolver.ffmpeg_has_library("x264").await) =>
+            {
+                busy_fallback = true;
+                warnings.push(format!(
+                    "Every {} session was in use, so this file was encoded with libx264",
+                    VIDEO_ENCODER
+                ));
+                None
+            }
+            None => {
+                log.line(&format!("Waiting for a free {} session", VIDEO_ENCODER));
+                callback(ConversionProgress::update(
+                    task_id,
+                    0.0,
+                    ConversionStatus::WaitingForEncoder,
+                ));
+                match slots.acquire(VIDEO_ENCODER, cancel).await {
+                    Ok(slot) => Some(slot),
+                    Err(e) => {
+                        callback(ConversionProgress::update(task_id, 0.0, ConversionStatus::Cancelled));
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    };
+    let video_action = if is_h264 {
+        // Video is already H.264, just copy
+        cmd.push(Section::Video, main_video(vec!["-c:v".to_string(), "copy".to_string()]));
+        StreamAction::Copied
+    } else {
+        let (mut args, action) = if busy_fallback {
+            software_encoder_args(&thread_count, rate_limit, crf)
+        } else {
+            video_encoder_args(&thread_count, rate_limit, crf, !options.hw_encode_only)
+        };
+        args.extend(color_args.iter().cloned());
+        cmd.push(Section::Video, main_video(args));
+        action
+    };
+    if !video_filters.is_empty() {
+        cmd.push(Section::Filters, main_video(vec!["-vf".to_string(), video_filters.join(",")]));
+    }
+    if options.dedup_frames {
+        // `-vsync` rather than `-fps_mode`, which needs ffmpeg 5.1
+        cmd.push(Section::Video, ["-vsync", "vfr"]);
+    }
+
+    // Pixel format for compatibility
+    // A copied stream ignores the pixel format; the audio fix leaves it out
+    let pix_fmt = match fixes_audio_only {
+        true => Vec::new(),
+        false => vec!["-pix_fmt".to_string(), "yuv420p".to_string()],
+    };
+    cmd.push(Section::Video, main_video(pix_fmt));
+    if cover.is_some() {
+        cmd.push(
+            Section::Video,
+            ["-c:v:1", "mjpeg", "-q:v:1", "2", "-disposition:v:1", "attached_pic"],
+        );
+    }
+    cmd.push(Section::Video, &options.extra_video_args);
+    match options.segment {
+        // Only H.264 video and AAC audio are ever copied, so the segments
+        // always hold codecs HLS players take
+        None if is_hls => {
+            let seconds = options.hls_segment_seconds.unwrap_or(DEFAULT_HLS_SECONDS);
+            let hls_dir = output_path.parent().unwrap_or(&output_dir);
+            cmd.push(Section::OutputFlags, hls_args(hls_dir, seconds, options.hls_segment_type, !is_h264));
+        }
+        Some(spec) => {
+            // Each segment gets its own faststart through the segment muxer
+            // The video stream plus the audio, rather than the container
+            // rate, which counts tracks the output may not keep; copied
+            // audio can run above the AAC it is otherwise encoded to
+            let audio = info.audio_bitrate.max(AAC_BITRATE);
+            let bitrate = match rate_limit.filter(|_| !is_h264) {
+                Some(limit) => limit.max_kbps as u64 * 1000 + AAC_BITRATE,
+                None if info.video_bitrate > 0 => info.video_bitrate + audio,
+                None => info.bitrate,
+            };
+            cmd.push(Section::OutputFlags, segment_args(spec.segment_seconds(bitrate), !is_h264));
+        }
+        None if is_mkv => {}
+        None => {
+            cmd.push(Section::OutputFlags, ["-movflags", "+faststart"]); // Enable fast start for web/mobile
+        }
+    }
+
+    // Smart audio encoding: copy if already AAC, otherwise re-encode
+    let audio_action = match &external_audio {
+        // Animated images have no sound to map or encode
+        None if info.is_animated_image => {
+            cmd.push(Section::Audio, ["-an"]);
+            StreamAction::Copied
+        }
+        None => {
+            let (args, audio_action) = audio_codec_args(is_aac, conform);
+            cmd.push(Section::Audio, args);
+            if !audio_filters.is_empty() {
+                cmd.push(Section::Filters, ["-af".to_string(), audio_filters.join(",")]);
+            }
+            audio_action
+        }
+        Some((external, _, probe)) => {
+            // Settings per output track, since the two tracks differ. An
+            // added track gets the tempo and volume changes but not the
+            // delay or channel fix, which are about the camera's own track.
+            let shifted = audio_delay.is_some() && delay_input.is_none();
+            let own_only = shifted as usize + balance_filter.is_some() as usize;
+            let mut external_filters = if replaces_audio {
+                audio_filters.clone()
+            } else {
+                audio_filters[own_only..].to_vec()
+            };
+            external_filters.extend(probe.fit_filters(info.duration));
+            let copies_external =
+                probe.codec == "aac" && external_filters.is_empty() && !conforms_audio;
+
+            let mut tracks = Vec::new();
+            if !replaces_audio {
+                tracks.push((is_aac, audio_filters.clone()));
+            }
+            tracks.push((copies_external, external_filters));
+            let mut actions = Vec::new();
+            for (index, (copy, filters)) in tracks.into_iter().enumerate() {
+                let (args, action) = audio_codec_args(copy, conform);
+                cmd.push(Section::Audio, args.iter().map(|arg| match arg.as_str() {
+                    "-c:a" | "-b:a" => format!("{}:{}", arg, index),
+                    "-ar" | "-ac" => format!("{}:a:{}", arg, index),
+                    _ => arg.clone(),
+                }));
+                if !filters.is_empty() {
+                    cmd.push(Section::Filters, [format!("-filter:a:{}", index), filters.join(",")]);
+                }
+                actions.push(action);
+            }
+            if !replaces_audio {
+                if let Some(language) = &external.language {
+                    cmd.push(
+                        Section::Metadata,
+                        ["-metadata:s:a:1".to_string(), format!("language={}", language)],
+                    );
+                }
+                if let Some(title) = &external.title {
+                    cmd.push(
+                        Section::Metadata,
+                        ["-metadata:s:a:1".to_string(), format!("title={}", title)],
+                    );
+                }
+            }
+            // The separate file is cut at the end of the video. `-shortest`
+            // would also count the one-frame cover, so `-t` does it then.
+            if cover.is_none() {
+                cmd.push(Section::OutputFlags, ["-shortest"]);
+            }
+            actions.swap_remove(0)
+        }
+    };
+    cmd.push(Section::Audio, &options.extra_audio_args);
+
+    if is_mkv {
+        cmd.push(Section::Subtitles, ["-c:s", "copy"]);
+    }
+    if let Some(sub) = subtitle.as_ref().filter(|_| muxes_subtitle) {
+        if !is_mkv {
+            cmd.push(Section::Subtitles, ["-c:s", "mov_text"]);
+        }
+        if let Some(language) = &sub.language {
+            cmd.push(
+                Section::Metadata,
+                ["-metadata:s:s:0".to_string(), format!("language={}", language)],
+            );
+        }
+    }
+
+    // A delayed track runs past the video; keep the source's length. An
+    // extended clip is cut to exactly the minimum, and a separate audio
+    // file or the longer source stream to the video when a cover rules out
+    // `-shortest`.
+    let cut_despite_cover = cover.is_some() && (external_audio.is_some() || cuts_to_shortest);
+    if audio_delay.is_some() || extension.is_some() || cut_despite_cover {
+        cmd.push(Section::OutputFlags, ["-t".to_string(), format!("{:.3}", duration)]);
+    } else if cuts_to_shortest {
+        cmd.push(Section::OutputFlags, ["-shortest"]);
+    }
+
+    cmd.push(Section::Metadata, &metadata_args)
+        .push(Section::OutputFlags, ["-progress", "pipe:1"])
+        .push(Section::OutputFlags, &options.extra_output_args)
+        .output(output_path_arg.as_str());
+
+    // Windows has no SIGINT to send a single process, so a partial stop
+    // types `q` instead. Segmented and HLS outputs are never kept partial,
+    // so ffmpeg only reads keys when one could be.
+    let can_keep_partial = options.segment.is_none() && !is_hls;
+    let stops_with_key = cfg!(windows) && can_keep_partial;
+    if stops_with_key {
+        cmd.accept_keys();
+    }
+    let args = cmd.build();
+    log.command(&ffmpeg_path, &args);
+
+    let mut child = Command::new(&ffmpeg_path);
+    child.current_dir(work_dir.path()).args(&args);
+    child.stdin(if stops_with_key { Stdio::piped() } else { Stdio::null() });
+    let mut child = match resolver.runner().spawn(&mut child) {
+        Ok(child) => child,
+        Err(e) => {
+            // The verified binary has gone away; search again next time
+            resolver.invalidate().await;
+            return Err(format!("Failed to start ffmpeg: {}", e).into());
+        }
+    };
+    let _registered = child.id().map(|pid| resolver.processes().register(task_id, pid));
+
+    let stdout = child.take_stdout().ok_or("Failed to capture stdout")?;
+    let mut reader = BufReader::new(stdout).lines();
+    // Verbose filters can fill the stderr pipe and stall ffmpeg, so it is
+    // drained alongside the progress output
+    let verbose_log = options.verbose_log.then(|| log.clone());
+    let stderr = tokio::spawn(read_stderr(child.take_stderr(), STDERR_TAIL_LINES, verbose_log));
+
+    let discard_output = || async {
+        let _ = tokio::fs::remove_file(&output_path).await;
+        if options.segment.is_some() {
+            remove_segments(&output_path);
+        }
+        if is_hls {
+            remove_hls_output(&output_path);
+        }
+    };
+
+    // Process progress output
+    let mut speed: Option<f64> = None;
+    let mut bytes_written: Option<u64> = None;
+    // Output time of the last progress block, to say where a failure hit
+    let mut last_out_time = 0.0;
+    let watches_speed = matches!(&video_action, StreamAction::Encoded(_));
+    let mut slow_warning: Option<String> = None;
+    loop {
+        let line = tokio::select! {
+            line = reader.next_line() => line,
+            _ = cancel.cancelled() => {
+                let keep_partial = can_keep_partial
+                    && resolver.take_cancel_mode(task_id) == CancelMode::KeepPartial;
+                if keep_partial && stop_for_partial(child.as_mut(), &mut reader, log).await {
+                    let output_bytes = file_size(&output_path_str);
+                    if output_bytes > 0 && last_out_time > 0.0 {
+                        let report = stderr.await.unwrap_or_default();
+                        let (output_bitrate, output_size, output_duration) =
+                            measure_output(resolver, &output_path_str).await;
+                        let kept = output_duration.unwrap_or(last_out_time);
+                        log.line(&format!("Cancelled; kept the first {:.1}s of output", kept));
+                        warnings.push(format!(
+                            "Cancelled partway; the output holds the first {:.1}s of {:.1}s",
+                            kept, duration
+                        ));
+                        callback(ConversionProgress {
+                            output_path: Some(output_path_str.clone()),
+                            video_action: Some(video_action.clone()),
+                            audio_action: Some(audio_action.clone()),
+                            warnings: warnings.clone(),
+                            ..ConversionProgress::update(
+                                task_id,
+                                last_out_time / duration.max(last_out_time) * 100.0,
+                                ConversionStatus::CancelledPartial,
+                            )
+                        });
+                        return Ok(ConversionResult {
+                            output_path: output_path_str,
+                            video_action,
+                            audio_action,
+                            duration: kept,
+                            output_bitrate,
+                            warnings,
+                            output_size,
+                            segment_paths: Vec::new(),
+                            rendition_paths: Vec::new(),
+                            hls_segment_count: 0,
+                            hw_decoder: hw_decoder.map(str::to_string),
+                            trimmed: trim,
+                            input_bytes,
+                            output_bytes,
+                            streams: selection,
+                            decode_error_count: report.decode_errors,
+                            status: ConversionStatus::CancelledPartial,
+                            input_sha256: None,
+                            output_sha256: None,
+                            output_dir: None,
+                            retry_of: None,
+                        });
+                    }
+                    log.line("Nothing was encoded before the cancel; the output was removed");
+                }
+                let _ = child.kill().await;
+                discard_output().await;
+                callback(ConversionProgress {
+                    task_id: task_id.to_string(),
+                    progress: 0.0,
+                    status: ConversionStatus::Cancelled,
+                    output_path: None,
+                    error: None,
+                    video_action: None,
+                    audio_action: None,
+                    indeterminate: false,
+                    speed: None,
+                    eta_seconds: None,
+                    warnings: Vec::new(),
+                    bytes_written: None,
+                    software_fallback: false,
+                    queue_position: None,
+                    estimated_start_seconds: None,
+                });
+                return Err(ConvertError::Cancelled);
+            }
+        };
+        let Ok(Some(line)) = line else {
+            break;
+        };
+        if line.starts_with("out_time=") {
+            let time_str = line.trim_start_matches("out_time=");
+            let time_seconds = parse_time_to_seconds(time_str);
+            last_out_time = time_seconds.max(last_out_time);
+            let percent = if duration > 0.0 {
+                (time_seconds / duration * 100.0).min(100.0)
+            } else {
+                0.0
+            };
+            // ffmpeg prints `speed=` after `out_time=`, so this is the
+            // previous block's speed
+            let eta_seconds = speed
+                .filter(|speed| *speed > 0.0 && duration > 0.0)
+                .map(|speed| (duration - time_seconds).max(0.0) / speed);
+            if options.abort_if_larger_than_input
+                && input_bytes > 0
+                && bytes_written.is_some_and(|written| written > input_bytes)
+            {
+                let _ = child.kill().await;
+                discard_output().await;
+                let error = ConvertError::OutputTooLarge(format!(
+                    "{} MB written at {:.0}%, more than the {} MB source",
+                    bytes_written.unwrap_or_default().div_ceil(MB),
+                    percent,
+                    input_bytes.div_ceil(MB)
+                ));
+                log.line(&error.to_string());
+                callback(failure_progress(task_id, &error));
+                return Err(error);
+            }
+            let size_warning = projected_size(bytes_written, percent)
+                .filter(|projected| input_bytes > 0 && *projected > input_bytes)
+                .map(|projected| {
+                    format!(
+                        "The output is on track to be about {} MB, larger than the {} MB source",
+                        projected.div_ceil(MB),
+                        input_bytes.div_ceil(MB)
+                    )
+                });
+            if slow_warning.is_none() && watches_speed && percent >= SPEED_CHECK_MIN_PERCENT {
+                slow_warning = slow_encode_warning(&video_action, speed, options.expected_encode_speed);
+                if let Some(warning) = &slow_warning {
+                    log.line(&format!("Warning: {}", warning));
+                }
+            }
+            callback_clone(ConversionProgress {
+                task_id: task_id_owned.clone(),
+                progress: percent,
+                status: if fixes_audio_only {
+                    ConversionStatus::FixingAudio
+                } else {
+                    ConversionStatus::Converting
+                },
+                output_path: None,
+                error: None,
+                video_action: None,
+                audio_action: None,
+                indeterminate: false,
+                speed,
+                eta_seconds,
+                warnings: size_warning.into_iter().chain(slow_warning.clone()).collect(),
+                bytes_written,
+                software_fallback: slow_warning.is_some(),
+                queue_position: None,
+                estimated_start_seconds: None,
+            });
+        } else if let Some(value) = line.strip_prefix("total_size=") {
+            // `N/A` until the muxer has written something
+            bytes_written = value.trim().parse().ok().or(bytes_written);
+        } else if let Some(value) = line.strip_prefix("speed=") {
+            speed = value.trim().trim_end_matches('x').parse().ok().or(speed);
+        } else if line == "progress=end" {
+            // All frames are encoded; +faststart still has to rewrite the
+            // file, which can take a while on large outputs
+            callback_clone(ConversionProgress {
+                indeterminate: true,
+                ..ConversionProgress::update(&task_id_owned, 100.0, ConversionStatus::Finalizing)
+            });
+        }
+    }
+
+    let StderrReport {
+        tail: stderr_tail,
+        warnings: ffmpeg_warnings,
+        software_encoder,
+        decode_errors: decode_error_count,
+    } = stderr.await.unwrap_or_default();
+    // Damaged sources decode to a "successful" output with smeared stretches
+    let completed_status =
+        if decode_error_count > options.max_decode_errors.unwrap_or(DEFAULT_MAX_DECODE_ERRORS) {
+            ConversionStatus::CompletedWithErrors
+        } else {
+            ConversionStatus::Completed
+        };
+    if software_encoder && slow_warning.is_none() {
+        slow_warning = Some(SOFTWARE_ENCODER_WARNING.to_string());
+    }
+    let status = child.wait().await.map_err(|e| format!("FFmpeg process error: {}", e))?;
+
+    let mut segment_paths = Vec::new();
+    let mut output_path_str = output_path_str;
+    if let Some(spec) = options.segment.filter(|_| status.success()) {
+        let mut segments = existing_segments(&output_path);
+        if let Some(max_bytes) = spec.max_bytes() {
+            match enforce_size_limit(resolver, segments, max_bytes, log, cancel).await {
+                Ok((checked, split_warnings)) => {
+                    segments = checked;
+                    warnings.extend(split_warnings);
+                }
+                Err(e) => {
+                    remove_segments(&output_path);
+                    let status = match e {
+                        ConvertError::Cancelled => ConversionStatus::Cancelled,
+                        _ => ConversionStatus::Error,
+                    };
+                    callback(ConversionProgress {
+                        error: (status == ConversionStatus::Error).then(|| e.to_string()),
+                        ..ConversionProgress::update(task_id, 0.0, status)
+                    });
+                    return Err(e);
+                }
+            }
+        }
+        segment_paths = segments.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        if let Some(first) = segment_paths.first() {
+            output_path_str = first.clone();
+        }
+    } else if options.segment.is_some() {
+        remove_segments(&output_path);
+    }
+    let hls_segment_count = if is_hls { count_playlist_segments(&output_path) } else { 0 };
+
+    if status.success() && Path::new(&output_path_str).exists() {
+        for warning in &ffmpeg_warnings {
+            log.line(&format!("Warning: {}", warning));
+        }
+        warnings.extend(ffmpeg_warnings);
+        warnings.extend(slow_warning.clone());
+        if completed_status == ConversionStatus::CompletedWithErrors {
+            log.line(&format!("{} decoding errors, flagged for review", decode_error_count));
+        }
+        // Segments and HLS parts each hold a slice, so only single files
+        // are compared with the plan
+        if options.segment.is_none() && !is_hls {
+            let checked = check_output(resolver, &output_path, &expected, options, log, cancel);
+            if let Err(e) = checked.await {
+                callback(failure_progress(task_id, &e));
+                return Err(e);
+            }
+        }
+        if options.replace_original && completed_status == ConversionStatus::CompletedWithErrors {
+            warnings.push(
+                "The original was kept because of the decoding errors; review the fixed file \
+                 before replacing it"
+                    .to_string(),
+            );
+        } else if options.replace_original {
+            match replace_file(&output_path, &original_path) {
+                Ok(()) => {
+                    log.line(&format!("Replaced {} with the fixed file", original_path.display()));
+                    output_path_str = original_path.to_string_lossy().to_string();
+                }
+                Err(e) => warnings.push(format!("{}; the fixed file was kept instead", e)),
+            }
+        }
+        callback(ConversionProgress {
+            task_id: task_id.to_string(),
+            progress: 100.0,
+            status: completed_status,
+            output_path: Some(output_path_str.clone()),
+            error: None,
+            video_action: Some(video_action.clone()),
+            audio_action: Some(audio_action.clone()),
+            indeterminate: false,
+            speed: None,
+            eta_seconds: None,
+            warnings: warnings.clone(),
+            bytes_written: None,
+            software_fallback: slow_warning.is_some(),
+            queue_position: None,
+            estimated_start_seconds: None,
+        });
+        let (output_bitrate, output_size, output_duration) =
+            measure_output(resolver, &output_path_str).await;
+        if extension.is_some() && output_duration.is_some_and(|d| d + DURATION_TOLERANCE < duration) {
+            warnings.push(format!(
+                "The output is {:.2}s long, short of the {:.2}s minimum",
+                output_duration.unwrap_or_default(),
+                duration
+            ));
+        }
+        let output_bytes = if is_hls {
+            hls_dir_bytes(output_path.parent().unwrap_or(&output_dir))
+        } else if segment_paths.is_empty() {
+            file_size(&output_path_str)
+        } else {
+            segment_paths.iter().map(|path| file_size(path)).sum()
+        };
+        Ok(ConversionResult {
+            output_path: output_path_str,
+            video_action,
+            audio_action,
+            duration,
+            output_bitrate,
+            warnings,
+            output_size,
+            segment_paths,
+            rendition_paths: Vec::new(),
+            hls_segment_count,
+            hw_decoder: hw_decoder.map(str::to_string),
+            trimmed: trim,
+            input_bytes,
+            output_bytes,
+            streams: selection,
+            decode_error_count,
+            status: completed_status,
+            input_sha256: None,
+            output_sha256: None,
+            output_dir: None,
+            retry_of: None,
+        })
+    } else {
+        let error_msg = if !status.success() {
+            for line in &stderr_tail {
+                log.line(line);
+            }
+            match stderr_tail.last() {
+                Some(reason) => format!("FFmpeg exited with status: {}: {}", status, reason),
+                None => format!("FFmpeg exited with status: {}", status),
+            }
+        } else {
+            "Output file not created".to_string()
+        };
+        if is_hls {
+            remove_hls_output(&output_path);
+        }
+        if hw_decoder.is_some() && hw_decode_failed(&stderr_tail) {
+            // Cleared so the retry gets the same output name
+            let _ = std::fs::remove_file(&output_path);
+            if options.segment.is_some() {
+                remove_segments(&output_path);
+            }
+            let reason = stderr_tail.last().map(String::as_str).unwrap_or_default();
+            return Err(format!("{}: {}", HW_DECODE_FAILED, reason).into());
+        }
+        let no_hw_session = options.hw_encode_only
+            && stderr_tail.iter().any(|line| line.contains("cannot create compression session"));
+        let e = if denied_in_output(&stderr_tail) {
+            denied_error(Path::new(input_path), staged.is_some())
+        } else if no_hw_session {
+            "No hardware encoder session was available and software encoding is turned off. \
+             Turn off hardware-only encoding or try again when other encodes have finished."
+                .into()
+        } else {
+            unavailable_or(error_msg.into(), &info.path).await
+        };
+        // A failure partway through comes with a slice of the source around
+        // it, in source time
+        let e = match e {
+            ConvertError::Failed(message) if !status.success() && last_out_time > 0.0 => {
+                let failed_at = trim.map_or(0.0, |range| range.start_seconds)
+                    + last_out_time * options.speed.unwrap_or(1.0);
+                let sample = sample_range(failed_at, info.duration);
+                log.line(&format!(
+                    "Failed {:.1}s into the source; sample {:.1}s to {:.1}s to reproduce",
+                    failed_at, sample.start_seconds, sample.end_seconds
+                ));
+                ConvertError::EncodeFailed { message, sample }
+            }
+            e => e,
+        };
+        callback(failure_progress(task_id, &e));
+        Err(e)
+    }
+}
+
+/// Verify a finished output. A cancelled check removes it like any other
+/// cancelled run; a failed one leaves it for inspection.
+async fn check_output(
+    resolver: &FfmpegResolver,
+    output_path: &Path,
+    expected: &ExpectedOutput,
+    options: &ConversionOptions,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+) -> Result<(), ConvertError> {
+    let result = verify_output(resolver, output_path, expected, options.verify_output, cancel).await;
+    match &result {
+        Err(ConvertError::Cancelled) => {
+            let _ = std::fs::remove_file(output_path);
+        }
+        Err(e) => log.line(&format!("{}; the output was kept at {}", e, output_path.display())),
+        Ok(()) => {}
+    }
+    result
+}
+
+/// Move `from` over `to`. Across volumes the file is first copied next to
+/// `to`, so the original is only ever swapped for a complete file.
+pub(crate) fn replace_file(from: &Path, to: &Path) -> Result<(), String> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let mut partial = to.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let copied = std::fs::copy(from, &partial).and_then(|_| std::fs::rename(&partial, to));
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(&partial);
+        return Err(format!("Failed to replace {}: {}", to.display(), e));
+    }
+    let _ = std::fs::remove_file(from);
+    Ok(())
+}
+
+/// Report a failure as `InputUnavailable` when the input has gone missing,
+/// since ffmpeg's own error then only says that reading failed
+async fn unavailable_or(error: ConvertError, input_path: &str) -> ConvertError {
+    if input_unavailable(Path::new(input_path)).await {
+        ConvertError::InputUnavailable(input_path.to_string())
+    } else {
+        error
+    }
+}
+
+fn failure_progress(task_id: &str, error: &ConvertError) -> ConversionProgress {
+    let status = match error {
+        ConvertError::InputUnavailable(_) => ConversionStatus::InputUnavailable,
+        ConvertError::OutputTooLarge(_) => ConversionStatus::OutputTooLarge,
+        ConvertError::Cancelled => ConversionStatus::Cancelled,
+        _ => ConversionStatus::Error,
+    };
+    ConversionProgress {
+        error: (status != ConversionStatus::Cancelled).then(|| error.to_string()),
+        ..ConversionProgress::update(task_id, 0.0, status)
+    }
+}
+
+const MB: u64 = 1024 * 1024;
+/// Progress before the output size is extrapolated; the container header
+/// and encoder warm-up skew anything earlier
+const SIZE_PROJECTION_MIN_PERCENT: f64 = 10.0;
+
+/// Final output size if it keeps growing at the rate so far
+fn projected_size(bytes_written: Option<u64>, percent: f64) -> Option<u64> {
+    let written = bytes_written?;
+    (percent >= SIZE_PROJECTION_MIN_PERCENT).then(|| (written as f64 * 100.0 / percent) as u64)
+}
+
+/// Lines of ffmpeg's stderr kept for the log and the error message
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Interrupt ffmpeg so it writes out what it has encoded; whether it exited
+/// within `PARTIAL_STOP_GRACE_SECS`. The progress output is drained
+/// meanwhile, so a full pipe can't hold ffmpeg up.
+async fn stop_for_partial(
+    child: &mut dyn RunningProcess,
+    progress: &mut tokio::io::Lines<BufReader<ProcessPipe>>,
+    log: &TaskLog,
+) -> bool {
+    if let Err(e) = child.interrupt().await {
+        log.line(&format!("Failed to interrupt ffmpeg, discarding the output: {}", e));
+        return false;
+    }
+    let finished = async {
+        while let Ok(Some(_)) = progress.next_line().await {}
+        child.wait().await
+    };
+    let grace = std::time::Duration::from_secs(PARTIAL_STOP_GRACE_SECS);
+    match tokio::time::timeout(grace, finished).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            log.line(&format!("FFmpeg process error: {}", e));
+            false
+        }
+        Err(_) => {
+            log.line(&format!(
+                "FFmpeg did not finish the output within {}s; discarding it",
+                PARTIAL_STOP_GRACE_SECS
+            ));
+            false
+        }
+    }
+}
+
+/// Messages ffmpeg prints for damaged or badly timed input, which it works
+/// around but which usually show up as glitches in the output
+const FFMPEG_WARNING_PATTERNS: &[(&str, &str)] = &[
+    ("non-monotonous dts", "non-monotonous timestamps"),
+    ("non monotonically increasing dts", "non-monotonous timestamps"),
+    ("corrupt decoded frame", "corrupt decoded frames"),
+    ("error while decoding", "decoding errors"),
+    ("concealing", "concealed decoding errors"),
+    ("invalid nal unit", "invalid NAL units"),
+    ("packet corrupt", "corrupt packets"),
+    ("past duration", "timestamp drift"),
+    ("queue input is backward in time", "audio timestamps going backwards"),
+];
+
+/// Lines meaning part of a source frame couldn't be decoded. ffmpeg still
+/// succeeds, but hundreds of them leave seconds of grey smear.
+const DECODE_ERROR_PATTERNS: &[&str] = &[
+    "error while decoding",
+    "concealing",
+    "corrupt decoded frame",
+    "invalid nal unit",
+];
+
+/// Decoding errors a conversion may log before its result is marked
+/// `CompletedWithErrors`, unless the options say otherwise
+pub const DEFAULT_MAX_DECODE_ERRORS: usize = 10;
+
+#[derive(Debug, Default)]
+struct StderrReport {
+    /// The last non-empty lines, for error messages
+    tail: Vec<String>,
+    /// One line per kind of known warning seen, with how often it appeared
+    warnings: Vec<String>,
+    /// VideoToolbox said it set up a software session
+    software_encoder: bool,
+    /// Lines matching `DECODE_ERROR_PATTERNS`
+    decode_errors: usize,
+}
+
+/// What VideoToolbox prints when it sets up a software session; only lines
+/// of that encoder are checked
+const VT_SOFTWARE_MESSAGES: &[&str] = &["software encoder", "software session"];
+const SOFTWARE_ENCODER_WARNING: &str = "VideoToolbox used its software encoder, which is much \
+     slower than hardware encoding; libx264 is the better choice for CPU encoding";
+/// Progress before the speed is compared with the benchmark, letting
+/// startup and the first keyframes settle
+const SPEED_CHECK_MIN_PERCENT: f64 = 5.0;
+/// Share of the benchmarked speed below which an encode counts as slow
+const SLOW_ENCODE_FRACTION: f64 = 0.3;
+
+/// A warning when the encode runs far below what the benchmark measured
+fn slow_encode_warning(
+    video_action: &StreamAction,
+    speed: Option<f64>,
+    expected: Option<f64>,
+) -> Option<String> {
+    let StreamAction::Encoded(encoder) = video_action else {
+        return None;
+    };
+    let (speed, expected) = (speed?, expected?);
+    if speed <= 0.0 || speed >= expected * SLOW_ENCODE_FRACTION {
+        return None;
+    }
+    let mut warning = format!(
+        "Encoding runs at {:.2}x, far below the {:.2}x measured for {} on this machine",
+        speed, expected, encoder
+    );
+    if encoder == "h264_videotoolbox" {
+        warning.push_str(
+            "; VideoToolbox has probably fallen back to its software encoder, and libx264 \
+             is the better choice for CPU encoding",
+        );
+    }
+    Some(warning)
+}
+
+/// Read ffmpeg's stderr to the end, keeping its last lines and counting the
+/// known warnings and decoding errors on the way; every line also goes to
+/// `log` when given
+async fn read_stderr(pipe: Option<ProcessPipe>, keep: usize, log: Option<TaskLog>) -> StderrReport {
+    let mut tail = std::collections::VecDeque::with_capacity(keep);
+    // Labels in first-seen order, since several patterns can share one
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    let mut software_encoder = false;
+    let mut decode_errors = 0;
+    if let Some(pipe) = pipe {
+        // Byte lines, since stderr may echo file names that aren't UTF-8
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        while matches!(reader.read_until(b'\n', &mut buf).await, Ok(n) if n > 0) {
+            let line = String::from_utf8_lossy(&buf).trim().to_string();
+            buf.clear();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(log) = &log {
+                log.line(&line);
+            }
+            let lower = line.to_lowercase();
+            software_encoder |= lower.contains("videotoolbox")
+                && VT_SOFTWARE_MESSAGES.iter().any(|message| lower.contains(message));
+            if DECODE_ERROR_PATTERNS.iter().any(|pattern| lower.contains(pattern)) {
+                decode_errors += 1;
+            }
+            if let Some((_, label)) =
+                FFMPEG_WARNING_PATTERNS.iter().find(|(pattern, _)| lower.contains(pattern))
+            {
+                match counts.iter_mut().find(|(seen, _)| seen == label) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((label, 1)),
+                }
+            }
+            if tail.len() == keep {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    }
+    let warnings = counts
+        .into_iter()
+        .map(|(label, count)| match count {
+            1 => format!("ffmpeg reported {} once; the output may have glitches", label),
+            _ => format!("ffmpeg reported {} {} times; the output may have glitches", label, count),
+        })
+        .collect();
+    StderrReport { tail: tail.into(), warnings, software_encoder, decode_errors }
+}
+
+fn file_size(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Probe a finished output for its overall bitrate, displayed size and
+/// duration
+async fn measure_output(
+    resolver: &FfmpegResolver,
+    path: &str,
+) -> (Option<u64>, Option<(u32, u32)>, Option<f64>) {
+    match get_video_info(resolver, path).await {
+        Ok(info) => (
+            (info.bitrate > 0).then_some(info.bitrate),
+            Some(info.display_size()),
+            Some(info.duration),
+        ),
+        Err(_) => (None, None, None),
+    }
+}
+
+/// Delete a file the caller's allow-list accepts (see `validate_deletable`)
+pub async fn delete_file<F>(path: &str, is_allowed: F) -> Result<(), ConvertError>
+where
+    F: Fn(&Path) -> bool,
+{
+    let path = validate_deletable(path, is_allowed)?;
+    tokio::fs::remove_file(path)
+        .await
+        .map_err(|e| format!("Failed to delete file: {}", e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::scripted::{Script, ScriptedRunner};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    const VERSION: &str = "ffmpeg version 6.1\nconfiguration: --enable-libx264\n";
+
+    fn video_stream(codec: &str) -> Value {
+        json!({
+            "index": 0, "codec_type": "video", "codec_name": codec, "width": 1280,
+            "height": 720, "r_frame_rate": "30/1", "avg_frame_rate": "30/1",
+            "pix_fmt": "yuv420p", "duration": "10.000000", "sample_aspect_ratio": "1:1",
+            "display_aspect_ratio": "16:9", "disposition": {"default": 1}
+        })
+    }
+
+    fn audio_stream(codec: &str) -> Value {
+        json!({
+            "index": 1, "codec_type": "audio", "codec_name": codec, "sample_rate": "48000",
+            "channels": 2, "duration": "10.000000", "disposition": {"default": 1}
+        })
+    }
+
+    /// ffprobe's JSON for a 10 second QuickTime file with these streams
+    fn probe(streams: Vec<Value>) -> String {
+        probe_as("mov,mp4,m4a,3gp,3g2,mj2", "qt  ", streams)
+    }
+
+    fn probe_as(format_name: &str, major_brand: &str, streams: Vec<Value>) -> String {
+        json!({
+            "streams": streams,
+            "format": {
+                "format_name": format_name, "duration": "10.000000", "bit_rate": "2000000",
+                "tags": {"major_brand": major_brand}
+            }
+        })
+        .to_string()
+    }
+
+    /// `stream` with `field` set
+    fn with(mut stream: Value, field: &str, value: Value) -> Value {
+        stream[field] = value;
+        stream
+    }
+
+    type Events = Arc<Mutex<Vec<ConversionProgress>>>;
+
+    /// `source.mov` in a folder of its own, converted through a scripted
+    /// runner whose outputs probe as 10 seconds of H.264/AAC
+    struct Fixture {
+        dir: PathBuf,
+        input: String,
+        runner: Arc<ScriptedRunner>,
+        resolver: FfmpegResolver,
+    }
+
+    impl Fixture {
+        fn new(source: String, ffmpeg: Script) -> Self {
+            let dir = std::env::temp_dir().join(format!("converter-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let dir = std::fs::canonicalize(dir).unwrap();
+            let input = dir.join("source.mov");
+            std::fs::write(&input, vec![0; 4096]).unwrap();
+            let output = probe(vec![video_stream("h264"), audio_stream("aac")]);
+            let runner = Arc::new(ScriptedRunner::new([
+                Script::new("ffmpeg").with_arg("-version").stdout(VERSION),
+                Script::new("ffprobe").with_arg("-version").stdout(VERSION),
+                Script::new("ffprobe").with_arg("_converted").stdout(output),
+                Script::new("ffprobe").with_arg("source.mov").stdout(source),
+                ffmpeg.with_arg("-progress"),
+            ]));
+            let resolver = FfmpegResolver::with_runner(
+                Some("ffmpeg".to_string()),
+                Some("ffprobe".to_string()),
+                runner.clone(),
+            );
+            Fixture { dir, input: input.to_string_lossy().to_string(), runner, resolver }
+        }
+
+        /// A conversion that runs to the end and writes its output
+        fn finishing(source: String) -> Self {
+            let progress = "out_time=00:00:05.000000\nspeed=2.0x\nprogress=continue\n\
+                            out_time=00:00:10.000000\nprogress=end\n";
+            Fixture::new(source, Script::new("ffmpeg").stdout(progress).writes_output("mp4"))
+        }
+
+        async fn convert_with(
+            &self,
+            options: &ConversionOptions,
+            cancel: &CancellationToken,
+            on_progress: impl Fn(&ConversionProgress) + Send + Sync + 'static,
+        ) -> (Result<ConversionResult, ConvertError>, Vec<ConversionProgress>) {
+            let events: Events = Arc::default();
+            let seen = Arc::clone(&events);
+            let result = convert_attempt(
+                &self.resolver,
+                &self.input,
+                &self.dir.to_string_lossy(),
+                "task",
+                options,
+                Some(&self.dir),
+                &TaskLog::default(),
+                cancel,
+                move |progress| {
+                    on_progress(&progress);
+                    seen.lock().unwrap().push(progress);
+                },
+            )
+            .await;
+            let events = events.lock().unwrap().clone();
+            (result, events)
+        }
+
+        async fn convert(
+            &self,
+            options: &ConversionOptions,
+        ) -> Result<ConversionResult, ConvertError> {
+            self.convert_with(options, &CancellationToken::new(), |_| {}).await.0
+        }
+
+        /// The argv of the conversion run, without the program
+        fn ffmpeg_args(&self) -> Vec<String> {
+            let runs = self.runner.calls_with("ffmpeg", "-progress");
+            assert_eq!(runs.len(), 1, "expected one conversion run");
+            runs[0][1..].to_vec()
+        }
+
+        /// The value after the last `flag` in the conversion argv
+        fn arg_after(&self, flag: &str) -> Option<String> {
+            let args = self.ffmpeg_args();
+            args.windows(2).filter(|pair| pair[0] == flag).map(|pair| pair[1].clone()).next_back()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// The platform's re-encode settings at the default quality
+    fn default_encoder_args(threads: &str) -> Vec<&str> {
+        if cfg!(target_os = "macos") {
+            vec![
+                "-c:v", "h264_videotoolbox", "-q:v", "65", "-profile:v", "main", "-level", "4.0",
+                "-allow_sw", "1",
+            ]
+        } else {
+            vec![
+                "-c:v", "libx264", "-preset", "fast", "-crf", "23", "-profile:v", "main",
+                "-level", "4.0", "-threads", threads,
+            ]
+        }
+    }
+
+    #[tokio::test]
+    async fn conversion_runs_the_expected_argv() {
+        let threads = get_thread_count();
+        let hwaccel: Vec<&str> =
+            hw_decode_method().into_iter().flat_map(|method| ["-hwaccel", method]).collect();
+        let encoder = default_encoder_args(&threads);
+        // libx264 names its own thread count, which stands in for the trailing one
+        let own_threads = VIDEO_ENCODER == "libx264";
+        let trailing: &[&str] = if own_threads { &[] } else { &["-threads", &threads] };
+        let cases = [
+            ("h264", &[][..], &["-c:v", "copy"][..], &["-threads", &threads][..]),
+            ("hevc", &hwaccel[..], &encoder[..], trailing),
+        ];
+        for (codec, input_options, video, trailing) in cases {
+            let fixture = Fixture::finishing(probe(vec![video_stream(codec), audio_stream("aac")]));
+            fixture.convert(&Default::default()).await.unwrap();
+            let input = format!("file:{}", fixture.input);
+            let output = format!("file:{}", fixture.dir.join("source_converted.mp4").display());
+            let expected = [
+                &["-nostdin", "-threads", &threads, "-y"][..],
+                input_options,
+                &["-i", &input, "-map", "0:v:0", "-map", "0:a:0?", "-map_chapters", "0"],
+                video,
+                &["-pix_fmt", "yuv420p", "-c:a", "copy", "-movflags", "+faststart"],
+                &["-progress", "pipe:1"],
+                trailing,
+                &[&output],
+            ]
+            .concat();
+            assert_eq!(fixture.ffmpeg_args(), expected, "{}", codec);
+        }
+    }
+
+    #[tokio::test]
+    async fn copies_or_encodes_each_stream() {
+        // The scripted output keeps the source's length, which a faster one
+        // wouldn't pass verification with
+        let speed = ConversionOptions {
+            speed: Some(2.0),
+            verify_output: Verification::Off,
+            ..Default::default()
+        };
+        let cases = [
+            ("h264", "aac", ConversionOptions::default(), "copy", "copy"),
+            ("hevc", "aac", ConversionOptions::default(), VIDEO_ENCODER, "copy"),
+            ("h264", "mp3", ConversionOptions::default(), "copy", "aac"),
+            ("vp9", "opus", ConversionOptions::default(), VIDEO_ENCODER, "aac"),
+            ("h264", "aac", speed, VIDEO_ENCODER, "aac"),
+        ];
+        for (video, audio, options, video_codec, audio_codec) in cases {
+            let source = probe(vec![video_stream(video), audio_stream(audio)]);
+            let fixture = Fixture::finishing(source);
+            let case = format!("{}/{} {:?}", video, audio, options.speed);
+            let result = fixture.convert(&options).await;
+            let result = result.unwrap_or_else(|e| panic!("{}: {}", case, e));
+            assert_eq!(fixture.arg_after("-c:v").as_deref(), Some(video_codec), "{}", case);
+            assert_eq!(fixture.arg_after("-c:a").as_deref(), Some(audio_codec), "{}", case);
+            let copied = video_codec == "copy";
+            assert_eq!(result.video_action == StreamAction::Copied, copied, "{}", case);
+        }
+    }
+
+    #[tokio::test]
+    async fn megabytes_of_stderr_dont_stall_the_progress_pipe() {
+        let progress = "out_time=00:00:05.000000\nprogress=continue\n\
+                        out_time=00:00:10.000000\nprogress=end\n";
+        let line = "[mp4 @ 0x5586] Application provided invalid, non monotonically increasing dts\n";
+        let stderr = line.repeat(4 * 1024 * 1024 / line.len());
+        let ffmpeg = Script::new("ffmpeg").stdout(progress).stderr(stderr).writes_output("mp4");
+        let fixture =
+            Fixture::new(probe(vec![video_stream("hevc"), audio_stream("aac")]), ffmpeg.piped());
+        let (options, cancel) = (ConversionOptions::default(), CancellationToken::new());
+        let conversion = fixture.convert_with(&options, &cancel, |_| {});
+        let (result, events) = tokio::time::timeout(std::time::Duration::from_secs(30), conversion)
+            .await
+            .expect("the conversion stalled");
+        assert_eq!(result.unwrap().status, ConversionStatus::Completed);
+        let percents: Vec<f64> = events
+            .iter()
+            .filter(|e| e.status == ConversionStatus::Converting)
+            .map(|e| e.progress)
+            .collect();
+        assert_eq!(percents, [50.0, 100.0]);
+    }
+
+    #[tokio::test]
+    async fn reports_progress_from_the_progress_pipe() {
+        let fixture = Fixture::finishing(probe(vec![video_stream("hevc"), audio_stream("aac")]));
+        let (result, events) =
+            fixture.convert_with(&Default::default(), &CancellationToken::new(), |_| {}).await;
+        let result = result.unwrap();
+        assert_eq!(result.status, ConversionStatus::Completed);
+        assert!(result.output_path.ends_with("source_converted.mp4"));
+        let converting: Vec<&ConversionProgress> =
+            events.iter().filter(|e| e.status == ConversionStatus::Converting).collect();
+        let percents: Vec<f64> = converting.iter().map(|e| e.progress).collect();
+        assert_eq!(percents, [50.0, 100.0]);
+        // Speed comes after the time it belongs to, so the second block has it
+        assert_eq!(converting[1].speed, Some(2.0));
+        assert_eq!(converting[1].eta_seconds, Some(0.0));
+        let statuses: Vec<ConversionStatus> = events.iter().map(|e| e.status).collect();
+        assert_eq!(statuses.first(), Some(&ConversionStatus::Starting));
+        assert!(statuses.contains(&ConversionStatus::Finalizing));
+        assert_eq!(statuses.last(), Some(&ConversionStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn a_failed_run_reports_the_last_error_line() {
+        let fail = Script::new("ffmpeg")
+            .stderr("Invalid data found when processing input\nConversion failed!\n")
+            .exit_code(1);
+        let fixture = Fixture::new(probe(vec![video_stream("hevc"), audio_stream("aac")]), fail);
+        let (result, events) =
+            fixture.convert_with(&Default::default(), &CancellationToken::new(), |_| {}).await;
+        match result {
+            Err(ConvertError::Failed(message)) => {
+                assert!(message.ends_with(": Conversion failed!"), "{}", message)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(events.last().map(|e| e.status), Some(ConversionStatus::Error));
+    }
+
+    #[tokio::test]
+    async fn a_failure_partway_comes_with_a_sample_range() {
+        let fail = Script::new("ffmpeg")
+            .stdout("out_time=00:00:04.000000\nprogress=continue\n")
+            .stderr("Conversion failed!\n")
+            .exit_code(1);
+        let fixture = Fixture::new(probe(vec![video_stream("hevc"), audio_stream("aac")]), fail);
+        match fixture.convert(&Default::default()).await {
+            Err(ConvertError::EncodeFailed { sample, .. }) => {
+                assert!(sample.start_seconds <= 4.0 && sample.end_seconds >= 4.0)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_run_without_output_fails() {
+        let fixture = Fixture::new(probe(vec![video_stream("hevc")]), Script::new("ffmpeg"));
+        let result = fixture.convert(&Default::default()).await;
+        assert_eq!(result.err(), Some(ConvertError::Failed("Output file not created".to_string())));
+    }
+
+    #[tokio::test]
+    async fn cancelling_stops_the_run_and_removes_the_output() {
+        let hang = Script::new("ffmpeg")
+            .stdout("out_time=00:00:01.000000\nprogress=continue\n")
+            .writes_output("partial")
+            .hangs();
+        let fixture = Fixture::new(probe(vec![video_stream("hevc"), audio_stream("aac")]), hang);
+        let cancel = CancellationToken::new();
+        let stop = cancel.clone();
+        let (result, events) = fixture
+            .convert_with(&Default::default(), &cancel, move |progress| {
+                if progress.status == ConversionStatus::Converting {
+                    stop.cancel();
+                }
+            })
+            .await;
+        assert_eq!(result.err(), Some(ConvertError::Cancelled));
+        assert_eq!(events.last().map(|e| e.status), Some(ConversionStatus::Cancelled));
+        assert!(!fixture.dir.join("source_converted.mp4").exists());
+    }
+
+    #[tokio::test]
+    async fn ffmpeg_reads_keys_only_when_a_partial_stop_can_be_asked_for() {
+        let hls = ConversionOptions { output_format: OutputFormat::Hls, ..Default::default() };
+        let source = probe(vec![video_stream("h264"), audio_stream("aac")]);
+        for (options, reads_keys) in [(Default::default(), cfg!(windows)), (hls, false)] {
+            let fixture = Fixture::finishing(source.clone());
+            let _ = fixture.convert(&options).await;
+            let args = fixture.ffmpeg_args();
+            assert_eq!(!args.iter().any(|arg| arg == "-nostdin"), reads_keys, "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn opus_audio_is_always_encoded_to_aac() {
+        let mp4 = |streams| probe_as("mov,mp4,m4a,3gp,3g2,mj2", "isom", streams);
+        let cases = [
+            (probe(vec![video_stream("vp9"), audio_stream("opus")]), "aac"),
+            // Opus in MP4 next to H.264 video, which is copied
+            (mp4(vec![video_stream("h264"), audio_stream("opus")]), "aac"),
+            // A second, Opus track isn't copied along with the AAC one
+            (mp4(vec![video_stream("h264"), audio_stream("aac"), audio_stream("opus")]), "copy"),
+        ];
+        for (source, audio_codec) in cases {
+            let fixture = Fixture::finishing(source);
+            let result = fixture.convert(&Default::default()).await.unwrap();
+            assert_eq!(fixture.arg_after("-c:a").as_deref(), Some(audio_codec));
+            let args = fixture.ffmpeg_args();
+            assert!(!args.iter().any(|arg| arg.starts_with("0:a:1")), "{:?}", args);
+            let encoded = result.audio_action != StreamAction::Copied;
+            assert_eq!(encoded, audio_codec == "aac");
+        }
+    }
+
+    #[tokio::test]
+    async fn transparent_video_is_composited_onto_the_background() {
+        let webm = |video| probe_as("matroska,webm", "", vec![video, audio_stream("opus")]);
+        let tagged = with(video_stream("vp9"), "tags", json!({"alpha_mode": "1"}));
+        let native = with(video_stream("vp9"), "pix_fmt", json!("yuva420p"));
+        for (video, decoder) in [(tagged, Some("libvpx-vp9")), (native, None)] {
+            let fixture = Fixture::finishing(webm(video));
+            let options = ConversionOptions {
+                alpha_background: Some("black".to_string()),
+                ..Default::default()
+            };
+            let result = fixture.convert(&options).await.unwrap();
+            let filters = fixture.arg_after("-vf").unwrap_or_default();
+            assert!(filters.contains(&alpha_composite_filter("black")), "{}", filters);
+            assert!(filters.contains("split[fg][base]") && filters.contains("overlay"));
+            assert!(filters.contains("drawbox=c=black@1"));
+            let args = fixture.ffmpeg_args();
+            let input = args.iter().position(|arg| arg == "-i").unwrap();
+            let decoder_arg = args[..input].windows(2).find(|pair| pair[0] == "-c:v");
+            assert_eq!(decoder_arg.map(|pair| pair[1].as_str()), decoder);
+            assert!(result.warnings.iter().any(|w| w.contains("on a black background")));
+            assert_eq!(fixture.arg_after("-c:a").as_deref(), Some("aac"));
+        }
+    }
+
+    #[tokio::test]
+    async fn anamorphic_legacy_video_is_widened_to_square_pixels() {
+        let anamorphic = |codec| {
+            let stream = with(video_stream(codec), "sample_aspect_ratio", json!("32:27"));
+            let stream = with(stream, "width", json!(720));
+            with(stream, "height", json!(480))
+        };
+        let widen = "scale=trunc(iw*sar/2)*2:ih,setsar=1";
+        let cases = [
+            (probe_as("avi", "", vec![anamorphic("mpeg4"), audio_stream("mp3")]), true),
+            (probe_as("asf", "", vec![anamorphic("wmv3"), audio_stream("wmav2")]), true),
+            // H.264 is copied, keeping the ratio stored in the stream
+            (probe(vec![anamorphic("h264"), audio_stream("aac")]), false),
+        ];
+        for (source, encodes) in cases {
+            let fixture = Fixture::finishing(source);
+            let result = fixture.convert(&Default::default()).await.unwrap();
+            let filters = fixture.arg_after("-vf").unwrap_or_default();
+            assert_eq!(filters.contains(widen), encodes, "{}", filters);
+            let audio_codec = if encodes { "aac" } else { "copy" };
+            assert_eq!(fixture.arg_after("-c:a").as_deref(), Some(audio_codec));
+            let encoded = matches!(result.video_action, StreamAction::Encoded(_));
+            assert_eq!(encoded, encodes);
+        }
+    }
+
+    #[tokio::test]
+    async fn retimed_chapters_are_mapped_from_an_ffmetadata_input() {
+        let mut source: Value =
+            serde_json::from_str(&probe(vec![video_stream("h264"), audio_stream("aac")])).unwrap();
+        source["chapters"] = json!([
+            {"start_time": "0.000000", "end_time": "5.000000", "tags": {"title": "One"}},
+            {"start_time": "5.000000", "end_time": "10.000000", "tags": {"title": "Two"}}
+        ]);
+        let options = ConversionOptions {
+            speed: Some(2.0),
+            verify_output: Verification::Off,
+            ..Default::default()
+        };
+        let fixture = Fixture::finishing(source.to_string());
+        fixture.convert(&options).await.unwrap();
+        let args = fixture.ffmpeg_args();
+        let inputs: Vec<&String> =
+            args.windows(2).filter(|pair| pair[0] == "-i").map(|pair| &pair[1]).collect();
+        assert_eq!(inputs.len(), 2, "{:?}", args);
+        assert!(inputs[1].ends_with("chapters.txt"), "{}", inputs[1]);
+        assert_eq!(fixture.arg_after("-map_chapters").as_deref(), Some("1"));
+
+        // Without a timeline change the source's own chapters are copied
+        let fixture = Fixture::finishing(source.to_string());
+        fixture.convert(&Default::default()).await.unwrap();
+        assert_eq!(fixture.arg_after("-map_chapters").as_deref(), Some("0"));
+        assert!(!fixture.ffmpeg_args().iter().any(|arg| arg.ends_with("chapters.txt")));
+    }
+
+    #[tokio::test]
+    async fn protected_sources_are_refused_before_converting() {
+        let video = with(video_stream("h264"), "disposition", json!({"encrypted": 1}));
+        let fixture = Fixture::finishing(probe(vec![video, audio_stream("aac")]));
+        let result = fixture.convert(&Default::default()).await;
+        assert_eq!(result.err(), Some(ConvertError::DrmProtected(fixture.input.clone())));
+        assert_eq!(fixture.runner.calls_with("ffmpeg", "-progress"), Vec::<Vec<String>>::new());
+    }
+
+    /// VideoToolbox is the only hardware encoder conversions pick, so only
+    /// macOS ever waits for a session
+    #[cfg(target_os = "macos")]
+    #[tokio::test]
+    async fn encodes_wait_for_a_free_encoder_session() {
+        let fixture = Fixture::finishing(probe(vec![video_stream("hevc"), audio_stream("aac")]));
+        let slots = fixture.resolver.encoder_slots();
+        slots.set_limits([("videotoolbox".to_string(), 1)].into());
+        let held = slots.try_acquire(VIDEO_ENCODER).unwrap();
+        let waiting = Arc::new(tokio::sync::Notify::new());
+        let notify = Arc::clone(&waiting);
+        let (options, cancel) = (ConversionOptions::default(), CancellationToken::new());
+        let conversion = fixture.convert_with(&options, &cancel, move |progress| {
+            if progress.status == ConversionStatus::WaitingForEncoder {
+                notify.notify_one();
+            }
+        });
+        let release = async {
+            waiting.notified().await;
+            assert!(fixture.runner.calls_with("ffmpeg", "-progress").is_empty());
+            drop(held);
+        };
+        let ((result, events), ()) = tokio::join!(conversion, release);
+        assert_eq!(result.unwrap().status, ConversionStatus::Completed);
+        assert!(events.iter().any(|e| e.status == ConversionStatus::WaitingForEncoder));
+        assert_eq!(slots.in_use("videotoolbox"), 0);
+    }
+
+    #[tokio::test]
+    async fn picture_filters_run_in_a_fixed_order() {
+        let crop = Some(CropRect { x: 11, y: 20, w: 1101, h: 600 });
+        let light = "hqdn3d=2:1.5:3:2.25";
+        let cases = [
+            (Denoise::Light, false, None, None, light.to_string()),
+            (Denoise::Strong, false, None, None, "nlmeans=s=3:p=7:r=15".to_string()),
+            (Denoise::Off, true, None, None, SHARPEN_FILTER.to_string()),
+            (Denoise::Off, false, None, Some(481), "scale=-2:min(ih\\,480)".to_string()),
+            (
+                Denoise::Medium,
+                true,
+                None,
+                Some(480),
+                "hqdn3d=4:3:6:4.5,scale=-2:min(ih\\,480),unsharp=5:5:0.6:3:3:0.0".to_string(),
+            ),
+            // Noise is removed from the kept picture, at the source resolution
+            (
+                Denoise::Light,
+                true,
+                crop,
+                Some(480),
+                format!("{},crop=1100:600:10:20,scale=-2:min(ih\\,480),{}", light, SHARPEN_FILTER),
+            ),
+        ];
+        let source = || probe(vec![video_stream("h264"), audio_stream("aac")]);
+        for (denoise, sharpen, crop, max_height, expected) in cases {
+            let fixture = Fixture::finishing(source());
+            let options =
+                ConversionOptions { denoise, sharpen, crop, max_height, ..Default::default() };
+            fixture.convert(&options).await.unwrap();
+            assert_eq!(fixture.arg_after("-vf"), Some(expected));
+            assert_eq!(fixture.arg_after("-c:v").as_deref(), Some(VIDEO_ENCODER));
+        }
+        let fixture = Fixture::finishing(source());
+        fixture.convert(&Default::default()).await.unwrap();
+        assert_eq!(fixture.arg_after("-vf"), None);
+    }
+
+    /// ffprobe's JSON for an iPhone clip tagged with where it was shot
+    fn tagged_probe() -> String {
+        let mut source: Value =
+            serde_json::from_str(&probe(vec![video_stream("h264"), audio_stream("aac")])).unwrap();
+        source["format"]["tags"] = json!({
+            "major_brand": "qt  ",
+            "creation_time": "2024-05-01T10:00:00.000000Z",
+            "location": "+35.6762+139.6503/",
+            "location-eng": "+35.6762+139.6503/",
+            "com.apple.quicktime.location.ISO6709": "+35.6762+139.6503+040.000/",
+            "com.apple.quicktime.model": "iPhone 15"
+        });
+        source.to_string()
+    }
+
+    /// The `-metadata` values of the conversion run
+    fn metadata_values(fixture: &Fixture) -> Vec<String> {
+        let args = fixture.ffmpeg_args();
+        args.windows(2).filter(|pair| pair[0] == "-metadata").map(|pair| pair[1].clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn stripping_metadata_clears_location_tags() {
+        let fixture = Fixture::finishing(tagged_probe());
+        let options = ConversionOptions {
+            strip_metadata: true,
+            preserve_timestamps: true,
+            ..Default::default()
+        };
+        fixture.convert(&options).await.unwrap();
+        let args = fixture.ffmpeg_args();
+        for flag in ["-map_metadata", "-map_metadata:s:v", "-map_metadata:s:a"] {
+            let at = args.iter().position(|arg| arg == flag).unwrap();
+            assert_eq!(args[at + 1], "-1", "{}", flag);
+        }
+        assert_eq!(
+            metadata_values(&fixture),
+            [
+                "location=",
+                "location-eng=",
+                "com.apple.quicktime.location.ISO6709=",
+                "creation_time=2024-05-01T10:00:00.000000Z",
+            ]
+        );
+
+        // Copied metadata is left alone
+        let fixture = Fixture::finishing(tagged_probe());
+        fixture.convert(&Default::default()).await.unwrap();
+        assert_eq!(metadata_values(&fixture), Vec::<String>::new());
+        assert!(!fixture.ffmpeg_args().iter().any(|arg| arg.starts_with("-map_metadata")));
+    }
+
+    #[tokio::test]
+    async fn explicit_metadata_keeps_cjk_text_and_wins() {
+        let metadata: BTreeMap<String, String> = [
+            ("title".to_string(), "日本語のタイトル".to_string()),
+            ("comment".to_string(), "東京で撮影 2024".to_string()),
+            ("location".to_string(), "+48.8566+002.3522/".to_string()),
+        ]
+        .into();
+        for strip_metadata in [false, true] {
+            let fixture = Fixture::finishing(tagged_probe());
+            let options = ConversionOptions {
+                strip_metadata,
+                metadata: Some(metadata.clone()),
+                ..Default::default()
+            };
+            fixture.convert(&options).await.unwrap();
+            let values = metadata_values(&fixture);
+            // One argv element each, after the blanked tags they override
+            let explicit =
+                ["comment=東京で撮影 2024", "location=+48.8566+002.3522/", "title=日本語のタイトル"];
+            assert_eq!(values[values.len() - 3..], explicit, "{:?}", values);
+            assert_eq!(values.iter().any(|value| value == "location="), strip_metadata);
+        }
+    }
+
+    #[tokio::test]
+    async fn data_streams_are_dropped_unless_kept() {
+        let telemetry = json!({
+            "index": 2, "codec_type": "data", "codec_name": "bin_data", "codec_tag_string": "gpmd"
+        });
+        let source = || probe(vec![video_stream("h264"), audio_stream("aac"), telemetry.clone()]);
+        let maps = |fixture: &Fixture| -> Vec<String> {
+            let args = fixture.ffmpeg_args();
+            args.windows(2).filter(|pair| pair[0] == "-map").map(|pair| pair[1].clone()).collect()
+        };
+
+        let fixture = Fixture::finishing(source());
+        let result = fixture.convert(&Default::default()).await.unwrap();
+        assert_eq!(maps(&fixture), ["0:v:0", "0:a:0?"]);
+        assert!(!fixture.ffmpeg_args().iter().any(|arg| arg == "-copy_unknown"));
+        assert!(result.warnings.iter().any(|w| w.contains("Dropped 1 data stream(s) (gpmd)")));
+
+        let fixture = Fixture::finishing(source());
+        let options = ConversionOptions { keep_data_streams: true, ..Default::default() };
+        let result = fixture.convert(&options).await.unwrap();
+        assert_eq!(maps(&fixture), ["0:v:0", "0:a:0?", "0:d?"]);
+        assert_eq!(fixture.arg_after("-c:d").as_deref(), Some("copy"));
+        assert!(fixture.ffmpeg_args().iter().any(|arg| arg == "-copy_unknown"));
+        assert!(!result.warnings.iter().any(|w| w.contains("data stream")));
+    }
+
+    fn extra_args(args: &[&str]) -> Result<(), String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        validate_extra_args("extra_output_args", &args)
+    }
+
+    #[test]
+    fn extra_args_accept_options_and_their_values() {
+        for args in [
+            &[][..],
+            &["-an"],
+            &["-c:v", "libx264", "-preset", "slow", "-an"],
+            &["-b:v", "2M", "-maxrate:v", "3M", "-bufsize", "6M"],
+            &["-metadata:s:a:0", "language=eng", "-movflags", "+faststart"],
+            &["-qscale:v", "-1"],
+            &["-f", "mp4"],
+        ] {
+            assert_eq!(extra_args(args), Ok(()), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn extra_args_reject_extra_outputs_and_files() {
+        for args in [
+            &["/tmp/x.mp4"][..],
+            &["-an", "/tmp/x.mp4"],
+            &["-shortest", "out.mp4"],
+            &["-c:v", "libx264", "/tmp/x.mp4"],
+            &["-unknown_option", "/tmp/x.mp4"],
+            &["-i", "/tmp/other.mp4"],
+            &["-y"],
+            &["-progress", "pipe:2"],
+            &["-passlogfile", "/tmp/log"],
+            &["-vstats_file", "/tmp/stats"],
+            &["-vstats"],
+            &["-report"],
+            &["-f", "tee"],
+            &["-/vf", "/tmp/filter.txt"],
+            &["-filter_complex_script", "/tmp/graph.txt"],
+            &["-vf", "scale=640:-2"],
+            &["-af", "volume=2"],
+            &["-filter:v", "movie=/etc/passwd"],
+            &["-filter:a:0", "amovie=/tmp/other.wav"],
+            &["-filter_complex", "[0:v]null"],
+            &["-lavfi", "movie=/tmp/other.mp4"],
+        ] {
+            assert!(extra_args(args).is_err(), "{:?} was accepted", args);
+        }
+    }
+}
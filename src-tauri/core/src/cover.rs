@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::VideoInfo;
+use crate::error::ConvertError;
+use crate::paths::{ffmpeg_path_arg, validate_input_path};
+use crate::process::output_cancellable;
+use crate::resolver::FfmpegResolver;
+use crate::task_log::TaskLog;
+
+/// `cover_image` value that picks a frame from the video itself
+pub const AUTO_COVER: &str = "auto";
+/// How far into the video the automatic poster frame is taken
+const AUTO_COVER_POSITION: f64 = 0.1;
+const JPEG_EXTENSIONS: &[&str] = &["jpg", "jpeg"];
+
+/// A JPEG ready to embed as cover art; extracted or converted copies are
+/// deleted when dropped
+pub struct CoverImage {
+    pub path: PathBuf,
+    temporary: bool,
+}
+
+impl Drop for CoverImage {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Turn a `cover_image` option into a JPEG file: `auto` grabs the frame 10%
+/// into the video, JPEGs are used as they are and any other image ffmpeg
+/// can read is converted. Doing it up front means an unreadable image fails
+/// here rather than halfway through the conversion.
+pub async fn prepare_cover(
+    resolver: &FfmpegResolver,
+    cover: &str,
+    info: &VideoInfo,
+    work_dir: &Path,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+) -> Result<CoverImage, ConvertError> {
+    let temp = CoverImage {
+        path: work_dir.join("cover.jpg"),
+        temporary: true,
+    };
+
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin", "-y"]);
+    // A frame of the converted stream, or the only picture of an image
+    let stream = if cover == AUTO_COVER { info.video_stream_index } else { 0 };
+    if cover == AUTO_COVER {
+        let position = info.duration * AUTO_COVER_POSITION;
+        cmd.arg("-ss")
+            .arg(format!("{:.3}", position))
+            .arg("-i")
+            .arg(ffmpeg_path_arg(Path::new(&info.path)));
+    } else {
+        let source = validate_input_path(cover)?;
+        let extension =
+            source.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if JPEG_EXTENSIONS.contains(&extension.as_str()) {
+            return Ok(CoverImage { path: source, temporary: false });
+        }
+        cmd.arg("-i").arg(ffmpeg_path_arg(&source));
+    }
+    cmd.arg("-map")
+        .arg(format!("0:v:{}", stream))
+        .args(["-frames:v", "1", "-q:v", "2"])
+        .arg(ffmpeg_path_arg(&temp.path));
+    let args: Vec<String> =
+        cmd.as_std().get_args().map(|a| a.to_string_lossy().to_string()).collect();
+    log.command(&ffmpeg_path, &args);
+
+    let output = output_cancellable(resolver.runner(), &mut cmd, cancel).await?;
+    if !output.status.success() || !temp.path.exists() {
+        return Err(match cover {
+            AUTO_COVER => "Failed to extract a cover frame from the video".into(),
+            _ => format!("Failed to read cover image: {} is not an image ffmpeg can decode", cover)
+                .into(),
+        });
+    }
+    Ok(temp)
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+
+use crate::converter::VideoInfo;
+
+/// Devices an H.264 file is checked against before its video is copied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceProfile {
+    #[default]
+    ModernPhone,
+    /// Smart TVs and set-top boxes from the early 1080p days
+    OldTv,
+    /// Browser playback through `<video>`
+    Web,
+    /// Car head units and similar players that only take 44.1 kHz stereo
+    /// AAC next to conservative H.264
+    StrictDevice,
+}
+
+/// What a device profile plays reliably. ffprobe reports levels times ten,
+/// so 4.1 is 41.
+struct DeviceLimits {
+    device: DeviceProfile,
+    name: &'static str,
+    h264_profiles: &'static [&'static str],
+    max_level: u32,
+    max_ref_frames: u32,
+    max_frame_rate: f64,
+    /// Long side by short side, so portrait video is judged the same
+    max_size: (u32, u32),
+    /// None where any sample rate and channel count AAC allows plays
+    audio: Option<AudioLimits>,
+}
+
+/// Audio a device takes; the first sample rate is the one converted to
+struct AudioLimits {
+    sample_rates: &'static [u32],
+    max_channels: u32,
+}
+
+/// Every profile 8-bit 4:2:0 players handle; High 10 and 4:2:2 sources are
+/// re-encoded regardless
+const COMMON_PROFILES: &[&str] = &["Constrained Baseline", "Baseline", "Main", "High"];
+
+const DEVICE_LIMITS: &[DeviceLimits] = &[
+    DeviceLimits {
+        device: DeviceProfile::ModernPhone,
+        name: "modern phones",
+        h264_profiles: COMMON_PROFILES,
+        max_level: 52,
+        max_ref_frames: 16,
+        max_frame_rate: 120.0,
+        max_size: (3840, 2160),
+        audio: None,
+    },
+    DeviceLimits {
+        device: DeviceProfile::OldTv,
+        name: "older TVs",
+        h264_profiles: COMMON_PROFILES,
+        max_level: 41,
+        max_ref_frames: 3,
+        max_frame_rate: 30.0,
+        max_size: (1920, 1080),
+        audio: None,
+    },
+    DeviceLimits {
+        device: DeviceProfile::Web,
+        name: "web browsers",
+        h264_profiles: COMMON_PROFILES,
+        max_level: 51,
+        max_ref_frames: 16,
+        max_frame_rate: 60.0,
+        max_size: (3840, 2160),
+        audio: None,
+    },
+    DeviceLimits {
+        device: DeviceProfile::StrictDevice,
+        name: "strict players such as car head units",
+        h264_profiles: COMMON_PROFILES,
+        max_level: 41,
+        max_ref_frames: 4,
+        max_frame_rate: 30.0,
+        max_size: (1920, 1080),
+        audio: Some(AudioLimits { sample_rates: &[44100], max_channels: 2 }),
+    },
+];
+
+/// Lets a frame rate like 30000/1001 pass a 30 fps limit
+const FRAME_RATE_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    Profile,
+    Level,
+    RefFrames,
+    FrameRate,
+    Resolution,
+    SampleRate,
+    Channels,
+}
+
+impl IssueKind {
+    /// Whether re-encoding with the standard encoder settings fixes it; the
+    /// encoder keeps the frame rate and size
+    pub fn fixed_by_reencode(self) -> bool {
+        matches!(self, IssueKind::Profile | IssueKind::Level | IssueKind::RefFrames)
+    }
+
+    /// An audio problem, fixed by converting the audio (see `audio_targets`)
+    pub fn is_audio(self) -> bool {
+        matches!(self, IssueKind::SampleRate | IssueKind::Channels)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatibilityIssue {
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+fn limits_for(device: DeviceProfile) -> Option<&'static DeviceLimits> {
+    DEVICE_LIMITS.iter().find(|limits| limits.device == device)
+}
+
+/// Ways the source may not play on a device profile: copied H.264 video
+/// (other codecs are re-encoded anyway) and, for profiles with audio
+/// limits, the audio track
+pub fn compatibility_issues(info: &VideoInfo, device: DeviceProfile) -> Vec<CompatibilityIssue> {
+    let Some(limits) = limits_for(device) else {
+        return Vec::new();
+    };
+    let mut issues = Vec::new();
+    let mut issue = |kind, message: String| {
+        issues.push(CompatibilityIssue {
+            kind,
+            message: format!("{}; {} may not play it", message, limits.name),
+        })
+    };
+
+    let targets = audio_targets(info, device);
+    if let (Some(_), Some(rate)) = (targets.sample_rate, info.audio_sample_rate) {
+        issue(IssueKind::SampleRate, format!("The audio is sampled at {} Hz", rate));
+    }
+    if let (Some(_), Some(channels)) = (targets.channels, info.audio_channels) {
+        issue(IssueKind::Channels, format!("The audio has {} channels", channels));
+    }
+    if info.codec != "h264" {
+        return issues;
+    }
+
+    if let Some(profile) = info.profile.as_deref() {
+        if !limits.h264_profiles.contains(&profile) {
+            issue(IssueKind::Profile, format!("The video uses the H.264 {} profile", profile));
+        }
+    }
+    if let Some(level) = info.level.filter(|level| *level > limits.max_level) {
+        issue(
+            IssueKind::Level,
+            format!(
+                "The video is H.264 level {}.{}, above {}.{}",
+                level / 10,
+                level % 10,
+                limits.max_level / 10,
+                limits.max_level % 10
+            ),
+        );
+    }
+    if let Some(refs) = info.ref_frames.filter(|refs| *refs > limits.max_ref_frames) {
+        issue(
+            IssueKind::RefFrames,
+            format!("The video uses {} reference frames, more than {}", refs, limits.max_ref_frames),
+        );
+    }
+    if info.frame_rate > limits.max_frame_rate + FRAME_RATE_TOLERANCE {
+        issue(
+            IssueKind::FrameRate,
+            format!("The video is {:.2} fps, above {:.0}", info.frame_rate, limits.max_frame_rate),
+        );
+    }
+    let (width, height) = info.display_size();
+    let (long, short) = (width.max(height), width.min(height));
+    if long > limits.max_size.0 || short > limits.max_size.1 {
+        issue(
+            IssueKind::Resolution,
+            format!(
+                "The video is {}x{}, larger than {}x{}",
+                width, height, limits.max_size.0, limits.max_size.1
+            ),
+        );
+    }
+    issues
+}
+
+/// Sample rate and channel count the audio has to be converted to for a
+/// device; None for each that already fits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioTargets {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+pub fn audio_targets(info: &VideoInfo, device: DeviceProfile) -> AudioTargets {
+    let Some(audio) = limits_for(device).and_then(|limits| limits.audio.as_ref()) else {
+        return AudioTargets::default();
+    };
+    if !info.has_audio() {
+        return AudioTargets::default();
+    }
+    AudioTargets {
+        sample_rate: info
+            .audio_sample_rate
+            .filter(|rate| !audio.sample_rates.contains(rate))
+            .map(|_| audio.sample_rates[0]),
+        channels: info
+            .audio_channels
+            .filter(|channels| *channels > audio.max_channels)
+            .map(|_| audio.max_channels),
+    }
+}
+
+/// `compatibility_issues` as display text
+pub fn compatibility_warnings(info: &VideoInfo, device: DeviceProfile) -> Vec<String> {
+    compatibility_issues(info, device).into_iter().map(|issue| issue.message).collect()
+}
@@ -0,0 +1,253 @@
+//! First-run download of a static ffmpeg build for machines that have none.
+//!
+//! The transfer and unpacking go through the system `curl` and `tar`, which
+//! ship with macOS, Windows 10 and later, and practically every Linux
+//! distribution; `tar` there is bsdtar or GNU tar, so zip and tar.xz
+//! archives both unpack.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::ConvertError;
+use crate::hashing::{is_sha256, sha256_file};
+use crate::process::{output_cancellable, ProcessRunner};
+
+/// Where a platform's ffmpeg comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadSource {
+    /// `https://` URL of a static build archive containing `ffmpeg` and,
+    /// optionally, `ffprobe`
+    pub url: String,
+    /// Hex SHA-256 of the archive
+    pub sha256: String,
+}
+
+impl DownloadSource {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("https://") {
+            return Err(format!("Download URL must use https: {}", self.url));
+        }
+        let sha256 = self.sha256.trim();
+        if !is_sha256(sha256) {
+            return Err(format!("Invalid SHA-256 checksum: {}", self.sha256));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub downloaded_bytes: u64,
+    /// None when the server doesn't say how big the archive is
+    pub total_bytes: Option<u64>,
+}
+
+/// Binaries installed by [`download_ffmpeg`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadedBinaries {
+    pub ffmpeg: String,
+    /// None when the archive only had ffmpeg
+    pub ffprobe: Option<String>,
+}
+
+/// How often progress is reported while the archive downloads
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const CONNECT_TIMEOUT_SECS: &str = "15";
+/// curl exit codes for a proxy or host that can't be resolved, a refused
+/// connection and a timeout
+const CURL_OFFLINE_CODES: &[i32] = &[5, 6, 7, 28];
+/// curl exit code for a failed write to the output file
+const CURL_WRITE_ERROR: i32 = 23;
+
+/// Key of the running platform in the download source map, e.g. `macos-aarch64`
+pub fn platform_key() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// The scratch directory a download unpacks into; removed again however the
+/// download ends
+struct PartialDownload {
+    dir: PathBuf,
+}
+
+impl Drop for PartialDownload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Download the archive in `source`, check it against its checksum and
+/// install the ffmpeg and ffprobe inside into `<data_dir>/ffmpeg`.
+///
+/// Being offline, a checksum mismatch and an unwritable data dir each fail
+/// with their own [`ConvertError`] variant. Binaries already installed are
+/// only replaced once the new archive has been verified and unpacked.
+pub async fn download_ffmpeg<F>(
+    runner: &dyn ProcessRunner,
+    source: &DownloadSource,
+    data_dir: &Path,
+    cancel: &CancellationToken,
+    on_progress: F,
+) -> Result<DownloadedBinaries, ConvertError>
+where
+    F: Fn(DownloadProgress),
+{
+    source.validate()?;
+    let install_dir = data_dir.join("ffmpeg");
+    let partial = PartialDownload {
+        dir: data_dir.join(format!("ffmpeg-download-{}", uuid::Uuid::new_v4())),
+    };
+    for dir in [&install_dir, &partial.dir] {
+        std::fs::create_dir_all(dir).map_err(|e| not_writable(dir, e))?;
+    }
+
+    let archive_name = source
+        .url
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty() && !name.contains(['?', '#']))
+        .unwrap_or("ffmpeg-archive");
+    let archive = partial.dir.join(archive_name);
+    let total_bytes = content_length(runner, &source.url, cancel).await?;
+
+    let mut cmd = Command::new("curl");
+    cmd.args(["-fL", "-sS", "--proto", "=https", "--connect-timeout", CONNECT_TIMEOUT_SECS])
+        .arg("-o")
+        .arg(&archive)
+        .arg(&source.url);
+    let download = output_cancellable(runner, &mut cmd, cancel);
+    tokio::pin!(download);
+    let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+    let output = loop {
+        tokio::select! {
+            output = &mut download => break output?,
+            _ = ticker.tick() => on_progress(DownloadProgress {
+                downloaded_bytes: file_len(&archive),
+                total_bytes,
+            }),
+        }
+    };
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(match output.status.code() {
+            Some(code) if CURL_OFFLINE_CODES.contains(&code) => ConvertError::Offline(message),
+            Some(CURL_WRITE_ERROR) => ConvertError::NotWritable(message),
+            _ => format!("Failed to download ffmpeg: {}", message).into(),
+        });
+    }
+    let downloaded_bytes = file_len(&archive);
+    on_progress(DownloadProgress {
+        downloaded_bytes,
+        total_bytes: total_bytes.or(Some(downloaded_bytes)),
+    });
+
+    let expected = source.sha256.trim().to_lowercase();
+    let actual = sha256_file(archive.clone(), cancel.clone()).await?;
+    if actual != expected {
+        return Err(ConvertError::ChecksumMismatch(format!(
+            "expected {}, got {}",
+            expected, actual
+        )));
+    }
+
+    let unpacked = partial.dir.join("unpacked");
+    std::fs::create_dir_all(&unpacked).map_err(|e| not_writable(&unpacked, e))?;
+    let mut cmd = Command::new("tar");
+    cmd.arg("-xf").arg(&archive).arg("-C").arg(&unpacked);
+    let output = output_cancellable(runner, &mut cmd, cancel).await?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to unpack the ffmpeg archive: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    let ffmpeg = install_binary(runner, &unpacked, &install_dir, "ffmpeg")
+        .await?
+        .ok_or("The downloaded archive does not contain ffmpeg")?;
+    let ffprobe = install_binary(runner, &unpacked, &install_dir, "ffprobe").await?;
+    Ok(DownloadedBinaries { ffmpeg, ffprobe })
+}
+
+/// Size the server reports for the archive, after redirects
+async fn content_length(
+    runner: &dyn ProcessRunner,
+    url: &str,
+    cancel: &CancellationToken,
+) -> Result<Option<u64>, ConvertError> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-fsIL", "--proto", "=https", "--connect-timeout", CONNECT_TIMEOUT_SECS]).arg(url);
+    let output = output_cancellable(runner, &mut cmd, cancel).await?;
+    // Not every server answers HEAD; the download itself tells the real story
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .filter_map(|(_, value)| value.trim().parse().ok())
+        .next_back())
+}
+
+/// Move one binary from the unpacked archive into `install_dir` and make it
+/// runnable; None when the archive doesn't have it
+async fn install_binary(
+    runner: &dyn ProcessRunner,
+    unpacked: &Path,
+    install_dir: &Path,
+    name: &str,
+) -> Result<Option<String>, ConvertError> {
+    let file_name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    let Some(found) = find_file(unpacked, &file_name) else {
+        return Ok(None);
+    };
+    let target = install_dir.join(&file_name);
+    std::fs::rename(&found, &target).map_err(|e| not_writable(&target, e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| not_writable(&target, e))?;
+    }
+    if cfg!(target_os = "macos") {
+        // Gatekeeper refuses to run quarantined binaries; missing the
+        // attribute in the first place is fine
+        let mut cmd = Command::new("xattr");
+        cmd.args(["-d", "com.apple.quarantine"]).arg(&target);
+        let _ = output_cancellable(runner, &mut cmd, &CancellationToken::new()).await;
+    }
+    Ok(Some(target.to_string_lossy().to_string()))
+}
+
+/// Find a file by name anywhere under `dir`; static builds keep their
+/// binaries in a versioned folder, often under `bin/`
+fn find_file(dir: &Path, file_name: &str) -> Option<PathBuf> {
+    let mut subdirs = Vec::new();
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_file() && entry.file_name() == file_name {
+            return Some(path);
+        }
+        if file_type.is_dir() {
+            subdirs.push(path);
+        }
+    }
+    subdirs.iter().find_map(|subdir| find_file(subdir, file_name))
+}
+
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+fn not_writable(path: &Path, e: std::io::Error) -> ConvertError {
+    ConvertError::NotWritable(format!("{}: {}", path.display(), e))
+}
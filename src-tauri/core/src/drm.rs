@@ -0,0 +1,132 @@
+//! Copy-protected inputs, which are refused with a clear reason instead of
+//! failing as a generic probe or decode error.
+
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+
+/// Sample entry types of FairPlay (`drmi`, `drms`) and Common Encryption
+/// (`encv`, `enca`) tracks
+const PROTECTED_TAGS: &[&str] = &["drmi", "drms", "drmA", "encv", "enca"];
+/// Extensions the iTunes Store used for protected video and music
+const FAIRPLAY_EXTENSIONS: &[&str] = &["m4v", "m4p"];
+/// `ftyp` major brands of iTunes files
+const FAIRPLAY_BRANDS: &[&[u8; 4]] = &[b"M4V ", b"M4VP", b"M4VH", b"M4P "];
+/// Bytes from the start searched for the protection box
+const HEADER_SCAN_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Whether a successful probe shows an encrypted stream: a protected sample
+/// entry, an `encrypted` disposition or encryption side data
+pub fn probe_shows_protection(json: &Value) -> bool {
+    let Some(streams) = json["streams"].as_array() else {
+        return false;
+    };
+    streams.iter().any(|stream| {
+        let tagged = stream["codec_tag_string"]
+            .as_str()
+            .is_some_and(|tag| PROTECTED_TAGS.contains(&tag));
+        let disposed = stream["disposition"]["encrypted"] == 1;
+        let side_data = stream["side_data_list"].as_array().is_some_and(|list| {
+            list.iter().any(|data| {
+                data["side_data_type"]
+                    .as_str()
+                    .is_some_and(|kind| kind.contains("Encryption"))
+            })
+        });
+        tagged || disposed || side_data
+    })
+}
+
+/// Whether a failed probe is down to FairPlay: ffprobe called the data
+/// invalid, the file looks like an iTunes download, and its header holds a
+/// `sinf` protection box. The box check keeps ordinary corrupt files from
+/// being reported as protected.
+pub fn failure_suggests_fairplay(path: &Path, stderr: &str) -> bool {
+    if !stderr.contains("Invalid data") {
+        return false;
+    }
+    let Some(header) = read_header(path) else {
+        return false;
+    };
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let brand = header.get(8..12);
+    let looks_itunes = FAIRPLAY_EXTENSIONS.contains(&extension.as_str())
+        || brand.is_some_and(|brand| FAIRPLAY_BRANDS.iter().any(|b| b.as_slice() == brand));
+    looks_itunes && header.windows(4).any(|window| window == b"sinf")
+}
+
+fn read_header(path: &Path) -> Option<Vec<u8>> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut header = Vec::new();
+    file.take(HEADER_SCAN_BYTES).read_to_end(&mut header).ok()?;
+    Some(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn probe(stream: Value) -> Value {
+        let audio = json!({"codec_type": "audio", "codec_tag_string": "mp4a"});
+        json!({"streams": [stream, audio]})
+    }
+
+    #[test]
+    fn encrypted_streams_show_in_the_probe() {
+        let plain = json!({"codec_type": "video", "codec_tag_string": "avc1"});
+        let protected = [
+            json!({"codec_type": "video", "codec_tag_string": "encv"}),
+            json!({"codec_type": "video", "codec_tag_string": "drmi"}),
+            json!({"codec_type": "video", "disposition": {"default": 1, "encrypted": 1}}),
+            json!({
+                "codec_type": "video",
+                "side_data_list": [{"side_data_type": "Encryption initialization data"}]
+            }),
+        ];
+        for stream in protected {
+            assert!(probe_shows_protection(&probe(stream.clone())), "{}", stream);
+        }
+        assert!(!probe_shows_protection(&probe(plain)));
+        let clear = json!({
+            "codec_type": "video", "codec_tag_string": "avc1",
+            "disposition": {"default": 1, "encrypted": 0},
+            "side_data_list": [{"side_data_type": "Display Matrix"}]
+        });
+        assert!(!probe_shows_protection(&probe(clear)));
+        assert!(!probe_shows_protection(&json!({})));
+    }
+
+    #[test]
+    fn fairplay_needs_an_itunes_file_with_a_protection_box() {
+        let dir = std::env::temp_dir().join(format!("drm-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, brand: &[u8; 4], boxes: &[u8]| {
+            let path = dir.join(name);
+            let header = [&b"\0\0\0\x18ftyp"[..], brand, b"\0\0\0\0", boxes].concat();
+            std::fs::write(&path, header).unwrap();
+            path
+        };
+        let stderr = "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x1] moov atom not found\n\
+                      movie.m4v: Invalid data found when processing input";
+
+        let store = file("movie.m4v", b"M4V ", b"\0\0\0\x10sinf\0\0\0\0");
+        assert!(failure_suggests_fairplay(&store, stderr));
+        // The brand says iTunes even when the file was renamed
+        let renamed = file("movie.mp4", b"M4VP", b"\0\0\0\x10sinf\0\0\0\0");
+        assert!(failure_suggests_fairplay(&renamed, stderr));
+
+        // A different failure, a corrupt iTunes file without the box, or a
+        // protection box in an ordinary MP4 aren't FairPlay
+        assert!(!failure_suggests_fairplay(&store, "Permission denied"));
+        let corrupt = file("broken.m4v", b"M4V ", b"\0\0\0\x08free");
+        assert!(!failure_suggests_fairplay(&corrupt, stderr));
+        let ordinary = file("clip.mp4", b"isom", b"\0\0\0\x10sinf\0\0\0\0");
+        assert!(!failure_suggests_fairplay(&ordinary, stderr));
+        assert!(!failure_suggests_fairplay(&dir.join("missing.m4v"), stderr));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
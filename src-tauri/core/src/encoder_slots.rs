@@ -0,0 +1,218 @@
+//! Session limits of hardware encoders. NVENC on consumer GPUs, for one,
+//! refuses to open a session past a number its driver sets, so conversions
+//! that would go over wait for a running one to finish instead of failing.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::ConvertError;
+
+/// Hardware encoder families, by the suffix of their ffmpeg encoder names
+pub const ENCODER_FAMILIES: &[&str] = &["nvenc", "videotoolbox", "qsv", "amf", "vaapi"];
+
+/// Sessions a family allows at once unless configured otherwise; families
+/// not listed have no limit. GeForce drivers have allowed as few as two
+/// NVENC sessions.
+pub const DEFAULT_SESSION_LIMITS: &[(&str, usize)] = &[("nvenc", 2)];
+
+/// The hardware family an encoder belongs to, e.g. `nvenc` for
+/// `h264_nvenc`; None for software encoders
+pub fn encoder_family(encoder: &str) -> Option<&'static str> {
+    ENCODER_FAMILIES
+        .iter()
+        .copied()
+        .find(|family| encoder.strip_suffix(family).is_some_and(|rest| rest.ends_with('_')))
+}
+
+#[derive(Debug, Default)]
+struct SlotState {
+    /// Configured limits, over `DEFAULT_SESSION_LIMITS`
+    limits: BTreeMap<String, usize>,
+    in_use: BTreeMap<&'static str, usize>,
+}
+
+/// Sessions in use per encoder family, shared by every conversion
+#[derive(Debug, Default)]
+pub struct EncoderSlots {
+    state: Mutex<SlotState>,
+    freed: Notify,
+}
+
+impl EncoderSlots {
+    /// Replace the configured limits, keyed by family (`nvenc`, `qsv`...)
+    pub fn set_limits(&self, limits: BTreeMap<String, usize>) {
+        self.state.lock().unwrap().limits = limits;
+        // A raised limit can let waiting conversions start
+        self.freed.notify_waiters();
+    }
+
+    /// Sessions `family` allows at once; None when unlimited
+    pub fn limit(&self, family: &str) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        limit_in(&state, family)
+    }
+
+    pub fn in_use(&self, family: &str) -> usize {
+        self.state.lock().unwrap().in_use.get(family).copied().unwrap_or(0)
+    }
+
+    /// A session of `encoder`'s family if one is free. Software encoders and
+    /// unlimited families always get one.
+    pub fn try_acquire(self: &Arc<Self>, encoder: &str) -> Option<EncoderSlot> {
+        let Some(family) = encoder_family(encoder) else {
+            return Some(EncoderSlot { slots: Arc::clone(self), family: None });
+        };
+        let mut state = self.state.lock().unwrap();
+        let used = state.in_use.get(family).copied().unwrap_or(0);
+        if limit_in(&state, family).is_some_and(|limit| used >= limit) {
+            return None;
+        }
+        *state.in_use.entry(family).or_default() += 1;
+        Some(EncoderSlot { slots: Arc::clone(self), family: Some(family) })
+    }
+
+    /// Wait until a session of `encoder`'s family is free
+    pub async fn acquire(
+        self: &Arc<Self>,
+        encoder: &str,
+        cancel: &CancellationToken,
+    ) -> Result<EncoderSlot, ConvertError> {
+        loop {
+            // Registered before checking, so a release in between isn't missed
+            let freed = self.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            if let Some(slot) = self.try_acquire(encoder) {
+                return Ok(slot);
+            }
+            tokio::select! {
+                _ = freed => {}
+                _ = cancel.cancelled() => return Err(ConvertError::Cancelled),
+            }
+        }
+    }
+}
+
+fn limit_in(state: &SlotState, family: &str) -> Option<usize> {
+    state.limits.get(family).copied().or_else(|| {
+        DEFAULT_SESSION_LIMITS
+            .iter()
+            .find(|(name, _)| *name == family)
+            .map(|(_, limit)| *limit)
+    })
+}
+
+/// A session held for as long as this lives
+#[derive(Debug)]
+pub struct EncoderSlot {
+    slots: Arc<EncoderSlots>,
+    family: Option<&'static str>,
+}
+
+impl Drop for EncoderSlot {
+    fn drop(&mut self) {
+        let Some(family) = self.family else {
+            return;
+        };
+        if let Some(used) = self.slots.state.lock().unwrap().in_use.get_mut(family) {
+            *used = used.saturating_sub(1);
+        }
+        self.slots.freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn slots(limits: &[(&str, usize)]) -> Arc<EncoderSlots> {
+        let slots = Arc::new(EncoderSlots::default());
+        slots.set_limits(limits.iter().map(|(family, n)| (family.to_string(), *n)).collect());
+        slots
+    }
+
+    #[test]
+    fn names_hardware_families() {
+        assert_eq!(encoder_family("h264_nvenc"), Some("nvenc"));
+        assert_eq!(encoder_family("hevc_videotoolbox"), Some("videotoolbox"));
+        assert_eq!(encoder_family("libx264"), None);
+        assert_eq!(encoder_family("nvenc"), None);
+    }
+
+    #[test]
+    fn try_acquire_honours_the_configured_limits() {
+        let slots = slots(&[("qsv", 1), ("nvenc", 3)]);
+        let qsv = slots.try_acquire("h264_qsv").unwrap();
+        assert!(slots.try_acquire("hevc_qsv").is_none());
+        // Configured limits replace the defaults
+        let nvenc: Vec<EncoderSlot> =
+            (0..3).map(|_| slots.try_acquire("h264_nvenc").unwrap()).collect();
+        assert!(slots.try_acquire("h264_nvenc").is_none());
+        assert_eq!((slots.in_use("qsv"), slots.in_use("nvenc")), (1, 3));
+        // Families without a limit and software encoders always get one
+        let unlimited: Vec<EncoderSlot> =
+            (0..5).map(|_| slots.try_acquire("h264_amf").unwrap()).collect();
+        assert!(slots.try_acquire("libx264").is_some());
+        assert_eq!(slots.in_use("amf"), 5);
+        drop((qsv, nvenc, unlimited));
+
+        let defaults = Arc::new(EncoderSlots::default());
+        assert_eq!(defaults.limit("nvenc"), Some(2));
+        assert_eq!(defaults.limit("qsv"), None);
+    }
+
+    #[test]
+    fn dropping_a_slot_frees_it() {
+        let slots = slots(&[("nvenc", 1)]);
+        let slot = slots.try_acquire("h264_nvenc").unwrap();
+        assert_eq!(slots.in_use("nvenc"), 1);
+        drop(slot);
+        assert_eq!(slots.in_use("nvenc"), 0);
+        assert!(slots.try_acquire("h264_nvenc").is_some());
+        assert_eq!(slots.in_use("nvenc"), 0);
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_for_a_free_slot() {
+        let slots = slots(&[("nvenc", 1)]);
+        let held = slots.try_acquire("h264_nvenc").unwrap();
+        let waiting = {
+            let slots = Arc::clone(&slots);
+            tokio::spawn(async move { slots.acquire("h264_nvenc", &CancellationToken::new()).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(held);
+        let slot = tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap();
+        assert!(slot.is_ok());
+        assert_eq!(slots.in_use("nvenc"), 1);
+    }
+
+    #[tokio::test]
+    async fn a_raised_limit_or_a_cancel_ends_the_wait() {
+        let slots = slots(&[("nvenc", 1)]);
+        let _held = slots.try_acquire("h264_nvenc").unwrap();
+        let cancel = CancellationToken::new();
+        let waiting = {
+            let (slots, cancel) = (Arc::clone(&slots), cancel.clone());
+            tokio::spawn(async move { slots.acquire("h264_nvenc", &cancel).await })
+        };
+        cancel.cancel();
+        let result = tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap();
+        assert_eq!(result.err(), Some(ConvertError::Cancelled));
+
+        let waiting = {
+            let slots = Arc::clone(&slots);
+            tokio::spawn(async move { slots.acquire("h264_nvenc", &CancellationToken::new()).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        slots.set_limits([("nvenc".to_string(), 2)].into());
+        let result = tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap();
+        assert!(result.is_ok());
+        assert_eq!(slots.in_use("nvenc"), 2);
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::analysis::TrimRange;
+use crate::validation::OptionError;
+
+/// Error returned by commands, serialized as `{ "kind": ..., "message": ... }`
+/// so the frontend can react to specific failures without string matching.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum ConvertError {
+    /// A path was refused because ffmpeg could treat it as something other
+    /// than a plain local file (protocol, option, device...)
+    Security(String),
+    /// The path is outside what the app is allowed to touch
+    PermissionDenied(String),
+    /// The operation was cancelled by the user
+    Cancelled,
+    /// ffprobe didn't answer in time; carries the path being probed
+    ProbeTimeout(String),
+    /// The input went away, usually with its drive or network share; carries
+    /// the path
+    InputUnavailable(String),
+    /// A download couldn't reach its server; carries curl's message
+    Offline(String),
+    /// A downloaded file doesn't match its published checksum
+    ChecksumMismatch(String),
+    /// A directory the app needs to write to refused it; carries the path
+    /// and the reason
+    NotWritable(String),
+    /// The conversion options don't work, alone or for this input; carries
+    /// every problem found
+    InvalidOptions(Vec<OptionError>),
+    /// ffmpeg reported success but the output doesn't hold what was planned;
+    /// carries every mismatch. The file is kept for inspection.
+    VerificationFailed(String),
+    /// The input is copy-protected (FairPlay or similar); carries the path
+    DrmProtected(String),
+    /// `abort_if_larger_than_input` stopped an output that outgrew its
+    /// source; carries the sizes
+    OutputTooLarge(String),
+    /// ffmpeg failed after getting part of the way through; `sample` is a
+    /// slice of the source around where it stopped, for `convert_sample`
+    EncodeFailed { message: String, sample: TrimRange },
+    /// Anything else; the message is meant for display
+    Failed(String),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Security(message) => write!(f, "Security error: {}", message),
+            ConvertError::PermissionDenied(message) => write!(f, "Permission denied: {}", message),
+            ConvertError::Cancelled => write!(f, "Cancelled"),
+            ConvertError::ProbeTimeout(path) => write!(
+                f,
+                "Timed out reading {}. It may be on a disconnected network share or not \
+                 downloaded yet; for a slow drive, raise the probe timeout.",
+                path
+            ),
+            ConvertError::InputUnavailable(path) => write!(
+                f,
+                "{} is no longer available. Reconnect the drive or network share and try again.",
+                path
+            ),
+            ConvertError::Offline(message) => write!(
+                f,
+                "Could not reach the download server ({}). Check the internet connection and \
+                 try again.",
+                message
+            ),
+            ConvertError::ChecksumMismatch(message) => write!(
+                f,
+                "The download is corrupted or was tampered with: checksum {}",
+                message
+            ),
+            ConvertError::NotWritable(message) => write!(f, "Cannot write to {}", message),
+            ConvertError::InvalidOptions(errors) => {
+                let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+                write!(f, "{}", messages.join("; "))
+            }
+            ConvertError::VerificationFailed(message) => {
+                write!(f, "The output failed verification: {}", message)
+            }
+            ConvertError::DrmProtected(path) => write!(
+                f,
+                "{} is copy-protected (DRM). Protected videos can't be converted; use an \
+                 unprotected copy instead.",
+                path
+            ),
+            ConvertError::OutputTooLarge(message) => write!(
+                f,
+                "Stopped because the output grew larger than the source ({}). Try a lower \
+                 quality setting, or keep the original.",
+                message
+            ),
+            ConvertError::EncodeFailed { message, sample } => write!(
+                f,
+                "{}. To reproduce it without converting the whole file again, convert a \
+                 sample from {:.1}s to {:.1}s.",
+                message, sample.start_seconds, sample.end_seconds
+            ),
+            ConvertError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ConvertError {
+    /// The serialized `kind`, e.g. `input_unavailable`
+    pub fn code(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value["kind"].as_str().map(str::to_string))
+            .unwrap_or_else(|| "failed".to_string())
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+impl From<String> for ConvertError {
+    fn from(message: String) -> Self {
+        ConvertError::Failed(message)
+    }
+}
+
+impl From<&str> for ConvertError {
+    fn from(message: &str) -> Self {
+        ConvertError::Failed(message.to_string())
+    }
+}
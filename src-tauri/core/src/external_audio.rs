@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::error::ConvertError;
+use crate::paths::ffmpeg_path_arg;
+use crate::process::output_with_timeout;
+use crate::resolver::FfmpegResolver;
+
+/// Sample rates every AAC player handles; anything else is resampled
+const PORTABLE_SAMPLE_RATES: &[u32] = &[44_100, 48_000];
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// What happens to the video's own audio when a separate track is muxed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioMuxMode {
+    /// Drop the original audio and use the file instead
+    #[default]
+    Replace,
+    /// Keep the original as track 1 and add the file as track 2
+    Add,
+}
+
+/// A separately recorded audio file to marry with the video
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalAudio {
+    pub path: String,
+    #[serde(default)]
+    pub mode: AudioMuxMode,
+    /// Language tag for an added track, e.g. `eng`
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Track title for an added track, e.g. `Lav mic`
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+/// The first audio stream of an external audio file
+#[derive(Debug, Clone)]
+pub struct AudioFileInfo {
+    pub codec: String,
+    pub duration: f64,
+    pub sample_rate: u32,
+}
+
+impl AudioFileInfo {
+    /// Filters that fit the track to a video of `video_duration` seconds
+    /// (source timeline). Longer audio is cut by `-shortest` instead.
+    pub fn fit_filters(&self, video_duration: f64) -> Vec<String> {
+        let mut filters = Vec::new();
+        if !PORTABLE_SAMPLE_RATES.contains(&self.sample_rate) {
+            filters.push(format!("aresample={}", DEFAULT_SAMPLE_RATE));
+        }
+        if self.duration < video_duration {
+            filters.push("apad".to_string());
+        }
+        filters
+    }
+}
+
+pub async fn probe_audio_file(
+    resolver: &FfmpegResolver,
+    path: &Path,
+) -> Result<AudioFileInfo, ConvertError> {
+    let ffprobe_path = resolver.ffprobe().await?;
+    let mut cmd = Command::new(&ffprobe_path);
+    cmd.args([
+        "-v",
+        "quiet",
+        "-print_format",
+        "json",
+        "-show_format",
+        "-show_streams",
+        "-select_streams",
+        "a:0",
+    ])
+    .arg(ffmpeg_path_arg(path));
+    let output = output_with_timeout(resolver.runner(), &mut cmd, resolver.probe_timeout())
+        .await
+        .map_err(|e| format!("Failed to read audio file: {}", e))?
+        .ok_or_else(|| ConvertError::ProbeTimeout(path.display().to_string()))?;
+    if !output.status.success() {
+        return Err("Failed to probe audio file".into());
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+    let stream = json["streams"]
+        .as_array()
+        .and_then(|streams| streams.first())
+        .ok_or("No audio stream found in the audio file")?;
+    let parse_str = |value: &serde_json::Value| value.as_str().and_then(|v| v.parse::<f64>().ok());
+    Ok(AudioFileInfo {
+        codec: stream["codec_name"].as_str().unwrap_or("unknown").to_string(),
+        duration: parse_str(&json["format"]["duration"])
+            .or_else(|| parse_str(&stream["duration"]))
+            .unwrap_or(0.0),
+        sample_rate: stream["sample_rate"].as_str().and_then(|r| r.parse().ok()).unwrap_or(0),
+    })
+}
@@ -0,0 +1,120 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::{get_video_info, output_file_name, ConversionOptions};
+use crate::error::ConvertError;
+use crate::naming::{resolve_output_path, CollisionPolicy};
+use crate::paths::{ffmpeg_path_arg, validate_output_dir};
+use crate::process::output_cancellable;
+use crate::resolver::FfmpegResolver;
+use crate::task_log::TaskLog;
+
+/// Top-level boxes looked at before giving up; real files have a handful
+const MAX_BOXES: usize = 64;
+
+/// Whether an MP4/MOV file has its `moov` index ahead of `mdat`, so players
+/// can start before the whole file has downloaded.
+///
+/// Only box headers are read, seeking over the contents, so this is cheap
+/// even for large files. Anything unreadable counts as not faststart.
+pub fn is_faststart(path: &Path) -> bool {
+    scan_boxes(path, |kind| match kind {
+        b"moov" => Some(true),
+        b"mdat" => Some(false),
+        _ => None,
+    })
+}
+
+/// Whether an MP4/MOV file is fragmented: its media sits in `moof`
+/// fragments after an (often empty) `moov`, which several players can't
+/// seek in. Read as cheaply as [`is_faststart`].
+pub fn is_fragmented(path: &Path) -> bool {
+    scan_boxes(path, |kind| (kind == b"moof").then_some(true))
+}
+
+/// Walk the top-level box headers until `decide` returns an answer; false
+/// when none does or the file can't be read
+fn scan_boxes(path: &Path, decide: impl Fn(&[u8]) -> Option<bool>) -> bool {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return false;
+    };
+    for _ in 0..MAX_BOXES {
+        let mut header = [0u8; 8];
+        if file.read_exact(&mut header).is_err() {
+            return false;
+        }
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        if let Some(answer) = decide(&header[4..8]) {
+            return answer;
+        }
+        let skip = match size {
+            // The box runs to the end of the file
+            0 => return false,
+            1 => {
+                let mut large = [0u8; 8];
+                if file.read_exact(&mut large).is_err() {
+                    return false;
+                }
+                u64::from_be_bytes(large).saturating_sub(16)
+            }
+            size => size.saturating_sub(8),
+        };
+        if file.seek(SeekFrom::Current(skip as i64)).is_err() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Rewrite a compatible MP4 with its index at the front, copying every
+/// stream. Files that are already faststart, or need a real conversion,
+/// are refused so this never rewrites anything for nothing.
+pub async fn optimize_faststart(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+) -> Result<String, ConvertError> {
+    let info = get_video_info(resolver, input_path).await?;
+    // A fragmented file's `moov` comes first too, but the remux is its fix
+    if info.is_faststart && !info.is_fragmented {
+        return Err("The file is already optimized for streaming".into());
+    }
+    if info.needs_conversion && !info.only_needs_defragment() {
+        return Err("The file needs a full conversion, not just a remux".into());
+    }
+    let output_dir = validate_output_dir(output_dir)?;
+    let file_name = output_file_name(&info, None, &ConversionOptions::default())?;
+    let output_path = resolve_output_path(&output_dir, &file_name, CollisionPolicy::Rename);
+    if output_path == Path::new(&info.path) {
+        return Err("The optimized file would replace the original".into());
+    }
+
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin", "-n", "-i"])
+        .arg(ffmpeg_path_arg(Path::new(&info.path)))
+        // Telemetry and timecode tracks (GoPro, DJI) make the mp4 muxer fail
+        .args(["-map", "0", "-map", "-0:d", "-c", "copy", "-movflags", "+faststart"])
+        .arg(ffmpeg_path_arg(&output_path));
+    let args: Vec<String> =
+        cmd.as_std().get_args().map(|a| a.to_string_lossy().to_string()).collect();
+    log.command(&ffmpeg_path, &args);
+
+    let result = output_cancellable(resolver.runner(), &mut cmd, cancel).await;
+    match result {
+        Ok(output) if output.status.success() => Ok(output_path.to_string_lossy().to_string()),
+        Ok(output) => {
+            let _ = std::fs::remove_file(&output_path);
+            Err(format!("Failed to optimize file: ffmpeg exited with status: {}", output.status)
+                .into())
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&output_path);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Inputs that are still being written, such as an OBS recording in
+//! progress or a download that hasn't finished.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::ConvertError;
+
+/// Time between size samples
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the size has to hold still before the file counts as finished
+const STABLE_FOR: Duration = Duration::from_secs(5);
+/// Give up on a file that is still growing after this long
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Wait until `path` stops growing. Two samples a second apart decide
+/// whether it is growing at all; only then is `on_wait` called and the file
+/// watched until its size has held for a few seconds.
+pub async fn wait_until_stable<F>(
+    path: &Path,
+    cancel: &CancellationToken,
+    on_wait: F,
+) -> Result<(), ConvertError>
+where
+    F: FnOnce(),
+{
+    let mut size = file_len(path).await;
+    pause(cancel).await?;
+    if file_len(path).await == size {
+        return Ok(());
+    }
+
+    on_wait();
+    let started = Instant::now();
+    let mut stable_since = Instant::now();
+    loop {
+        if started.elapsed() > WAIT_TIMEOUT {
+            return Err(ConvertError::Failed(format!(
+                "{} was still being written after {} minutes",
+                path.display(),
+                WAIT_TIMEOUT.as_secs() / 60
+            )));
+        }
+        pause(cancel).await?;
+        let current = file_len(path).await;
+        if current != size {
+            size = current;
+            stable_since = Instant::now();
+        } else if stable_since.elapsed() >= STABLE_FOR {
+            return Ok(());
+        }
+    }
+}
+
+async fn file_len(path: &Path) -> Option<u64> {
+    tokio::fs::metadata(path).await.ok().map(|m| m.len())
+}
+
+/// Sleep one poll interval, or stop right away on cancellation
+async fn pause(cancel: &CancellationToken) -> Result<(), ConvertError> {
+    tokio::select! {
+        _ = cancel.cancelled() => Err(ConvertError::Cancelled),
+        _ = tokio::time::sleep(POLL_INTERVAL) => Ok(()),
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hashing::is_sha256;
+use crate::naming::civil_date;
+
+/// One finished conversion, successful or not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// UTC time the conversion ended, `YYYY-MM-DDTHH:MM:SSZ`
+    pub finished_at: String,
+    pub input_path: String,
+    pub output_path: Option<String>,
+    pub source_codec: String,
+    pub success: bool,
+    pub input_bytes: u64,
+    /// Zero for failed conversions
+    pub output_bytes: u64,
+    /// Wall-clock time the conversion took
+    pub encode_seconds: f64,
+    /// With `compute_hashes`; absent from older entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_sha256: Option<String>,
+    /// Absent from older entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    /// The first failed task of those this one retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_of: Option<String>,
+    /// `kind` of the error of a failed conversion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+/// Totals for one slice of the history
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryTotals {
+    pub conversions: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Input and output sizes of the successful conversions
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// Input minus output over successful conversions that got smaller
+    pub bytes_saved: u64,
+    pub encode_seconds: f64,
+}
+
+impl HistoryTotals {
+    fn add(&mut self, entry: &HistoryEntry) {
+        self.conversions += 1;
+        if entry.success {
+            self.succeeded += 1;
+            self.input_bytes += entry.input_bytes;
+            self.output_bytes += entry.output_bytes;
+            self.bytes_saved += entry.input_bytes.saturating_sub(entry.output_bytes);
+        } else {
+            self.failed += 1;
+        }
+        self.encode_seconds += entry.encode_seconds;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Statistics {
+    pub totals: HistoryTotals,
+    /// Keyed by the source video codec, e.g. `hevc`
+    pub by_codec: BTreeMap<String, HistoryTotals>,
+    /// Keyed by `YYYY-MM`
+    pub by_month: BTreeMap<String, HistoryTotals>,
+}
+
+/// Every finished conversion, appended as a JSON line to `history.jsonl`
+/// in the app data dir.
+///
+/// Entries are never rewritten, so deleting an output later doesn't change
+/// what has been converted. Writing is best effort like the task logs.
+pub struct HistoryStore {
+    path: Option<PathBuf>,
+    lock: Mutex<()>,
+}
+
+impl HistoryStore {
+    pub fn new(data_dir: Option<&Path>) -> Self {
+        HistoryStore {
+            path: data_dir.map(|dir| dir.join("history.jsonl")),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let Ok(line) = serde_json::to_string(entry) else {
+            return;
+        };
+        let _guard = self.lock.lock().unwrap();
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    /// Add up the history one line at a time, so its size doesn't matter.
+    /// `since` is a `YYYY-MM-DD` date; earlier entries are left out.
+    /// Unreadable lines (a crash mid-write) are skipped.
+    pub fn statistics(&self, since: Option<&str>) -> Result<Statistics, String> {
+        if let Some(date) = since {
+            let bytes = date.as_bytes();
+            let valid = bytes.len() == 10
+                && bytes[4] == b'-'
+                && bytes[7] == b'-'
+                && date.chars().enumerate().all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit());
+            if !valid {
+                return Err(format!("Invalid date: {} (expected YYYY-MM-DD)", date));
+            }
+        }
+
+        let mut statistics = Statistics::default();
+        let Some(path) = &self.path else {
+            return Ok(statistics);
+        };
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(statistics),
+            Err(e) => return Err(format!("Failed to read history: {}", e)),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("Failed to read history: {}", e))?;
+            let Ok(entry) = serde_json::from_str::<HistoryEntry>(&line) else {
+                continue;
+            };
+            // Timestamps sort as text, so a date compares against them directly
+            if since.is_some_and(|date| entry.finished_at.as_str() < date) {
+                continue;
+            }
+            statistics.totals.add(&entry);
+            statistics.by_codec.entry(entry.source_codec.clone()).or_default().add(&entry);
+            let month = entry.finished_at.get(..7).unwrap_or_default().to_string();
+            statistics.by_month.entry(month).or_default().add(&entry);
+        }
+        Ok(statistics)
+    }
+
+    /// Entries whose input or output had this SHA-256, oldest first; a
+    /// source converted before shows up by its input hash
+    pub fn find_by_hash(&self, hash: &str) -> Result<Vec<HistoryEntry>, String> {
+        if !is_sha256(hash) {
+            return Err(format!("Invalid SHA-256: {}", hash));
+        }
+        let hash = hash.to_ascii_lowercase();
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read history: {}", e)),
+        };
+        let mut found = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("Failed to read history: {}", e))?;
+            // Cheap check first; most lines have neither hash
+            if !line.contains(&hash) {
+                continue;
+            }
+            let Ok(entry) = serde_json::from_str::<HistoryEntry>(&line) else {
+                continue;
+            };
+            let matches = |own: &Option<String>| own.as_deref() == Some(hash.as_str());
+            if matches(&entry.input_sha256) || matches(&entry.output_sha256) {
+                found.push(entry);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The current UTC time as `YYYY-MM-DDTHH:MM:SSZ`
+pub fn utc_timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let time = secs % 86_400;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        civil_date((secs / 86_400) as i64),
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::naming::CollisionPolicy;
+
+/// Segment length used unless the job asks for another
+pub const DEFAULT_HLS_SECONDS: f64 = 6.0;
+/// The playlist's name inside the video's folder
+const PLAYLIST_NAME: &str = "index.m3u8";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One faststart `.mp4` file
+    #[default]
+    Mp4,
+    /// A VOD playlist plus segments in a folder of their own
+    Hls,
+    /// One `.mkv` file that keeps every subtitle, attachment (fonts) and
+    /// audio track of the source as-is
+    Mkv,
+}
+
+impl OutputFormat {
+    /// Extension of the output file, or of the name HLS folders are named
+    /// after
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 | OutputFormat::Hls => "mp4",
+            OutputFormat::Mkv => "mkv",
+        }
+    }
+}
+
+/// Container of HLS segments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HlsSegmentType {
+    /// MPEG-TS `.ts` segments, which every HLS player takes
+    #[default]
+    Mpegts,
+    /// Fragmented MP4 `.m4s` segments after an `init.mp4`
+    Fmp4,
+}
+
+pub fn validate_hls_seconds(seconds: f64) -> Result<(), String> {
+    if !(1.0..=60.0).contains(&seconds) {
+        return Err(format!("HLS segments must be 1 to 60 seconds long, got {}", seconds));
+    }
+    Ok(())
+}
+
+/// Folder a video's HLS files go in, named after the would-be `.mp4` output.
+/// An existing folder counts as a collision, like an existing file would;
+/// overwriting only ever reuses a folder that holds an earlier playlist.
+pub fn resolve_hls_dir(output_path: &Path, policy: CollisionPolicy) -> PathBuf {
+    let stem = output_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let dir = output_path.with_file_name(&stem);
+    let reusable = policy == CollisionPolicy::Overwrite && playlist_path(&dir).exists();
+    if reusable || !dir.exists() {
+        return dir;
+    }
+    (1..)
+        .map(|n| output_path.with_file_name(format!("{} ({})", stem, n)))
+        .find(|dir| !dir.exists())
+        .unwrap_or(dir)
+}
+
+pub fn playlist_path(hls_dir: &Path) -> PathBuf {
+    hls_dir.join(PLAYLIST_NAME)
+}
+
+/// Output arguments that replace the plain mp4 muxer; the output path is
+/// then the playlist
+pub fn hls_args(
+    hls_dir: &Path,
+    seconds: f64,
+    segment_type: HlsSegmentType,
+    encodes_video: bool,
+) -> Vec<String> {
+    let mut args = Vec::new();
+    if encodes_video {
+        // Keyframes on the boundaries keep segments the advertised length;
+        // copied video can only be cut on the keyframes it already has
+        args.push("-force_key_frames".to_string());
+        args.push(format!("expr:gte(t,n_forced*{:.3})", seconds));
+    }
+    args.extend(
+        ["-f", "hls", "-hls_time", &format!("{:.3}", seconds), "-hls_playlist_type", "vod"]
+            .iter()
+            .map(|a| a.to_string()),
+    );
+    // Segment names are written into the playlist as given, so they are
+    // plain file names resolved next to it
+    let pattern = match segment_type {
+        HlsSegmentType::Mpegts => "segment_%03d.ts",
+        HlsSegmentType::Fmp4 => {
+            args.extend(["-hls_segment_type", "fmp4", "-hls_fmp4_init_filename", "init.mp4"]
+                .iter()
+                .map(|a| a.to_string()));
+            "segment_%03d.m4s"
+        }
+    };
+    let dir = hls_dir.to_string_lossy().replace('%', "%%");
+    args.push("-hls_segment_filename".to_string());
+    args.push(Path::new(&dir).join(pattern).to_string_lossy().to_string());
+    args
+}
+
+/// Segments listed in a finished playlist
+pub fn count_playlist_segments(playlist: &Path) -> usize {
+    std::fs::read_to_string(playlist)
+        .map(|text| {
+            text.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#')).count()
+        })
+        .unwrap_or(0)
+}
+
+/// Total size of the playlist and everything next to it
+pub fn hls_dir_bytes(hls_dir: &Path) -> u64 {
+    std::fs::read_dir(hls_dir)
+        .map(|entries| {
+            entries.flatten().filter_map(|entry| entry.metadata().ok()).map(|m| m.len()).sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Delete an HLS output given its playlist path, segments and all
+pub fn remove_hls_output(playlist: &Path) {
+    if let Some(dir) = playlist.parent() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
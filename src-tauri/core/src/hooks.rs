@@ -0,0 +1,397 @@
+//! Follow-up actions run on each finished output: moving it into a folder,
+//! copying it somewhere, or running a program on it.
+//!
+//! Hooks are configured in the settings and off until enabled there. They
+//! run in order once a conversion has succeeded and its output was
+//! verified. A hook that fails or runs out of time becomes a warning on the
+//! result; the conversion itself still succeeded.
+//!
+//! Templates take `{token}`s from `TOKENS` and nothing else. Commands are
+//! an argv like everywhere else in the crate: each argument is substituted
+//! on its own and the program is started directly, never through a shell,
+//! so a file name with spaces or `;` stays one argument.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+
+use crate::converter::{replace_file, ConversionResult, StreamAction};
+use crate::naming::{civil_date, expand_tokens, parse_template, resolve_output_path, CollisionPolicy};
+use crate::paths::{create_output_subdir, validate_output_dir};
+use crate::process::{output_with_timeout, ProcessRunner};
+use crate::task_log::TaskLog;
+
+/// How long a hook may take unless it sets its own limit
+pub const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 300;
+
+/// Tokens hook templates may use
+pub const TOKENS: &[&str] = &[
+    "output_path",
+    "output_dir",
+    "output_name",
+    "output_stem",
+    "output_bytes",
+    "codec",
+    "input_path",
+    "date",
+];
+
+/// One follow-up action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HookAction {
+    /// Move the output into the folder the template expands to, e.g.
+    /// `/Volumes/Archive/{date}`. The absolute folder it starts from must
+    /// exist; the folders under it are created if needed.
+    MoveTo { dir_template: String },
+    /// Copy the output into an absolute folder that must exist; later hooks
+    /// still see the original
+    CopyTo { dir: String },
+    /// Run a program, e.g. `["rsync", "-a", "{output_path}", "nas:/videos/"]`
+    RunCommand { argv: Vec<String> },
+}
+
+/// A hook and how long it may take
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostHook {
+    #[serde(flatten)]
+    pub action: HookAction,
+    /// None uses `DEFAULT_HOOK_TIMEOUT_SECS`
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+impl PostHook {
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(DEFAULT_HOOK_TIMEOUT_SECS))
+    }
+
+    fn describe(&self) -> String {
+        match &self.action {
+            HookAction::MoveTo { dir_template } => format!("move to {}", dir_template),
+            HookAction::CopyTo { dir } => format!("copy to {}", dir),
+            HookAction::RunCommand { argv } => {
+                format!("run {}", argv.first().map_or("", String::as_str))
+            }
+        }
+    }
+}
+
+/// Check hooks before they are saved: known tokens only, balanced braces,
+/// folders that start from an existing absolute folder, a program to run
+/// and a timeout above zero
+pub fn validate_hooks(hooks: &[PostHook]) -> Result<(), String> {
+    for hook in hooks {
+        if hook.timeout_secs == Some(0) {
+            return Err(format!("Hook \"{}\" needs a timeout above zero", hook.describe()));
+        }
+        match &hook.action {
+            HookAction::MoveTo { dir_template } => check_dir_template(dir_template)?,
+            HookAction::CopyTo { dir } => check_dir_template(dir)?,
+            HookAction::RunCommand { argv } => {
+                if argv.first().is_none_or(|program| program.trim().is_empty()) {
+                    return Err("A run_command hook needs a program to run".to_string());
+                }
+                for arg in argv {
+                    check_template(arg)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_template(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("Hook folders and arguments cannot be empty".to_string());
+    }
+    parse_template(template, TOKENS, "hook template").map(|_| ())
+}
+
+/// Split a folder template into the folder it starts from and the part
+/// the tokens fill in under it: `/Volumes/Archive/{date}` into
+/// `/Volumes/Archive/` and `{date}`. `{output_dir}` can start one too.
+fn split_dir_template(template: &str) -> (&str, &str) {
+    let (root, relative) = match template.strip_prefix("{output_dir}") {
+        Some(rest) => ("{output_dir}", rest),
+        None => match template.find('{') {
+            Some(open) => {
+                let cut = template[..open].rfind(['/', '\\']).map_or(0, |i| i + 1);
+                template.split_at(cut)
+            }
+            None => (template, ""),
+        },
+    };
+    (root, relative.trim_start_matches(['/', '\\']))
+}
+
+fn check_dir_template(template: &str) -> Result<(), String> {
+    check_template(template)?;
+    match split_dir_template(template).0 {
+        "{output_dir}" => Ok(()),
+        root => validate_output_dir(root).map(|_| ()).map_err(|e| {
+            format!("Hook folders must start from an existing absolute folder: {}", e)
+        }),
+    }
+}
+
+/// Values of the tokens for one output
+#[derive(Clone)]
+struct HookContext {
+    output_path: PathBuf,
+    output_bytes: u64,
+    codec: String,
+    input_path: String,
+    date: String,
+}
+
+impl HookContext {
+    /// Expand a folder template into the canonical folder it names, which
+    /// has to stay under the folder the template starts from. `create`
+    /// makes the folders under that one.
+    fn expand_dir(&self, template: &str, create: bool) -> Result<PathBuf, String> {
+        let (root, relative) = split_dir_template(template);
+        let root = validate_output_dir(&self.expand(root)?).map_err(|e| e.to_string())?;
+        let relative = self.expand(relative)?;
+        if relative.is_empty() {
+            return Ok(root);
+        }
+        if create {
+            return create_output_subdir(&root, Path::new(&relative)).map_err(|e| e.to_string());
+        }
+        let dir = validate_output_dir(&root.join(&relative).to_string_lossy())
+            .map_err(|e| e.to_string())?;
+        if !dir.starts_with(&root) {
+            return Err(format!("Folder {} is outside {}", dir.display(), root.display()));
+        }
+        Ok(dir)
+    }
+
+    fn expand(&self, template: &str) -> Result<String, String> {
+        let file_part = |part: Option<&std::ffi::OsStr>| {
+            part.map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+        };
+        let dir = self.output_path.parent().map(Path::as_os_str);
+        expand_tokens(template, TOKENS, "hook template", |token| match token {
+            "output_path" => self.output_path.to_string_lossy().to_string(),
+            "output_dir" => file_part(dir),
+            "output_name" => file_part(self.output_path.file_name()),
+            "output_stem" => file_part(self.output_path.file_stem()),
+            "output_bytes" => self.output_bytes.to_string(),
+            "codec" => self.codec.clone(),
+            "input_path" => self.input_path.clone(),
+            _ => self.date.clone(),
+        })
+    }
+}
+
+/// The video codec of the output: the source's when copied, else the one
+/// the encoder writes
+fn output_codec(action: &StreamAction, source_codec: &str) -> String {
+    let StreamAction::Encoded(encoder) = action else {
+        return source_codec.to_string();
+    };
+    if encoder.contains("264") {
+        "h264".to_string()
+    } else if encoder.contains("265") || encoder.contains("hevc") {
+        "hevc".to_string()
+    } else {
+        encoder.clone()
+    }
+}
+
+/// Run `hooks` in order on a finished conversion. `result.output_path`
+/// follows the file when a hook moves it, and every failure is added to
+/// `result.warnings`.
+pub async fn run_post_hooks(
+    runner: &dyn ProcessRunner,
+    hooks: &[PostHook],
+    input_path: &str,
+    source_codec: &str,
+    result: &mut ConversionResult,
+    log: &TaskLog,
+) {
+    if hooks.is_empty() {
+        return;
+    }
+    // A playlist, its segments or several renditions can't be moved as one file
+    if !result.segment_paths.is_empty()
+        || !result.rendition_paths.is_empty()
+        || result.hls_segment_count > 0
+    {
+        result.warnings.push(
+            "Post-conversion hooks were skipped: they only handle single-file outputs".to_string(),
+        );
+        return;
+    }
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86_400);
+    let mut context = HookContext {
+        output_path: PathBuf::from(&result.output_path),
+        output_bytes: result.output_bytes,
+        codec: output_codec(&result.video_action, source_codec),
+        input_path: input_path.to_string(),
+        date: civil_date(days as i64),
+    };
+    for hook in hooks {
+        log.line(&format!("Post hook: {}", hook.describe()));
+        match run_hook(runner, hook, &context).await {
+            Ok(Some(moved_to)) => {
+                result.output_path = moved_to.to_string_lossy().to_string();
+                context.output_path = moved_to;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log.line(&format!("Post hook failed: {}", e));
+                result.warnings.push(format!("Post hook \"{}\" failed: {}", hook.describe(), e));
+            }
+        }
+    }
+}
+
+/// Run one hook; where the output went when it was moved
+async fn run_hook(
+    runner: &dyn ProcessRunner,
+    hook: &PostHook,
+    context: &HookContext,
+) -> Result<Option<PathBuf>, String> {
+    let timeout = hook.timeout();
+    match &hook.action {
+        HookAction::MoveTo { dir_template } => {
+            // Creating the folders can hang on a share just like the move
+            let (context, dir_template) = (context.clone(), dir_template.clone());
+            let moved = tokio::task::spawn_blocking(move || {
+                let dir = context.expand_dir(&dir_template, true)?;
+                move_into(&context.output_path, &dir)
+            });
+            match tokio::time::timeout(timeout, moved).await {
+                Ok(moved) => {
+                    moved.map_err(|e| format!("Failed to move the output: {}", e))?.map(Some)
+                }
+                // The move can't be taken back halfway; it finishes on its own
+                Err(_) => Err(format!(
+                    "Gave up waiting after {}s; the move goes on in the background",
+                    timeout.as_secs()
+                )),
+            }
+        }
+        HookAction::CopyTo { dir } => {
+            let dir = context.expand_dir(dir, false)?;
+            let name = context.output_path.file_name().unwrap_or_default();
+            let target =
+                resolve_output_path(&dir, &name.to_string_lossy(), CollisionPolicy::Rename);
+            let copied = tokio::fs::copy(&context.output_path, &target);
+            match tokio::time::timeout(timeout, copied).await {
+                Ok(copied) => {
+                    copied.map(|_| None).map_err(|e| format!("Failed to copy the output: {}", e))
+                }
+                Err(_) => {
+                    let _ = std::fs::remove_file(&target);
+                    Err(format!("Copy timed out after {}s", timeout.as_secs()))
+                }
+            }
+        }
+        HookAction::RunCommand { argv } => {
+            let argv = argv.iter().map(|arg| context.expand(arg)).collect::<Result<Vec<_>, _>>()?;
+            let Some((program, args)) = argv.split_first() else {
+                return Err("No program to run".to_string());
+            };
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            let Some(output) = output_with_timeout(runner, &mut cmd, timeout).await? else {
+                return Err(format!("{} timed out after {}s", program, timeout.as_secs()));
+            };
+            if output.status.success() {
+                return Ok(None);
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match stderr.lines().rev().map(str::trim).find(|line| !line.is_empty()) {
+                Some(last) => Err(format!("{} exited with {}: {}", program, output.status, last)),
+                None => Err(format!("{} exited with {}", program, output.status)),
+            }
+        }
+    }
+}
+
+/// Move `from` into `dir` under a free name
+fn move_into(from: &Path, dir: &Path) -> Result<PathBuf, String> {
+    let name = from.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let target = resolve_output_path(dir, &name, CollisionPolicy::Rename);
+    replace_file(from, &target)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hooks-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    fn context(output_path: &Path) -> HookContext {
+        HookContext {
+            output_path: output_path.to_path_buf(),
+            output_bytes: 42,
+            codec: "h264".to_string(),
+            input_path: "/videos/{date}.mov".to_string(),
+            date: "2026-10-14".to_string(),
+        }
+    }
+
+    #[test]
+    fn expands_substituted_values_once() {
+        let context = context(Path::new("/out/{codec} clip.mp4"));
+        assert_eq!(
+            context.expand("{output_name}|{input_path}|{date}|{output_bytes}"),
+            Ok("{codec} clip.mp4|/videos/{date}.mov|2026-10-14|42".to_string())
+        );
+    }
+
+    #[test]
+    fn splits_folder_templates() {
+        for (template, root, relative) in [
+            ("/archive/{date}", "/archive/", "{date}"),
+            ("/archive/x{date}/{codec}", "/archive/", "x{date}/{codec}"),
+            ("/archive", "/archive", ""),
+            ("{output_dir}/done", "{output_dir}", "done"),
+            ("{date}", "", "{date}"),
+        ] {
+            assert_eq!(split_dir_template(template), (root, relative), "{}", template);
+        }
+    }
+
+    #[test]
+    fn hook_folders_must_be_absolute_and_exist() {
+        let dir = temp_dir();
+        let copy = |dir: &str| PostHook {
+            action: HookAction::CopyTo { dir: dir.to_string() },
+            timeout_secs: None,
+        };
+        let root = dir.to_string_lossy().to_string();
+        assert_eq!(validate_hooks(&[copy(&root)]), Ok(()));
+        assert_eq!(validate_hooks(&[copy(&format!("{}/{{date}}", root))]), Ok(()));
+        assert_eq!(validate_hooks(&[copy("{output_dir}/done")]), Ok(()));
+        for bad in ["archive", "{date}", "./archive/{date}", "/no/such/folder/{date}"] {
+            assert!(validate_hooks(&[copy(bad)]).is_err(), "{} was accepted", bad);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hook_folders_stay_under_their_root() {
+        let dir = temp_dir();
+        let root = dir.to_string_lossy().to_string();
+        // A source named `...mp4` has the stem `..`
+        let context = context(&dir.join("...mp4"));
+        let template = format!("{}/{{output_stem}}", root);
+        assert!(context.expand_dir(&template, true).is_err());
+        assert!(context.expand_dir(&template, false).is_err());
+        let dated = context.expand_dir(&format!("{}/{{date}}", root), true).unwrap();
+        assert_eq!(dated, dir.join("2026-10-14"));
+        assert_eq!(context.expand_dir(&format!("{}/{{date}}", root), false), Ok(dated));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
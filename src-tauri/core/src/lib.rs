@@ -0,0 +1,65 @@
+//! Conversion engine behind MP4 Converter, usable without Tauri.
+//!
+//! Everything runs ffmpeg/ffprobe as child processes:
+//!
+//! - [`resolver::FfmpegResolver`] finds and verifies the binaries; construct
+//!   it with explicit paths to control which ones are used.
+//! - [`converter::get_video_info`] probes a file and
+//!   [`converter::convert_video`] converts it, reporting
+//!   [`converter::ConversionProgress`] through a callback and stopping when
+//!   the given `CancellationToken` is cancelled.
+//! - [`analysis`] holds the read-only tools (crop detection, contact sheets,
+//!   quality comparison) and [`benchmark`] measures encoder speed.
+//! - [`process::ProcessRunner`] starts the child processes; pass a custom
+//!   one to [`resolver::FfmpegResolver::with_runner`] to replace them.
+//!
+//! Errors are [`error::ConvertError`], which serializes as
+//! `{ "kind": ..., "message": ... }` for UIs.
+
+pub mod analysis;
+pub mod aspect;
+pub mod audit;
+pub mod benchmark;
+pub mod cache_manager;
+pub mod chapters;
+mod chunked;
+pub mod command;
+pub mod container;
+pub mod converter;
+pub mod cover;
+pub mod devices;
+pub mod downloader;
+pub mod drm;
+pub mod encoder_slots;
+pub mod error;
+pub mod external_audio;
+pub mod faststart;
+pub mod growing;
+pub mod hashing;
+pub mod history;
+pub mod hls;
+pub mod hooks;
+pub mod naming;
+pub mod output_dir;
+pub mod paths;
+pub mod presets;
+pub mod preview;
+pub mod probe_cache;
+pub mod probe_sanity;
+pub mod queue;
+pub mod process;
+pub mod progress_file;
+pub mod renditions;
+pub mod resolver;
+pub mod sandbox;
+pub mod segments;
+pub mod settings;
+pub mod staging;
+pub mod subtitles;
+pub mod task_dir;
+pub mod task_log;
+pub mod telemetry;
+pub mod tracks;
+pub mod validation;
+pub mod verify;
+pub mod volumes;
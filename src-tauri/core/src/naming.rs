@@ -0,0 +1,397 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::converter::VideoInfo;
+use crate::paths::{extended_length, path_len, MAX_PLAIN_PATH};
+
+/// Matches the historical `{stem}_converted.mp4` output name
+pub const DEFAULT_OUTPUT_TEMPLATE: &str = "{stem}_converted";
+
+const TOKENS: &[&str] = &["stem", "codec", "height", "date", "quality"];
+
+/// Tokens of output folder templates; the dates are the source's
+const DIR_TOKENS: &[&str] = &["output_root", "year", "month", "day", "date", "codec", "height"];
+
+/// What to do when the output file already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Pick a free name by appending ` (1)`, ` (2)`, ...
+    Rename,
+}
+
+/// Characters that can't appear in a file name on this platform
+#[cfg(target_os = "windows")]
+const ILLEGAL_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+#[cfg(not(target_os = "windows"))]
+const ILLEGAL_CHARS: &[char] = &['/'];
+
+/// Longest file name common filesystems accept, in bytes
+const MAX_FILE_NAME_BYTES: usize = 255;
+/// Left free for ` (12)` collision and `_000` segment suffixes
+const SUFFIX_ROOM_BYTES: usize = 12;
+/// Names Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Make an expanded name (without its extension) safe to create in the output dir.
+///
+/// Inputs can come from filesystems that allow more than the output's does,
+/// e.g. `clip:final?` from a NAS, so characters the platform rejects become
+/// `_`, and on Windows trailing dots and spaces and device names are fixed
+/// too. Long names are cut on a character boundary.
+pub fn sanitize_file_stem(stem: &str) -> String {
+    let mut name: String = stem
+        .chars()
+        .map(|c| if ILLEGAL_CHARS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect();
+
+    let max = MAX_FILE_NAME_BYTES - ".mp4".len() - SUFFIX_ROOM_BYTES;
+    if name.len() > max {
+        let cut = (0..=max).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+        name.truncate(cut);
+    }
+
+    if cfg!(target_os = "windows") {
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+        let base = name.split('.').next().unwrap_or_default();
+        if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base)) {
+            name.insert(0, '_');
+        }
+    }
+
+    if name.is_empty() {
+        "output".to_string()
+    } else {
+        name
+    }
+}
+
+/// A piece of a template: literal text or the name inside a `{token}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TemplatePart<'a> {
+    Text(&'a str),
+    Token(&'a str),
+}
+
+/// Split a template into text and tokens, rejecting unbalanced braces and
+/// tokens not in `tokens`. `kind` names the template in errors, e.g.
+/// "output template".
+pub(crate) fn parse_template<'a>(
+    template: &'a str,
+    tokens: &[&str],
+    kind: &str,
+) -> Result<Vec<TemplatePart<'a>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err(format!("Unmatched '}}' in {}: {}", kind, template));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed '{{' in {}: {}", kind, template))?;
+        let token = &rest[open + 1..open + close];
+        if !tokens.contains(&token) {
+            return Err(format!("Unknown token '{{{}}}' in {}", token, kind));
+        }
+        if open > 0 {
+            parts.push(TemplatePart::Text(&rest[..open]));
+        }
+        parts.push(TemplatePart::Token(token));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Text(rest));
+    }
+    Ok(parts)
+}
+
+/// Expand a template in one pass, so a value that itself contains
+/// `{token}` is kept as it is rather than expanded again
+pub(crate) fn expand_tokens(
+    template: &str,
+    tokens: &[&str],
+    kind: &str,
+    value: impl Fn(&str) -> String,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    for part in parse_template(template, tokens, kind)? {
+        match part {
+            TemplatePart::Text(text) => expanded.push_str(text),
+            TemplatePart::Token(token) => expanded.push_str(&value(token)),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Check a template for unknown tokens, unbalanced braces and characters the
+/// filesystem would reject
+pub fn validate_template(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("Output template cannot be empty".to_string());
+    }
+    parse_template(template, TOKENS, "output template")?;
+
+    if let Some(c) = template.chars().find(|c| ILLEGAL_CHARS.contains(c) || c.is_control()) {
+        return Err(format!("Output template contains an illegal character: {:?}", c));
+    }
+    Ok(())
+}
+
+/// Expand a template into an output file name with the given extension
+pub fn expand_template(
+    template: &str,
+    info: &VideoInfo,
+    quality: &str,
+    extension: &str,
+) -> Result<String, String> {
+    validate_template(template)?;
+
+    let template = template
+        .strip_suffix(&format!(".{}", extension))
+        .or_else(|| template.strip_suffix(".mp4"))
+        .unwrap_or(template);
+    let stem = Path::new(&info.path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "output".to_string());
+
+    let name = expand_tokens(template, TOKENS, "output template", |token| match token {
+        "stem" => stem.clone(),
+        "codec" => info.codec.clone(),
+        "height" => info.height.to_string(),
+        "date" => source_date(info),
+        _ => quality.to_string(),
+    })?;
+
+    Ok(format!("{}.{}", sanitize_file_stem(&name), extension))
+}
+
+/// Check an output folder template such as `{output_root}/{year}/{month}`:
+/// known tokens only, and relative folders that stay under the root
+pub fn validate_dir_template(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("Output folder template cannot be empty".to_string());
+    }
+    parse_template(template, DIR_TOKENS, "output folder template")?;
+    let relative = template.strip_prefix("{output_root}").unwrap_or(template);
+    if relative.contains("{output_root}") {
+        return Err("{output_root} can only start an output folder template".to_string());
+    }
+    let absolute = relative.starts_with(['/', '\\']) || Path::new(relative).is_absolute();
+    if template == relative && absolute {
+        return Err(format!("Output folder template must be relative: {}", template));
+    }
+    if dir_components(relative).any(|part| part == "." || part == "..") {
+        return Err(format!("Output folder template can't leave the output folder: {}", template));
+    }
+    Ok(())
+}
+
+/// Expand a folder template into a path relative to the output root, one
+/// sanitized name per folder
+pub fn expand_dir_template(template: &str, info: &VideoInfo) -> Result<PathBuf, String> {
+    validate_dir_template(template)?;
+    let date = source_date(info);
+    let relative = template.strip_prefix("{output_root}").unwrap_or(template);
+    let expanded = expand_tokens(relative, DIR_TOKENS, "output folder template", |token| {
+        match token {
+            "year" => date.get(..4).unwrap_or_default().to_string(),
+            "month" => date.get(5..7).unwrap_or_default().to_string(),
+            "day" => date.get(8..10).unwrap_or_default().to_string(),
+            "date" => date.clone(),
+            "codec" => info.codec.clone(),
+            "height" => info.height.to_string(),
+            // Only ever the prefix, stripped above
+            _ => String::new(),
+        }
+    })?;
+    // Token values are checked again, so a codec name can't smuggle in `..`
+    Ok(dir_components(&expanded)
+        .filter(|part| *part != "." && *part != "..")
+        .map(sanitize_file_stem)
+        .collect())
+}
+
+/// The folder names of a relative template, empty ones skipped
+fn dir_components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\']).map(str::trim).filter(|part| !part.is_empty())
+}
+
+/// Stems aren't shortened below this to fit a path limit; past that the
+/// output uses an extended-length path instead
+const MIN_FITTED_STEM_CHARS: usize = 16;
+
+/// Shorten the stem of `file_name` so the output path stays within the
+/// platform's plain path limit (`MAX_PATH` on Windows), with room for a
+/// collision suffix. Deep output folders where even a short stem won't fit
+/// are left to `resolve_output_path`'s extended-length form.
+pub fn fit_output_name(output_dir: &Path, file_name: &str) -> String {
+    let limit = MAX_PLAIN_PATH - SUFFIX_ROOM_BYTES;
+    let overflow = path_len(&output_dir.join(file_name)).saturating_sub(limit);
+    if overflow == 0 {
+        return file_name.to_string();
+    }
+    let name = Path::new(file_name);
+    let stem = name.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = name.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    let keep = stem.chars().count().saturating_sub(overflow).max(MIN_FITTED_STEM_CHARS);
+    let stem: String = stem.chars().take(keep).collect();
+    format!("{}.{}", sanitize_file_stem(stem.trim_end()), extension)
+}
+
+/// Join the expanded name onto the output dir, applying the collision policy
+pub fn resolve_output_path(output_dir: &Path, file_name: &str, policy: CollisionPolicy) -> PathBuf {
+    let candidate = extended_length(&output_dir.join(file_name));
+    if policy == CollisionPolicy::Overwrite || !candidate.exists() {
+        return candidate;
+    }
+
+    let name = Path::new(file_name);
+    let stem = name.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = name.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    (1..)
+        .map(|n| extended_length(&output_dir.join(format!("{} ({}).{}", stem, n, extension))))
+        .find(|path| !path.exists())
+        .unwrap_or(candidate)
+}
+
+/// Source creation date as `YYYY-MM-DD`, falling back to the file's mtime
+fn source_date(info: &VideoInfo) -> String {
+    if let Some(date) = info
+        .creation_time
+        .as_deref()
+        .and_then(|t| t.get(..10))
+        .filter(|d| d.as_bytes()[4] == b'-' && d.as_bytes()[7] == b'-')
+    {
+        return date.to_string();
+    }
+
+    std::fs::metadata(&info.path)
+        .and_then(|m| m.modified())
+        .unwrap_or_else(|_| SystemTime::now())
+        .duration_since(UNIX_EPOCH)
+        .map(|d| civil_date((d.as_secs() / 86_400) as i64))
+        .unwrap_or_default()
+}
+
+/// Convert days since the Unix epoch to a `YYYY-MM-DD` string
+pub(crate) fn civil_date(days: i64) -> String {
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_text_and_tokens() {
+        assert_eq!(
+            parse_template("{stem}_{height}p.mp4", TOKENS, "output template"),
+            Ok(vec![
+                TemplatePart::Token("stem"),
+                TemplatePart::Text("_"),
+                TemplatePart::Token("height"),
+                TemplatePart::Text("p.mp4"),
+            ])
+        );
+        assert_eq!(parse_template("", TOKENS, "output template"), Ok(vec![]));
+    }
+
+    #[test]
+    fn rejects_bad_braces_and_tokens() {
+        for (template, error) in [
+            ("a}b", "Unmatched '}' in output template: a}b"),
+            ("{stem", "Unclosed '{' in output template: {stem"),
+            ("{size}", "Unknown token '{size}' in output template"),
+            ("{}", "Unknown token '{}' in output template"),
+        ] {
+            assert_eq!(validate_template(template), Err(error.to_string()));
+        }
+        assert!(validate_dir_template("{output_root}/{quality}").is_err());
+        assert!(validate_dir_template("{year}/../x").is_err());
+        assert!(validate_dir_template("/abs/{year}").is_err());
+        assert_eq!(validate_dir_template("{output_root}/{year}/{month}"), Ok(()));
+    }
+
+    #[test]
+    fn expands_in_one_pass() {
+        let expanded = expand_tokens("{stem}-{codec}", TOKENS, "output template", |token| {
+            match token {
+                "stem" => "clip {codec}".to_string(),
+                _ => "h264".to_string(),
+            }
+        });
+        assert_eq!(expanded, Ok("clip {codec}-h264".to_string()));
+    }
+
+    /// A folder whose path is about `len` long, nested since no single name
+    /// may pass 255 bytes
+    fn deep_dir(root: &Path, len: usize) -> PathBuf {
+        let mut dir = root.to_path_buf();
+        while path_len(&dir) + 1 < len {
+            let room = len - path_len(&dir) - 1;
+            dir.push("d".repeat(room.min(200)));
+        }
+        std::fs::create_dir_all(extended_length(&dir)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn long_output_paths_are_shortened_and_stay_usable() {
+        let root = std::env::temp_dir().join(format!("naming-{}", uuid::Uuid::new_v4()));
+        let dir = deep_dir(&root, MAX_PLAIN_PATH - 60);
+        let file_name = format!("{}_converted.mp4", "holiday in the mountains ".repeat(4));
+        assert!(path_len(&dir.join(&file_name)) > MAX_PLAIN_PATH);
+
+        let fitted = fit_output_name(&dir, &file_name);
+        assert!(fitted.len() < file_name.len(), "{}", fitted);
+        assert!(fitted.starts_with("holiday in the mountains") && fitted.ends_with(".mp4"));
+        assert!(path_len(&dir.join(&fitted)) <= MAX_PLAIN_PATH - SUFFIX_ROOM_BYTES);
+
+        // The collision suffix still fits, and both files can be written
+        let first = resolve_output_path(&dir, &fitted, CollisionPolicy::Rename);
+        std::fs::write(&first, b"first").unwrap();
+        let second = resolve_output_path(&dir, &fitted, CollisionPolicy::Rename);
+        assert_ne!(first, second);
+        assert!(path_len(&second) <= MAX_PLAIN_PATH);
+        std::fs::write(&second, b"second").unwrap();
+        assert_eq!(std::fs::read(&first).unwrap(), b"first");
+
+        // Names that already fit are kept
+        assert_eq!(fit_output_name(&root, &file_name), file_name);
+        std::fs::remove_dir_all(extended_length(&root)).unwrap();
+    }
+
+    /// Past `MAX_PATH` even with a short name, the extended-length form
+    /// takes over
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn deeper_output_folders_use_extended_length_paths() {
+        let root = std::env::temp_dir().join(format!("naming-{}", uuid::Uuid::new_v4()));
+        let dir = deep_dir(&root, MAX_PLAIN_PATH - 10);
+        let fitted = fit_output_name(&dir, "a clip with a fairly long name.mp4");
+        let path = resolve_output_path(&dir, &fitted, CollisionPolicy::Rename);
+        assert!(path.to_string_lossy().starts_with(r"\\?\"), "{}", path.display());
+        std::fs::write(&path, b"clip").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"clip");
+        std::fs::remove_dir_all(extended_length(&root)).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+//! Where a conversion writes when the caller doesn't name a folder: next
+//! to the source, into a fixed folder, or nowhere until the user picks one.
+//!
+//! Folders the user does pick are remembered by the volume of the source,
+//! keyed by `volume_key`, so sources on a read-only disc or share go where
+//! the last ones from that volume went.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::ConvertError;
+use crate::paths::validate_output_dir;
+use crate::settings::Settings;
+use crate::volumes::volume_key;
+
+/// How conversions without an explicit folder pick one
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum OutputDirMode {
+    /// The source's own folder, or the fallback when it can't be written to
+    SameAsInput,
+    /// Always the same folder
+    Fixed { path: String },
+    /// Every conversion names its folder; the user is asked for one
+    #[default]
+    Ask,
+}
+
+/// Why a conversion writes where it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputDirSource {
+    /// Passed with the conversion
+    Explicit,
+    /// The source's folder
+    SameAsInput,
+    /// The folder of the `fixed` mode
+    Fixed,
+    /// The source's folder is read-only, so the fallback folder
+    Fallback,
+    /// The source's folder is read-only, so the folder last picked for a
+    /// source on the same volume
+    Remembered,
+}
+
+/// The folder a conversion writes to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedOutputDir {
+    /// Canonical path
+    pub dir: String,
+    pub source: OutputDirSource,
+}
+
+/// The folder a conversion of `input_path` writes to: `explicit` when
+/// given and not blank, else whatever `settings.output_dir_mode` says
+pub fn resolve_output_dir(
+    input_path: &str,
+    explicit: Option<&str>,
+    settings: &Settings,
+) -> Result<ResolvedOutputDir, ConvertError> {
+    let resolved = |dir: &str, source| {
+        validate_output_dir(dir)
+            .map(|dir| ResolvedOutputDir { dir: dir.to_string_lossy().to_string(), source })
+    };
+    if let Some(dir) = explicit.map(str::trim).filter(|dir| !dir.is_empty()) {
+        return resolved(dir, OutputDirSource::Explicit);
+    }
+    match &settings.output_dir_mode {
+        OutputDirMode::Fixed { path } => resolved(path, OutputDirSource::Fixed),
+        OutputDirMode::Ask => {
+            Err(ConvertError::Failed("Choose a folder for the converted file".to_string()))
+        }
+        OutputDirMode::SameAsInput => {
+            let source_dir = Path::new(input_path).parent().unwrap_or(Path::new(input_path));
+            let own = resolved(&source_dir.to_string_lossy(), OutputDirSource::SameAsInput)?;
+            if is_writable(Path::new(&own.dir)) {
+                return Ok(own);
+            }
+            if let Some(fallback) = &settings.fallback_output_dir {
+                return resolved(fallback, OutputDirSource::Fallback);
+            }
+            match remembered_output_dir(input_path, settings) {
+                Some(dir) => resolved(&dir, OutputDirSource::Remembered),
+                None => Err(ConvertError::Failed(format!(
+                    "Can't write next to the source in {}; choose a folder for the converted file",
+                    own.dir
+                ))),
+            }
+        }
+    }
+}
+
+/// The folder last picked for a source on the same volume as
+/// `input_path`, if it is still there
+pub fn remembered_output_dir(input_path: &str, settings: &Settings) -> Option<String> {
+    let key = volume_key(Path::new(input_path))?;
+    settings.last_output_dirs.get(&key).filter(|dir| Path::new(dir).is_dir()).cloned()
+}
+
+/// Remember `dir` as the folder picked for sources on the volume of
+/// `input_path`
+pub fn remember_output_dir(settings: &mut Settings, input_path: &str, dir: &str) {
+    if let Some(key) = volume_key(Path::new(input_path)) {
+        settings.last_output_dirs.insert(key, dir.to_string());
+    }
+}
+
+/// Whether a file can be created in `dir`. Permissions alone don't tell:
+/// a read-only mount or share refuses writes whatever the mode bits say.
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".mp4-converter-write-test-{}", uuid::Uuid::new_v4()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
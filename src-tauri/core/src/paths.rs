@@ -0,0 +1,336 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::ConvertError;
+
+/// Check a user-supplied input path and return its canonical form.
+///
+/// ffmpeg treats strings like `concat:a|b`, `http://...`, `pipe:0` or
+/// `-foo` as protocols or options rather than files, so anything that could
+/// be read that way is refused. Only absolute paths to existing regular files
+/// are accepted.
+pub fn validate_input_path(raw: &str) -> Result<PathBuf, ConvertError> {
+    let path = check_local_path(raw)?;
+    if std::fs::metadata(&path).is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound) {
+        return Err(ConvertError::InputUnavailable(raw.to_string()));
+    }
+    let canonical = canonicalize(&path)?;
+    let metadata = std::fs::metadata(&canonical)
+        .map_err(|e| ConvertError::Failed(format!("Cannot read {}: {}", raw, e)))?;
+    if !metadata.is_file() {
+        return Err(ConvertError::Security(format!("Not a regular file: {}", raw)));
+    }
+    Ok(canonical)
+}
+
+/// A stat on a dead network mount can hang; past this the input counts as gone
+const UNAVAILABLE_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether an input that was readable has since gone missing, for telling a
+/// pulled drive or dropped share apart from an ffmpeg failure
+pub async fn input_unavailable(path: &Path) -> bool {
+    match tokio::time::timeout(UNAVAILABLE_CHECK_TIMEOUT, tokio::fs::metadata(path)).await {
+        Ok(Ok(metadata)) => !metadata.is_file(),
+        Ok(Err(e)) => e.kind() != std::io::ErrorKind::PermissionDenied,
+        Err(_) => true,
+    }
+}
+
+/// Check a user-supplied output directory and return its canonical form
+pub fn validate_output_dir(raw: &str) -> Result<PathBuf, ConvertError> {
+    let path = check_local_path(raw)?;
+    let canonical = canonicalize(&path)?;
+    if !canonical.is_dir() {
+        return Err(ConvertError::Failed(format!("Output directory does not exist: {}", raw)));
+    }
+    Ok(canonical)
+}
+
+/// Create `relative` under the canonical output `root` and return its
+/// canonical form. A link among existing folders that leads outside the
+/// root is refused like a `..` would be, before anything is created.
+pub fn create_output_subdir(root: &Path, relative: &Path) -> Result<PathBuf, ConvertError> {
+    let dir = root.join(relative);
+    let existing = dir.ancestors().find(|ancestor| ancestor.exists()).unwrap_or(root);
+    check_under(root, &canonicalize(existing)?)?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create output folder {}: {}", dir.display(), e))?;
+    let canonical = canonicalize(&dir)?;
+    check_under(root, &canonical)?;
+    Ok(canonical)
+}
+
+fn check_under(root: &Path, path: &Path) -> Result<(), ConvertError> {
+    if path.starts_with(root) {
+        Ok(())
+    } else {
+        Err(ConvertError::Security(format!(
+            "Output folder {} is outside {}",
+            path.display(),
+            root.display()
+        )))
+    }
+}
+
+/// Check where a progress file may be written and return the path to use.
+/// Its folder must exist and sit inside one of `allowed_dirs`, with links
+/// resolved, so options can't aim the writes at arbitrary files.
+pub fn validate_progress_file(raw: &str, allowed_dirs: &[PathBuf]) -> Result<PathBuf, ConvertError> {
+    let path = check_local_path(raw)?;
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(ConvertError::Security(format!("Not a file path: {}", raw)));
+    };
+    let parent = canonicalize(parent)?;
+    let allowed = allowed_dirs
+        .iter()
+        .filter_map(|dir| canonicalize(dir).ok())
+        .any(|dir| parent.starts_with(dir));
+    let target = parent.join(name);
+    if !allowed || target.is_dir() {
+        return Err(ConvertError::PermissionDenied(format!(
+            "Progress files can only be written to the app data folder or the allowed \
+             progress folder, not {}",
+            raw
+        )));
+    }
+    Ok(target)
+}
+
+/// Check that a file may be deleted and return the path to remove.
+///
+/// Only regular files that `is_allowed` accepts can be deleted. Symlinks are
+/// resolved first, so a link inside an allowed folder that points somewhere
+/// else is refused, and system locations are refused no matter what.
+pub fn validate_deletable<F>(raw: &str, is_allowed: F) -> Result<PathBuf, ConvertError>
+where
+    F: Fn(&Path) -> bool,
+{
+    let path = check_local_path(raw)?;
+    let link_metadata = std::fs::symlink_metadata(&path)
+        .map_err(|e| ConvertError::Failed(format!("Cannot access {}: {}", raw, e)))?;
+    if link_metadata.is_dir() {
+        return Err(ConvertError::PermissionDenied(format!(
+            "Refusing to delete a directory: {}",
+            raw
+        )));
+    }
+
+    let canonical = validate_input_path(raw)?;
+    if is_system_location(&canonical) {
+        return Err(ConvertError::PermissionDenied(format!(
+            "Refusing to delete a system file: {}",
+            canonical.display()
+        )));
+    }
+    if !is_allowed(&canonical) || !is_allowed(&path) {
+        return Err(ConvertError::PermissionDenied(format!(
+            "{} was not produced by the app or picked by the user",
+            raw
+        )));
+    }
+    Ok(path)
+}
+
+#[cfg(target_os = "windows")]
+fn is_system_location(path: &Path) -> bool {
+    ["SystemRoot", "ProgramFiles", "ProgramFiles(x86)", "ProgramData"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .any(|root| {
+            let root = PathBuf::from(root).to_string_lossy().to_lowercase();
+            path.to_string_lossy().to_lowercase().starts_with(&root)
+        })
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_system_location(path: &Path) -> bool {
+    const SYSTEM_ROOTS: &[&str] = &[
+        "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/proc", "/sbin", "/sys", "/usr",
+        "/var/lib", "/System", "/Library", "/private/etc", "/private/var/db",
+    ];
+    SYSTEM_ROOTS.iter().any(|root| path.starts_with(root))
+}
+
+/// Format a local path for ffmpeg's command line.
+///
+/// The explicit `file:` protocol makes ffmpeg read the rest verbatim, so
+/// colons or a leading dash in a file name can't change its meaning.
+pub fn ffmpeg_path_arg(path: &Path) -> String {
+    format!("file:{}", extended_length(path).to_string_lossy())
+}
+
+/// Longest path Windows APIs take without the `\\?\` prefix, not counting
+/// the terminating NUL
+#[cfg(target_os = "windows")]
+pub const MAX_PLAIN_PATH: usize = 259;
+#[cfg(not(target_os = "windows"))]
+pub const MAX_PLAIN_PATH: usize = 4095;
+
+/// Length of a path in the units the platform limits it by: UTF-16 code
+/// units on Windows, bytes elsewhere
+pub fn path_len(path: &Path) -> usize {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        path.as_os_str().encode_wide().count()
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        path.as_os_str().len()
+    }
+}
+
+/// Give an absolute Windows path past `MAX_PATH` its extended-length form,
+/// which both our own file calls and ffmpeg accept: `C:\...` becomes
+/// `\\?\C:\...` and `\\server\share\...` becomes `\\?\UNC\server\share\...`.
+/// Shorter paths, paths already in that form and other platforms are left
+/// alone.
+pub fn extended_length(path: &Path) -> PathBuf {
+    if !cfg!(target_os = "windows") || path_len(path) <= MAX_PLAIN_PATH {
+        return path.to_path_buf();
+    }
+    let text = path.to_string_lossy();
+    if text.starts_with(r"\\?\") || text.starts_with(r"\\.\") {
+        return path.to_path_buf();
+    }
+    // The prefix turns off Windows' own handling of `/`, `.` and `..`, so
+    // that is done here the same lexical way first
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other),
+        }
+    }
+    let normalized = resolved.to_string_lossy().replace('/', r"\");
+    match normalized.strip_prefix(r"\\") {
+        Some(unc) => PathBuf::from(format!(r"\\?\UNC\{}", unc)),
+        None if normalized.as_bytes().get(1) == Some(&b':') => {
+            PathBuf::from(format!(r"\\?\{}", normalized))
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+/// Reject empty, relative, option-like and protocol-like strings
+fn check_local_path(raw: &str) -> Result<PathBuf, ConvertError> {
+    if raw.trim().is_empty() {
+        return Err(ConvertError::Security("Empty path".to_string()));
+    }
+    if raw.starts_with('-') {
+        return Err(ConvertError::Security(format!(
+            "Path looks like a command-line option: {}",
+            raw
+        )));
+    }
+    if has_protocol_prefix(raw) {
+        return Err(ConvertError::Security(format!("Protocol paths are not allowed: {}", raw)));
+    }
+    if raw.contains('\0') {
+        return Err(ConvertError::Security("Path contains a NUL byte".to_string()));
+    }
+
+    let path = PathBuf::from(raw);
+    if !path.is_absolute() {
+        return Err(ConvertError::Security(format!("Path must be absolute: {}", raw)));
+    }
+    Ok(extended_length(&path))
+}
+
+/// Match a URL-style scheme (`concat:`, `http:`, `pipe:`...) at the start.
+///
+/// Single letters are left alone so Windows drive letters (`C:`) still work,
+/// and anything starting with `/` never matches, so unix names containing
+/// colons are fine.
+fn has_protocol_prefix(raw: &str) -> bool {
+    let Some((scheme, _)) = raw.split_once(':') else {
+        return false;
+    };
+    scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_'))
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, ConvertError> {
+    let canonical = std::fs::canonicalize(path)
+        .map_err(|e| ConvertError::Failed(format!("Cannot access {}: {}", path.display(), e)))?;
+    Ok(simplify_verbatim(canonical))
+}
+
+/// On Windows `canonicalize` returns `\\?\C:\...`; turn plain drive paths
+/// back into `C:\...` so they look like what the user picked. Paths too long
+/// to work without the prefix keep it.
+#[cfg(target_os = "windows")]
+fn simplify_verbatim(path: PathBuf) -> PathBuf {
+    let text = path.to_string_lossy();
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') && rest.len() <= MAX_PLAIN_PATH => {
+            PathBuf::from(rest)
+        }
+        _ => path,
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn simplify_verbatim(path: PathBuf) -> PathBuf {
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(raw: &str) -> bool {
+        matches!(check_local_path(raw), Err(ConvertError::Security(_)))
+    }
+
+    #[test]
+    fn rejects_option_and_protocol_paths() {
+        for raw in [
+            "",
+            "   ",
+            "-i",
+            "-y.mp4",
+            "pipe:0",
+            "concat:a.mp4|b.mp4",
+            "http://example.com/a.mp4",
+            "file:/tmp/a.mp4",
+            "/tmp/a\0.mp4",
+            "a.mp4",
+            "videos/a.mp4",
+            "./a.mp4",
+        ] {
+            assert!(rejected(raw), "{:?} was accepted", raw);
+        }
+    }
+
+    #[test]
+    fn accepts_absolute_local_paths() {
+        assert_eq!(check_local_path("/tmp/a:b.mp4").ok(), Some(PathBuf::from("/tmp/a:b.mp4")));
+        // A drive letter is not a scheme, though only Windows calls it absolute
+        assert_eq!(check_local_path(r"C:\Videos\a.mp4").is_ok(), cfg!(target_os = "windows"));
+    }
+
+    #[test]
+    fn protocol_prefixes() {
+        let cases = [
+            ("pipe:0", true),
+            ("concat:a|b", true),
+            ("http://host/a", true),
+            ("rtmp+tls:x", true),
+            (r"C:\Videos\a.mp4", false),
+            ("C:/Videos/a.mp4", false),
+            ("/tmp/a:b.mp4", false),
+            ("a.mp4", false),
+            ("1x:y", false),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(has_protocol_prefix(raw), expected, "{}", raw);
+        }
+    }
+}
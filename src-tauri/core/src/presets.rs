@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::converter::ConversionOptions;
+
+/// Names accepted by `builtin_preset`
+pub const BUILTIN_PRESETS: &[&str] = &["phone", "streaming"];
+
+/// Option sets for common targets
+pub fn builtin_preset(name: &str) -> Option<ConversionOptions> {
+    let options = match name {
+        // The defaults already target phones: H.264 Main 4.0, AAC, faststart
+        "phone" => ConversionOptions::default(),
+        // Upload services commonly reject peaks above 8 Mbit/s
+        "streaming" => ConversionOptions {
+            max_bitrate_kbps: Some(8000),
+            ..ConversionOptions::default()
+        },
+        _ => return None,
+    };
+    Some(options)
+}
+
+/// Version written to exported preset files; files from newer versions are
+/// refused rather than half understood
+pub const PRESET_SCHEMA_VERSION: u32 = 1;
+
+/// A shareable preset file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetFile {
+    pub schema_version: u32,
+    /// Kept as raw JSON so one bad preset doesn't reject the whole file
+    pub presets: BTreeMap<String, serde_json::Value>,
+}
+
+/// What an import does with a preset whose name is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportConflict {
+    /// Import it as `name (2)`, `name (3)`, ...
+    #[default]
+    Rename,
+    /// Replace the existing user preset; built-in presets are never replaced
+    Overwrite,
+    /// Keep the existing preset
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectedPreset {
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Names the presets were saved under, after any renaming
+    pub added: Vec<String>,
+    pub skipped: Vec<String>,
+    pub rejected: Vec<RejectedPreset>,
+}
+
+/// Serialize user presets for sharing
+pub fn export_presets(presets: &BTreeMap<String, ConversionOptions>) -> Result<String, String> {
+    let presets = presets
+        .iter()
+        .map(|(name, options)| Ok((name.clone(), serde_json::to_value(options)?)))
+        .collect::<Result<_, serde_json::Error>>()
+        .map_err(|e| format!("Failed to serialize presets: {}", e))?;
+    let file = PresetFile { schema_version: PRESET_SCHEMA_VERSION, presets };
+    serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to serialize presets: {}", e))
+}
+
+/// Merge an exported preset file into `presets`. Each preset is checked on
+/// its own; invalid ones are reported and left out.
+pub fn import_presets(
+    presets: &mut BTreeMap<String, ConversionOptions>,
+    json: &str,
+    on_conflict: ImportConflict,
+) -> Result<ImportReport, String> {
+    let file: PresetFile =
+        serde_json::from_str(json).map_err(|e| format!("Not a preset file: {}", e))?;
+    if file.schema_version == 0 || file.schema_version > PRESET_SCHEMA_VERSION {
+        return Err(format!(
+            "Unsupported preset file version {} (this app reads up to {})",
+            file.schema_version, PRESET_SCHEMA_VERSION
+        ));
+    }
+
+    let mut report = ImportReport::default();
+    for (name, value) in file.presets {
+        let name = name.trim().to_string();
+        let checked = if name.is_empty() {
+            Err("Preset name cannot be empty".to_string())
+        } else {
+            serde_json::from_value::<ConversionOptions>(value)
+                .map_err(|e| format!("Invalid preset: {}", e))
+                .and_then(|options| options.validate().map(|()| options))
+        };
+        let options = match checked {
+            Ok(options) => options,
+            Err(reason) => {
+                report.rejected.push(RejectedPreset { name, reason });
+                continue;
+            }
+        };
+
+        let is_builtin = BUILTIN_PRESETS.contains(&name.as_str());
+        let taken = |candidate: &str| {
+            BUILTIN_PRESETS.contains(&candidate) || presets.contains_key(candidate)
+        };
+        let target = match on_conflict {
+            _ if !taken(&name) => Some(name.clone()),
+            ImportConflict::Overwrite if !is_builtin => Some(name.clone()),
+            ImportConflict::Rename => {
+                (2..).map(|n| format!("{} ({})", name, n)).find(|candidate| !taken(candidate))
+            }
+            _ => None,
+        };
+        match target {
+            Some(target) => {
+                presets.insert(target.clone(), options);
+                report.added.push(target);
+            }
+            None => report.skipped.push(name),
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,232 @@
+//! Quick preview encodes: a few seconds of the source converted with the
+//! chosen settings, to judge quality before committing to the full run.
+
+use serde::Serialize;
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+
+use crate::analysis::TrimRange;
+use crate::converter::{
+    convert_video, get_video_info, ConversionOptions, ConversionProgress, ConversionResult,
+};
+use crate::error::ConvertError;
+use crate::hls::OutputFormat;
+use crate::naming::CollisionPolicy;
+use crate::resolver::FfmpegResolver;
+use crate::task_log::TaskLog;
+
+pub const DEFAULT_PREVIEW_SECONDS: f64 = 15.0;
+const MAX_PREVIEW_SECONDS: f64 = 120.0;
+/// Where in the source previews start; openings are often black frames or
+/// titles that say little about the settings
+const PREVIEW_START_FRACTION: f64 = 0.25;
+/// Once the preview folder holds more than this, the least recently
+/// written previews are removed
+pub const PREVIEW_CACHE_BYTES: u64 = 500 * 1024 * 1024;
+/// How much a suggested troubleshooting sample covers, and how much of
+/// that comes before the point where the conversion failed
+const SAMPLE_SECONDS: f64 = 30.0;
+const SAMPLE_LEAD_SECONDS: f64 = 10.0;
+const MAX_SAMPLE_SECONDS: f64 = 600.0;
+
+/// The slice of a `duration` long source a preview of `seconds` covers:
+/// from a quarter in, moved earlier when that would run past the end
+pub fn preview_range(duration: f64, seconds: f64) -> TrimRange {
+    if duration <= seconds {
+        return TrimRange { start_seconds: 0.0, end_seconds: duration };
+    }
+    let start = (duration * PREVIEW_START_FRACTION).min(duration - seconds);
+    TrimRange { start_seconds: start, end_seconds: start + seconds }
+}
+
+/// The troubleshooting sample for a failure `failed_at` seconds into a
+/// `duration` long source (0 when unknown), kept inside the source
+pub fn sample_range(failed_at: f64, duration: f64) -> TrimRange {
+    let (failed_at, end_limit) = if duration > 0.0 {
+        (failed_at.min(duration), duration)
+    } else {
+        (failed_at, f64::INFINITY)
+    };
+    let start = (failed_at - SAMPLE_LEAD_SECONDS).max(0.0);
+    TrimRange { start_seconds: start, end_seconds: (start + SAMPLE_SECONDS).min(end_limit) }
+}
+
+/// `options` limited to `range`, with everything that only makes sense for
+/// the real output (splitting, renditions, HLS, replacing the source, the
+/// progress file) turned off. Encoder choice and filters are left alone so
+/// the preview looks like the result.
+pub fn preview_options(options: &ConversionOptions, range: TrimRange) -> ConversionOptions {
+    ConversionOptions {
+        clip: Some(range),
+        auto_trim_silence: false,
+        output_template: Some("{stem}_preview".to_string()),
+        collision_policy: CollisionPolicy::Overwrite,
+        segment: None,
+        renditions: Vec::new(),
+        output_format: OutputFormat::Mp4,
+        replace_original: false,
+        progress_file: None,
+        abort_if_larger_than_input: false,
+        ..options.clone()
+    }
+}
+
+/// Convert `seconds` of the input into `preview_dir`, which is pruned to
+/// `PREVIEW_CACHE_BYTES` first. Progress and cancellation work as for
+/// `convert_video`.
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_preview<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    options: &ConversionOptions,
+    seconds: f64,
+    preview_dir: &Path,
+    cache_dir: Option<&Path>,
+    task_id: &str,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    if !(seconds > 0.0 && seconds <= MAX_PREVIEW_SECONDS) {
+        return Err(format!(
+            "Preview length must be above 0 and at most {} seconds",
+            MAX_PREVIEW_SECONDS
+        )
+        .into());
+    }
+    let info = get_video_info(resolver, input_path).await?;
+    std::fs::create_dir_all(preview_dir)
+        .map_err(|e| format!("Failed to create preview directory: {}", e))?;
+    prune_previews(preview_dir, PREVIEW_CACHE_BYTES);
+
+    let options = preview_options(options, preview_range(info.duration, seconds));
+    convert_video(
+        resolver,
+        input_path,
+        &preview_dir.to_string_lossy(),
+        task_id,
+        &options,
+        cache_dir,
+        log,
+        cancel,
+        progress_callback,
+    )
+    .await
+}
+
+/// What a troubleshooting sample came to, for attaching to a bug report
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleOutcome {
+    /// The converted sample; None when it failed as well
+    pub output_path: Option<String>,
+    /// The task log with ffmpeg's verbose output
+    pub log_path: Option<String>,
+    /// How the sample failed, if it did; the failure is usually what the
+    /// sample was made to reproduce
+    pub error: Option<ConvertError>,
+}
+
+/// Convert `duration_seconds` of the input from `start_seconds` into
+/// `sample_dir` with ffmpeg's verbose output in `log`, to reproduce a
+/// failure (see `ConvertError::EncodeFailed`) in seconds instead of hours.
+/// Only bad arguments and cancellation are errors; a failing sample is an
+/// outcome.
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_sample<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    options: &ConversionOptions,
+    start_seconds: f64,
+    duration_seconds: f64,
+    sample_dir: &Path,
+    cache_dir: Option<&Path>,
+    task_id: &str,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<SampleOutcome, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    if !(duration_seconds > 0.0 && duration_seconds <= MAX_SAMPLE_SECONDS) {
+        return Err(format!(
+            "Sample length must be above 0 and at most {} seconds",
+            MAX_SAMPLE_SECONDS
+        )
+        .into());
+    }
+    let info = get_video_info(resolver, input_path).await?;
+    if !(start_seconds >= 0.0 && (info.duration <= 0.0 || start_seconds < info.duration)) {
+        return Err(format!(
+            "The sample must start within the {:.1}s source, not at {:.1}s",
+            info.duration, start_seconds
+        )
+        .into());
+    }
+    std::fs::create_dir_all(sample_dir)
+        .map_err(|e| format!("Failed to create sample directory: {}", e))?;
+    prune_previews(sample_dir, PREVIEW_CACHE_BYTES);
+
+    let mut end_seconds = start_seconds + duration_seconds;
+    if info.duration > 0.0 {
+        end_seconds = end_seconds.min(info.duration);
+    }
+    let range = TrimRange { start_seconds, end_seconds };
+    let options = ConversionOptions {
+        output_template: Some(format!("{{stem}}_sample_{:.0}s", start_seconds)),
+        verbose_log: true,
+        ..preview_options(options, range)
+    };
+    log.line(&format!(
+        "Troubleshooting sample of {} from {:.1}s to {:.1}s",
+        input_path, start_seconds, end_seconds
+    ));
+    let result = convert_video(
+        resolver,
+        input_path,
+        &sample_dir.to_string_lossy(),
+        task_id,
+        &options,
+        cache_dir,
+        log,
+        cancel,
+        progress_callback,
+    )
+    .await;
+    let log_path = log.path().map(|path| path.to_string_lossy().to_string());
+    match result {
+        Ok(done) => Ok(SampleOutcome { output_path: Some(done.output_path), log_path, error: None }),
+        Err(ConvertError::Cancelled) => Err(ConvertError::Cancelled),
+        Err(e) => Ok(SampleOutcome { output_path: None, log_path, error: Some(e) }),
+    }
+}
+
+/// Remove the oldest previews until the rest fit in `max_bytes`
+pub fn prune_previews(dir: &Path, max_bytes: u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok().filter(|meta| meta.is_file())?;
+            Some((meta.modified().ok()?, meta.len(), entry.path()))
+        })
+        .collect();
+    files.sort_by_key(|(modified, _, _)| std::cmp::Reverse(*modified));
+    let mut kept = 0u64;
+    for (_, len, path) in files {
+        kept += len;
+        if kept > max_bytes {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Delete every preview, as the app does on exit
+pub fn clear_previews(dir: &Path) {
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::converter::VideoInfo;
+
+/// Entries kept before the cache starts over; a session rarely comes close
+const MAX_ENTRIES: usize = 1024;
+
+/// Identifies one version of a file: a probe is only reused while both match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: SystemTime,
+    size: u64,
+}
+
+impl FileStamp {
+    /// None when the file can't be stat'ed or the platform has no mtime,
+    /// in which case nothing is cached for it
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(FileStamp {
+            modified: metadata.modified().ok()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+/// ffprobe results by canonical path.
+///
+/// Probing is slow on network drives, and a file is usually probed once
+/// when it's added and again when it's converted.
+#[derive(Debug, Default)]
+pub struct ProbeCache {
+    entries: Mutex<HashMap<PathBuf, (FileStamp, VideoInfo)>>,
+}
+
+impl ProbeCache {
+    pub fn get(&self, path: &Path, stamp: FileStamp) -> Option<VideoInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|(cached, _)| *cached == stamp)
+            .map(|(_, info)| info.clone())
+    }
+
+    pub fn insert(&self, path: PathBuf, stamp: FileStamp, info: VideoInfo) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&path) {
+            entries.clear();
+        }
+        entries.insert(path, (stamp, info));
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,108 @@
+//! Plausibility checks on what ffprobe reports. Damaged files can claim
+//! decades of runtime or terabit bitrates; `VideoInfo` gets values the ETA
+//! and size estimates can work with, and `get_raw_probe` keeps the
+//! originals.
+
+use crate::converter::VideoInfo;
+
+/// Longest duration taken at face value unless the resolver is set up with
+/// another ceiling
+pub const DEFAULT_MAX_PROBE_DURATION_SECS: u64 = 24 * 60 * 60;
+
+/// A reported bitrate this many times off the one size and duration give
+/// is replaced by the latter
+const BITRATE_TOLERANCE: f64 = 10.0;
+
+/// Clean `info` in place. `stream_durations` are the per-stream durations
+/// ffprobe gave, tried when the overall one is implausible; `file_bytes` is
+/// the source's size, 0 when unknown.
+pub fn sanitize(info: &mut VideoInfo, stream_durations: &[f64], file_bytes: u64, max_duration: f64) {
+    let plausible = |seconds: f64| seconds.is_finite() && seconds >= 0.0 && seconds <= max_duration;
+    if !plausible(info.duration) {
+        info.duration_suspect = true;
+        // Unknown rather than the ceiling itself, which would still make the
+        // ETA hours off
+        info.duration = stream_durations
+            .iter()
+            .copied()
+            .filter(|seconds| plausible(*seconds))
+            .fold(0.0, f64::max);
+    }
+
+    if info.duration > 0.0 && file_bytes > 0 {
+        let computed = (file_bytes as f64 * 8.0 / info.duration) as u64;
+        if info.bitrate == 0 || disagrees(info.bitrate, computed) {
+            info.bitrate = computed;
+            info.bitrate_estimated = true;
+        }
+        // No stream carries much more than the whole file does
+        let ceiling = computed as f64 * BITRATE_TOLERANCE;
+        if info.video_bitrate as f64 > ceiling {
+            info.video_bitrate = 0;
+        }
+        for stream in &mut info.video_streams {
+            if stream.bitrate as f64 > ceiling {
+                stream.bitrate = 0;
+            }
+        }
+        // Without a video bitrate (Matroska, some TS), whatever of the file
+        // the audio tracks don't take is the video's
+        if info.video_bitrate == 0 {
+            let audio: u64 = info.audio_tracks.iter().filter_map(|track| track.bitrate).sum();
+            info.video_bitrate = computed.saturating_sub(audio);
+            info.video_bitrate_estimated = info.video_bitrate > 0;
+        }
+    }
+
+    if info.width == 0 || info.height == 0 {
+        info.needs_integrity_check = true;
+    }
+}
+
+/// Whether a reported bitrate is wildly off the computed one; unreported
+/// (0) ones are left alone
+fn disagrees(reported: u64, computed: u64) -> bool {
+    if reported == 0 || computed == 0 {
+        return false;
+    }
+    let ratio = reported as f64 / computed as f64;
+    !(1.0 / BITRATE_TOLERANCE..=BITRATE_TOLERANCE).contains(&ratio)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::converter::{video_info_from_probe, StreamChoice};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn matroska_video_bitrate_is_estimated_from_size_less_audio() {
+        // Matroska leaves `bit_rate` out of the streams and the format; the
+        // audio gives only its BPS statistics tag
+        let probe = json!({
+            "format": {"format_name": "matroska,webm", "duration": "10.000000"},
+            "streams": [
+                {"codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720,
+                 "r_frame_rate": "30/1", "avg_frame_rate": "30/1",
+                 "tags": {"DURATION": "00:00:10.000000000"}},
+                {"codec_type": "audio", "codec_name": "aac", "sample_rate": "48000",
+                 "channels": 2, "tags": {"BPS": "128000", "DURATION": "00:00:10.000000000"}},
+            ],
+        });
+        let path = std::env::temp_dir().join(format!("sanity-{}.mkv", uuid::Uuid::new_v4()));
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(1_000_000).unwrap();
+        let max = Duration::from_secs(super::DEFAULT_MAX_PROBE_DURATION_SECS);
+
+        let info = video_info_from_probe(&path, &probe, StreamChoice::default(), max).unwrap();
+        assert_eq!((info.bitrate, info.bitrate_estimated), (800_000, true));
+        assert_eq!(info.audio_bitrate, 128_000);
+        assert_eq!(info.video_bitrate, 800_000 - 128_000);
+        assert!(info.video_bitrate_estimated);
+
+        // Without the file's size there's nothing to estimate from
+        let _ = std::fs::remove_file(&path);
+        let info = video_info_from_probe(&path, &probe, StreamChoice::default(), max).unwrap();
+        assert_eq!((info.video_bitrate, info.video_bitrate_estimated), (0, false));
+    }
+}
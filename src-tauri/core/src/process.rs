@@ -0,0 +1,500 @@
+//! Child process execution behind a trait, so the engine can be driven by
+//! something other than real ffmpeg/ffprobe binaries.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::ConvertError;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+pub type ProcessPipe = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Starts child processes.
+///
+/// The command carries the program, arguments and stdin setting; runners
+/// always pipe stdout and stderr and must stop the process when the
+/// returned handle is dropped.
+pub trait ProcessRunner: Send + Sync + Debug {
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Box<dyn RunningProcess>>;
+}
+
+/// A started child process
+pub trait RunningProcess: Send {
+    /// Take the stdout pipe; None once taken
+    fn take_stdout(&mut self) -> Option<ProcessPipe>;
+    /// Take the stderr pipe; None once taken
+    fn take_stderr(&mut self) -> Option<ProcessPipe>;
+    fn wait(&mut self) -> BoxFuture<'_, io::Result<ExitStatus>>;
+    /// Ask the process to stop without waiting for it
+    fn start_kill(&mut self) -> io::Result<()>;
+    /// Stop the process and wait for it to exit
+    fn kill(&mut self) -> BoxFuture<'_, io::Result<()>>;
+    /// The OS process id, for usage telemetry; None when there is no real
+    /// process or it has exited
+    fn id(&self) -> Option<u32> {
+        None
+    }
+    /// Ask the process to finish up and exit, as Ctrl-C would: SIGINT on
+    /// Unix, `q` on its stdin elsewhere (which needs stdin piped)
+    fn interrupt(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Err(io::Error::new(io::ErrorKind::Unsupported, "cannot interrupt")) })
+    }
+}
+
+/// Runs real processes with tokio
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioRunner;
+
+impl ProcessRunner for TokioRunner {
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Box<dyn RunningProcess>> {
+        let child = cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        Ok(Box::new(child))
+    }
+}
+
+impl RunningProcess for Child {
+    fn take_stdout(&mut self) -> Option<ProcessPipe> {
+        self.stdout.take().map(|pipe| Box::new(pipe) as ProcessPipe)
+    }
+
+    fn take_stderr(&mut self) -> Option<ProcessPipe> {
+        self.stderr.take().map(|pipe| Box::new(pipe) as ProcessPipe)
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, io::Result<ExitStatus>> {
+        Box::pin(Child::wait(self))
+    }
+
+    fn start_kill(&mut self) -> io::Result<()> {
+        Child::start_kill(self)
+    }
+
+    fn kill(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(Child::kill(self))
+    }
+
+    fn id(&self) -> Option<u32> {
+        Child::id(self)
+    }
+
+    fn interrupt(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async move {
+            #[cfg(unix)]
+            {
+                let pid = Child::id(self)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "already exited"))?;
+                // SAFETY: signals only the child's own pid
+                if unsafe { libc::kill(pid as libc::pid_t, libc::SIGINT) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            }
+            #[cfg(not(unix))]
+            {
+                use tokio::io::AsyncWriteExt;
+                let stdin = self
+                    .stdin
+                    .as_mut()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "stdin not piped"))?;
+                stdin.write_all(b"q").await?;
+                stdin.flush().await
+            }
+        })
+    }
+}
+
+/// Wait for a process while draining both pipes, so a chatty child can't
+/// fill one and stall
+async fn wait_with_output(child: &mut dyn RunningProcess) -> io::Result<Output> {
+    let stdout = child.take_stdout();
+    let stderr = child.take_stderr();
+    let (status, stdout, stderr) =
+        tokio::join!(child.wait(), read_to_end(stdout), read_to_end(stderr));
+    status.map(|status| Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Run a command to completion, capturing stdout and stderr
+pub async fn output(runner: &dyn ProcessRunner, cmd: &mut Command) -> io::Result<Output> {
+    let mut child = runner.spawn(cmd.stdin(Stdio::null()))?;
+    wait_with_output(child.as_mut()).await
+}
+
+/// Run a command to completion, capturing stdout and stderr, and kill it
+/// if the token is cancelled first.
+pub async fn output_cancellable(
+    runner: &dyn ProcessRunner,
+    cmd: &mut Command,
+    cancel: &CancellationToken,
+) -> Result<Output, ConvertError> {
+    let mut child = runner
+        .spawn(cmd.stdin(Stdio::null()))
+        .map_err(|e| format!("Failed to start process: {}", e))?;
+
+    // Returning drops the handle, which stops the child
+    tokio::select! {
+        output = wait_with_output(child.as_mut()) => {
+            output.map_err(|e| format!("Process error: {}", e).into())
+        }
+        _ = cancel.cancelled() => Err(ConvertError::Cancelled),
+    }
+}
+
+/// Run a command to completion, giving up after `timeout`.
+///
+/// Returns None on timeout. The child is killed and, if it exits promptly,
+/// reaped here; one stuck in the kernel (a dead network mount) is left to
+/// tokio's background reaper rather than blocking the caller.
+pub async fn output_with_timeout(
+    runner: &dyn ProcessRunner,
+    cmd: &mut Command,
+    timeout: Duration,
+) -> Result<Option<Output>, String> {
+    let mut child = runner
+        .spawn(cmd.stdin(Stdio::null()))
+        .map_err(|e| format!("Failed to start process: {}", e))?;
+
+    match tokio::time::timeout(timeout, wait_with_output(child.as_mut())).await {
+        Ok(output) => output.map(Some).map_err(|e| format!("Process error: {}", e)),
+        Err(_) => {
+            let _ = child.start_kill();
+            let _ = tokio::time::timeout(Duration::from_secs(1), child.wait()).await;
+            Ok(None)
+        }
+    }
+}
+
+async fn read_to_end<R: AsyncRead + Unpin>(pipe: Option<R>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut buf).await;
+    }
+    buf
+}
+
+/// A runner that replays canned processes instead of starting real ones,
+/// for tests that must not need ffmpeg
+#[cfg(test)]
+pub(crate) mod scripted {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+    use tokio::task::JoinHandle;
+
+    /// Bytes an OS pipe holds before the writer blocks (Linux's default)
+    const PIPE_CAPACITY: usize = 64 * 1024;
+
+    /// What one started process does
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct Script {
+        program: String,
+        args: Vec<String>,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        code: i32,
+        output: Option<Vec<u8>>,
+        hangs: bool,
+        once: bool,
+        piped: bool,
+    }
+
+    impl Script {
+        /// Matches a run of `program`, by file name
+        pub(crate) fn new(program: &str) -> Self {
+            Script { program: program.to_string(), ..Default::default() }
+        }
+
+        /// Only matches when some argument contains `arg`
+        pub(crate) fn with_arg(mut self, arg: &str) -> Self {
+            self.args.push(arg.to_string());
+            self
+        }
+
+        pub(crate) fn stdout(mut self, stdout: impl Into<Vec<u8>>) -> Self {
+            self.stdout = stdout.into();
+            self
+        }
+
+        pub(crate) fn stderr(mut self, stderr: impl Into<Vec<u8>>) -> Self {
+            self.stderr = stderr.into();
+            self
+        }
+
+        pub(crate) fn exit_code(mut self, code: i32) -> Self {
+            self.code = code;
+            self
+        }
+
+        /// Write `bytes` to the last argument, the output file, on start
+        pub(crate) fn writes_output(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+            self.output = Some(bytes.into());
+            self
+        }
+
+        /// Keep stdout open and never exit until killed
+        pub(crate) fn hangs(mut self) -> Self {
+            self.hangs = true;
+            self
+        }
+
+        /// Write stdout and stderr through pipes the size of an OS pipe,
+        /// a line of stdout after each share of stderr, and exit once both
+        /// are written; like a real process, it stalls while a full pipe
+        /// isn't read
+        pub(crate) fn piped(mut self) -> Self {
+            self.piped = true;
+            self
+        }
+
+        /// Used up by the first run it matches
+        pub(crate) fn once(mut self) -> Self {
+            self.once = true;
+            self
+        }
+
+        fn matches(&self, program: &str, args: &[String]) -> bool {
+            let name = Path::new(program).file_stem().map(|s| s.to_string_lossy().to_string());
+            name.as_deref() == Some(self.program.as_str())
+                && self.args.iter().all(|wanted| args.iter().any(|arg| arg.contains(wanted)))
+        }
+    }
+
+    /// Replays the first script matching each run, in the order added, and
+    /// records every argv, program first. A run nothing matches fails to
+    /// start, like a missing binary.
+    #[derive(Debug, Default)]
+    pub(crate) struct ScriptedRunner {
+        scripts: Mutex<Vec<Script>>,
+        calls: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl ScriptedRunner {
+        pub(crate) fn new(scripts: impl IntoIterator<Item = Script>) -> Self {
+            ScriptedRunner { scripts: Mutex::new(scripts.into_iter().collect()), ..Default::default() }
+        }
+
+        /// Every argv started so far
+        pub(crate) fn calls(&self) -> Vec<Vec<String>> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        /// The argvs of runs of `program` with an argument containing `arg`
+        pub(crate) fn calls_with(&self, program: &str, arg: &str) -> Vec<Vec<String>> {
+            let script = Script::new(program).with_arg(arg);
+            self.calls().into_iter().filter(|argv| script.matches(&argv[0], &argv[1..])).collect()
+        }
+    }
+
+    impl ProcessRunner for ScriptedRunner {
+        fn spawn(&self, cmd: &mut Command) -> io::Result<Box<dyn RunningProcess>> {
+            let cmd = cmd.as_std();
+            let program = cmd.get_program().to_string_lossy().to_string();
+            let args: Vec<String> =
+                cmd.get_args().map(|arg| arg.to_string_lossy().to_string()).collect();
+            let argv = std::iter::once(program.clone()).chain(args.clone()).collect();
+            self.calls.lock().unwrap().push(argv);
+
+            let script = {
+                let mut scripts = self.scripts.lock().unwrap();
+                let found = scripts.iter().position(|script| script.matches(&program, &args));
+                match found {
+                    Some(index) if scripts[index].once => scripts.remove(index),
+                    Some(index) => scripts[index].clone(),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no script for {} {}", program, args.join(" ")),
+                        ))
+                    }
+                }
+            };
+            if let (Some(bytes), Some(last)) = (&script.output, args.last()) {
+                let path = last.strip_prefix("file:").unwrap_or(last);
+                std::fs::write(path, bytes)?;
+            }
+            if script.piped {
+                let (stdout, stderr, writer) = write_through_pipes(script.stdout, script.stderr);
+                return Ok(Box::new(ScriptedProcess {
+                    stdout: Some(Box::new(stdout)),
+                    stderr: Some(Box::new(stderr)),
+                    code: script.code,
+                    running: None,
+                    writer: Some(writer),
+                }));
+            }
+            let (running, open) = match script.hangs {
+                true => {
+                    let (writer, reader) = tokio::io::duplex(64);
+                    (Some(writer), Some(reader))
+                }
+                false => (None, None),
+            };
+            let stdout = Cursor::new(script.stdout);
+            let stdout: ProcessPipe = match open {
+                Some(open) => Box::new(stdout.chain(open)),
+                None => Box::new(stdout),
+            };
+            Ok(Box::new(ScriptedProcess {
+                stdout: Some(stdout),
+                stderr: Some(Box::new(Cursor::new(script.stderr))),
+                code: script.code,
+                running,
+                writer: None,
+            }))
+        }
+    }
+
+    /// Readers of `stdout` and `stderr` as written by a task that stalls
+    /// whenever a pipe is full
+    fn write_through_pipes(
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    ) -> (DuplexStream, DuplexStream, JoinHandle<()>) {
+        let (mut out_writer, out_reader) = tokio::io::duplex(PIPE_CAPACITY);
+        let (mut err_writer, err_reader) = tokio::io::duplex(PIPE_CAPACITY);
+        let writer = tokio::spawn(async move {
+            let lines: Vec<&[u8]> = stdout.split_inclusive(|byte| *byte == b'\n').collect();
+            let share = (stderr.len() / (lines.len() + 1)).max(1);
+            let mut shares = stderr.chunks(share);
+            for line in lines {
+                if let Some(share) = shares.next() {
+                    let _ = err_writer.write_all(share).await;
+                }
+                let _ = out_writer.write_all(line).await;
+            }
+            for share in shares {
+                let _ = err_writer.write_all(share).await;
+            }
+        });
+        (out_reader, err_reader, writer)
+    }
+
+    struct ScriptedProcess {
+        stdout: Option<ProcessPipe>,
+        stderr: Option<ProcessPipe>,
+        code: i32,
+        /// Keeps stdout open while a hanging process runs
+        running: Option<DuplexStream>,
+        /// Still writing the output of a `piped` script
+        writer: Option<JoinHandle<()>>,
+    }
+
+    impl RunningProcess for ScriptedProcess {
+        fn take_stdout(&mut self) -> Option<ProcessPipe> {
+            self.stdout.take()
+        }
+
+        fn take_stderr(&mut self) -> Option<ProcessPipe> {
+            self.stderr.take()
+        }
+
+        fn wait(&mut self) -> BoxFuture<'_, io::Result<ExitStatus>> {
+            let running = self.running.is_some();
+            let code = self.code;
+            let writer = self.writer.take();
+            Box::pin(async move {
+                if running {
+                    std::future::pending::<()>().await;
+                }
+                if let Some(writer) = writer {
+                    let _ = writer.await;
+                }
+                Ok(exit_status(code))
+            })
+        }
+
+        fn start_kill(&mut self) -> io::Result<()> {
+            self.running = None;
+            if let Some(writer) = &self.writer {
+                writer.abort();
+            }
+            self.code = KILLED_CODE;
+            Ok(())
+        }
+
+        fn kill(&mut self) -> BoxFuture<'_, io::Result<()>> {
+            let killed = self.start_kill();
+            Box::pin(async move { killed })
+        }
+    }
+
+    /// Exit code of a killed process
+    const KILLED_CODE: i32 = 137;
+
+    fn exit_status(code: i32) -> ExitStatus {
+        #[cfg(unix)]
+        {
+            std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+        }
+        #[cfg(windows)]
+        {
+            std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_scripts_in_order() {
+        let runner = ScriptedRunner::new([
+            Script::new("ffprobe").with_arg("a.mp4").stdout("first").once(),
+            Script::new("ffprobe").stdout("rest").stderr("oops").exit_code(1),
+        ]);
+        let run = |arg: &str| {
+            let mut cmd = Command::new("/usr/bin/ffprobe");
+            cmd.arg(arg);
+            cmd
+        };
+        let first = output(&runner, &mut run("a.mp4")).await.unwrap();
+        assert_eq!(first.stdout, b"first");
+        assert!(first.status.success());
+        let second = output(&runner, &mut run("a.mp4")).await.unwrap();
+        assert_eq!((second.stdout, second.stderr), (b"rest".to_vec(), b"oops".to_vec()));
+        assert_eq!(second.status.code(), Some(1));
+        assert!(runner.spawn(&mut Command::new("ffmpeg")).is_err());
+        assert_eq!(runner.calls().len(), 3);
+        assert_eq!(runner.calls_with("ffprobe", "a.mp4").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_piped_process_stalls_until_stderr_is_read() {
+        let stderr = vec![b'x'; 4 * PIPE_CAPACITY];
+        let script = Script::new("ffmpeg").stdout("a\nb\n").stderr(stderr).piped();
+        let runner = ScriptedRunner::new([script]);
+        let mut child = runner.spawn(&mut Command::new("ffmpeg")).unwrap();
+        let mut stdout = child.take_stdout().unwrap();
+        let mut read = Vec::new();
+        let stalled =
+            tokio::time::timeout(Duration::from_millis(50), stdout.read_to_end(&mut read)).await;
+        assert!(stalled.is_err());
+
+        let mut stderr = child.take_stderr().unwrap();
+        let mut errors = Vec::new();
+        let (out, err) =
+            tokio::join!(stdout.read_to_end(&mut read), stderr.read_to_end(&mut errors));
+        assert_eq!((out.unwrap(), err.unwrap()), (4, 4 * PIPE_CAPACITY));
+        assert!(child.wait().await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn a_hanging_process_runs_until_killed() {
+        let runner = ScriptedRunner::new([Script::new("ffmpeg").stdout("line\n").hangs()]);
+        let timeout = Duration::from_millis(50);
+        let ran = output_with_timeout(&runner, &mut Command::new("ffmpeg"), timeout).await;
+        assert_eq!(ran.map(|output| output.is_none()), Ok(true));
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::converter::ConversionProgress;
+use crate::task_log::TaskLog;
+
+/// Updates in between are dropped; terminal ones are always written
+const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Latest progress of a conversion as one line of JSON in a file, for
+/// tooling that can't listen to app events. Writes are best effort: a
+/// failure is logged and the conversion goes on.
+pub struct ProgressFile {
+    path: PathBuf,
+    log: TaskLog,
+    last_write: Mutex<Option<Instant>>,
+    finished: AtomicBool,
+}
+
+impl ProgressFile {
+    pub fn new(path: PathBuf, log: TaskLog) -> Self {
+        ProgressFile {
+            path,
+            log,
+            last_write: Mutex::new(None),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, progress: &ConversionProgress) {
+        let terminal = progress.status.is_terminal();
+        {
+            let mut last_write = self.last_write.lock().unwrap();
+            if !terminal && last_write.is_some_and(|at| at.elapsed() < WRITE_INTERVAL) {
+                return;
+            }
+            *last_write = Some(Instant::now());
+        }
+        if terminal {
+            self.finished.store(true, Ordering::SeqCst);
+        }
+        let result = serde_json::to_string(progress)
+            .map_err(|e| e.to_string())
+            .and_then(|line| write_atomic(&self.path, &line).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            self.log.line(&format!(
+                "Failed to write progress file {}: {}",
+                self.path.display(),
+                e
+            ));
+        }
+    }
+
+    /// Whether a terminal status has been written
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
+
+/// Write through a temporary file next to the target, so readers never see
+/// half a line
+fn write_atomic(path: &Path, line: &str) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    std::fs::write(&temp, format!("{}\n", line))?;
+    std::fs::rename(&temp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })
+}
@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::converter::ConversionOptions;
+use crate::error::ConvertError;
+
+/// A file waiting to be converted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    pub task_id: String,
+    pub input_path: String,
+    pub output_dir: Option<String>,
+    pub options: Option<ConversionOptions>,
+    /// The failed task this entry converts again
+    #[serde(default)]
+    pub retry_of: Option<String>,
+}
+
+/// A conversion that failed this session, kept so it can be retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedTask {
+    pub task_id: String,
+    pub input_path: String,
+    pub output_dir: Option<String>,
+    /// As the conversion was started with, before settings filled anything in
+    pub options: ConversionOptions,
+    pub error: ConvertError,
+    /// The task it was itself a retry of
+    pub retry_of: Option<String>,
+}
+
+/// What retrying failed tasks queued
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetryReport {
+    /// New entries, one per retried task
+    pub entries: Vec<QueueEntry>,
+    /// Tasks that couldn't be retried, by task id
+    pub failed: BTreeMap<String, ConvertError>,
+}
+
+/// Fields of `ConversionOptions` to change, by name, e.g.
+/// `{"hw_encode_only": false}`; `null` clears an optional one
+pub type OptionOverrides = serde_json::Map<String, serde_json::Value>;
+
+/// An entry converting `failed`'s input again under a new task id. Its
+/// options are the original ones with every field in `overrides` replaced.
+/// A chain of retries all point back at the first task, so they group
+/// under it.
+pub fn retry_entry(
+    failed: &FailedTask,
+    overrides: Option<&OptionOverrides>,
+) -> Result<QueueEntry, ConvertError> {
+    let options = match overrides {
+        Some(overrides) => overlay_options(&failed.options, overrides)?,
+        None => failed.options.clone(),
+    };
+    Ok(QueueEntry {
+        task_id: uuid::Uuid::new_v4().to_string(),
+        input_path: failed.input_path.clone(),
+        output_dir: failed.output_dir.clone(),
+        options: Some(options),
+        retry_of: Some(failed.retry_of.clone().unwrap_or_else(|| failed.task_id.clone())),
+    })
+}
+
+/// `base` with every field in `overrides` replaced; a field that isn't an
+/// option is refused rather than dropped
+fn overlay_options(
+    base: &ConversionOptions,
+    overrides: &OptionOverrides,
+) -> Result<ConversionOptions, ConvertError> {
+    let fail = |e: serde_json::Error| format!("Failed to merge options: {}", e);
+    let mut merged = serde_json::to_value(base).map_err(fail)?;
+    let Some(fields) = merged.as_object_mut() else {
+        return Err("Failed to merge options: they are not an object".into());
+    };
+    for (field, value) in overrides {
+        if !fields.contains_key(field) {
+            return Err(format!("Unknown option: {}", field).into());
+        }
+        fields.insert(field.clone(), value.clone());
+    }
+    Ok(serde_json::from_value(merged).map_err(fail)?)
+}
+
+/// Where a waiting entry stands, sent with its `queued` progress event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueEstimate {
+    pub task_id: String,
+    /// 1 for the next entry to start
+    pub queue_position: usize,
+    /// Rough seconds until it starts; None once an entry ahead of it can't
+    /// be estimated
+    pub estimated_start_seconds: Option<f64>,
+}
+
+/// Start estimates for pending entries, in queue order, from the seconds
+/// until the running tasks are done and each entry's expected encode time.
+/// Running tasks share the machine, so the work ahead of an entry adds up.
+pub fn estimate_starts(
+    busy_seconds: Option<f64>,
+    entries: &[(String, Option<f64>)],
+) -> Vec<QueueEstimate> {
+    let mut start = busy_seconds;
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, (task_id, work_seconds))| {
+            let estimate = QueueEstimate {
+                task_id: task_id.clone(),
+                queue_position: index + 1,
+                estimated_start_seconds: start,
+            };
+            start = start.zip(*work_seconds).map(|(start, work)| start + work);
+            estimate
+        })
+        .collect()
+}
+
+/// What was still queued when the app last quit
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestoredQueue {
+    pub entries: Vec<QueueEntry>,
+    /// Entries whose input file is gone and can't be converted any more
+    pub unrecoverable: Vec<QueueEntry>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    /// Not-yet-started entries of this session
+    pending: Vec<QueueEntry>,
+    /// Entries from the last session the user hasn't resumed or discarded
+    restored: Vec<QueueEntry>,
+}
+
+/// The pending queue, persisted as `queue.json` in the app data dir so it
+/// survives a crash or quit.
+///
+/// Writing is best effort: the queue is a convenience and a failed save
+/// never blocks a conversion.
+pub struct QueueStore {
+    path: Option<PathBuf>,
+    state: Mutex<QueueState>,
+}
+
+impl QueueStore {
+    /// Load last session's queue; it stays on disk until resumed or discarded
+    pub fn load(data_dir: Option<&Path>) -> Self {
+        let path = data_dir.map(|dir| dir.join("queue.json"));
+        let restored = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        QueueStore {
+            path,
+            state: Mutex::new(QueueState {
+                pending: Vec::new(),
+                restored,
+            }),
+        }
+    }
+
+    /// Replace this session's pending entries
+    pub fn set_pending(&self, entries: Vec<QueueEntry>) {
+        let mut state = self.state.lock().unwrap();
+        state.pending = entries;
+        self.save(&state);
+    }
+
+    pub fn pending(&self) -> Vec<QueueEntry> {
+        self.state.lock().unwrap().pending.clone()
+    }
+
+    /// Drop an entry once its conversion starts
+    pub fn remove(&self, task_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        let before = state.pending.len();
+        state.pending.retain(|entry| entry.task_id != task_id);
+        if state.pending.len() != before {
+            self.save(&state);
+        }
+    }
+
+    /// Last session's entries, split by whether their input still exists
+    pub fn restored(&self) -> RestoredQueue {
+        let state = self.state.lock().unwrap();
+        let (entries, unrecoverable) = state
+            .restored
+            .iter()
+            .cloned()
+            .partition(|entry| Path::new(&entry.input_path).is_file());
+        RestoredQueue {
+            entries,
+            unrecoverable,
+        }
+    }
+
+    /// Hand back the recoverable restored entries as pending ones
+    pub fn resume_restored(&self) -> RestoredQueue {
+        let restored = self.restored();
+        let mut state = self.state.lock().unwrap();
+        state.restored.clear();
+        state.pending.extend(restored.entries.iter().cloned());
+        self.save(&state);
+        restored
+    }
+
+    pub fn discard_restored(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.restored.clear();
+        self.save(&state);
+    }
+
+    fn save(&self, state: &QueueState) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        // Undecided entries from last time are kept alongside this session's
+        let entries: Vec<&QueueEntry> = state.restored.iter().chain(&state.pending).collect();
+        if entries.is_empty() {
+            let _ = std::fs::remove_file(path);
+            return;
+        }
+        let Ok(json) = serde_json::to_string_pretty(&entries) else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        // Write to a temp file and rename so a crash can't leave half a file
+        let temp_path = path.with_extension("json.tmp");
+        if std::fs::write(&temp_path, json).is_ok() {
+            let _ = std::fs::rename(&temp_path, path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(options: ConversionOptions) -> FailedTask {
+        FailedTask {
+            task_id: "first".to_string(),
+            input_path: "/videos/a.mov".to_string(),
+            output_dir: None,
+            options,
+            error: ConvertError::Failed("encoder missing".to_string()),
+            retry_of: None,
+        }
+    }
+
+    fn overrides(json: serde_json::Value) -> OptionOverrides {
+        json.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn overrides_can_restore_defaults() {
+        let options = ConversionOptions {
+            replace_original: true,
+            hw_encode_only: true,
+            speed: Some(2.0),
+            ..Default::default()
+        };
+        let set = overrides(serde_json::json!({
+            "replace_original": false,
+            "hw_encode_only": false,
+            "speed": null,
+        }));
+        let entry = retry_entry(&failed(options), Some(&set)).unwrap();
+        let options = entry.options.unwrap();
+        assert!(!options.replace_original);
+        assert!(!options.hw_encode_only);
+        assert_eq!(options.speed, None);
+        assert_eq!(entry.retry_of.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn overrides_keep_fields_they_leave_out() {
+        let options = ConversionOptions { speed: Some(2.0), ..Default::default() };
+        let set = overrides(serde_json::json!({ "hw_encode_only": true }));
+        let options = retry_entry(&failed(options), Some(&set)).unwrap().options.unwrap();
+        assert!(options.hw_encode_only);
+        assert_eq!(options.speed, Some(2.0));
+    }
+
+    #[test]
+    fn overrides_refuse_unknown_and_mistyped_fields() {
+        let task = failed(ConversionOptions::default());
+        for set in [
+            serde_json::json!({ "hw_encode": false }),
+            serde_json::json!({ "hw_encode_only": "no" }),
+        ] {
+            assert!(retry_entry(&task, Some(&overrides(set))).is_err());
+        }
+    }
+
+    #[test]
+    fn retries_point_at_the_first_task() {
+        let mut task = failed(ConversionOptions::default());
+        task.task_id = "second".to_string();
+        task.retry_of = Some("first".to_string());
+        let entry = retry_entry(&task, None).unwrap();
+        assert_eq!(entry.retry_of.as_deref(), Some("first"));
+        assert_ne!(entry.task_id, "second");
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::{
+    convert_single, get_video_info, ConversionOptions, ConversionProgress, ConversionResult,
+    ConversionStatus,
+};
+use crate::error::ConvertError;
+use crate::hls::{remove_hls_output, OutputFormat};
+use crate::naming::DEFAULT_OUTPUT_TEMPLATE;
+use crate::resolver::FfmpegResolver;
+use crate::task_log::TaskLog;
+
+/// One output of a multi-rendition job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenditionSpec {
+    /// Appended to the output name, e.g. `_small`
+    pub suffix: String,
+    /// Scale down to at most this many lines; None keeps the source size
+    pub max_height: Option<u32>,
+    /// Quality on the libx264 CRF scale; None keeps the job's
+    pub crf: Option<u32>,
+}
+
+/// Characters a suffix can't have: template braces and path separators
+const SUFFIX_FORBIDDEN: &[char] = &['{', '}', '/', '\\'];
+
+pub(crate) fn validate_renditions(specs: &[RenditionSpec]) -> Result<(), String> {
+    let mut suffixes = HashSet::new();
+    for spec in specs {
+        if spec.suffix.is_empty() || spec.suffix.contains(SUFFIX_FORBIDDEN) {
+            return Err(format!("Invalid rendition suffix: {:?}", spec.suffix));
+        }
+        if !suffixes.insert(spec.suffix.as_str()) {
+            return Err(format!("Rendition suffix {} is used twice", spec.suffix));
+        }
+    }
+    Ok(())
+}
+
+/// Encode one output per rendition, one after another, reporting progress
+/// over all of them as a single task.
+///
+/// Each rendition is a regular conversion with the job's options plus its
+/// own size, quality and name suffix. Heights above the source are clamped
+/// to it with a warning. When any rendition fails or the job is cancelled,
+/// the renditions already written are removed too.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn convert_renditions<F>(
+    resolver: &FfmpegResolver,
+    input_path: &str,
+    output_dir: &str,
+    task_id: &str,
+    options: &ConversionOptions,
+    cache_dir: Option<&Path>,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<ConversionResult, ConvertError>
+where
+    F: Fn(ConversionProgress) + Send + Sync + 'static,
+{
+    let info = get_video_info(resolver, input_path).await?;
+    let source_height = info.display_size().1;
+    let template = options.output_template.as_deref().unwrap_or(DEFAULT_OUTPUT_TEMPLATE);
+    let callback = Arc::new(progress_callback);
+    let count = options.renditions.len() as f64;
+
+    let mut warnings = Vec::new();
+    let mut done: Vec<ConversionResult> = Vec::new();
+    for (index, spec) in options.renditions.iter().enumerate() {
+        let max_height = match spec.max_height {
+            Some(height) if height > source_height => {
+                warnings.push(format!(
+                    "Rendition {} asked for {}p, above the {}p source, so the source height \
+                     was kept",
+                    spec.suffix, height, source_height
+                ));
+                None
+            }
+            height => height.or(options.max_height),
+        };
+        let rendition_options = ConversionOptions {
+            output_template: Some(format!("{}{}", template, spec.suffix)),
+            max_height,
+            crf: spec.crf.or(options.crf),
+            renditions: Vec::new(),
+            ..options.clone()
+        };
+        log.line(&format!("Rendition {} of {}: {}", index + 1, count, spec.suffix));
+
+        let callback = Arc::clone(&callback);
+        let offset = index as f64;
+        let result = convert_single(
+            resolver,
+            input_path,
+            output_dir,
+            task_id,
+            &rendition_options,
+            cache_dir,
+            log,
+            cancel,
+            move |progress| match progress.status {
+                // The job reports one completion once every rendition is done
+                status if status.is_completed() => {}
+                ConversionStatus::CancelledPartial => {}
+                status if status.is_terminal() => callback(progress),
+                _ => callback(ConversionProgress {
+                    progress: (offset * 100.0 + progress.progress) / count,
+                    ..progress
+                }),
+            },
+        )
+        .await;
+        match result {
+            // Later renditions never started; the finished ones are kept
+            // along with the cut-short one
+            Ok(result) if result.status == ConversionStatus::CancelledPartial => {
+                done.push(result);
+                break;
+            }
+            Ok(result) => done.push(result),
+            Err(e) => {
+                for result in &done {
+                    match options.output_format {
+                        OutputFormat::Hls => remove_hls_output(Path::new(&result.output_path)),
+                        OutputFormat::Mp4 | OutputFormat::Mkv => {
+                            let _ = std::fs::remove_file(&result.output_path);
+                        }
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    for result in &done {
+        for warning in &result.warnings {
+            if !warnings.contains(warning) {
+                warnings.push(warning.clone());
+            }
+        }
+    }
+    let mut combined = done[0].clone();
+    combined.rendition_paths = done.iter().map(|result| result.output_path.clone()).collect();
+    combined.output_bytes = done.iter().map(|result| result.output_bytes).sum();
+    combined.warnings = warnings;
+    combined.decode_error_count = done.iter().map(|result| result.decode_error_count).sum();
+    if done.iter().any(|result| result.status == ConversionStatus::CompletedWithErrors) {
+        combined.status = ConversionStatus::CompletedWithErrors;
+    }
+    if done.iter().any(|result| result.status == ConversionStatus::CancelledPartial) {
+        combined.status = ConversionStatus::CancelledPartial;
+    }
+
+    callback(ConversionProgress {
+        output_path: Some(combined.output_path.clone()),
+        video_action: Some(combined.video_action.clone()),
+        audio_action: Some(combined.audio_action.clone()),
+        warnings: combined.warnings.clone(),
+        ..ConversionProgress::update(task_id, 100.0, combined.status)
+    });
+    Ok(combined)
+}
@@ -0,0 +1,490 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::cache_manager::CacheManager;
+use crate::converter::CancelMode;
+use crate::encoder_slots::EncoderSlots;
+use crate::probe_cache::ProbeCache;
+use crate::probe_sanity::DEFAULT_MAX_PROBE_DURATION_SECS;
+use crate::process::{output_with_timeout, ProcessRunner, TokioRunner};
+use crate::telemetry::ProcessTable;
+
+/// How long ffprobe (and `-version` checks) may take before giving up
+pub const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 15;
+
+/// The two binaries the converter shells out to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binary {
+    Ffmpeg,
+    Ffprobe,
+}
+
+impl Binary {
+    pub fn name(self) -> &'static str {
+        match self {
+            Binary::Ffmpeg => "ffmpeg",
+            Binary::Ffprobe => "ffprobe",
+        }
+    }
+}
+
+/// Where a resolved binary came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinarySource {
+    UserConfigured,
+    Bundled,
+    System,
+}
+
+/// A binary that passed verification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryInfo {
+    pub path: String,
+    pub source: BinarySource,
+    /// First line of `-version` output
+    pub version: String,
+    /// The `configuration:` line of `-version` output, the `--enable-…`
+    /// flags the build was made with; empty when it doesn't print one
+    pub configuration: String,
+}
+
+/// What became of one place the resolver looked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateOutcome {
+    Accepted,
+    /// There, but `-version` didn't run or failed
+    Failed,
+    /// No file at the path
+    Missing,
+    /// Skipped because an earlier candidate was accepted
+    NotTried,
+}
+
+/// One candidate of the last search, in the order tried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchedCandidate {
+    pub path: String,
+    pub source: BinarySource,
+    pub outcome: CandidateOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FfmpegInfo {
+    pub ffmpeg: Option<BinaryInfo>,
+    pub ffprobe: Option<BinaryInfo>,
+    /// Every place looked for each binary, so packaging problems show
+    pub ffmpeg_search: Vec<SearchedCandidate>,
+    pub ffprobe_search: Vec<SearchedCandidate>,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    user_path: Option<String>,
+    resolved: Option<BinaryInfo>,
+    searched: Vec<SearchedCandidate>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    ffmpeg: Slot,
+    ffprobe: Slot,
+    /// The app's resource dir, where some installers put the sidecars
+    resource_dir: Option<PathBuf>,
+}
+
+impl Cache {
+    fn slot(&mut self, binary: Binary) -> &mut Slot {
+        match binary {
+            Binary::Ffmpeg => &mut self.ffmpeg,
+            Binary::Ffprobe => &mut self.ffprobe,
+        }
+    }
+}
+
+/// Finds working ffmpeg/ffprobe binaries once and remembers them.
+///
+/// Candidates are tried in order (user-configured path, bundled sidecar,
+/// then `PATH`) and each is verified with a `-version` run before being
+/// accepted, so a broken or quarantined binary falls through to the next
+/// one instead of failing mid-conversion.
+///
+/// It also owns the [`ProcessRunner`] every ffmpeg/ffprobe run goes
+/// through, real processes unless another is given to `with_runner`, the
+/// cache of probe results, the hardware encoder sessions in use and the
+/// running ffmpeg processes.
+#[derive(Debug)]
+pub struct FfmpegResolver {
+    cache: Mutex<Cache>,
+    probe_timeout_secs: AtomicU64,
+    max_probe_duration_secs: AtomicU64,
+    runner: Arc<dyn ProcessRunner>,
+    probe_cache: ProbeCache,
+    encoder_slots: Arc<EncoderSlots>,
+    cache_manager: CacheManager,
+    processes: Arc<ProcessTable>,
+    /// How tasks about to be cancelled want to be stopped, when not the
+    /// default
+    cancel_modes: std::sync::Mutex<HashMap<String, CancelMode>>,
+}
+
+impl FfmpegResolver {
+    pub fn new(ffmpeg_path: Option<String>, ffprobe_path: Option<String>) -> Self {
+        Self::with_runner(ffmpeg_path, ffprobe_path, Arc::new(TokioRunner))
+    }
+
+    pub fn with_runner(
+        ffmpeg_path: Option<String>,
+        ffprobe_path: Option<String>,
+        runner: Arc<dyn ProcessRunner>,
+    ) -> Self {
+        let mut cache = Cache::default();
+        cache.ffmpeg.user_path = ffmpeg_path;
+        cache.ffprobe.user_path = ffprobe_path;
+        FfmpegResolver {
+            cache: Mutex::new(cache),
+            probe_timeout_secs: AtomicU64::new(DEFAULT_PROBE_TIMEOUT_SECS),
+            max_probe_duration_secs: AtomicU64::new(DEFAULT_MAX_PROBE_DURATION_SECS),
+            runner,
+            probe_cache: ProbeCache::default(),
+            encoder_slots: Arc::default(),
+            cache_manager: CacheManager::default(),
+            processes: Arc::default(),
+            cancel_modes: Default::default(),
+        }
+    }
+
+    /// Also look for bundled binaries in the app's resource dir (AppImage
+    /// and NSIS installs put the sidecars there)
+    pub fn with_resource_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.cache.get_mut().resource_dir = dir;
+        self
+    }
+
+    pub fn runner(&self) -> &dyn ProcessRunner {
+        self.runner.as_ref()
+    }
+
+    /// Probe results shared by everything using this resolver
+    pub fn probe_cache(&self) -> &ProbeCache {
+        &self.probe_cache
+    }
+
+    /// Hardware encoder sessions shared by every conversion using this
+    /// resolver
+    pub fn encoder_slots(&self) -> &Arc<EncoderSlots> {
+        &self.encoder_slots
+    }
+
+    /// Index of the files kept in the app cache dir
+    pub fn cache_manager(&self) -> &CacheManager {
+        &self.cache_manager
+    }
+
+    /// The ffmpeg processes of running conversions, for usage telemetry
+    pub fn processes(&self) -> &Arc<ProcessTable> {
+        &self.processes
+    }
+
+    /// Say how `task_id` should stop once its token is cancelled; set it
+    /// before cancelling
+    pub fn set_cancel_mode(&self, task_id: &str, mode: CancelMode) {
+        self.cancel_modes.lock().unwrap().insert(task_id.to_string(), mode);
+    }
+
+    /// The mode set for `task_id`, forgetting it
+    pub fn take_cancel_mode(&self, task_id: &str) -> CancelMode {
+        self.cancel_modes.lock().unwrap().remove(task_id).unwrap_or_default()
+    }
+
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_secs(self.probe_timeout_secs.load(Ordering::Relaxed))
+    }
+
+    pub fn set_probe_timeout(&self, seconds: u64) {
+        self.probe_timeout_secs.store(seconds, Ordering::Relaxed);
+    }
+
+    /// Probed durations above this are treated as garbage timestamps
+    pub fn max_probe_duration(&self) -> Duration {
+        Duration::from_secs(self.max_probe_duration_secs.load(Ordering::Relaxed))
+    }
+
+    /// Change the ceiling; cached probes are dropped so they get judged by
+    /// the new one
+    pub fn set_max_probe_duration(&self, seconds: u64) {
+        self.max_probe_duration_secs.store(seconds, Ordering::Relaxed);
+        self.probe_cache.clear();
+    }
+
+    pub async fn ffmpeg(&self) -> Result<String, String> {
+        self.resolve(Binary::Ffmpeg).await.map(|info| info.path)
+    }
+
+    pub async fn ffprobe(&self) -> Result<String, String> {
+        self.resolve(Binary::Ffprobe).await.map(|info| info.path)
+    }
+
+    pub async fn info(&self) -> FfmpegInfo {
+        let ffmpeg = self.resolve(Binary::Ffmpeg).await.ok();
+        let ffprobe = self.resolve(Binary::Ffprobe).await.ok();
+        let cache = self.cache.lock().await;
+        FfmpegInfo {
+            ffmpeg,
+            ffprobe,
+            ffmpeg_search: cache.ffmpeg.searched.clone(),
+            ffprobe_search: cache.ffprobe.searched.clone(),
+        }
+    }
+
+    /// Whether the resolved ffmpeg was built with an external library,
+    /// e.g. `soxr` for `--enable-libsoxr`
+    pub async fn ffmpeg_has_library(&self, name: &str) -> bool {
+        self.resolve(Binary::Ffmpeg).await.is_ok_and(|info| {
+            let flag = format!("--enable-lib{}", name);
+            info.configuration.split_whitespace().any(|option| option == flag)
+        })
+    }
+
+    /// Forget the cached paths so the next call searches again
+    pub async fn invalidate(&self) {
+        let mut cache = self.cache.lock().await;
+        cache.ffmpeg.resolved = None;
+        cache.ffprobe.resolved = None;
+    }
+
+    /// Replace the user-configured path for a binary (None clears it)
+    pub async fn set_user_path(&self, binary: Binary, path: Option<String>) {
+        let mut cache = self.cache.lock().await;
+        let slot = cache.slot(binary);
+        slot.user_path = path;
+        slot.resolved = None;
+        if binary == Binary::Ffprobe {
+            // A different build may report streams differently
+            self.probe_cache.clear();
+        }
+    }
+
+    async fn resolve(&self, binary: Binary) -> Result<BinaryInfo, String> {
+        // Holding the lock while verifying keeps concurrent callers from
+        // racing to run the same `-version` checks
+        let mut cache = self.cache.lock().await;
+        let resource_dir = cache.resource_dir.clone();
+        let slot = cache.slot(binary);
+        if let Some(info) = &slot.resolved {
+            return Ok(info.clone());
+        }
+
+        slot.searched.clear();
+        let mut found = None;
+        for (path, source) in candidates(binary, slot.user_path.as_deref(), resource_dir.as_deref())
+        {
+            let outcome = if found.is_some() {
+                CandidateOutcome::NotTried
+            } else if source == BinarySource::Bundled && !Path::new(&path).is_file() {
+                CandidateOutcome::Missing
+            } else if let Some((version, configuration)) = verify(self.runner(), &path).await {
+                found = Some(BinaryInfo {
+                    path: path.clone(),
+                    source,
+                    version,
+                    configuration,
+                });
+                CandidateOutcome::Accepted
+            } else {
+                CandidateOutcome::Failed
+            };
+            slot.searched.push(SearchedCandidate { path, source, outcome });
+        }
+        slot.resolved = found.clone();
+        found.ok_or_else(|| format!("No working {} binary found", binary.name()))
+    }
+}
+
+/// Candidate paths for a binary, in the order they should be tried: the
+/// user's, the bundled sidecar (next to the executable, in the resource dir
+/// or a `bin/` folder in either, under its plain or target-suffixed name),
+/// then `PATH`
+fn candidates(
+    binary: Binary,
+    user_path: Option<&str>,
+    resource_dir: Option<&Path>,
+) -> Vec<(String, BinarySource)> {
+    let mut candidates = Vec::new();
+
+    if let Some(path) = user_path {
+        candidates.push((path.to_string(), BinarySource::UserConfigured));
+    }
+
+    let mut file_names = vec![format!("{}{}", binary.name(), std::env::consts::EXE_SUFFIX)];
+    if let Some(triple) = target_triple() {
+        file_names.push(format!("{}-{}{}", binary.name(), triple, std::env::consts::EXE_SUFFIX));
+    }
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for base in [get_bundled_bin_dir(), resource_dir.map(Path::to_path_buf)].into_iter().flatten() {
+        for dir in [base.clone(), base.join("bin")] {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    for dir in &dirs {
+        for name in &file_names {
+            let path = dir.join(name).to_string_lossy().to_string();
+            candidates.push((path, BinarySource::Bundled));
+        }
+    }
+
+    // Fallback to whatever is on PATH
+    candidates.push((binary.name().to_string(), BinarySource::System));
+    candidates
+}
+
+/// The target triple Tauri appends to sidecar names at build time; bundles
+/// usually drop it, but not every packaging path does
+fn target_triple() -> Option<&'static str> {
+    if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        Some("aarch64-apple-darwin")
+    } else if cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+        Some("x86_64-apple-darwin")
+    } else if cfg!(all(target_os = "windows", target_arch = "x86_64")) {
+        Some("x86_64-pc-windows-msvc")
+    } else if cfg!(all(target_os = "windows", target_arch = "aarch64")) {
+        Some("aarch64-pc-windows-msvc")
+    } else if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some("x86_64-unknown-linux-gnu")
+    } else if cfg!(all(target_os = "linux", target_arch = "aarch64")) {
+        Some("aarch64-unknown-linux-gnu")
+    } else {
+        None
+    }
+}
+
+/// Check that a binary actually runs, returning its version and
+/// configuration lines
+async fn verify(runner: &dyn ProcessRunner, path: &str) -> Option<(String, String)> {
+    let output = run_capture(runner, path, &["-version"]).await.ok()?;
+    let version = output.lines().next().unwrap_or_default().to_string();
+    let configuration = output
+        .lines()
+        .find_map(|line| line.strip_prefix("configuration:"))
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    Some((version, configuration))
+}
+
+async fn run_capture(
+    runner: &dyn ProcessRunner,
+    path: &str,
+    args: &[&str],
+) -> Result<String, String> {
+    let timeout = Duration::from_secs(DEFAULT_PROBE_TIMEOUT_SECS);
+    let output = output_with_timeout(runner, Command::new(path).args(args), timeout)
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", path, e))?
+        .ok_or_else(|| format!("{} {} did not answer in time", path, args.join(" ")))?;
+    if !output.status.success() {
+        return Err(format!("{} {} exited with status: {}", path, args.join(" "), output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Hardware H.264 encoders that can stand in for libx264
+const HW_H264_ENCODERS: &[&str] = &[
+    "h264_videotoolbox",
+    "h264_nvenc",
+    "h264_qsv",
+    "h264_amf",
+    "h264_vaapi",
+];
+
+/// Check that a user-supplied binary is usable before accepting it.
+///
+/// Beyond running at all, ffmpeg must be able to produce what we need:
+/// an H.264 encoder (libx264 or hardware), the AAC encoder, and the mp4 muxer.
+pub async fn validate_binary(binary: Binary, path: &str) -> Result<(), String> {
+    let file = Path::new(path);
+    let metadata = std::fs::metadata(file).map_err(|_| format!("File not found: {}", path))?;
+    if !metadata.is_file() {
+        return Err(format!("Not a file: {}", path));
+    }
+    if !is_executable(&metadata) {
+        return Err(format!("File is not executable: {}", path));
+    }
+
+    let version = run_capture(&TokioRunner, path, &["-version"])
+        .await
+        .map_err(|e| format!("Binary does not respond to -version: {}", e))?;
+    if !version.starts_with(&format!("{} version", binary.name())) {
+        return Err(format!("{} is not an {} binary", path, binary.name()));
+    }
+
+    if binary == Binary::Ffmpeg {
+        let encoders = run_capture(&TokioRunner, path, &["-hide_banner", "-encoders"]).await?;
+        if h264_encoders_in(&encoders).is_empty() {
+            return Err("ffmpeg build has no H.264 encoder (libx264 or hardware)".to_string());
+        }
+        if !has_encoder(&encoders, "aac") {
+            return Err("ffmpeg build has no AAC encoder".to_string());
+        }
+
+        let muxers = run_capture(&TokioRunner, path, &["-hide_banner", "-muxers"]).await?;
+        if !muxers
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some("mp4"))
+        {
+            return Err("ffmpeg build has no mp4 muxer".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// H.264 encoders this ffmpeg build offers, libx264 first. Hardware entries
+/// only mean support was compiled in, not that the hardware is present.
+pub async fn h264_encoders(path: &str) -> Result<Vec<String>, String> {
+    let encoders = run_capture(&TokioRunner, path, &["-hide_banner", "-encoders"]).await?;
+    Ok(h264_encoders_in(&encoders))
+}
+
+fn h264_encoders_in(encoders: &str) -> Vec<String> {
+    std::iter::once("libx264")
+        .chain(HW_H264_ENCODERS.iter().copied())
+        .filter(|name| has_encoder(encoders, name))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Check `-encoders` output for an encoder name
+fn has_encoder(encoders: &str, name: &str) -> bool {
+    encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(name))
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    true
+}
+
+/// Get the directory containing the bundled binaries
+fn get_bundled_bin_dir() -> Option<PathBuf> {
+    let exe_path = std::env::current_exe().ok()?;
+
+    // On macOS the binary is at App.app/Contents/MacOS/app-name and external
+    // binaries sit next to it; Windows and Linux bundles use the same layout
+    exe_path.parent().map(|p| p.to_path_buf())
+}
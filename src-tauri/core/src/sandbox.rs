@@ -0,0 +1,117 @@
+//! Access to user-picked files from a macOS App Sandbox build.
+//!
+//! Built with the `sandbox` feature, the app has to claim security-scoped
+//! access to paths the user picked before it (and the ffmpeg processes it
+//! starts) can read them. Without the feature, or on other platforms,
+//! everything here does nothing.
+
+use std::path::Path;
+
+use crate::error::ConvertError;
+
+/// Messages ffmpeg and ffprobe print when the OS refuses a file (EPERM and
+/// EACCES), as opposed to a file they can read but not make sense of
+const DENIED_MESSAGES: &[&str] = &["Operation not permitted", "Permission denied"];
+
+/// Whether this build runs inside the App Sandbox
+pub fn is_sandboxed() -> bool {
+    cfg!(all(target_os = "macos", feature = "sandbox"))
+}
+
+/// Security-scoped access to a set of paths, held until dropped
+#[derive(Default)]
+pub struct ScopedAccess {
+    #[cfg(all(target_os = "macos", feature = "sandbox"))]
+    urls: Vec<scoped::ScopedUrl>,
+}
+
+impl ScopedAccess {
+    /// Start access to each path. Paths the sandbox already lets the app
+    /// read (its container, or ones it was never granted) are skipped.
+    pub fn start<'a, I>(paths: I) -> ScopedAccess
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        #[cfg(all(target_os = "macos", feature = "sandbox"))]
+        {
+            ScopedAccess { urls: paths.into_iter().filter_map(scoped::ScopedUrl::start).collect() }
+        }
+        #[cfg(not(all(target_os = "macos", feature = "sandbox")))]
+        {
+            let _ = paths;
+            ScopedAccess::default()
+        }
+    }
+}
+
+/// Whether ffmpeg output says the sandbox kept it from a file
+pub fn denied_in_output(lines: &[String]) -> bool {
+    is_sandboxed()
+        && lines.iter().any(|line| DENIED_MESSAGES.iter().any(|message| line.contains(message)))
+}
+
+/// The error for a file ffmpeg wasn't let at. Still being able to read it
+/// ourselves means the grant is fine but doesn't reach the child process,
+/// which a local copy gets around.
+pub fn denied_error(path: &Path, staged: bool) -> ConvertError {
+    let readable = std::fs::File::open(path).is_ok();
+    ConvertError::PermissionDenied(if readable && !staged {
+        format!(
+            "ffmpeg can't read {} from here; turn on copying to local disk first and try again",
+            path.display()
+        )
+    } else {
+        format!("{} is no longer accessible; pick it again to grant access", path.display())
+    })
+}
+
+#[cfg(all(target_os = "macos", feature = "sandbox"))]
+mod scoped {
+    use core_foundation_sys::base::{kCFAllocatorDefault, CFIndex, CFRelease, CFTypeRef};
+    use core_foundation_sys::url::{
+        CFURLCreateFromFileSystemRepresentation, CFURLRef,
+        CFURLStartAccessingSecurityScopedResource, CFURLStopAccessingSecurityScopedResource,
+    };
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    pub struct ScopedUrl(CFURLRef);
+
+    // CFURL is immutable and CoreFoundation's retain/release is thread-safe
+    unsafe impl Send for ScopedUrl {}
+    unsafe impl Sync for ScopedUrl {}
+
+    impl ScopedUrl {
+        pub fn start(path: &Path) -> Option<ScopedUrl> {
+            let bytes = path.as_os_str().as_bytes();
+            // SAFETY: the buffer outlives the call, which copies it
+            let url = unsafe {
+                CFURLCreateFromFileSystemRepresentation(
+                    kCFAllocatorDefault,
+                    bytes.as_ptr(),
+                    bytes.len() as CFIndex,
+                    path.is_dir() as u8,
+                )
+            };
+            if url.is_null() {
+                return None;
+            }
+            // SAFETY: `url` is a valid CFURL we own
+            if unsafe { CFURLStartAccessingSecurityScopedResource(url) } == 0 {
+                unsafe { CFRelease(url as CFTypeRef) };
+                return None;
+            }
+            Some(ScopedUrl(url))
+        }
+    }
+
+    impl Drop for ScopedUrl {
+        fn drop(&mut self) {
+            // SAFETY: access was started on this URL, which we still own
+            unsafe {
+                CFURLStopAccessingSecurityScopedResource(self.0);
+                CFRelease(self.0 as CFTypeRef);
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::get_video_info;
+use crate::error::ConvertError;
+use crate::naming::CollisionPolicy;
+use crate::paths::ffmpeg_path_arg;
+use crate::process::output_cancellable;
+use crate::resolver::FfmpegResolver;
+use crate::task_log::TaskLog;
+
+/// Bitrate assumed for size-limited splitting when the source doesn't say
+const FALLBACK_BITRATE: u64 = 8_000_000;
+/// Aim this far under a size limit, since bitrate varies within a file
+const SIZE_MARGIN: f64 = 0.9;
+const MB: u64 = 1024 * 1024;
+
+/// How to split an output for upload targets with file limits
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentSpec {
+    MaxDurationSeconds(f64),
+    MaxSizeMb(u64),
+}
+
+impl SegmentSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            SegmentSpec::MaxDurationSeconds(seconds) if !(1.0..).contains(&seconds) => {
+                Err("Segment duration must be at least one second".to_string())
+            }
+            SegmentSpec::MaxSizeMb(0) => Err("Segment size must be at least 1 MB".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn max_bytes(&self) -> Option<u64> {
+        match *self {
+            SegmentSpec::MaxSizeMb(mb) => Some(mb.saturating_mul(MB)),
+            SegmentSpec::MaxDurationSeconds(_) => None,
+        }
+    }
+
+    /// Segment length in seconds; for a size limit, estimated from the
+    /// expected output bitrate in bits/s (0 when unknown)
+    pub fn segment_seconds(&self, bitrate: u64) -> f64 {
+        match *self {
+            SegmentSpec::MaxDurationSeconds(seconds) => seconds,
+            SegmentSpec::MaxSizeMb(mb) => seconds_for_size(mb.saturating_mul(MB), bitrate),
+        }
+    }
+}
+
+fn seconds_for_size(bytes: u64, bitrate: u64) -> f64 {
+    let bitrate = if bitrate > 0 { bitrate } else { FALLBACK_BITRATE };
+    (bytes as f64 * 8.0 / bitrate as f64 * SIZE_MARGIN).max(1.0)
+}
+
+/// Path of segment `index` for an output, e.g. `movie_002.mp4`
+pub fn segment_path(output_path: &Path, index: usize) -> PathBuf {
+    output_path.with_file_name(format!("{}_{:03}.mp4", stem(output_path), index))
+}
+
+/// The segment muxer's printf-style output pattern for an output path
+pub fn segment_pattern(output_path: &Path) -> PathBuf {
+    output_path.with_file_name(format!("{}_%03d.mp4", stem(output_path).replace('%', "%%")))
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Pick the output path segments are named after. Collisions are judged by
+/// the first segment, since the base file itself is never written.
+pub fn resolve_segment_base(output_path: PathBuf, policy: CollisionPolicy) -> PathBuf {
+    if policy == CollisionPolicy::Overwrite || !segment_path(&output_path, 0).exists() {
+        return output_path;
+    }
+    let base = stem(&output_path);
+    (1..)
+        .map(|n| output_path.with_file_name(format!("{} ({}).mp4", base, n)))
+        .find(|path| !segment_path(path, 0).exists())
+        .unwrap_or(output_path)
+}
+
+/// Output arguments that replace the plain mp4 muxer
+pub fn segment_args(seconds: f64, encodes_video: bool) -> Vec<String> {
+    let mut args = Vec::new();
+    if encodes_video {
+        // Keyframes on the boundaries make each cut exact; copied video can
+        // only be cut on the keyframes it already has
+        args.push("-force_key_frames".to_string());
+        args.push(format!("expr:gte(t,n_forced*{:.3})", seconds));
+    }
+    args.extend(
+        [
+            "-f",
+            "segment",
+            "-segment_time",
+            &format!("{:.3}", seconds),
+            "-reset_timestamps",
+            "1",
+            "-segment_format",
+            "mp4",
+            "-segment_format_options",
+            "movflags=+faststart",
+        ]
+        .iter()
+        .map(|a| a.to_string()),
+    );
+    args
+}
+
+/// Segments written so far for an output, in order
+pub fn existing_segments(output_path: &Path) -> Vec<PathBuf> {
+    (0..)
+        .map(|index| segment_path(output_path, index))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+/// Delete every segment of an output, including a partial last one
+pub fn remove_segments(output_path: &Path) {
+    remove_all(existing_segments(output_path));
+}
+
+fn remove_all(paths: Vec<PathBuf>) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Split any segment over the size limit again, keeping the order.
+///
+/// Copied video can't be cut between keyframes, so a segment that still
+/// can't be split small enough is kept and reported in the returned warnings.
+pub async fn enforce_size_limit(
+    resolver: &FfmpegResolver,
+    segments: Vec<PathBuf>,
+    max_bytes: u64,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+) -> Result<(Vec<PathBuf>, Vec<String>), ConvertError> {
+    let mut result = Vec::new();
+    let mut warnings = Vec::new();
+
+    for segment in segments {
+        let size = std::fs::metadata(&segment).map(|m| m.len()).unwrap_or(0);
+        if size <= max_bytes {
+            result.push(segment);
+            continue;
+        }
+
+        log.line(&format!("{} is {} bytes, splitting it again", segment.display(), size));
+        let duration = get_video_info(resolver, &segment.to_string_lossy()).await?.duration;
+        let seconds = seconds_for_size(max_bytes, (size as f64 * 8.0 / duration.max(1.0)) as u64);
+        let parts = resplit(resolver, &segment, seconds, log, cancel).await?;
+        if parts.len() > 1 {
+            let _ = std::fs::remove_file(&segment);
+            result.extend(parts);
+        } else {
+            remove_all(parts);
+            warnings.push(format!(
+                "{} is over the size limit but has no keyframe to split at",
+                segment.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            result.push(segment);
+        }
+    }
+    Ok((result, warnings))
+}
+
+/// Cut one segment into `<segment>_NN.mp4` parts without re-encoding
+async fn resplit(
+    resolver: &FfmpegResolver,
+    segment: &Path,
+    seconds: f64,
+    log: &TaskLog,
+    cancel: &CancellationToken,
+) -> Result<Vec<PathBuf>, ConvertError> {
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let base = stem(segment);
+    let pattern = segment.with_file_name(format!("{}_%02d.mp4", base.replace('%', "%%")));
+    let part = |index: usize| segment.with_file_name(format!("{}_{:02}.mp4", base, index));
+
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin", "-y", "-i"])
+        .arg(ffmpeg_path_arg(segment))
+        .args(["-map", "0", "-c", "copy"])
+        .args(segment_args(seconds, false))
+        .arg(ffmpeg_path_arg(&pattern));
+    let args: Vec<String> =
+        cmd.as_std().get_args().map(|a| a.to_string_lossy().to_string()).collect();
+    log.command(&ffmpeg_path, &args);
+
+    let parts = || -> Vec<PathBuf> { (0..).map(part).take_while(|p| p.exists()).collect() };
+    let output = match output_cancellable(resolver.runner(), &mut cmd, cancel).await {
+        Ok(output) => output,
+        Err(e) => {
+            remove_all(parts());
+            return Err(e);
+        }
+    };
+    if !output.status.success() {
+        remove_all(parts());
+        return Err(format!(
+            "Failed to split segment: ffmpeg exited with status: {}",
+            output.status
+        )
+        .into());
+    }
+    Ok(parts())
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::benchmark::Benchmark;
+use crate::converter::ConversionOptions;
+use crate::devices::DeviceProfile;
+use crate::downloader::DownloadSource;
+use crate::hooks::PostHook;
+use crate::output_dir::OutputDirMode;
+
+/// Persistent user settings, stored as `settings.json` in the app config dir
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// User-chosen ffmpeg binary, used ahead of the bundled one
+    pub ffmpeg_path: Option<String>,
+    /// User-chosen ffprobe binary, used ahead of the bundled one
+    pub ffprobe_path: Option<String>,
+    /// Last encoder benchmark, reused while its fingerprint matches
+    pub benchmark: Option<Benchmark>,
+    /// Seconds to wait for ffprobe before giving up; None uses the default
+    pub probe_timeout_secs: Option<u64>,
+    /// Probed durations above this many seconds count as garbage; None uses
+    /// the default
+    pub max_probe_duration_secs: Option<u64>,
+    /// Post a system notification when a conversion finishes while the
+    /// window is in the background
+    pub notify_on_completion: bool,
+    /// Closing the window while conversions run hides it to the tray
+    /// instead of quitting
+    pub keep_running_in_tray: bool,
+    /// Treat compatible MP4s without faststart as needing conversion
+    pub strict_streaming: bool,
+    /// Devices probed files are checked against for compatibility warnings
+    pub device_profile: DeviceProfile,
+    /// Folder besides the app data dir that conversions may write progress
+    /// files to
+    pub progress_file_dir: Option<String>,
+    /// File conversion lifecycle events are appended to as JSON lines; None
+    /// keeps no audit log
+    pub audit_log_path: Option<String>,
+    /// Hardware encoder sessions allowed at once by family (`nvenc`,
+    /// `videotoolbox`...), over the built-in defaults; drivers differ
+    pub encoder_session_limits: BTreeMap<String, usize>,
+    /// Language codes audio tracks and forced subtitles are picked by,
+    /// most wanted first, for conversions that don't name their own
+    pub preferred_languages: Vec<String>,
+    /// Most the cache dir may hold in previews, samples, contact sheets and
+    /// staged inputs; None uses the default
+    pub cache_cap_bytes: Option<u64>,
+    /// Decoding errors a conversion may report before its output is flagged
+    /// for review, for conversions that don't set their own; None uses the
+    /// default
+    pub max_decode_errors: Option<usize>,
+    /// Run `post_hooks` after each successful conversion; off unless the
+    /// user turns it on
+    pub post_hooks_enabled: bool,
+    /// Follow-up actions on each output, in order
+    pub post_hooks: Vec<PostHook>,
+    /// User-defined option sets by name, next to the built-in presets
+    pub presets: BTreeMap<String, ConversionOptions>,
+    /// Where the ffmpeg download comes from, keyed by platform
+    /// (`windows-x86_64`, `macos-aarch64`...)
+    pub ffmpeg_downloads: BTreeMap<String, DownloadSource>,
+    /// Where conversions that don't name a folder write to
+    pub output_dir_mode: OutputDirMode,
+    /// Used by `same_as_input` for sources whose folder is read-only
+    pub fallback_output_dir: Option<String>,
+    /// The folder last picked for sources on each volume, by `volume_key`
+    pub last_output_dirs: BTreeMap<String, String>,
+}
+
+pub struct SettingsStore {
+    path: Option<PathBuf>,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    /// Load settings from the config dir; a missing or unreadable file gives defaults
+    pub fn load(config_dir: Option<&Path>) -> Self {
+        let path = config_dir.map(|dir| dir.join("settings.json"));
+        let settings = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        SettingsStore {
+            path,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Apply a change and persist it. The in-memory settings only change if
+    /// the file was written, so a failed save never leaves the two out of sync.
+    pub fn update<F>(&self, change: F) -> Result<Settings, String>
+    where
+        F: FnOnce(&mut Settings),
+    {
+        let mut settings = self.settings.lock().unwrap();
+        let mut updated = settings.clone();
+        change(&mut updated);
+        self.save(&updated)?;
+        *settings = updated.clone();
+        Ok(updated)
+    }
+
+    fn save(&self, settings: &Settings) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Err("Settings directory is not available".to_string());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create settings directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(settings)
+            .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+
+        // Write to a temp file and rename so a crash can't leave half a file
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, json).map_err(|e| format!("Failed to save settings: {}", e))?;
+        std::fs::rename(&temp_path, path).map_err(|e| format!("Failed to save settings: {}", e))
+    }
+}
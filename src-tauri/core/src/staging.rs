@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::VideoInfo;
+use crate::error::ConvertError;
+use crate::volumes::{available_space, is_network_path, same_volume};
+
+const MB: u64 = 1024 * 1024;
+/// Network inputs expected to be at least this big are staged automatically
+const AUTO_STAGE_MIN_BYTES: u64 = 500 * MB;
+const COPY_BUFFER_BYTES: usize = 4 * MB as usize;
+
+/// A local copy of an input, deleted when dropped
+pub struct StagedInput {
+    pub path: PathBuf,
+}
+
+impl Drop for StagedInput {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Whether to copy an input to local disk before converting: when asked to,
+/// or when it is a large file on a network share
+pub fn should_stage(requested: bool, info: &VideoInfo) -> bool {
+    let estimated = (info.bitrate as f64 * info.duration / 8.0) as u64;
+    requested || (estimated >= AUTO_STAGE_MIN_BYTES && is_network_path(Path::new(&info.path)))
+}
+
+/// Check there is room in `work_dir` for the staged copy, plus the expected
+/// output when both end up on the same volume
+pub fn check_staging_space(
+    source_bytes: u64,
+    expected_output_bytes: u64,
+    work_dir: &Path,
+    output_dir: &Path,
+) -> Result<(), ConvertError> {
+    let needed = if same_volume(work_dir, output_dir) {
+        source_bytes + expected_output_bytes
+    } else {
+        source_bytes
+    };
+    match available_space(work_dir) {
+        Some(free) if free < needed => Err(format!(
+            "Not enough free space to copy the input to {}: {} MB needed, {} MB free",
+            work_dir.display(),
+            needed.div_ceil(MB),
+            free / MB
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Copy `source` into `work_dir`, reporting the percentage copied.
+/// A cancelled or failed copy leaves nothing behind.
+pub async fn stage_input<F>(
+    source: &Path,
+    work_dir: &Path,
+    cancel: &CancellationToken,
+    on_progress: F,
+) -> Result<StagedInput, ConvertError>
+where
+    F: Fn(f64),
+{
+    let extension = source.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    let staged = StagedInput {
+        path: work_dir.join(format!("staged.{}", extension)),
+    };
+
+    let mut reader = tokio::fs::File::open(source)
+        .await
+        .map_err(|e| format!("Failed to open input for copying: {}", e))?;
+    let total = reader.metadata().await.map(|m| m.len()).unwrap_or(0);
+    let mut writer = tokio::fs::File::create(&staged.path)
+        .await
+        .map_err(|e| format!("Failed to create local copy: {}", e))?;
+
+    let mut buf = vec![0u8; COPY_BUFFER_BYTES];
+    let mut copied = 0u64;
+    let mut reported = 0.0;
+    loop {
+        let read = tokio::select! {
+            read = reader.read(&mut buf) => read,
+            _ = cancel.cancelled() => return Err(ConvertError::Cancelled),
+        };
+        let n = read.map_err(|e| format!("Failed to read input: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        writer
+            .write_all(&buf[..n])
+            .await
+            .map_err(|e| format!("Failed to write local copy: {}", e))?;
+        copied += n as u64;
+
+        let percent = if total > 0 { (copied as f64 / total as f64 * 100.0).min(100.0) } else { 0.0 };
+        if percent - reported >= 1.0 {
+            reported = percent;
+            on_progress(percent);
+        }
+    }
+    writer.flush().await.map_err(|e| format!("Failed to write local copy: {}", e))?;
+    Ok(staged)
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How an external subtitle file ends up in the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtitleMode {
+    /// Mux as a selectable mov_text track
+    #[default]
+    Copy,
+    /// Render into the video frames (forces a video re-encode)
+    Burn,
+}
+
+/// A subtitle file that has been checked and, if needed, transcoded to UTF-8
+#[derive(Debug)]
+pub struct PreparedSubtitle {
+    pub path: PathBuf,
+    /// ISO 639-2 language code taken from the file name (`movie.en.srt`)
+    pub language: Option<String>,
+    is_temp: bool,
+}
+
+impl Drop for PreparedSubtitle {
+    fn drop(&mut self) {
+        if self.is_temp {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+const SUPPORTED_EXTENSIONS: &[&str] = &["srt", "vtt", "ass", "ssa"];
+
+/// Validate a subtitle file and make sure ffmpeg gets it as UTF-8.
+///
+/// Non-UTF-8 files (GBK, Big5, Windows-1252 are common) are detected and
+/// transcoded into a temp file in `work_dir` that is removed when the result
+/// is dropped.
+pub fn prepare_subtitle(source: &Path, work_dir: &Path) -> Result<PreparedSubtitle, String> {
+    let extension = source
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !SUPPORTED_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(format!("Unsupported subtitle format: {}", source.display()));
+    }
+
+    let bytes = std::fs::read(source).map_err(|e| format!("Failed to read subtitle file: {}", e))?;
+    let (text, transcoded) = decode_subtitle(&bytes);
+
+    if !looks_valid(&extension, &text) {
+        return Err(format!("Subtitle file could not be parsed: {}", source.display()));
+    }
+
+    let language = language_from_filename(source);
+
+    if !transcoded {
+        return Ok(PreparedSubtitle {
+            path: source.to_path_buf(),
+            language,
+            is_temp: false,
+        });
+    }
+
+    let temp_path = work_dir.join(format!("subtitle.{}", extension));
+    std::fs::write(&temp_path, text.as_bytes())
+        .map_err(|e| format!("Failed to write transcoded subtitle file: {}", e))?;
+
+    Ok(PreparedSubtitle {
+        path: temp_path,
+        language,
+        is_temp: true,
+    })
+}
+
+/// Decode subtitle bytes, returning the text and whether it needed transcoding
+fn decode_subtitle(bytes: &[u8]) -> (String, bool) {
+    if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        if let Ok(text) = std::str::from_utf8(rest) {
+            return (text.to_string(), false);
+        }
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (text.to_string(), false);
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, false);
+    let (text, _, _) = encoding.decode(bytes);
+    (text.into_owned(), true)
+}
+
+fn looks_valid(extension: &str, text: &str) -> bool {
+    match extension {
+        "srt" => text.lines().any(is_srt_timing_line),
+        "vtt" => text.trim_start().starts_with("WEBVTT"),
+        _ => text.contains("[Events]"),
+    }
+}
+
+/// Match `00:00:01,000 --> 00:00:02,500` style cue timing lines
+fn is_srt_timing_line(line: &str) -> bool {
+    let Some((start, end)) = line.split_once("-->") else {
+        return false;
+    };
+    let is_timestamp = |s: &str| {
+        // Cue settings may follow the end time (`--> 00:00:02,500 X1:40`)
+        let s = s.split_whitespace().next().unwrap_or("");
+        let parts: Vec<&str> = s.split([':', ',', '.']).collect();
+        parts.len() == 4
+            && parts
+                .iter()
+                .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+    };
+    is_timestamp(start) && is_timestamp(end)
+}
+
+/// Take the language tag from names like `movie.en.srt` or `movie.chi.srt`
+fn language_from_filename(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy().to_string();
+    let tag = Path::new(&stem).extension()?.to_string_lossy().to_lowercase();
+    match tag.len() {
+        2 => iso639_1_to_2(&tag).map(|s| s.to_string()),
+        3 if tag.chars().all(|c| c.is_ascii_alphabetic()) => Some(tag),
+        _ => None,
+    }
+}
+
+/// MP4 stores ISO 639-2 codes, so map the common two-letter ones
+fn iso639_1_to_2(code: &str) -> Option<&'static str> {
+    let mapped = match code {
+        "en" => "eng",
+        "zh" => "chi",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "fr" => "fre",
+        "de" => "ger",
+        "es" => "spa",
+        "it" => "ita",
+        "pt" => "por",
+        "ru" => "rus",
+        "ar" => "ara",
+        "nl" => "dut",
+        "sv" => "swe",
+        "pl" => "pol",
+        "tr" => "tur",
+        "th" => "tha",
+        "vi" => "vie",
+        _ => return None,
+    };
+    Some(mapped)
+}
+
+/// Escape a path for use as the `subtitles=` filter's filename.
+///
+/// The value goes through two parsers: the filter option parser (which
+/// splits on `:`) and the filtergraph parser, so it is escaped for both.
+/// Backslashes are turned into forward slashes first, which ffmpeg accepts
+/// on Windows and keeps `C:\...` from turning into an escape soup.
+pub fn escape_filter_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    #[cfg(target_os = "windows")]
+    let path = path.replace('\\', "/");
+
+    let mut option_escaped = String::new();
+    for c in path.chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            option_escaped.push('\\');
+        }
+        option_escaped.push(c);
+    }
+
+    let mut graph_escaped = String::new();
+    for c in option_escaped.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph_escaped.push('\\');
+        }
+        graph_escaped.push(c);
+    }
+    graph_escaped
+}
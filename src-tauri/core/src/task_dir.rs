@@ -0,0 +1,61 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Task directories untouched for this long belong to a run that crashed
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Private scratch directory of one task, `tasks/<task_id>` under the app
+/// cache dir (or the system temp dir without one).
+///
+/// ffmpeg runs with it as the working directory, so anything it drops
+/// there (two-pass logs and the like) can't collide with another task, and
+/// staged inputs, chunk segments and other temp files go in it too. It is
+/// removed with everything inside when dropped.
+#[derive(Debug)]
+pub struct TaskDir {
+    path: PathBuf,
+}
+
+impl TaskDir {
+    pub fn create(cache_dir: Option<&Path>, task_id: &str) -> Result<Self, String> {
+        let path = tasks_root(cache_dir).join(task_id);
+        std::fs::create_dir_all(&path)
+            .map_err(|e| format!("Failed to create work directory {}: {}", path.display(), e))?;
+        Ok(TaskDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TaskDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Remove task directories left behind by runs that never finished
+pub fn sweep_stale_task_dirs(cache_dir: Option<&Path>) {
+    let Ok(entries) = std::fs::read_dir(tasks_root(cache_dir)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > STALE_AFTER);
+        if stale {
+            let _ = std::fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+fn tasks_root(cache_dir: Option<&Path>) -> PathBuf {
+    match cache_dir {
+        Some(dir) => dir.join("tasks"),
+        None => std::env::temp_dir().join("mp4-converter").join("tasks"),
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::audit::{AuditEvent, AuditLog};
+
+/// Per-task log file, written next to the app logs as `tasks/<task_id>.log`,
+/// or the app-wide `app.log`.
+///
+/// Logging is best effort: a missing log dir or a failed write never
+/// affects the conversion itself.
+#[derive(Debug, Clone, Default)]
+pub struct TaskLog {
+    path: Option<PathBuf>,
+    /// Also sent the commands, with the task id they belong to
+    audit: Option<(AuditLog, String)>,
+}
+
+impl TaskLog {
+    pub fn new(log_dir: Option<&Path>, task_id: &str) -> Self {
+        let path = log_dir.and_then(|dir| {
+            let tasks_dir = dir.join("tasks");
+            std::fs::create_dir_all(&tasks_dir).ok()?;
+            Some(tasks_dir.join(format!("{}.log", task_id)))
+        });
+        TaskLog { path, audit: None }
+    }
+
+    /// The app-wide log, `app.log` in the log dir, for failures that
+    /// belong to no task
+    pub fn app(log_dir: Option<&Path>) -> Self {
+        let path = log_dir.and_then(|dir| {
+            std::fs::create_dir_all(dir).ok()?;
+            Some(dir.join("app.log"))
+        });
+        TaskLog { path, audit: None }
+    }
+
+    /// Also record every command in the audit log
+    pub fn with_audit(mut self, audit: &AuditLog, task_id: &str) -> Self {
+        if audit.is_enabled() {
+            self.audit = Some((audit.clone(), task_id.to_string()));
+        }
+        self
+    }
+
+    /// The log file; None when there is no log dir
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Append a single line to the log
+    pub fn line(&self, message: &str) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Ok(mut file) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            let _ = writeln!(file, "{}", message);
+        }
+    }
+
+    /// Record the full command line about to be run
+    pub fn command(&self, program: &str, args: &[String]) {
+        let rendered: Vec<String> = std::iter::once(program.to_string())
+            .chain(args.iter().cloned())
+            .map(|arg| quote_arg(&arg))
+            .collect();
+        self.line(&format!("$ {}", rendered.join(" ")));
+        if let Some((audit, task_id)) = &self.audit {
+            audit.record(AuditEvent::Command {
+                task_id: task_id.clone(),
+                argv: std::iter::once(program.to_string()).chain(args.iter().cloned()).collect(),
+            });
+        }
+    }
+}
+
+/// Quote an argument for display only; it is never passed through a shell
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        arg.to_string()
+    } else {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    }
+}
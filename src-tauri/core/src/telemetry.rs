@@ -0,0 +1,224 @@
+//! CPU and memory use of the ffmpeg processes of running conversions.
+//!
+//! Conversions register each ffmpeg child they start in the resolver's
+//! `ProcessTable`; a single sampler reads the processes' stats from the OS
+//! every few seconds while any are registered. A process that has already
+//! exited or can't be read is simply left out of a sample.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+/// The ffmpeg processes running for each task
+#[derive(Debug, Default)]
+pub struct ProcessTable {
+    pids: Mutex<BTreeMap<String, Vec<u32>>>,
+    /// Woken when the first process is registered
+    started: Notify,
+}
+
+impl ProcessTable {
+    /// Track `pid` as running for `task_id` for as long as the guard lives
+    pub fn register(self: &Arc<Self>, task_id: &str, pid: u32) -> RegisteredProcess {
+        let mut pids = self.pids.lock().unwrap();
+        pids.entry(task_id.to_string()).or_default().push(pid);
+        self.started.notify_one();
+        RegisteredProcess { table: Arc::clone(self), task_id: task_id.to_string(), pid }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pids.lock().unwrap().is_empty()
+    }
+
+    /// Wait until at least one process is registered
+    pub async fn wait_for_processes(&self) {
+        loop {
+            let started = self.started.notified();
+            tokio::pin!(started);
+            started.as_mut().enable();
+            if !self.is_empty() {
+                return;
+            }
+            started.await;
+        }
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, Vec<u32>> {
+        self.pids.lock().unwrap().clone()
+    }
+}
+
+/// A process registered in a `ProcessTable`; dropping it removes it
+#[derive(Debug)]
+pub struct RegisteredProcess {
+    table: Arc<ProcessTable>,
+    task_id: String,
+    pid: u32,
+}
+
+impl Drop for RegisteredProcess {
+    fn drop(&mut self) {
+        let mut pids = self.table.pids.lock().unwrap();
+        if let Some(list) = pids.get_mut(&self.task_id) {
+            list.retain(|pid| *pid != self.pid);
+            if list.is_empty() {
+                pids.remove(&self.task_id);
+            }
+        }
+    }
+}
+
+/// Resource use of one task's ffmpeg processes, summed
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskUsage {
+    pub task_id: String,
+    /// Share of one core since the previous sample, so a busy encode on
+    /// eight cores shows up to 800; None on the first sample of a process
+    pub cpu_percent: Option<f64>,
+    /// Resident memory
+    pub memory_bytes: u64,
+}
+
+/// Turns CPU time readings into rates by remembering the previous one of
+/// each process
+#[derive(Debug, Default)]
+pub struct UsageSampler {
+    previous: HashMap<u32, (f64, Instant)>,
+}
+
+impl UsageSampler {
+    /// Usage of every task with a readable process, by task id
+    pub fn sample(&mut self, table: &ProcessTable) -> Vec<TaskUsage> {
+        let now = Instant::now();
+        let mut seen = HashMap::new();
+        let mut usage = Vec::new();
+        for (task_id, pids) in table.snapshot() {
+            let mut task = TaskUsage { task_id, cpu_percent: None, memory_bytes: 0 };
+            let mut read_any = false;
+            for pid in pids {
+                let Some(stats) = process_stats(pid) else {
+                    continue;
+                };
+                read_any = true;
+                task.memory_bytes += stats.memory_bytes;
+                if let Some((cpu_seconds, at)) = self.previous.get(&pid) {
+                    let elapsed = now.duration_since(*at).as_secs_f64();
+                    if elapsed > 0.0 {
+                        let percent = (stats.cpu_seconds - cpu_seconds).max(0.0) / elapsed * 100.0;
+                        *task.cpu_percent.get_or_insert(0.0) += percent;
+                    }
+                }
+                seen.insert(pid, (stats.cpu_seconds, now));
+            }
+            if read_any {
+                usage.push(task);
+            }
+        }
+        // Finished processes are forgotten, so a reused pid starts afresh
+        self.previous = seen;
+        usage
+    }
+}
+
+struct ProcessStats {
+    /// User and system time used so far
+    cpu_seconds: f64,
+    memory_bytes: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn process_stats(pid: u32) -> Option<ProcessStats> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is in parentheses and may hold spaces; the fields
+    // after it start with the state, the third field of the line
+    let fields: Vec<&str> = stat.get(stat.rfind(')')? + 1..)?.split_whitespace().collect();
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system constant
+    let (ticks, page_size) =
+        unsafe { (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE)) };
+    if ticks <= 0 || page_size <= 0 {
+        return None;
+    }
+    Some(ProcessStats {
+        cpu_seconds: (utime + stime) / ticks as f64,
+        memory_bytes: resident_pages * page_size as u64,
+    })
+}
+
+#[cfg(target_os = "macos")]
+#[allow(deprecated)] // libc's mach_timebase_info
+fn process_stats(pid: u32) -> Option<ProcessStats> {
+    let mut info: libc::proc_taskinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+    // SAFETY: the buffer is a zeroed proc_taskinfo of the size passed
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut libc::proc_taskinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+    // Times are in Mach ticks, which are nanoseconds only on Intel
+    let mut timebase = libc::mach_timebase_info { numer: 0, denom: 0 };
+    // SAFETY: fills the struct it is given
+    if unsafe { libc::mach_timebase_info(&mut timebase) } != 0 || timebase.denom == 0 {
+        return None;
+    }
+    let ticks = (info.pti_total_user + info.pti_total_system) as f64;
+    Some(ProcessStats {
+        cpu_seconds: ticks * timebase.numer as f64 / timebase.denom as f64 / 1e9,
+        memory_bytes: info.pti_resident_size,
+    })
+}
+
+#[cfg(windows)]
+fn process_stats(pid: u32) -> Option<ProcessStats> {
+    use windows_sys::Win32::Foundation::{CloseHandle, FILETIME};
+    use windows_sys::Win32::System::ProcessStatus::{
+        K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    // SAFETY: the handle is checked and closed on every path, and the out
+    // parameters are plain structs of the sizes passed
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        let zero = FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 };
+        let (mut created, mut exited, mut kernel, mut user) = (zero, zero, zero, zero);
+        let mut memory: PROCESS_MEMORY_COUNTERS = std::mem::zeroed();
+        let size = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+        let read = GetProcessTimes(handle, &mut created, &mut exited, &mut kernel, &mut user) != 0
+            && K32GetProcessMemoryInfo(handle, &mut memory, size) != 0;
+        CloseHandle(handle);
+        if !read {
+            return None;
+        }
+        // FILETIMEs count 100 ns units
+        let units =
+            |time: FILETIME| ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64;
+        Some(ProcessStats {
+            cpu_seconds: (units(kernel) + units(user)) as f64 / 1e7,
+            memory_bytes: memory.WorkingSetSize as u64,
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn process_stats(_pid: u32) -> Option<ProcessStats> {
+    None
+}
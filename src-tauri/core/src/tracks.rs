@@ -0,0 +1,255 @@
+//! Which of a source's audio and subtitle tracks a conversion uses. Files
+//! with several say which to prefer through their disposition flags and
+//! language tags; an explicit pick and the user's languages come first.
+
+use serde::{Deserialize, Serialize};
+
+use crate::converter::{stream_bitrate, ConversionMode, ConversionOptions, VideoInfo};
+use crate::hls::OutputFormat;
+
+/// Subtitle codecs the `subtitles` filter can draw; picture-based ones
+/// (PGS, VobSub, DVB) would need an overlay instead
+const TEXT_SUBTITLE_CODECS: &[&str] = &["subrip", "ass", "ssa", "mov_text", "webvtt", "text"];
+
+/// Codes of one language: ISO 639-1, then the 639-2 forms files are tagged with
+const LANGUAGE_CODES: &[&[&str]] = &[
+    &["en", "eng"],
+    &["zh", "chi", "zho"],
+    &["ja", "jpn"],
+    &["ko", "kor"],
+    &["fr", "fre", "fra"],
+    &["de", "ger", "deu"],
+    &["es", "spa"],
+    &["it", "ita"],
+    &["pt", "por"],
+    &["ru", "rus"],
+    &["nl", "dut", "nld"],
+];
+
+/// One audio or subtitle track of a source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackInfo {
+    /// Position among the file's tracks of its kind, as in `-map 0:a:<n>`
+    pub index: usize,
+    pub codec: String,
+    /// `language` tag, e.g. `eng`
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub is_default: bool,
+    /// Meant to be shown even with subtitles off, usually for lines in
+    /// another language than the soundtrack
+    pub is_forced: bool,
+    /// Audio tracks only
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// As ffprobe names it, e.g. `stereo` or `5.1(side)`
+    pub channel_layout: Option<String>,
+    /// For display: `Mono`, `Stereo`, `5.1`, or the channel count
+    pub layout_label: Option<String>,
+    /// Bits/s, when the file says
+    pub bitrate: Option<u64>,
+}
+
+impl TrackInfo {
+    fn from_json(index: usize, stream: &serde_json::Value) -> Self {
+        let tag = |name: &str| {
+            stream["tags"][name].as_str().filter(|value| !value.is_empty()).map(str::to_string)
+        };
+        let channels = stream["channels"]
+            .as_u64()
+            .filter(|channels| *channels > 0)
+            .map(|channels| channels as u32);
+        let channel_layout = stream["channel_layout"]
+            .as_str()
+            .filter(|layout| !layout.is_empty() && *layout != "unknown")
+            .map(str::to_string);
+        TrackInfo {
+            index,
+            codec: stream["codec_name"].as_str().unwrap_or("unknown").to_string(),
+            // `und` is what muxers write when nobody said
+            language: tag("language").filter(|language| language != "und"),
+            title: tag("title"),
+            is_default: stream["disposition"]["default"] == 1,
+            is_forced: stream["disposition"]["forced"] == 1,
+            // ffprobe gives the sample rate as a string and the channels as a number
+            sample_rate: stream["sample_rate"]
+                .as_str()
+                .and_then(|rate| rate.parse::<u32>().ok())
+                .filter(|rate| *rate > 0),
+            channels,
+            layout_label: layout_label(channel_layout.as_deref(), channels),
+            channel_layout,
+            bitrate: stream_bitrate(stream),
+        }
+    }
+
+    /// Whether the `subtitles` filter can burn it in
+    pub fn is_text(&self) -> bool {
+        TEXT_SUBTITLE_CODECS.contains(&self.codec.as_str())
+    }
+
+    fn speaks(&self, language: &str) -> bool {
+        self.language.as_deref().is_some_and(|own| same_language(own, language))
+    }
+}
+
+/// `5.1(side)` shows as `5.1`; a track without a named layout (iPhone
+/// ambisonics, say) by its channel count
+fn layout_label(layout: Option<&str>, channels: Option<u32>) -> Option<String> {
+    let base = layout.map(|layout| layout.split('(').next().unwrap_or(layout));
+    match (base, channels) {
+        (Some("mono"), _) | (None, Some(1)) => Some("Mono".to_string()),
+        (Some("stereo"), _) | (None, Some(2)) => Some("Stereo".to_string()),
+        (Some("quad"), _) => Some("4.0".to_string()),
+        (Some(base), _) if base.starts_with(|c: char| c.is_ascii_digit()) => Some(base.to_string()),
+        (_, Some(channels)) => Some(format!("{} channels", channels)),
+        (Some(other), None) => Some(other.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// The tracks of one `codec_type` among ffprobe's streams
+pub(crate) fn tracks_of(all_streams: &[serde_json::Value], kind: &str) -> Vec<TrackInfo> {
+    all_streams
+        .iter()
+        .filter(|s| s["codec_type"] == kind)
+        .enumerate()
+        .map(|(index, s)| TrackInfo::from_json(index, s))
+        .collect()
+}
+
+/// Whether two language codes name the same language, e.g. `en` and `eng`
+pub fn same_language(a: &str, b: &str) -> bool {
+    let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+    a == b
+        || LANGUAGE_CODES
+            .iter()
+            .any(|codes| codes.contains(&a.as_str()) && codes.contains(&b.as_str()))
+}
+
+/// Why an audio track was picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionReason {
+    /// `audio_stream` named it
+    Explicit,
+    /// The first audio stream of the chosen program
+    Program,
+    /// In the earliest of `preferred_languages` the source has
+    PreferredLanguage,
+    /// Flagged as the default track
+    Default,
+    /// The first track, with nothing else to go by
+    First,
+}
+
+/// The source tracks a conversion reads
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamSelection {
+    /// `0:v:<n>`
+    pub video_stream: usize,
+    /// `0:a:<n>`; None for sources without audio
+    pub audio_stream: Option<usize>,
+    pub audio_reason: Option<SelectionReason>,
+    /// `0:s:<n>` of the forced subtitle track that goes with the audio
+    pub forced_subtitle: Option<usize>,
+    /// The forced track is burned into the picture; picture-based
+    /// subtitles can't be
+    pub burns_forced_subtitle: bool,
+    /// Further `0:a:<n>` written after the chosen track, in output order
+    pub kept_audio_streams: Vec<usize>,
+}
+
+/// The audio track to use: `explicit` when it exists, else one in the
+/// earliest preferred language (its default one if it has several), else
+/// the default-flagged track, else the first
+pub fn select_audio(
+    tracks: &[TrackInfo],
+    explicit: Option<usize>,
+    preferred_languages: &[String],
+) -> Option<(usize, SelectionReason)> {
+    if let Some(index) = explicit.filter(|index| *index < tracks.len()) {
+        return Some((index, SelectionReason::Explicit));
+    }
+    for language in preferred_languages {
+        let speaking: Vec<&TrackInfo> = tracks.iter().filter(|t| t.speaks(language)).collect();
+        if let Some(track) = speaking.iter().find(|t| t.is_default).or(speaking.first()) {
+            return Some((track.index, SelectionReason::PreferredLanguage));
+        }
+    }
+    if let Some(track) = tracks.iter().find(|t| t.is_default) {
+        return Some((track.index, SelectionReason::Default));
+    }
+    tracks.first().map(|track| (track.index, SelectionReason::First))
+}
+
+/// The forced subtitle track to show: one in a preferred language, else
+/// one in the soundtrack's, else the first forced track
+fn forced_subtitle<'a>(
+    tracks: &'a [TrackInfo],
+    preferred_languages: &[String],
+    audio_language: Option<&str>,
+) -> Option<&'a TrackInfo> {
+    let forced: Vec<&TrackInfo> = tracks.iter().filter(|t| t.is_forced).collect();
+    preferred_languages
+        .iter()
+        .map(String::as_str)
+        .chain(audio_language)
+        .find_map(|language| forced.iter().find(|t| t.speaks(language)))
+        .or(forced.first())
+        .copied()
+}
+
+/// Whether a forced source track gets burned in: not when turned off, when
+/// a subtitle file is added, for MKV (which keeps the track and its flag),
+/// audio-only fixes, or a chosen program, whose subtitles can't be told
+/// from the other programs'
+fn shows_forced_subtitles(info: &VideoInfo, options: &ConversionOptions) -> bool {
+    !options.ignore_forced_subtitles
+        && options.subtitle_file.is_none()
+        && options.output_format != OutputFormat::Mkv
+        && options.mode != ConversionMode::AudioOnlyFix
+        && info.program_id.is_none()
+}
+
+/// The audio tracks of a MOV/MP4 source that go along with the chosen one
+/// when its audio is copied: the other AAC tracks, in source order. iPhones
+/// write the main stereo mix first and flag it default, with spatial and
+/// alternate mixes after it, so the output keeps that order. The second
+/// list is the tracks MP4 can't hold (Apple's APAC spatial audio, say).
+pub fn companion_audio_tracks(info: &VideoInfo) -> (Vec<usize>, Vec<&TrackInfo>) {
+    if !info.container.contains("mov") || info.program_id.is_some() {
+        return (Vec::new(), Vec::new());
+    }
+    let (aac, other): (Vec<&TrackInfo>, Vec<&TrackInfo>) = info
+        .audio_tracks
+        .iter()
+        .filter(|track| track.index != info.audio_stream_index)
+        .partition(|track| track.codec == "aac");
+    (aac.into_iter().map(|track| track.index).collect(), other)
+}
+
+/// The tracks `options` make a conversion of `info` use
+pub fn select_streams(info: &VideoInfo, options: &ConversionOptions) -> StreamSelection {
+    let audio = if info.program_id.is_some() && options.audio_stream.is_none() {
+        info.has_audio().then_some((info.audio_stream_index, SelectionReason::Program))
+    } else {
+        select_audio(&info.audio_tracks, options.audio_stream, &options.preferred_languages)
+    };
+    let audio_language = audio
+        .and_then(|(index, _)| info.audio_tracks.get(index))
+        .and_then(|track| track.language.as_deref());
+    let forced = if shows_forced_subtitles(info, options) {
+        forced_subtitle(&info.subtitle_tracks, &options.preferred_languages, audio_language)
+    } else {
+        None
+    };
+    StreamSelection {
+        video_stream: info.video_stream_index,
+        audio_stream: audio.map(|(index, _)| index),
+        audio_reason: audio.map(|(_, reason)| reason),
+        forced_subtitle: forced.map(|track| track.index),
+        burns_forced_subtitle: forced.is_some_and(TrackInfo::is_text),
+        kept_audio_streams: Vec::new(),
+    }
+}
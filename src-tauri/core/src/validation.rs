@@ -0,0 +1,859 @@
+//! Checks on a set of conversion options, run before any ffmpeg process
+//! starts so a bad combination is explained instead of surfacing as an
+//! ffmpeg error.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+
+use crate::aspect::parse_ratio;
+use crate::converter::{
+    is_valid_color, validate_extra_args, validate_metadata, ConformDuration, ConversionMode, ConversionOptions,
+    VideoInfo, AUDIO_CHANNELS, AUDIO_SAMPLE_RATES, MAX_AUDIO_DELAY_MS, MAX_CRF, MAX_INTERPOLATE_FPS, MIN_MAX_HEIGHT,
+};
+use crate::hls::{validate_hls_seconds, OutputFormat, DEFAULT_HLS_SECONDS};
+use crate::naming::{validate_dir_template, validate_template};
+use crate::renditions::validate_renditions;
+use crate::verify::Verification;
+
+/// One problem with an option set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptionError {
+    /// Stable machine-readable code, e.g. `speed_out_of_range`
+    pub code: String,
+    /// The option fields at fault, as named in `ConversionOptions`
+    pub fields: Vec<String>,
+    /// Explanation meant for display
+    pub message: String,
+}
+
+impl OptionError {
+    fn new(code: &str, fields: &[&str], message: impl Into<String>) -> Self {
+        OptionError {
+            code: code.to_string(),
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Options that passed [`validate_for_input`], with settings that do
+/// nothing for this input cleared so later steps can take them at face value
+#[derive(Debug, Clone)]
+pub struct NormalizedOptions(ConversionOptions);
+
+impl Deref for NormalizedOptions {
+    type Target = ConversionOptions;
+
+    fn deref(&self) -> &ConversionOptions {
+        &self.0
+    }
+}
+
+/// Every problem that can be judged without the input file
+pub fn check_options(options: &ConversionOptions) -> Vec<OptionError> {
+    let mut errors = Vec::new();
+    let mut check = |code: &str, fields: &[&str], result: Result<(), String>| {
+        if let Err(message) = result {
+            errors.push(OptionError::new(code, fields, message));
+        }
+    };
+
+    for field in ["extra_video_args", "extra_audio_args", "extra_output_args"] {
+        let args = match field {
+            "extra_video_args" => &options.extra_video_args,
+            "extra_audio_args" => &options.extra_audio_args,
+            _ => &options.extra_output_args,
+        };
+        check(
+            "reserved_extra_arg",
+            &[field],
+            validate_extra_args(field, args),
+        );
+    }
+    if let Some(metadata) = &options.metadata {
+        check(
+            "invalid_metadata",
+            &["metadata"],
+            validate_metadata(metadata),
+        );
+    }
+    if let Some(template) = &options.output_template {
+        check(
+            "invalid_output_template",
+            &["output_template"],
+            validate_template(template),
+        );
+    }
+    if let Some(template) = &options.output_dir_template {
+        check(
+            "invalid_output_dir_template",
+            &["output_dir_template"],
+            validate_dir_template(template),
+        );
+    }
+    if let Some(spec) = &options.segment {
+        check("segment_out_of_range", &["segment"], spec.validate());
+    }
+    if let Some(ratio) = &options.aspect {
+        check(
+            "invalid_aspect",
+            &["aspect"],
+            parse_ratio(ratio).map(|_| ()),
+        );
+    }
+    if !options.renditions.is_empty() {
+        check(
+            "invalid_rendition",
+            &["renditions"],
+            validate_renditions(&options.renditions),
+        );
+    }
+    if options.output_format == OutputFormat::Hls {
+        let seconds = options.hls_segment_seconds.unwrap_or(DEFAULT_HLS_SECONDS);
+        check(
+            "hls_segment_out_of_range",
+            &["hls_segment_seconds"],
+            validate_hls_seconds(seconds),
+        );
+    }
+
+    let speed = options.speed.unwrap_or(1.0);
+    if !(speed > 0.0 && speed <= 100.0) {
+        errors.push(OptionError::new(
+            "speed_out_of_range",
+            &["speed"],
+            format!("Speed must be above 0 and at most 100, got {}", speed),
+        ));
+    }
+    if options.volume_db.is_some_and(|db| !db.is_finite()) {
+        errors.push(OptionError::new(
+            "volume_not_a_number",
+            &["volume_db"],
+            "Volume must be a number of dB",
+        ));
+    }
+    if let Some(ms) = options
+        .audio_delay_ms
+        .filter(|ms| ms.abs() > MAX_AUDIO_DELAY_MS)
+    {
+        errors.push(OptionError::new(
+            "audio_delay_out_of_range",
+            &["audio_delay_ms"],
+            format!(
+                "Audio delay must be within ±{} ms, got {} ms",
+                MAX_AUDIO_DELAY_MS, ms
+            ),
+        ));
+    }
+    if let Some(rate) = options
+        .audio_sample_rate
+        .filter(|rate| !AUDIO_SAMPLE_RATES.contains(rate))
+    {
+        errors.push(OptionError::new(
+            "audio_sample_rate_out_of_range",
+            &["audio_sample_rate"],
+            format!(
+                "Sample rate must be between {} and {} Hz, got {} Hz",
+                AUDIO_SAMPLE_RATES.start(),
+                AUDIO_SAMPLE_RATES.end(),
+                rate
+            ),
+        ));
+    }
+    if let Some(channels) = options
+        .audio_channels
+        .filter(|channels| !AUDIO_CHANNELS.contains(channels))
+    {
+        errors.push(OptionError::new(
+            "audio_channels_out_of_range",
+            &["audio_channels"],
+            format!(
+                "Channel count must be between {} and {}, got {}",
+                AUDIO_CHANNELS.start(),
+                AUDIO_CHANNELS.end(),
+                channels
+            ),
+        ));
+    }
+    if let Some(code) = options.preferred_languages.iter().find(|code| {
+        !(2..=3).contains(&code.len()) || !code.chars().all(|c| c.is_ascii_alphabetic())
+    }) {
+        errors.push(OptionError::new(
+            "invalid_language_code",
+            &["preferred_languages"],
+            format!("\"{}\" isn't a language code like en or eng", code),
+        ));
+    }
+    if let Some(fps) = options
+        .interpolate_fps
+        .filter(|fps| *fps > MAX_INTERPOLATE_FPS)
+    {
+        errors.push(OptionError::new(
+            "interpolate_fps_out_of_range",
+            &["interpolate_fps"],
+            format!(
+                "Can't interpolate above {} fps, got {}",
+                MAX_INTERPOLATE_FPS, fps
+            ),
+        ));
+    }
+    for (field, seconds) in [
+        ("fade_in_seconds", options.fade_in_seconds),
+        ("fade_out_seconds", options.fade_out_seconds),
+    ] {
+        if seconds.is_some_and(|s| !(s >= 0.0 && s.is_finite())) {
+            errors.push(OptionError::new(
+                "fade_out_of_range",
+                &[field],
+                "Fades must be a positive number of seconds",
+            ));
+        }
+    }
+    if let Some(clip) = options.clip {
+        let valid = clip.start_seconds >= 0.0
+            && clip.end_seconds.is_finite()
+            && clip.end_seconds > clip.start_seconds;
+        if !valid {
+            errors.push(OptionError::new(
+                "clip_out_of_range",
+                &["clip"],
+                "A clip must start at 0 or later and end after it starts",
+            ));
+        }
+        // The cut applies to the input alone, as for the silence trim
+        let blocked = options.subtitle_file.is_some()
+            || options.external_audio.is_some()
+            || options.audio_delay_ms.is_some_and(|ms| ms != 0)
+            || options.min_duration_seconds.is_some();
+        if blocked {
+            errors.push(OptionError::new(
+                "clip_conflict",
+                &["clip"],
+                "Only part of the source can't be converted together with subtitles, an added \
+                 audio file, an audio delay or a minimum duration",
+            ));
+        }
+    }
+    if options.dedup_frames && options.interpolate_fps.is_some() {
+        errors.push(OptionError::new(
+            "dedup_and_interpolate_exclusive",
+            &["dedup_frames", "interpolate_fps"],
+            "Dropping duplicate frames and interpolating can't be combined",
+        ));
+    }
+    if options
+        .min_duration_seconds
+        .is_some_and(|min| !(min > 0.0 && min.is_finite()))
+    {
+        errors.push(OptionError::new(
+            "min_duration_out_of_range",
+            &["min_duration_seconds"],
+            "Minimum duration must be a positive number of seconds",
+        ));
+    }
+    if options
+        .rate_limit()
+        .is_some_and(|limit| limit.max_kbps == 0 || limit.buffer_kbps == 0)
+    {
+        errors.push(OptionError::new(
+            "bitrate_cap_out_of_range",
+            &["max_bitrate_kbps", "buffer_size_kbps"],
+            "Bitrate cap and buffer size must be above 0",
+        ));
+    }
+    if let Some(height) = options.max_height.filter(|h| *h < MIN_MAX_HEIGHT) {
+        errors.push(OptionError::new(
+            "max_height_out_of_range",
+            &["max_height"],
+            format!(
+                "Maximum height must be at least {}, got {}",
+                MIN_MAX_HEIGHT, height
+            ),
+        ));
+    }
+    if let Some(crf) = options.crf.filter(|crf| *crf > MAX_CRF) {
+        errors.push(OptionError::new(
+            "crf_out_of_range",
+            &["crf"],
+            format!("CRF must be at most {}, got {}", MAX_CRF, crf),
+        ));
+    }
+    if options.segment.is_some() && options.output_format == OutputFormat::Hls {
+        errors.push(OptionError::new(
+            "hls_and_segment_exclusive",
+            &["output_format", "segment"],
+            "HLS output is already segmented and can't be split",
+        ));
+    }
+    if options.segment.is_some() && options.output_format == OutputFormat::Mkv {
+        errors.push(OptionError::new(
+            "mkv_and_segment_exclusive",
+            &["output_format", "segment"],
+            "Split output is only written as MP4 parts",
+        ));
+    }
+    if options.segment.is_some() && !options.renditions.is_empty() {
+        errors.push(OptionError::new(
+            "renditions_and_segment_exclusive",
+            &["renditions", "segment"],
+            "Renditions can't be combined with split output",
+        ));
+    }
+    if options.mode == ConversionMode::AudioOnlyFix
+        && (options.forces_video_encode()
+            || options.auto_crop
+            || options.crf.is_some()
+            || options.max_height.is_some()
+            || options.min_duration_seconds.is_some()
+            || options.conform_duration == ConformDuration::PadVideo)
+    {
+        errors.push(OptionError::new(
+            "audio_fix_changes_picture_conflict",
+            &["mode"],
+            "The audio-only fix keeps the video as-is, so it can't be combined with options \
+             that change the picture",
+        ));
+    }
+    if options.replace_original && options.mode != ConversionMode::AudioOnlyFix {
+        errors.push(OptionError::new(
+            "replace_original_requires_audio_fix",
+            &["replace_original", "mode"],
+            "Only the audio-only fix can replace the original file",
+        ));
+    }
+    if options.replace_original
+        && (options.segment.is_some()
+            || options.output_format != OutputFormat::Mp4
+            || !options.renditions.is_empty())
+    {
+        errors.push(OptionError::new(
+            "replace_original_requires_single_mp4",
+            &["replace_original"],
+            "Replacing the original needs a single MP4 output",
+        ));
+    }
+    if let Some(color) = options.pad_color.as_deref().filter(|c| !is_valid_color(c)) {
+        errors.push(OptionError::new(
+            "invalid_color",
+            &["pad_color"],
+            format!("Invalid pad color: {}", color),
+        ));
+    }
+    if let Some(color) = options
+        .alpha_background
+        .as_deref()
+        .filter(|c| !is_valid_color(c))
+    {
+        errors.push(OptionError::new(
+            "invalid_color",
+            &["alpha_background"],
+            format!("Invalid background color: {}", color),
+        ));
+    }
+    errors
+}
+
+/// Check the options against a probed input, returning every problem at
+/// once rather than the first
+pub fn validate_for_input(
+    options: &ConversionOptions,
+    info: &VideoInfo,
+) -> Result<NormalizedOptions, Vec<OptionError>> {
+    let mut errors = check_options(options);
+
+    if let Some(rect) = options.crop {
+        if let Err(message) = rect.validate(info.width, info.height) {
+            errors.push(OptionError::new("crop_out_of_bounds", &["crop"], message));
+        }
+    }
+    if let Some(fps) = options
+        .interpolate_fps
+        .filter(|fps| info.frame_rate >= *fps as f64)
+    {
+        errors.push(OptionError::new(
+            "interpolate_fps_not_above_source",
+            &["interpolate_fps"],
+            format!(
+                "The source is already {:.2} fps, at or above the {} fps target",
+                info.frame_rate, fps
+            ),
+        ));
+    }
+    if options.mode == ConversionMode::AudioOnlyFix && !options.copies_video(info) {
+        let reason = if info.codec != "h264" {
+            format!("it is {}, not H.264", info.codec)
+        } else {
+            "it needs re-encoding to play on phones".to_string()
+        };
+        errors.push(OptionError::new(
+            "audio_fix_video_not_copyable",
+            &["mode"],
+            format!(
+                "The video can't be kept as-is for an audio-only fix: {}",
+                reason
+            ),
+        ));
+    }
+    if let Some(clip) = options
+        .clip
+        .filter(|clip| info.duration > 0.0 && clip.start_seconds >= info.duration)
+    {
+        errors.push(OptionError::new(
+            "clip_out_of_range",
+            &["clip"],
+            format!(
+                "The clip starts at {:.2}s, past the {:.2}s source",
+                clip.start_seconds, info.duration
+            ),
+        ));
+    }
+    if let Some(index) = options
+        .video_stream
+        .filter(|index| info.video_streams.get(*index).is_none_or(|s| s.is_attached_pic))
+    {
+        errors.push(OptionError::new(
+            "video_stream_not_found",
+            &["video_stream"],
+            format!(
+                "The source has no video stream {} ({} video stream(s), cover art excluded)",
+                index, info.streams.video
+            ),
+        ));
+    }
+    if let Some(index) = options.audio_stream.filter(|index| *index >= info.audio_tracks.len()) {
+        errors.push(OptionError::new(
+            "audio_stream_not_found",
+            &["audio_stream"],
+            format!(
+                "The source has no audio stream {} ({} audio stream(s))",
+                index,
+                info.audio_tracks.len()
+            ),
+        ));
+    }
+    if let Some(id) = options.program_id {
+        if options.video_stream.is_some() {
+            errors.push(OptionError::new(
+                "program_conflicts_with_video_stream",
+                &["program_id", "video_stream"],
+                "Pick either a program or a video stream, not both",
+            ));
+        } else if !info
+            .programs
+            .iter()
+            .any(|program| program.program_id == id && program.video_stream.is_some())
+        {
+            let ids: Vec<String> =
+                info.programs.iter().map(|program| program.program_id.to_string()).collect();
+            errors.push(OptionError::new(
+                "program_not_found",
+                &["program_id"],
+                format!(
+                    "The source has no program {} with a video stream (programs: {})",
+                    id,
+                    if ids.is_empty() { "none".to_string() } else { ids.join(", ") }
+                ),
+            ));
+        }
+    }
+    if options.replace_original && !info.is_mp4_family {
+        errors.push(OptionError::new(
+            "replace_original_requires_mp4_source",
+            &["replace_original"],
+            format!(
+                "Only MP4 sources can be replaced in place, and this one is {}",
+                info.container
+            ),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut normalized = options.clone();
+    normalized.speed = options.speed.filter(|speed| *speed != 1.0);
+    normalized.volume_db = options.volume_db.filter(|db| *db != 0.0);
+    normalized.audio_delay_ms = options.audio_delay_ms.filter(|ms| *ms != 0);
+    // A cap at or above the source would only add a no-op scale filter
+    normalized.max_height = options
+        .max_height
+        .filter(|height| info.display_size().1 > *height);
+    // A source that looks damaged gets its output decoded back, not just probed
+    if info.needs_integrity_check && options.verify_output == Verification::Probe {
+        normalized.verify_output = Verification::Decode;
+    }
+    Ok(NormalizedOptions(normalized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{CropRect, TrimRange};
+    use crate::converter::{video_info_from_probe, StreamChoice};
+    use crate::renditions::RenditionSpec;
+    use crate::segments::SegmentSpec;
+    use serde_json::json;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// The codes and fields of every problem `check_options` finds
+    fn problems(errors: &[OptionError]) -> Vec<(&str, Vec<&str>)> {
+        errors
+            .iter()
+            .map(|error| {
+                (error.code.as_str(), error.fields.iter().map(String::as_str).collect())
+            })
+            .collect()
+    }
+
+    /// A 10 second 1920x1080 30 fps H.264/AAC MP4 with one audio track
+    fn source(video_codec: &str) -> VideoInfo {
+        let json = json!({
+            "streams": [
+                {
+                    "index": 0, "codec_type": "video", "codec_name": video_codec,
+                    "width": 1920, "height": 1080, "r_frame_rate": "30/1",
+                    "avg_frame_rate": "30/1", "pix_fmt": "yuv420p", "duration": "10.000000"
+                },
+                {
+                    "index": 1, "codec_type": "audio", "codec_name": "aac",
+                    "sample_rate": "48000", "channels": 2, "duration": "10.000000"
+                }
+            ],
+            "format": {
+                "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.000000",
+                "bit_rate": "2000000", "tags": {"major_brand": "isom"}
+            }
+        });
+        let path = Path::new("/nonexistent/source.mp4");
+        video_info_from_probe(path, &json, StreamChoice::default(), Duration::from_secs(86_400))
+            .unwrap()
+    }
+
+    #[test]
+    fn default_options_pass() {
+        assert_eq!(check_options(&ConversionOptions::default()), []);
+        assert!(validate_for_input(&ConversionOptions::default(), &source("h264")).is_ok());
+    }
+
+    #[test]
+    fn each_bad_option_is_reported_with_its_fields() {
+        let clip = |start_seconds, end_seconds| Some(TrimRange { start_seconds, end_seconds });
+        let rendition = |suffix: &str| RenditionSpec {
+            suffix: suffix.to_string(),
+            max_height: None,
+            crf: None,
+        };
+        let defaults = ConversionOptions::default;
+        let cases: Vec<(ConversionOptions, &str, &[&str])> = vec![
+            (
+                ConversionOptions { extra_output_args: vec!["-y".to_string()], ..defaults() },
+                "reserved_extra_arg",
+                &["extra_output_args"],
+            ),
+            (
+                ConversionOptions {
+                    metadata: Some([("-title".to_string(), "x".to_string())].into()),
+                    ..defaults()
+                },
+                "invalid_metadata",
+                &["metadata"],
+            ),
+            (
+                ConversionOptions { output_template: Some("{nope}".to_string()), ..defaults() },
+                "invalid_output_template",
+                &["output_template"],
+            ),
+            (
+                ConversionOptions { output_dir_template: Some("{nope}".to_string()), ..defaults() },
+                "invalid_output_dir_template",
+                &["output_dir_template"],
+            ),
+            (
+                ConversionOptions { segment: Some(SegmentSpec::MaxSizeMb(0)), ..defaults() },
+                "segment_out_of_range",
+                &["segment"],
+            ),
+            (
+                ConversionOptions { aspect: Some("wide".to_string()), ..defaults() },
+                "invalid_aspect",
+                &["aspect"],
+            ),
+            (
+                ConversionOptions {
+                    renditions: vec![rendition("_a"), rendition("_a")],
+                    ..defaults()
+                },
+                "invalid_rendition",
+                &["renditions"],
+            ),
+            (
+                ConversionOptions {
+                    output_format: OutputFormat::Hls,
+                    hls_segment_seconds: Some(0.5),
+                    ..defaults()
+                },
+                "hls_segment_out_of_range",
+                &["hls_segment_seconds"],
+            ),
+            (
+                ConversionOptions { speed: Some(0.0), ..defaults() },
+                "speed_out_of_range",
+                &["speed"],
+            ),
+            (
+                ConversionOptions { speed: Some(101.0), ..defaults() },
+                "speed_out_of_range",
+                &["speed"],
+            ),
+            (
+                ConversionOptions { volume_db: Some(f64::NAN), ..defaults() },
+                "volume_not_a_number",
+                &["volume_db"],
+            ),
+            (
+                ConversionOptions { audio_delay_ms: Some(-MAX_AUDIO_DELAY_MS - 1), ..defaults() },
+                "audio_delay_out_of_range",
+                &["audio_delay_ms"],
+            ),
+            (
+                ConversionOptions { audio_sample_rate: Some(4_000), ..defaults() },
+                "audio_sample_rate_out_of_range",
+                &["audio_sample_rate"],
+            ),
+            (
+                ConversionOptions { audio_channels: Some(0), ..defaults() },
+                "audio_channels_out_of_range",
+                &["audio_channels"],
+            ),
+            (
+                ConversionOptions { preferred_languages: vec!["en-US".to_string()], ..defaults() },
+                "invalid_language_code",
+                &["preferred_languages"],
+            ),
+            (
+                ConversionOptions { interpolate_fps: Some(MAX_INTERPOLATE_FPS + 1), ..defaults() },
+                "interpolate_fps_out_of_range",
+                &["interpolate_fps"],
+            ),
+            (
+                ConversionOptions { fade_out_seconds: Some(-1.0), ..defaults() },
+                "fade_out_of_range",
+                &["fade_out_seconds"],
+            ),
+            (
+                ConversionOptions { clip: clip(5.0, 5.0), ..defaults() },
+                "clip_out_of_range",
+                &["clip"],
+            ),
+            (
+                ConversionOptions {
+                    clip: clip(1.0, 5.0),
+                    subtitle_file: Some("/tmp/subs.srt".to_string()),
+                    ..defaults()
+                },
+                "clip_conflict",
+                &["clip"],
+            ),
+            (
+                ConversionOptions { dedup_frames: true, interpolate_fps: Some(60), ..defaults() },
+                "dedup_and_interpolate_exclusive",
+                &["dedup_frames", "interpolate_fps"],
+            ),
+            (
+                ConversionOptions { min_duration_seconds: Some(0.0), ..defaults() },
+                "min_duration_out_of_range",
+                &["min_duration_seconds"],
+            ),
+            (
+                ConversionOptions { max_bitrate_kbps: Some(0), ..defaults() },
+                "bitrate_cap_out_of_range",
+                &["max_bitrate_kbps", "buffer_size_kbps"],
+            ),
+            (
+                ConversionOptions { max_height: Some(MIN_MAX_HEIGHT - 1), ..defaults() },
+                "max_height_out_of_range",
+                &["max_height"],
+            ),
+            (
+                ConversionOptions { crf: Some(MAX_CRF + 1), ..defaults() },
+                "crf_out_of_range",
+                &["crf"],
+            ),
+            (
+                ConversionOptions {
+                    output_format: OutputFormat::Hls,
+                    segment: Some(SegmentSpec::MaxSizeMb(100)),
+                    ..defaults()
+                },
+                "hls_and_segment_exclusive",
+                &["output_format", "segment"],
+            ),
+            (
+                ConversionOptions {
+                    output_format: OutputFormat::Mkv,
+                    segment: Some(SegmentSpec::MaxSizeMb(100)),
+                    ..defaults()
+                },
+                "mkv_and_segment_exclusive",
+                &["output_format", "segment"],
+            ),
+            (
+                ConversionOptions {
+                    renditions: vec![rendition("_small")],
+                    segment: Some(SegmentSpec::MaxDurationSeconds(60.0)),
+                    ..defaults()
+                },
+                "renditions_and_segment_exclusive",
+                &["renditions", "segment"],
+            ),
+            (
+                ConversionOptions {
+                    mode: ConversionMode::AudioOnlyFix,
+                    max_height: Some(720),
+                    ..defaults()
+                },
+                "audio_fix_changes_picture_conflict",
+                &["mode"],
+            ),
+            (
+                ConversionOptions { replace_original: true, ..defaults() },
+                "replace_original_requires_audio_fix",
+                &["replace_original", "mode"],
+            ),
+            (
+                ConversionOptions { pad_color: Some("not a color".to_string()), ..defaults() },
+                "invalid_color",
+                &["pad_color"],
+            ),
+            (
+                ConversionOptions { alpha_background: Some("#12345".to_string()), ..defaults() },
+                "invalid_color",
+                &["alpha_background"],
+            ),
+        ];
+        for (options, code, fields) in cases {
+            let errors = check_options(&options);
+            assert_eq!(problems(&errors), [(code, fields.to_vec())], "{}", code);
+            assert!(!errors[0].message.is_empty());
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let options = ConversionOptions {
+            replace_original: true,
+            output_format: OutputFormat::Mkv,
+            speed: Some(-1.0),
+            ..Default::default()
+        };
+        let errors = check_options(&options);
+        let codes: Vec<&str> = problems(&errors).into_iter().map(|(code, _)| code).collect();
+        assert_eq!(
+            codes,
+            [
+                "speed_out_of_range",
+                "replace_original_requires_audio_fix",
+                "replace_original_requires_single_mp4",
+            ]
+        );
+    }
+
+    #[test]
+    fn options_are_checked_against_the_input() {
+        let crop = |w, h| Some(CropRect { x: 0, y: 0, w, h });
+        let defaults = ConversionOptions::default;
+        let cases: Vec<(&str, ConversionOptions, &str, &[&str])> = vec![
+            (
+                "h264",
+                ConversionOptions { crop: crop(2000, 1080), ..defaults() },
+                "crop_out_of_bounds",
+                &["crop"],
+            ),
+            (
+                "h264",
+                ConversionOptions { interpolate_fps: Some(30), ..defaults() },
+                "interpolate_fps_not_above_source",
+                &["interpolate_fps"],
+            ),
+            (
+                "hevc",
+                ConversionOptions { mode: ConversionMode::AudioOnlyFix, ..defaults() },
+                "audio_fix_video_not_copyable",
+                &["mode"],
+            ),
+            (
+                "h264",
+                ConversionOptions {
+                    clip: Some(TrimRange { start_seconds: 10.0, end_seconds: 12.0 }),
+                    ..defaults()
+                },
+                "clip_out_of_range",
+                &["clip"],
+            ),
+            (
+                "h264",
+                ConversionOptions { video_stream: Some(1), ..defaults() },
+                "video_stream_not_found",
+                &["video_stream"],
+            ),
+            (
+                "h264",
+                ConversionOptions { audio_stream: Some(1), ..defaults() },
+                "audio_stream_not_found",
+                &["audio_stream"],
+            ),
+            (
+                "h264",
+                ConversionOptions { program_id: Some(1), video_stream: Some(0), ..defaults() },
+                "program_conflicts_with_video_stream",
+                &["program_id", "video_stream"],
+            ),
+            (
+                "h264",
+                ConversionOptions { program_id: Some(1), ..defaults() },
+                "program_not_found",
+                &["program_id"],
+            ),
+        ];
+        for (codec, options, code, fields) in cases {
+            let errors = validate_for_input(&options, &source(codec)).unwrap_err();
+            assert_eq!(problems(&errors), [(code, fields.to_vec())], "{}", code);
+        }
+
+        let options = ConversionOptions {
+            mode: ConversionMode::AudioOnlyFix,
+            replace_original: true,
+            ..defaults()
+        };
+        let mkv = VideoInfo {
+            container: "matroska,webm".to_string(),
+            is_mp4_family: false,
+            ..source("h264")
+        };
+        let errors = validate_for_input(&options, &mkv).unwrap_err();
+        let expected = vec!["replace_original"];
+        assert_eq!(problems(&errors), [("replace_original_requires_mp4_source", expected)]);
+        assert!(validate_for_input(&options, &source("h264")).is_ok());
+    }
+
+    #[test]
+    fn settings_that_do_nothing_are_cleared() {
+        let options = ConversionOptions {
+            speed: Some(1.0),
+            volume_db: Some(0.0),
+            audio_delay_ms: Some(0),
+            max_height: Some(1080),
+            ..Default::default()
+        };
+        let normalized = validate_for_input(&options, &source("h264")).unwrap();
+        assert_eq!(normalized.speed, None);
+        assert_eq!(normalized.volume_db, None);
+        assert_eq!(normalized.audio_delay_ms, None);
+        assert_eq!(normalized.max_height, None);
+
+        let options = ConversionOptions { speed: Some(2.0), max_height: Some(720), ..options };
+        let normalized = validate_for_input(&options, &source("h264")).unwrap();
+        assert_eq!((normalized.speed, normalized.max_height), (Some(2.0), Some(720)));
+    }
+}
@@ -0,0 +1,122 @@
+//! Checks on a finished output, for encodes that exit cleanly but still
+//! leave a truncated or wrong file behind.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::converter::get_video_info;
+use crate::error::ConvertError;
+use crate::paths::ffmpeg_path_arg;
+use crate::process::output_cancellable;
+use crate::resolver::FfmpegResolver;
+
+/// Seconds from the end that `Verification::Decode` reads back
+const TAIL_SECONDS: u32 = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    Off,
+    /// Probe the output and compare its duration and codecs with the plan
+    #[default]
+    Probe,
+    /// Also decode the last two seconds, which catches a cut-off tail the
+    /// index still covers
+    Decode,
+}
+
+/// What a correct output looks like
+pub struct ExpectedOutput {
+    /// Seconds; 0 when the source didn't say, which skips the check
+    pub duration: f64,
+    pub has_audio: bool,
+    /// The source's audio and video were evened out, so they should now
+    /// end together
+    pub streams_conformed: bool,
+}
+
+/// Allowed gap between the expected and measured duration
+fn duration_tolerance(expected: f64) -> f64 {
+    (expected * 0.02).max(0.5)
+}
+
+/// Check an output against what the conversion meant to write. Every
+/// mismatch is listed in the error.
+pub async fn verify_output(
+    resolver: &FfmpegResolver,
+    path: &Path,
+    expected: &ExpectedOutput,
+    verification: Verification,
+    cancel: &CancellationToken,
+) -> Result<(), ConvertError> {
+    if verification == Verification::Off {
+        return Ok(());
+    }
+    let info = get_video_info(resolver, &path.to_string_lossy())
+        .await
+        .map_err(|e| {
+            ConvertError::VerificationFailed(format!("the output can't be read: {}", e))
+        })?;
+
+    let mut problems = Vec::new();
+    if expected.duration > 0.0
+        && (info.duration - expected.duration).abs() > duration_tolerance(expected.duration)
+    {
+        problems.push(format!(
+            "it is {:.2}s long instead of {:.2}s",
+            info.duration, expected.duration
+        ));
+    }
+    if info.codec != "h264" {
+        problems.push(format!("its video is {} instead of h264", info.codec));
+    }
+    if let Some(gap) = info.av_duration_mismatch_seconds.filter(|_| expected.streams_conformed) {
+        problems.push(format!("its audio and video still end {:.2}s apart", gap.abs()));
+    }
+    match (expected.has_audio, info.has_audio()) {
+        (true, false) => problems.push("it has no audio".to_string()),
+        (true, true) if info.audio_codec != "aac" => {
+            problems.push(format!("its audio is {} instead of aac", info.audio_codec))
+        }
+        _ => {}
+    }
+    if verification == Verification::Decode && problems.is_empty() {
+        if let Some(reason) = decode_tail(resolver, path, cancel).await? {
+            problems.push(format!(
+                "its last {} seconds don't decode: {}",
+                TAIL_SECONDS, reason
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ConvertError::VerificationFailed(problems.join("; ")))
+    }
+}
+
+/// Decode the end of the file; the first error ffmpeg reports, if any
+async fn decode_tail(
+    resolver: &FfmpegResolver,
+    path: &Path,
+    cancel: &CancellationToken,
+) -> Result<Option<String>, ConvertError> {
+    let ffmpeg_path = resolver.ffmpeg().await?;
+    let mut cmd = Command::new(&ffmpeg_path);
+    cmd.args(["-hide_banner", "-nostdin", "-v", "error", "-sseof"])
+        .arg(format!("-{}", TAIL_SECONDS))
+        .arg("-i")
+        .arg(ffmpeg_path_arg(path))
+        .args(["-f", "null", "-"]);
+    let output = output_cancellable(resolver.runner(), &mut cmd, cancel).await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let first_error = stderr.lines().map(str::trim).find(|line| !line.is_empty());
+    Ok(match first_error {
+        Some(line) => Some(line.to_string()),
+        None if !output.status.success() => Some(format!("ffmpeg exited with {}", output.status)),
+        None => None,
+    })
+}
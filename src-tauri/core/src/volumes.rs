@@ -0,0 +1,192 @@
+use std::path::{Path, PathBuf};
+
+/// Bytes free for the current user on the volume holding `path`
+pub fn available_space(path: &Path) -> Option<u64> {
+    platform::available_space(path)
+}
+
+/// Whether `path` lives on a network share (SMB, NFS, AFP, sshfs...)
+pub fn is_network_path(path: &Path) -> bool {
+    platform::is_network_path(path)
+}
+
+/// Whether two existing paths are on the same volume, so their space needs add up
+pub fn same_volume(a: &Path, b: &Path) -> bool {
+    match (volume_of(a), volume_of(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A name for the volume holding `path` that stays the same across runs:
+/// its mount point, or its drive root on Windows
+pub fn volume_key(path: &Path) -> Option<String> {
+    let dir = existing_ancestor(path)?;
+    let dir = dir.canonicalize().unwrap_or(dir);
+    platform::mount_point(&dir).map(|root| root.to_string_lossy().to_string())
+}
+
+#[cfg(unix)]
+fn volume_of(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|m| m.dev())
+}
+
+#[cfg(windows)]
+fn volume_of(path: &Path) -> Option<PathBuf> {
+    platform::volume_root(path)
+}
+
+/// Work out the first existing ancestor, since the output file doesn't
+/// exist yet when space is checked
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    path.ancestors().find(|p| p.exists()).map(Path::to_path_buf)
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::existing_ancestor;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    pub fn available_space(path: &Path) -> Option<u64> {
+        let dir = existing_ancestor(path)?;
+        let c_path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    const NETWORK_FILESYSTEMS: &[&str] =
+        &["nfs", "nfs4", "cifs", "smb3", "smbfs", "afpfs", "webdav", "fuse.sshfs", "9p"];
+
+    /// The mount point `path` is on and its filesystem type
+    #[cfg(target_os = "linux")]
+    fn mount_of(path: &Path) -> Option<(String, String)> {
+        let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+        // The longest mount point that contains the path is the one it's on
+        mounts
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().skip(1);
+                let mount_point = fields.next()?;
+                let fs_type = fields.next()?;
+                // /proc/mounts escapes spaces in mount points as \040
+                Some((mount_point.replace("\\040", " "), fs_type))
+            })
+            .filter(|(mount_point, _)| path.starts_with(mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.len())
+            .map(|(mount_point, fs_type)| (mount_point, fs_type.to_string()))
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn is_network_path(path: &Path) -> bool {
+        mount_of(path).is_some_and(|(_, fs_type)| NETWORK_FILESYSTEMS.contains(&fs_type.as_str()))
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn mount_point(path: &Path) -> Option<PathBuf> {
+        mount_of(path).map(|(mount_point, _)| PathBuf::from(mount_point))
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn mount_point(path: &Path) -> Option<PathBuf> {
+        let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer
+        if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(stat.f_mntonname.as_ptr()) };
+        Some(PathBuf::from(name.to_string_lossy().to_string()))
+    }
+
+    /// Without a mount table to read, the device number stands in
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub fn mount_point(path: &Path) -> Option<PathBuf> {
+        super::volume_of(path).map(|dev| PathBuf::from(format!("dev:{}", dev)))
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn is_network_path(path: &Path) -> bool {
+        let Some(c_path) = CString::new(path.as_os_str().as_bytes()).ok() else {
+            return false;
+        };
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer
+        if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return false;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+        NETWORK_FILESYSTEMS.contains(&name.to_string_lossy().as_ref())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub fn is_network_path(_path: &Path) -> bool {
+        false
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::existing_ancestor;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::{Path, PathBuf};
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetDiskFreeSpaceExW, GetDriveTypeW, GetVolumePathNameW,
+    };
+    use windows_sys::Win32::System::WindowsProgramming::DRIVE_REMOTE;
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    pub fn mount_point(path: &Path) -> Option<PathBuf> {
+        volume_root(path)
+    }
+
+    pub fn volume_root(path: &Path) -> Option<PathBuf> {
+        let dir = existing_ancestor(path)?;
+        let mut buf = vec![0u16; 1024];
+        // SAFETY: the input is NUL-terminated and `buf` holds `buf.len()` chars
+        if unsafe { GetVolumePathNameW(wide(&dir).as_ptr(), buf.as_mut_ptr(), buf.len() as u32) }
+            == 0
+        {
+            return None;
+        }
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        Some(PathBuf::from(std::ffi::OsString::from_wide(&buf[..len])))
+    }
+
+    pub fn available_space(path: &Path) -> Option<u64> {
+        let dir = existing_ancestor(path)?;
+        let mut free = 0u64;
+        // SAFETY: the path is NUL-terminated; unused outputs may be null
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide(&dir).as_ptr(),
+                &mut free,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        (ok != 0).then_some(free)
+    }
+
+    pub fn is_network_path(path: &Path) -> bool {
+        let text = path.to_string_lossy();
+        if (text.starts_with(r"\\") && !text.starts_with(r"\\?\")) || text.starts_with(r"\\?\UNC\")
+        {
+            return true;
+        }
+        // Mapped drive letters
+        volume_root(path).is_some_and(|root| {
+            // SAFETY: the root path is NUL-terminated
+            unsafe { GetDriveTypeW(wide(&root).as_ptr()) == DRIVE_REMOTE }
+        })
+    }
+}
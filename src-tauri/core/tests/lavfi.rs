@@ -0,0 +1,284 @@
+//! Real conversions of sources ffmpeg generates itself with `lavfi`. Each
+//! test returns early, with a note, when ffmpeg or ffprobe (or an encoder
+//! the source needs) can't be found.
+
+use std::path::PathBuf;
+
+use mp4_converter_core::converter::{
+    convert_video, get_raw_probe, get_video_info, ConversionOptions, ConversionResult, VideoInfo,
+};
+use mp4_converter_core::error::ConvertError;
+use mp4_converter_core::resolver::FfmpegResolver;
+use mp4_converter_core::task_log::TaskLog;
+use tokio_util::sync::CancellationToken;
+
+/// A folder of generated sources and the ffmpeg that made them
+struct Lab {
+    resolver: FfmpegResolver,
+    ffmpeg: String,
+    dir: PathBuf,
+}
+
+impl Lab {
+    async fn new() -> Option<Lab> {
+        let resolver = FfmpegResolver::new(None, None);
+        let (ffmpeg, ffprobe) = (resolver.ffmpeg().await, resolver.ffprobe().await);
+        let (Ok(ffmpeg), Ok(_)) = (ffmpeg, ffprobe) else {
+            eprintln!("skipping: ffmpeg and ffprobe aren't installed");
+            return None;
+        };
+        let dir = std::env::temp_dir()
+            .join(format!("mp4-converter-lavfi-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Some(Lab { resolver, ffmpeg, dir })
+    }
+
+    /// Whether ffmpeg was built with `--enable-lib<name>`
+    async fn has_library(&self, name: &str) -> bool {
+        let found = self.resolver.ffmpeg_has_library(name).await;
+        if !found {
+            eprintln!("skipping: ffmpeg was built without lib{}", name);
+        }
+        found
+    }
+
+    /// Run ffmpeg with `args` to write `name` into the lab
+    async fn generate(&self, name: &str, args: &[&str]) -> String {
+        let path = self.dir.join(name).to_string_lossy().to_string();
+        let output = tokio::process::Command::new(&self.ffmpeg)
+            .args(["-hide_banner", "-v", "error", "-y"])
+            .args(args)
+            .arg(&path)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        path
+    }
+
+    /// Whether this ffmpeg can decode `path` all the way through; older
+    /// builds read only the first frame of an animated WebP, or none
+    async fn decodes(&self, path: &str) -> bool {
+        let output = tokio::process::Command::new(&self.ffmpeg)
+            .args(["-hide_banner", "-v", "error", "-xerror", "-i", path, "-f", "null", "-"])
+            .output()
+            .await
+            .unwrap();
+        if !output.status.success() {
+            eprintln!("skipping: ffmpeg can't decode {}", path);
+        }
+        output.status.success()
+    }
+
+    async fn convert(
+        &self,
+        input: &str,
+        options: &ConversionOptions,
+    ) -> Result<ConversionResult, ConvertError> {
+        let output_dir = self.dir.join("out");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let output_dir = output_dir.to_string_lossy().to_string();
+        let cancel = CancellationToken::new();
+        let log = TaskLog::default();
+        let resolver = &self.resolver;
+        convert_video(resolver, input, &output_dir, "lavfi", options, None, &log, &cancel, |_| {})
+            .await
+    }
+
+    async fn probe(&self, path: &str) -> VideoInfo {
+        get_video_info(&self.resolver, path).await.unwrap()
+    }
+
+    /// The container tags ffprobe reads from `path`
+    async fn tags(&self, path: &str) -> serde_json::Map<String, serde_json::Value> {
+        let json = get_raw_probe(&self.resolver, path).await.unwrap();
+        json["format"]["tags"].as_object().cloned().unwrap_or_default()
+    }
+}
+
+impl Drop for Lab {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+const TEST_PICTURE: &str = "testsrc=duration=2:size=320x240:rate=25";
+const TEST_TONE: &str = "sine=frequency=440:duration=2";
+
+#[tokio::test]
+async fn converts_a_legacy_avi_to_h264_and_aac() {
+    let Some(lab) = Lab::new().await else { return };
+    let input = lab
+        .generate(
+            "source.avi",
+            &["-f", "lavfi", "-i", TEST_PICTURE, "-f", "lavfi", "-i", TEST_TONE,
+              "-c:v", "mpeg4", "-c:a", "pcm_s16le"],
+        )
+        .await;
+
+    let result = lab.convert(&input, &Default::default()).await.unwrap();
+    let output = lab.probe(&result.output_path).await;
+    assert_eq!((output.codec.as_str(), output.audio_codec.as_str()), ("h264", "aac"));
+    assert_eq!((output.width, output.height), (320, 240));
+    assert!((output.duration - 2.0).abs() < 0.5, "{}", output.duration);
+}
+
+#[tokio::test]
+async fn widens_anamorphic_video_to_square_pixels() {
+    let Some(lab) = Lab::new().await else { return };
+    let input = lab
+        .generate(
+            "anamorphic.avi",
+            &["-f", "lavfi", "-i", "testsrc=duration=2:size=720x480:rate=25,setsar=32/27",
+              "-f", "lavfi", "-i", TEST_TONE, "-c:v", "mpeg4", "-c:a", "pcm_s16le"],
+        )
+        .await;
+    assert_eq!(lab.probe(&input).await.sample_aspect_ratio.as_deref(), Some("32:27"));
+
+    let result = lab.convert(&input, &Default::default()).await.unwrap();
+    let output = lab.probe(&result.output_path).await;
+    assert_eq!(output.codec, "h264");
+    assert_eq!((output.width, output.height), (852, 480));
+    assert!(output.non_square_sar().is_none(), "{:?}", output.sample_aspect_ratio);
+}
+
+#[tokio::test]
+async fn composites_transparent_vp9_and_encodes_opus_to_aac() {
+    let Some(lab) = Lab::new().await else { return };
+    if !lab.has_library("vpx").await || !lab.has_library("opus").await {
+        return;
+    }
+    let input = lab
+        .generate(
+            "transparent.webm",
+            &["-f", "lavfi", "-i", "color=c=red@0.5:size=320x240:rate=25:duration=2",
+              "-f", "lavfi", "-i", TEST_TONE, "-vf", "format=yuva420p",
+              "-c:v", "libvpx-vp9", "-c:a", "libopus"],
+        )
+        .await;
+    assert!(lab.probe(&input).await.has_alpha);
+
+    let options = ConversionOptions {
+        alpha_background: Some("white".to_string()),
+        ..Default::default()
+    };
+    let result = lab.convert(&input, &options).await.unwrap();
+    let warnings = &result.warnings;
+    assert!(warnings.iter().any(|w| w.contains("on a white background")), "{:?}", warnings);
+    let output = lab.probe(&result.output_path).await;
+    assert_eq!((output.codec.as_str(), output.audio_codec.as_str()), ("h264", "aac"));
+    assert!(!output.has_alpha);
+}
+
+#[tokio::test]
+async fn keeps_three_chapters_through_a_speed_change() {
+    let Some(lab) = Lab::new().await else { return };
+    let chapters = lab.dir.join("chapters.txt");
+    let mut text = String::from(";FFMETADATA1\n");
+    for (index, title) in ["Intro", "Middle", "End"].iter().enumerate() {
+        let (start, end) = (index * 1000, index * 1000 + 1000);
+        text.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            start, end, title
+        ));
+    }
+    std::fs::write(&chapters, text).unwrap();
+    let chapters = chapters.to_string_lossy().to_string();
+    let input = lab
+        .generate(
+            "chapters.mp4",
+            &["-f", "lavfi", "-i", "testsrc=duration=3:size=320x240:rate=25",
+              "-f", "lavfi", "-i", "sine=duration=3", "-i", &chapters, "-map", "0", "-map", "1",
+              "-map_chapters", "2", "-c:v", "mpeg4", "-c:a", "aac"],
+        )
+        .await;
+    assert_eq!(lab.probe(&input).await.chapters.len(), 3);
+
+    let options = ConversionOptions { speed: Some(2.0), ..Default::default() };
+    let result = lab.convert(&input, &options).await.unwrap();
+    let output = lab.probe(&result.output_path).await;
+    let titles: Vec<&str> = output.chapters.iter().filter_map(|c| c.title.as_deref()).collect();
+    assert_eq!(titles, ["Intro", "Middle", "End"]);
+    assert!((output.chapters[2].end - 1.5).abs() < 0.1, "{:?}", output.chapters);
+}
+
+#[tokio::test]
+async fn stripping_metadata_leaves_no_location_behind() {
+    let Some(lab) = Lab::new().await else { return };
+    let input = lab
+        .generate(
+            "located.mov",
+            &["-f", "lavfi", "-i", TEST_PICTURE, "-f", "lavfi", "-i", TEST_TONE,
+              "-c:v", "mpeg4", "-c:a", "aac", "-metadata", "location=+35.6762+139.6503/"],
+        )
+        .await;
+    let has_location = |tags: &serde_json::Map<String, serde_json::Value>| {
+        tags.keys().any(|key| key.contains("location"))
+    };
+    assert!(has_location(&lab.tags(&input).await));
+
+    let options = ConversionOptions { strip_metadata: true, ..Default::default() };
+    let result = lab.convert(&input, &options).await.unwrap();
+    let tags = lab.tags(&result.output_path).await;
+    assert!(!has_location(&tags), "{:?}", tags);
+}
+
+#[tokio::test]
+async fn a_cjk_title_reaches_the_output() {
+    let Some(lab) = Lab::new().await else { return };
+    let input = lab
+        .generate(
+            "untitled.avi",
+            &["-f", "lavfi", "-i", TEST_PICTURE, "-f", "lavfi", "-i", TEST_TONE,
+              "-c:v", "mpeg4", "-c:a", "pcm_s16le"],
+        )
+        .await;
+    let title = "日本語のタイトル";
+    let options = ConversionOptions {
+        metadata: Some([("title".to_string(), title.to_string())].into()),
+        ..Default::default()
+    };
+    let result = lab.convert(&input, &options).await.unwrap();
+    let tags = lab.tags(&result.output_path).await;
+    assert_eq!(tags.get("title").and_then(|value| value.as_str()), Some(title), "{:?}", tags);
+}
+
+/// Two seconds of an odd-sized picture at 10 fps, as stickers often are
+const ANIMATION: &str = "testsrc=duration=2:size=321x241:rate=10";
+
+/// An animated image comes out as even-sized H.264 without audio at the
+/// length of its frames
+async fn check_animation(lab: &Lab, input: &str) {
+    let result = lab.convert(input, &Default::default()).await.unwrap();
+    let output = lab.probe(&result.output_path).await;
+    assert_eq!(output.codec, "h264");
+    assert_eq!((output.width, output.height), (320, 240));
+    assert!(output.audio_tracks.is_empty(), "{:?}", output.audio_tracks);
+    assert!((output.duration - 2.0).abs() < 0.5, "{}", output.duration);
+}
+
+#[tokio::test]
+async fn converts_an_animated_webp() {
+    let Some(lab) = Lab::new().await else { return };
+    if !lab.has_library("webp").await {
+        return;
+    }
+    let input = lab
+        .generate(
+            "sticker.webp",
+            &["-f", "lavfi", "-i", ANIMATION, "-c:v", "libwebp_anim", "-loop", "0"],
+        )
+        .await;
+    if lab.decodes(&input).await {
+        check_animation(&lab, &input).await;
+    }
+}
+
+#[tokio::test]
+async fn converts_an_animated_png() {
+    let Some(lab) = Lab::new().await else { return };
+    let input = lab
+        .generate("sticker.apng", &["-f", "lavfi", "-i", ANIMATION, "-c:v", "apng", "-plays", "0"])
+        .await;
+    check_animation(&lab, &input).await;
+}
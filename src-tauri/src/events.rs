@@ -0,0 +1,28 @@
+//! Names of the events the backend sends. They go to every window through
+//! the `AppHandle`, so any window (the main one, the queue monitor) gets the
+//! same stream; together with `cmd_snapshot_state` they are the stable API
+//! windows render from.
+
+/// `ConversionProgress` of one task, for conversions, muxes and previews
+pub fn conversion_progress(task_id: &str) -> String {
+    format!("conversion-progress-{}", task_id)
+}
+
+/// `OverallProgress` of the current batch
+pub const OVERALL_PROGRESS: &str = "conversion-progress-overall";
+
+/// `bool`, whether the queue is paused
+pub const QUEUE_PAUSED: &str = "queue-paused";
+
+/// `Vec<TaskUsage>`, CPU and memory of the running conversions' ffmpeg
+/// processes every few seconds; an empty list once the last has finished
+pub const CONVERSION_TELEMETRY: &str = "conversion-telemetry";
+
+/// `Vec<String>`, ids of the tasks a graceful quit is waiting for; the app
+/// exits once they are done
+pub const SHUTDOWN_FINISHING: &str = "shutdown-finishing";
+
+/// `DownloadProgress` of an ffmpeg download
+pub fn download_progress(task_id: &str) -> String {
+    format!("ffmpeg-download-progress-{}", task_id)
+}
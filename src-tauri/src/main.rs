@@ -3,65 +3,1405 @@
     windows_subsystem = "windows"
 )]
 
-mod converter;
+mod events;
+mod notifications;
+mod progress;
+mod shutdown;
+mod snapshot;
+mod tray;
 
-use converter::{check_ffmpeg, convert_video, delete_file, get_video_info, VideoInfo};
-use tauri::Emitter;
+use mp4_converter_core::analysis::{
+    compare_quality, detect_channel_balance, detect_crop, detect_silence, generate_contact_sheet,
+    ChannelBalance, ContactSheet, CropDetection, QualityMetric, QualityReport, SilentInterval,
+};
+use mp4_converter_core::audit::{self, AuditEvent, AuditLog};
+use mp4_converter_core::benchmark::{hardware_fingerprint, run_benchmark, Benchmark};
+use mp4_converter_core::converter::{
+    check_ffmpeg, convert_video, delete_file, get_raw_probe, get_video_info, output_destination,
+    output_file_name, probe_for_conversion,
+    CancelMode, ConversionOptions, ConversionProgress, ConversionResult, ConversionStatus,
+    VideoInfo, VIDEO_ENCODER,
+};
+use mp4_converter_core::devices::{compatibility_warnings, DeviceProfile};
+use mp4_converter_core::downloader::{download_ffmpeg, platform_key, DownloadSource};
+use mp4_converter_core::cache_manager::{CacheKind, CacheUsage, DEFAULT_CACHE_CAP_BYTES};
+use mp4_converter_core::encoder_slots::ENCODER_FAMILIES;
+use mp4_converter_core::error::ConvertError;
+use mp4_converter_core::external_audio::{AudioMuxMode, ExternalAudio};
+use mp4_converter_core::faststart::optimize_faststart;
+use mp4_converter_core::hooks::{run_post_hooks, validate_hooks, PostHook};
+use mp4_converter_core::history::{utc_timestamp, HistoryEntry, HistoryStore, Statistics};
+use mp4_converter_core::output_dir::{
+    remember_output_dir, remembered_output_dir, resolve_output_dir, OutputDirMode,
+    OutputDirSource, ResolvedOutputDir,
+};
+use mp4_converter_core::paths::{
+    input_unavailable, validate_input_path, validate_output_dir, validate_progress_file,
+};
+use mp4_converter_core::presets::{
+    export_presets, import_presets, ImportConflict, ImportReport, BUILTIN_PRESETS,
+};
+use mp4_converter_core::preview::{
+    clear_previews, convert_preview, convert_sample, SampleOutcome, DEFAULT_PREVIEW_SECONDS,
+};
+use mp4_converter_core::probe_sanity::DEFAULT_MAX_PROBE_DURATION_SECS;
+use mp4_converter_core::queue::{
+    estimate_starts, retry_entry, FailedTask, OptionOverrides, QueueEntry, QueueStore,
+    RestoredQueue, RetryReport,
+};
+use mp4_converter_core::resolver::{
+    validate_binary, Binary, FfmpegInfo, FfmpegResolver, DEFAULT_PROBE_TIMEOUT_SECS,
+};
+use mp4_converter_core::settings::{Settings, SettingsStore};
+use mp4_converter_core::task_dir::sweep_stale_task_dirs;
+use mp4_converter_core::task_log::TaskLog;
+use mp4_converter_core::telemetry::{TaskUsage, UsageSampler};
+use mp4_converter_core::tracks::StreamSelection;
+use mp4_converter_core::validation::{check_options, validate_for_input, OptionError};
+use tauri::{Emitter, Manager};
+use notifications::{Notifier, Outcome};
+use progress::{ProgressSnapshot, ProgressTracker};
+use snapshot::AppSnapshot;
+use tauri_plugin_fs::FsExt;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tauri::State;
+use tokio::sync::{watch, Notify};
+use tokio_util::sync::CancellationToken;
+use tray::Tray;
+
+/// How long quitting waits for cancelled tasks to clean up after themselves
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+/// Cache subfolder for preview encodes, emptied on exit
+const PREVIEW_DIR: &str = "previews";
+/// Troubleshooting samples, kept across runs for attaching to bug reports
+const SAMPLE_DIR: &str = "samples";
+/// How often the cache is checked against its cap besides after writes,
+/// since files of running tasks may have been all that was left to evict
+const CACHE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the ffmpeg processes of running conversions are sampled
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(2);
 
 struct AppState {
-    conversions: Mutex<std::collections::HashMap<String, bool>>,
+    /// Cancellation tokens for running tasks, keyed by task id
+    conversions: Mutex<std::collections::HashMap<String, CancellationToken>>,
+    /// Output files this app wrote, which the webview may delete again
+    produced_outputs: Mutex<HashSet<PathBuf>>,
+    resolver: FfmpegResolver,
+    settings: SettingsStore,
+    notifier: Notifier,
+    progress: ProgressTracker,
+    queue: QueueStore,
+    history: HistoryStore,
+    /// None where the desktop has no tray
+    tray: Option<Tray>,
+    /// While true, conversions wait before they start encoding
+    queue_paused: watch::Sender<bool>,
+    /// Set once quitting has begun cancelling or waiting for tasks
+    shutting_down: AtomicBool,
+    /// Set when the user chose to quit and cancel whatever runs
+    quit_confirmed: AtomicBool,
+    /// Set by a graceful quit: running encodes finish, nothing new starts
+    draining: AtomicBool,
+    /// Woken whenever the last running task ends
+    idle: Notify,
+    /// Writer for `audit_log_path`, replaced when the setting changes
+    audit: Mutex<AuditLog>,
+    /// Conversions that failed this session and weren't retried yet,
+    /// oldest first
+    failed_tasks: Mutex<Vec<FailedTask>>,
+}
+
+impl AppState {
+    /// Register a task. While a graceful quit drains the app the token is
+    /// cancelled from the start, so new tasks end right away.
+    fn start_task(&self, task_id: &str) -> CancellationToken {
+        let token = CancellationToken::new();
+        if self.draining.load(Ordering::SeqCst) {
+            token.cancel();
+        }
+        let mut conversions = self.conversions.lock().unwrap();
+        conversions.insert(task_id.to_string(), token.clone());
+        token
+    }
+
+    /// Like `start_task`, but a task without an id just can't be cancelled
+    fn start_optional_task(&self, task_id: Option<&str>) -> CancellationToken {
+        match task_id {
+            Some(id) => self.start_task(id),
+            None => CancellationToken::new(),
+        }
+    }
+
+    fn finish_task(&self, task_id: &str) {
+        // A mode set for a task that ended before it was cancelled
+        self.resolver.take_cancel_mode(task_id);
+        let mut conversions = self.conversions.lock().unwrap();
+        conversions.remove(task_id);
+        if conversions.is_empty() {
+            self.idle.notify_waiters();
+        }
+    }
+
+    /// Resolves once no task is running
+    async fn all_tasks_done(&self) {
+        loop {
+            // Registered before checking, so a task ending in between
+            // still wakes it
+            let idle = self.idle.notified();
+            if self.running_tasks() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    fn running_tasks(&self) -> usize {
+        self.conversions.lock().unwrap().len()
+    }
+
+    fn running_task_ids(&self) -> Vec<String> {
+        self.conversions.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn cancel_all(&self) {
+        for token in self.conversions.lock().unwrap().values() {
+            token.cancel();
+        }
+    }
+
+    fn set_queue_paused(&self, app: &tauri::AppHandle, paused: bool) {
+        self.queue_paused.send_replace(paused);
+        if let Some(tray) = &self.tray {
+            tray.set_paused(paused);
+        }
+        let _ = app.emit(events::QUEUE_PAUSED, paused);
+    }
+
+    /// Wait out a paused queue; false when the task is cancelled meanwhile
+    async fn wait_while_paused(&self, cancel: &CancellationToken) -> bool {
+        let mut paused = self.queue_paused.subscribe();
+        tokio::select! {
+            resumed = paused.wait_for(|paused| !paused) => resumed.is_ok(),
+            _ = cancel.cancelled() => false,
+        }
+    }
 }
 
 #[tauri::command]
-async fn cmd_check_ffmpeg() -> Result<bool, String> {
-    check_ffmpeg().await
+async fn cmd_check_ffmpeg(state: State<'_, AppState>) -> Result<bool, ConvertError> {
+    Ok(check_ffmpeg(&state.resolver).await?)
 }
 
 #[tauri::command]
-async fn cmd_get_video_info(path: String) -> Result<VideoInfo, String> {
-    get_video_info(&path).await
+async fn cmd_get_ffmpeg_info(state: State<'_, AppState>) -> Result<FfmpegInfo, ConvertError> {
+    Ok(state.resolver.info().await)
+}
+
+/// Validate and store a user-chosen binary; an empty path goes back to the default search.
+/// A rejected path leaves the previous configuration untouched.
+async fn set_binary_path(
+    state: &AppState,
+    binary: Binary,
+    path: Option<String>,
+) -> Result<FfmpegInfo, ConvertError> {
+    let path = path.filter(|p| !p.trim().is_empty());
+    if let Some(path) = &path {
+        validate_binary(binary, path).await?;
+    }
+
+    state.settings.update(|settings| match binary {
+        Binary::Ffmpeg => settings.ffmpeg_path = path.clone(),
+        Binary::Ffprobe => settings.ffprobe_path = path.clone(),
+    })?;
+    state.resolver.set_user_path(binary, path).await;
+    Ok(state.resolver.info().await)
 }
 
 #[tauri::command]
-async fn cmd_convert_video(
+async fn cmd_set_ffmpeg_path(
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<FfmpegInfo, ConvertError> {
+    set_binary_path(&state, Binary::Ffmpeg, path).await
+}
+
+#[tauri::command]
+async fn cmd_set_ffprobe_path(
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<FfmpegInfo, ConvertError> {
+    set_binary_path(&state, Binary::Ffprobe, path).await
+}
+
+/// Set where `cmd_download_ffmpeg` fetches ffmpeg from on a platform
+/// (`platform_key()` format); None removes the entry
+#[tauri::command]
+async fn cmd_set_ffmpeg_download(
+    platform: String,
+    source: Option<DownloadSource>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    if let Some(source) = &source {
+        source.validate()?;
+    }
+    state.settings.update(|settings| match source.clone() {
+        Some(source) => {
+            settings.ffmpeg_downloads.insert(platform.clone(), source);
+        }
+        None => {
+            settings.ffmpeg_downloads.remove(&platform);
+        }
+    })?;
+    Ok(())
+}
+
+/// Download ffmpeg (and ffprobe, when the archive has it) for this platform
+/// from the configured source and switch to it. Progress is emitted as
+/// `ffmpeg-download-progress-{task_id}`; `cmd_cancel_conversion` stops it.
+#[tauri::command]
+async fn cmd_download_ffmpeg(
+    task_id: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<FfmpegInfo, ConvertError> {
+    let platform = platform_key();
+    let source = state
+        .settings
+        .get()
+        .ffmpeg_downloads
+        .get(&platform)
+        .cloned()
+        .ok_or_else(|| format!("No ffmpeg download is configured for {}", platform))?;
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to locate the app data directory: {}", e))?;
+
+    let cancel = state.start_task(&task_id);
+    let event = events::download_progress(&task_id);
+    let result =
+        download_ffmpeg(state.resolver.runner(), &source, &data_dir, &cancel, |progress| {
+            let _ = app.emit(&event, progress);
+        })
+        .await;
+    state.finish_task(&task_id);
+
+    let binaries = result?;
+    set_binary_path(&state, Binary::Ffmpeg, Some(binaries.ffmpeg)).await?;
+    if let Some(ffprobe) = binaries.ffprobe {
+        set_binary_path(&state, Binary::Ffprobe, Some(ffprobe)).await?;
+    }
+    Ok(state.resolver.info().await)
+}
+
+/// Set how long probing a file may take; None restores the default
+#[tauri::command]
+async fn cmd_set_probe_timeout(
+    seconds: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    if seconds == Some(0) {
+        return Err("Probe timeout must be at least one second".into());
+    }
+    state.settings.update(|settings| settings.probe_timeout_secs = seconds)?;
+    state
+        .resolver
+        .set_probe_timeout(seconds.unwrap_or(DEFAULT_PROBE_TIMEOUT_SECS));
+    Ok(())
+}
+
+/// Set the longest duration a probe is believed; None restores the default
+#[tauri::command]
+async fn cmd_set_max_probe_duration(
+    seconds: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    if seconds == Some(0) {
+        return Err("The duration ceiling must be at least one second".into());
+    }
+    state.settings.update(|settings| settings.max_probe_duration_secs = seconds)?;
+    state
+        .resolver
+        .set_max_probe_duration(seconds.unwrap_or(DEFAULT_MAX_PROBE_DURATION_SECS));
+    Ok(())
+}
+
+/// Set how many sessions each hardware encoder family allows at once;
+/// families left out use the built-in limit
+#[tauri::command]
+async fn cmd_set_encoder_session_limits(
+    limits: BTreeMap<String, usize>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    if let Some(family) = limits.keys().find(|family| !ENCODER_FAMILIES.contains(&family.as_str())) {
+        return Err(format!("Unknown hardware encoder family: {}", family).into());
+    }
+    if limits.values().any(|limit| *limit == 0) {
+        return Err("An encoder session limit must be at least 1".into());
+    }
+    state.settings.update(|settings| settings.encoder_session_limits = limits.clone())?;
+    state.resolver.encoder_slots().set_limits(limits);
+    Ok(())
+}
+
+/// Set the languages conversions pick audio and forced subtitles by, most
+/// wanted first; empty goes by the files' own flags
+#[tauri::command]
+async fn cmd_set_preferred_languages(
+    languages: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    let options = ConversionOptions { preferred_languages: languages.clone(), ..Default::default() };
+    if let Some(error) = check_options(&options).into_iter().next() {
+        return Err(error.message.into());
+    }
+    state.settings.update(|settings| settings.preferred_languages = languages.clone())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn cmd_set_keep_running_in_tray(
+    enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    state.settings.update(|settings| settings.keep_running_in_tray = enabled)?;
+    Ok(())
+}
+
+/// Hold conversions that haven't started encoding yet; running ones go on
+#[tauri::command]
+async fn cmd_set_queue_paused(
+    paused: bool,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    state.set_queue_paused(&app, paused);
+    Ok(())
+}
+
+/// Quit once the files being encoded are done: queued entries and
+/// everything not encoding yet are cancelled, and no new task starts
+#[tauri::command]
+async fn cmd_request_graceful_shutdown(app: tauri::AppHandle) -> Result<(), ConvertError> {
+    shutdown::finish_running_then_quit(&app);
+    Ok(())
+}
+
+/// Progress of the current batch, for redrawing after the window was hidden
+#[tauri::command]
+async fn cmd_get_progress(state: State<'_, AppState>) -> Result<ProgressSnapshot, ConvertError> {
+    Ok(state.progress.snapshot())
+}
+
+/// Full current state (tasks with their latest progress, the queue, the
+/// settings windows show), for any window to call when it mounts
+#[tauri::command]
+async fn cmd_snapshot_state(state: State<'_, AppState>) -> Result<AppSnapshot, ConvertError> {
+    Ok(snapshot::snapshot(&state))
+}
+
+/// Every saved setting, post-conversion hooks and whether they run included
+#[tauri::command]
+async fn cmd_get_settings(state: State<'_, AppState>) -> Result<Settings, ConvertError> {
+    Ok(state.settings.get())
+}
+
+/// Set the actions run on each output after a successful conversion, and
+/// whether they run at all
+#[tauri::command]
+async fn cmd_set_post_hooks(
+    enabled: bool,
+    hooks: Vec<PostHook>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    validate_hooks(&hooks)?;
+    state.settings.update(|settings| {
+        settings.post_hooks_enabled = enabled;
+        settings.post_hooks = hooks.clone();
+    })?;
+    Ok(())
+}
+
+/// Set where conversions that don't name a folder write to, and where
+/// `same_as_input` falls back to for read-only sources
+#[tauri::command]
+async fn cmd_set_output_dir_mode(
+    mode: OutputDirMode,
+    fallback_dir: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    if let OutputDirMode::Fixed { path } = &mode {
+        validate_output_dir(path)?;
+    }
+    let fallback_dir = fallback_dir
+        .map(|dir| validate_output_dir(&dir).map(|dir| dir.to_string_lossy().to_string()))
+        .transpose()?;
+    state.settings.update(|settings| {
+        settings.output_dir_mode = mode.clone();
+        settings.fallback_output_dir = fallback_dir.clone();
+    })?;
+    Ok(())
+}
+
+/// The folder a conversion of `input_path` would write to, and why
+#[tauri::command]
+async fn cmd_resolve_output_dir(
+    input_path: String,
+    output_dir: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ResolvedOutputDir, ConvertError> {
+    resolve_output_dir(&input_path, output_dir.as_deref(), &state.settings.get())
+}
+
+/// The folder last picked for a source on the same volume, to start the
+/// folder picker in
+#[tauri::command]
+async fn cmd_remembered_output_dir(
+    input_path: String,
+    state: State<'_, AppState>,
+) -> Result<Option<String>, ConvertError> {
+    Ok(remembered_output_dir(&input_path, &state.settings.get()))
+}
+
+#[tauri::command]
+async fn cmd_set_notify_on_completion(
+    enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    state.settings.update(|settings| settings.notify_on_completion = enabled)?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn cmd_get_video_info(path: String, state: State<'_, AppState>) -> Result<VideoInfo, ConvertError> {
+    let mut info = get_video_info(&state.resolver, &path).await?;
+    let settings = state.settings.get();
+    info.needs_conversion = info.needs_conversion_for(settings.strict_streaming);
+    info.compatibility_warnings = compatibility_warnings(&info, settings.device_profile);
+    Ok(info)
+}
+
+/// ffprobe's own output for a file, values as reported
+#[tauri::command]
+async fn cmd_get_raw_probe(
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, ConvertError> {
+    get_raw_probe(&state.resolver, &path).await
+}
+
+/// The source tracks converting a file with `options` would read, and why
+#[tauri::command]
+async fn cmd_select_streams(
+    path: String,
+    options: Option<ConversionOptions>,
+    state: State<'_, AppState>,
+) -> Result<StreamSelection, ConvertError> {
+    let mut options = options.unwrap_or_default();
+    if options.preferred_languages.is_empty() {
+        options.preferred_languages = state.settings.get().preferred_languages;
+    }
+    Ok(probe_for_conversion(&state.resolver, &path, &options).await?.1)
+}
+
+/// Every problem with a set of options, checked against an input when one
+/// is given; empty when the conversion can go ahead
+#[tauri::command]
+async fn cmd_check_options(
+    path: Option<String>,
+    options: ConversionOptions,
+    state: State<'_, AppState>,
+) -> Result<Vec<OptionError>, ConvertError> {
+    let Some(path) = path else {
+        return Ok(check_options(&options));
+    };
+    let info = get_video_info(&state.resolver, &path).await?;
+    Ok(validate_for_input(&options, &info).err().unwrap_or_default())
+}
+
+#[tauri::command]
+async fn cmd_set_strict_streaming(
+    enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    state.settings.update(|settings| settings.strict_streaming = enabled)?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn cmd_set_device_profile(
+    profile: DeviceProfile,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    state.settings.update(|settings| settings.device_profile = profile)?;
+    Ok(())
+}
+
+/// Append conversion lifecycle events to this file as JSON lines; None
+/// stops the audit log
+#[tauri::command]
+async fn cmd_set_audit_log_path(
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    let path = path.map(|raw| audit_log_file(&raw)).transpose()?;
+    state.settings.update(|settings| {
+        settings.audit_log_path = path.as_ref().map(|path| path.to_string_lossy().to_string())
+    })?;
+    *state.audit.lock().unwrap() = path.map_or_else(AuditLog::default, AuditLog::open);
+    Ok(())
+}
+
+/// A file in a folder the app may write to, not a folder itself
+fn audit_log_file(raw: &str) -> Result<PathBuf, ConvertError> {
+    let path = PathBuf::from(raw);
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("Not a file path: {}", raw).into());
+    };
+    if path.is_dir() {
+        return Err(format!("Not a file path: {}", raw).into());
+    }
+    Ok(validate_output_dir(&dir.to_string_lossy())?.join(name))
+}
+
+/// The last lines of the audit log, oldest first
+#[tauri::command]
+async fn cmd_get_audit_log_tail(
+    lines: usize,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, ConvertError> {
+    let Some(path) = state.settings.get().audit_log_path else {
+        return Ok(Vec::new());
+    };
+    Ok(audit::tail(std::path::Path::new(&path), lines)?)
+}
+
+/// Allow progress files in a folder of the user's choosing, for automation
+/// that watches somewhere other than the app data dir
+#[tauri::command]
+async fn cmd_set_progress_file_dir(
+    dir: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    let dir = dir
+        .map(|dir| validate_output_dir(&dir).map(|dir| dir.to_string_lossy().to_string()))
+        .transpose()?;
+    state.settings.update(|settings| settings.progress_file_dir = dir)?;
+    Ok(())
+}
+
+/// User presets by name
+#[tauri::command]
+async fn cmd_get_presets(
+    state: State<'_, AppState>,
+) -> Result<BTreeMap<String, ConversionOptions>, ConvertError> {
+    Ok(state.settings.get().presets)
+}
+
+#[tauri::command]
+async fn cmd_save_preset(
+    name: String,
+    options: ConversionOptions,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Preset name cannot be empty".into());
+    }
+    if BUILTIN_PRESETS.contains(&name.as_str()) {
+        return Err(format!("{} is a built-in preset", name).into());
+    }
+    options.validate()?;
+    state.settings.update(|settings| {
+        settings.presets.insert(name, options);
+    })?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn cmd_delete_preset(name: String, state: State<'_, AppState>) -> Result<(), ConvertError> {
+    state.settings.update(|settings| {
+        settings.presets.remove(&name);
+    })?;
+    Ok(())
+}
+
+/// Write every user preset to a JSON file others can import
+#[tauri::command]
+async fn cmd_export_presets(path: String, state: State<'_, AppState>) -> Result<(), ConvertError> {
+    let target = PathBuf::from(&path);
+    let (Some(dir), Some(file_name)) = (target.parent(), target.file_name()) else {
+        return Err(format!("Not a file path: {}", path).into());
+    };
+    let dir = validate_output_dir(&dir.to_string_lossy())?;
+    let json = export_presets(&state.settings.get().presets)?;
+    std::fs::write(dir.join(file_name), json)
+        .map_err(|e| format!("Failed to write preset file: {}", e).into())
+}
+
+/// Merge presets from an exported file; built-in presets are never replaced
+#[tauri::command]
+async fn cmd_import_presets(
+    path: String,
+    on_conflict: Option<ImportConflict>,
+    state: State<'_, AppState>,
+) -> Result<ImportReport, ConvertError> {
+    let path = validate_input_path(&path)?;
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read preset file: {}", e))?;
+    let mut presets = state.settings.get().presets;
+    let report = import_presets(&mut presets, &json, on_conflict.unwrap_or_default())?;
+    if !report.added.is_empty() {
+        state.settings.update(|settings| settings.presets = presets)?;
+    }
+    Ok(report)
+}
+
+/// Past conversions whose input or output had this SHA-256, to spot a
+/// source that was already converted
+#[tauri::command]
+async fn cmd_find_duplicate_conversions(
+    hash: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<HistoryEntry>, ConvertError> {
+    Ok(state.history.find_by_hash(hash.trim())?)
+}
+
+/// Totals over the conversion history, optionally from a `YYYY-MM-DD` date on
+#[tauri::command]
+async fn cmd_get_statistics(
+    since: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Statistics, ConvertError> {
+    Ok(state.history.statistics(since.as_deref())?)
+}
+
+/// Remux a compatible MP4 with faststart; returns the new file's path
+#[tauri::command]
+async fn cmd_optimize_faststart(
     input_path: String,
     output_dir: String,
     task_id: String,
-    window: tauri::Window,
+    app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    {
-        let mut conversions = state.conversions.lock().unwrap();
-        conversions.insert(task_id.clone(), true);
+) -> Result<String, ConvertError> {
+    let cancel = state.start_task(&task_id);
+    let log_dir = app.path().app_log_dir().ok();
+    let log = TaskLog::new(log_dir.as_deref(), &task_id);
+    let result =
+        optimize_faststart(&state.resolver, &input_path, &output_dir, &log, &cancel).await;
+    state.finish_task(&task_id);
+    if let Ok(output) = &result {
+        state.produced_outputs.lock().unwrap().insert(PathBuf::from(output));
     }
+    result
+}
+
+#[tauri::command]
+async fn cmd_detect_crop(
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<CropDetection, ConvertError> {
+    let info = get_video_info(&state.resolver, &path).await?;
+    detect_crop(&state.resolver, &info).await
+}
 
+/// Check whether a file's stereo audio has sound in one channel only
+#[tauri::command]
+async fn cmd_detect_channel_balance(
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<ChannelBalance, ConvertError> {
+    let info = get_video_info(&state.resolver, &path).await?;
+    if !info.has_audio() {
+        return Err("The file has no audio".into());
+    }
+    detect_channel_balance(&state.resolver, &info).await
+}
+
+/// Silent stretches of a file's first audio track, quieter than
+/// `threshold_db` for at least `min_duration` seconds
+#[tauri::command]
+async fn cmd_detect_silence(
+    path: String,
+    threshold_db: f64,
+    min_duration: f64,
+    state: State<'_, AppState>,
+) -> Result<Vec<SilentInterval>, ConvertError> {
+    if !(min_duration > 0.0 && threshold_db.is_finite()) {
+        return Err("The silence threshold and minimum length must be numbers above 0".into());
+    }
+    let info = get_video_info(&state.resolver, &path).await?;
+    if !info.has_audio() {
+        return Err("The file has no audio".into());
+    }
+    detect_silence(&state.resolver, &info, threshold_db, min_duration, &CancellationToken::new())
+        .await
+}
+
+/// Convert one file. Without `output_dir` the folder comes from the
+/// `output_dir_mode` setting; a folder that is passed is remembered for
+/// the source's volume. `retry_of` links an entry from `cmd_retry_failed`
+/// to the task it retries.
+#[tauri::command]
+async fn cmd_convert_video(
+    input_path: String,
+    output_dir: Option<String>,
+    task_id: String,
+    options: Option<ConversionOptions>,
+    retry_of: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<ConversionResult, ConvertError> {
+    // Kept as asked for, so a retry fills in from the settings afresh
+    let (requested_dir, requested_options) = (output_dir.clone(), options.clone());
+    let resolved = resolve_output_dir(&input_path, output_dir.as_deref(), &state.settings.get())?;
+    // Only a change is saved, not every conversion
+    let settings = state.settings.get();
+    let mut remember_failure = None;
+    if resolved.source == OutputDirSource::Explicit
+        && remembered_output_dir(&input_path, &settings).as_deref() != Some(resolved.dir.as_str())
+    {
+        let remembered = state
+            .settings
+            .update(|settings| remember_output_dir(settings, &input_path, &resolved.dir));
+        // Reported with the result; the conversion itself goes ahead
+        remember_failure = remembered
+            .err()
+            .map(|e| format!("Failed to remember the output folder: {}", e));
+    }
+    let output_dir = resolved.dir.clone();
+    let mut options = options.unwrap_or_default();
+    if options.preferred_languages.is_empty() {
+        options.preferred_languages = state.settings.get().preferred_languages;
+    }
+    if options.max_decode_errors.is_none() {
+        options.max_decode_errors = state.settings.get().max_decode_errors;
+    }
+    if let Some(raw) = &options.progress_file {
+        let mut allowed: Vec<PathBuf> = app.path().app_data_dir().ok().into_iter().collect();
+        allowed.extend(state.settings.get().progress_file_dir.map(PathBuf::from));
+        let path = validate_progress_file(raw, &allowed)?;
+        options.progress_file = Some(path.to_string_lossy().to_string());
+    }
+    let cancel = state.start_task(&task_id);
+    state.queue.remove(&task_id);
+    let audit = state.audit.lock().unwrap().clone();
+    audit.record(AuditEvent::Enqueued {
+        task_id: task_id.clone(),
+        input_path: input_path.clone(),
+    });
     let task_id_clone = task_id.clone();
+    let log_dir = app.path().app_log_dir().ok();
+    let log = TaskLog::new(log_dir.as_deref(), &task_id).with_audit(&audit, &task_id);
+    if let Some(message) = &remember_failure {
+        log.line(message);
+    }
+    let cache_dir = app.path().app_cache_dir().ok();
+    // Already cached by the time the file was added, so this costs nothing
+    let info = get_video_info(&state.resolver, &input_path).await.ok();
+    let duration = info.as_ref().map_or(0.0, |info| info.duration);
+    if options.expected_encode_speed.is_none() {
+        if let (Some(benchmark), Some(info)) = (saved_benchmark(&state).await, &info) {
+            options.expected_encode_speed = benchmark.expected_speed(info, VIDEO_ENCODER);
+        }
+    }
+    state.progress.start(&app, &task_id, duration);
+    publish_queue_estimates(&app).await;
+    if !state.wait_while_paused(&cancel).await {
+        state.finish_task(&task_id);
+        state.progress.finish(&app, &task_id);
+        audit.record(AuditEvent::from_error(&task_id, &ConvertError::Cancelled));
+        return Err(ConvertError::Cancelled);
+    }
+    let started = Instant::now();
+    audit.record(AuditEvent::Started {
+        task_id: task_id.clone(),
+        input_path: input_path.clone(),
+        output_dir: output_dir.clone(),
+        options: Box::new(options.clone()),
+    });
 
-    let result = convert_video(&input_path, &output_dir, &task_id, move |progress| {
-        let _ = window.emit(&format!("conversion-progress-{}", task_id_clone), progress);
-    })
+    // Sent app-wide rather than to the calling window, so every window gets
+    // it and closing the caller doesn't matter
+    let emitter = app.clone();
+    let mut result = convert_video(
+        &state.resolver,
+        &input_path,
+        &output_dir,
+        &task_id,
+        &options,
+        cache_dir.as_deref(),
+        &log,
+        &cancel,
+        move |progress| {
+            let state = emitter.state::<AppState>();
+            // A finished task can no longer be cancelled
+            if progress.status.is_terminal() {
+                state.finish_task(&task_id_clone);
+            }
+            state.progress.update(&emitter, &progress);
+            let _ = emitter.emit(&events::conversion_progress(&task_id_clone), progress);
+        },
+    )
     .await;
+    match &mut result {
+        Ok(done) => {
+            done.output_dir = Some(resolved);
+            done.retry_of = retry_of.clone();
+            done.warnings.extend(remember_failure);
+        }
+        Err(ConvertError::Cancelled) => {}
+        Err(e) => state.failed_tasks.lock().unwrap().push(FailedTask {
+            task_id: task_id.clone(),
+            input_path: input_path.clone(),
+            output_dir: requested_dir,
+            options: requested_options.unwrap_or_default(),
+            error: e.clone(),
+            retry_of: retry_of.clone(),
+        }),
+    }
 
-    {
-        let mut conversions = state.conversions.lock().unwrap();
-        conversions.remove(&task_id);
+    let settings = state.settings.get();
+    if let (Ok(done), true) = (&mut result, settings.post_hooks_enabled) {
+        if done.status == ConversionStatus::CompletedWithErrors {
+            done.warnings.push(
+                "Post-conversion hooks were skipped because the output needs review".to_string(),
+            );
+        } else if done.status != ConversionStatus::CancelledPartial {
+            let source_codec = info.as_ref().map_or("unknown", |info| info.codec.as_str());
+            let hooks = &settings.post_hooks;
+            run_post_hooks(state.resolver.runner(), hooks, &input_path, source_codec, done, &log)
+                .await;
+        }
+    }
+
+    // Also covers failures that happen before any status is emitted
+    state.finish_task(&task_id);
+    state.progress.finish(&app, &task_id);
+    publish_queue_estimates(&app).await;
+
+    let kept_partial =
+        matches!(&result, Ok(done) if done.status == ConversionStatus::CancelledPartial);
+    audit.record(match &result {
+        Ok(_) if kept_partial => AuditEvent::Cancelled { task_id: task_id.clone() },
+        Ok(done) => AuditEvent::Completed {
+            task_id: task_id.clone(),
+            output_path: done.output_path.clone(),
+            input_bytes: done.input_bytes,
+            output_bytes: done.output_bytes,
+            output_sha256: done.output_sha256.clone(),
+        },
+        Err(e) => AuditEvent::from_error(&task_id, e),
+    });
+
+    if let Ok(done) = &result {
+        let mut outputs = state.produced_outputs.lock().unwrap();
+        outputs.insert(PathBuf::from(&done.output_path));
+        outputs.extend(done.segment_paths.iter().map(PathBuf::from));
+        outputs.extend(done.rendition_paths.iter().map(PathBuf::from));
+    }
+
+    // Cancelled runs weren't conversions the user wanted counted
+    if !matches!(result, Err(ConvertError::Cancelled)) && !kept_partial {
+        let input_bytes = std::fs::metadata(&input_path).map(|m| m.len()).unwrap_or(0);
+        state.history.append(&HistoryEntry {
+            finished_at: utc_timestamp(),
+            input_path: input_path.clone(),
+            output_path: result.as_ref().ok().map(|done| done.output_path.clone()),
+            source_codec: info.map_or_else(|| "unknown".to_string(), |info| info.codec),
+            success: result.is_ok(),
+            input_bytes: result.as_ref().map_or(input_bytes, |done| done.input_bytes),
+            output_bytes: result.as_ref().map_or(0, |done| done.output_bytes),
+            encode_seconds: started.elapsed().as_secs_f64(),
+            input_sha256: result.as_ref().ok().and_then(|done| done.input_sha256.clone()),
+            output_sha256: result.as_ref().ok().and_then(|done| done.output_sha256.clone()),
+            task_id: Some(task_id.clone()),
+            retry_of: retry_of.clone(),
+            error_code: result.as_ref().err().map(ConvertError::code),
+        });
+    }
+
+    // Only worth a notification when the user is looking elsewhere
+    let focused =
+        app.webview_windows().values().any(|window| window.is_focused().unwrap_or(false));
+    if state.settings.get().notify_on_completion && !focused {
+        let name = std::path::Path::new(&input_path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| input_path.clone());
+        let outcome = match &result {
+            Ok(_) if kept_partial => None,
+            Ok(done) => {
+                let size = std::fs::metadata(&done.output_path).ok().map(|m| m.len());
+                Some(Outcome::Converted(name, size))
+            }
+            Err(ConvertError::Cancelled) => None,
+            Err(e) => Some(Outcome::Failed(name, e.to_string())),
+        };
+        if let Some(outcome) = outcome {
+            state.notifier.push(&app, outcome);
+        }
     }
 
     result
 }
 
+/// Marry a video with a separately recorded audio file. Runs as a normal
+/// conversion task, so progress and cancellation work the same way.
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
-async fn cmd_cancel_conversion(task_id: String, state: State<'_, AppState>) -> Result<(), String> {
+async fn cmd_mux_audio(
+    video_path: String,
+    audio_path: String,
+    output_dir: String,
+    task_id: String,
+    mode: AudioMuxMode,
+    language: Option<String>,
+    title: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<ConversionResult, ConvertError> {
+    let options = ConversionOptions {
+        external_audio: Some(ExternalAudio {
+            path: audio_path,
+            mode,
+            language,
+            title,
+        }),
+        ..ConversionOptions::default()
+    };
+    cmd_convert_video(video_path, Some(output_dir), task_id, Some(options), None, app, state).await
+}
+
+/// Inputs among `paths` that no longer exist, checked before a batch starts so
+/// files on a disconnected drive fail without launching ffmpeg
+#[tauri::command]
+async fn cmd_missing_inputs(paths: Vec<String>) -> Result<Vec<String>, ConvertError> {
+    let mut missing = Vec::new();
+    for path in paths {
+        if input_unavailable(std::path::Path::new(&path)).await {
+            missing.push(path);
+        }
+    }
+    Ok(missing)
+}
+
+/// Persist the entries still waiting to be converted
+#[tauri::command]
+async fn cmd_set_pending_queue(
+    entries: Vec<QueueEntry>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    state.queue.set_pending(entries);
+    publish_queue_estimates(&app).await;
+    Ok(())
+}
+
+/// Send each pending entry a `queued` progress event with its place in the
+/// queue and a rough start estimate. Called whenever the order, the
+/// entries or the running tasks change, so the numbers stay fresh.
+async fn publish_queue_estimates(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    let benchmark = saved_benchmark(&state).await;
+    let speed = state.progress.measured_speed();
+    let mut entries = Vec::new();
+    for entry in state.queue.pending() {
+        let work = match get_video_info(&state.resolver, &entry.input_path).await {
+            // A copy takes seconds, next to encodes that take minutes
+            Ok(info) if entry.options.clone().unwrap_or_default().copies_video(&info) => Some(0.0),
+            Ok(info) => benchmark
+                .as_ref()
+                .and_then(|benchmark| benchmark.estimate_seconds(&info, VIDEO_ENCODER))
+                .or_else(|| speed.map(|speed| info.duration / speed)),
+            Err(_) => None,
+        };
+        entries.push((entry.task_id, work));
+    }
+    for estimate in estimate_starts(state.progress.busy_seconds(), &entries) {
+        let progress = ConversionProgress {
+            queue_position: Some(estimate.queue_position),
+            estimated_start_seconds: estimate.estimated_start_seconds,
+            ..ConversionProgress::update(&estimate.task_id, 0.0, ConversionStatus::Queued)
+        };
+        let _ = app.emit(&events::conversion_progress(&estimate.task_id), progress);
+    }
+}
+
+/// The saved benchmark, if it was measured on this machine and ffmpeg build
+async fn saved_benchmark(state: &AppState) -> Option<Benchmark> {
+    let ffmpeg = state.resolver.info().await.ffmpeg?;
+    state
+        .settings
+        .get()
+        .benchmark
+        .filter(|benchmark| benchmark.fingerprint == hardware_fingerprint(&ffmpeg.version))
+}
+
+/// Entries left queued when the app last quit
+#[tauri::command]
+async fn cmd_get_restored_queue(state: State<'_, AppState>) -> Result<RestoredQueue, ConvertError> {
+    Ok(state.queue.restored())
+}
+
+/// Take the restored entries back into the pending queue. Entries whose
+/// input file is gone are returned as unrecoverable and dropped.
+#[tauri::command]
+async fn cmd_resume_restored_queue(
+    state: State<'_, AppState>,
+) -> Result<RestoredQueue, ConvertError> {
+    Ok(state.queue.resume_restored())
+}
+
+/// Conversions that failed this session and can be retried, oldest first
+#[tauri::command]
+async fn cmd_get_failed_tasks(state: State<'_, AppState>) -> Result<Vec<FailedTask>, ConvertError> {
+    Ok(state.failed_tasks.lock().unwrap().clone())
+}
+
+/// Queue failed tasks again under new task ids: those in `task_ids`, or
+/// all of them. Each field in `option_overrides` replaces that option of
+/// every task, e.g. `{"hw_encode_only": false}` after a hardware encoder
+/// went missing.
+/// Tasks whose input is gone stay failed and come back as
+/// `input_unavailable`; the others leave the failed list.
+#[tauri::command]
+async fn cmd_retry_failed(
+    task_ids: Option<Vec<String>>,
+    option_overrides: Option<OptionOverrides>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<RetryReport, ConvertError> {
+    let failed = state.failed_tasks.lock().unwrap().clone();
+    let chosen: Vec<FailedTask> = match &task_ids {
+        Some(ids) => {
+            if let Some(unknown) = ids.iter().find(|id| !failed.iter().any(|f| f.task_id == **id)) {
+                return Err(format!("No failed task {}", unknown).into());
+            }
+            failed.into_iter().filter(|f| ids.contains(&f.task_id)).collect()
+        }
+        None => failed,
+    };
+    let mut report = RetryReport::default();
+    let mut retried = Vec::new();
+    for task in chosen {
+        if input_unavailable(Path::new(&task.input_path)).await {
+            let error = ConvertError::InputUnavailable(task.input_path.clone());
+            report.failed.insert(task.task_id, error);
+            continue;
+        }
+        report.entries.push(retry_entry(&task, option_overrides.as_ref())?);
+        retried.push(task.task_id);
+    }
+    state.failed_tasks.lock().unwrap().retain(|task| !retried.contains(&task.task_id));
+    let mut pending = state.queue.pending();
+    pending.extend(report.entries.iter().cloned());
+    state.queue.set_pending(pending);
+    publish_queue_estimates(&app).await;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn cmd_discard_restored_queue(state: State<'_, AppState>) -> Result<(), ConvertError> {
+    state.queue.discard_restored();
+    Ok(())
+}
+
+/// Cancel a running task. `keep_partial` as the mode keeps what a
+/// conversion has encoded so far as a playable file, reported with status
+/// `cancelled_partial`; the default discards it.
+#[tauri::command]
+async fn cmd_cancel_conversion(
+    task_id: String,
+    cancel_mode: Option<CancelMode>,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
     let mut conversions = state.conversions.lock().unwrap();
-    conversions.remove(&task_id);
+    if let Some(token) = conversions.remove(&task_id) {
+        state.resolver.set_cancel_mode(&task_id, cancel_mode.unwrap_or_default());
+        token.cancel();
+    }
     Ok(())
 }
 
+/// Build a thumbnail grid in the app cache dir. Passing a `task_id` lets
+/// `cmd_cancel_conversion` stop it.
 #[tauri::command]
-async fn cmd_delete_file(path: String) -> Result<(), String> {
-    delete_file(&path).await
+async fn cmd_generate_contact_sheet(
+    path: String,
+    columns: u32,
+    rows: u32,
+    width: u32,
+    task_id: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<ContactSheet, ConvertError> {
+    let cache_dir = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to find cache directory: {}", e))?
+        .join("contact-sheets");
+    let cancel = state.start_optional_task(task_id.as_deref());
+
+    let result = async {
+        let info = get_video_info(&state.resolver, &path).await?;
+        generate_contact_sheet(&state.resolver, &info, columns, rows, width, &cache_dir, &cancel)
+            .await
+    }
+    .await;
+    if let Ok(sheet) = &result {
+        state.resolver.cache_manager().register(
+            Path::new(&sheet.path),
+            CacheKind::ContactSheet,
+            task_id.as_deref(),
+        );
+    }
+
+    if let Some(id) = &task_id {
+        state.finish_task(id);
+    }
+    result
+}
+
+/// Score a converted file against its original. Passing a `task_id` lets
+/// `cmd_cancel_conversion` stop it.
+#[tauri::command]
+async fn cmd_compare_quality(
+    original: String,
+    converted: String,
+    metric: QualityMetric,
+    task_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<QualityReport, ConvertError> {
+    let cancel = state.start_optional_task(task_id.as_deref());
+
+    let result = async {
+        let original = get_video_info(&state.resolver, &original).await?;
+        let converted = get_video_info(&state.resolver, &converted).await?;
+        compare_quality(&state.resolver, &original, &converted, metric, &cancel).await
+    }
+    .await;
+
+    if let Some(id) = &task_id {
+        state.finish_task(id);
+    }
+    result
+}
+
+/// Measure encoder throughput, reusing the saved result for this machine
+/// unless `force` is set
+#[tauri::command]
+async fn cmd_run_benchmark(
+    duration_seconds: f64,
+    force: Option<bool>,
+    task_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Benchmark, ConvertError> {
+    let ffmpeg = state.resolver.info().await.ffmpeg.ok_or("No working ffmpeg binary found")?;
+    let fingerprint = hardware_fingerprint(&ffmpeg.version);
+    if let Some(cached) = state.settings.get().benchmark {
+        let reusable =
+            cached.fingerprint == fingerprint && cached.duration_seconds == duration_seconds;
+        if reusable && !force.unwrap_or(false) {
+            return Ok(cached);
+        }
+    }
+
+    let cancel = state.start_optional_task(task_id.as_deref());
+    let result = run_benchmark(&ffmpeg.path, fingerprint, duration_seconds, &cancel).await;
+    if let Some(id) = &task_id {
+        state.finish_task(id);
+    }
+
+    let benchmark = result?;
+    state.settings.update(|settings| settings.benchmark = Some(benchmark.clone()))?;
+    Ok(benchmark)
+}
+
+/// Estimated encode time in seconds from the saved benchmark. None when the
+/// video would be copied or no benchmark for this machine exists yet.
+#[tauri::command]
+async fn cmd_estimate_conversion_time(
+    path: String,
+    options: Option<ConversionOptions>,
+    state: State<'_, AppState>,
+) -> Result<Option<f64>, ConvertError> {
+    let info = get_video_info(&state.resolver, &path).await?;
+    if options.unwrap_or_default().copies_video(&info) {
+        return Ok(None);
+    }
+    let Some(benchmark) = saved_benchmark(&state).await else {
+        return Ok(None);
+    };
+    Ok(benchmark.estimate_seconds(&info, VIDEO_ENCODER))
+}
+
+/// Convert a short slice (15 seconds unless `seconds` says otherwise) from a
+/// quarter into the input with `options`, and return the path of the
+/// preview for the UI to play. Progress events and `cmd_cancel_conversion`
+/// work as for `cmd_convert_video`.
+#[tauri::command]
+async fn cmd_preview_conversion(
+    input_path: String,
+    options: Option<ConversionOptions>,
+    seconds: Option<f64>,
+    task_id: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<String, ConvertError> {
+    let cache_dir = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to find cache directory: {}", e))?;
+    let log = TaskLog::new(app.path().app_log_dir().ok().as_deref(), &task_id);
+    let cancel = state.start_task(&task_id);
+    let task_id_clone = task_id.clone();
+    let emitter = app.clone();
+
+    let result = convert_preview(
+        &state.resolver,
+        &input_path,
+        &options.unwrap_or_default(),
+        seconds.unwrap_or(DEFAULT_PREVIEW_SECONDS),
+        &cache_dir.join(PREVIEW_DIR),
+        Some(&cache_dir),
+        &task_id,
+        &log,
+        &cancel,
+        move |progress| {
+            let _ = emitter.emit(&events::conversion_progress(&task_id_clone), progress);
+        },
+    )
+    .await;
+
+    state.finish_task(&task_id);
+    let path = result?.output_path;
+    state.produced_outputs.lock().unwrap().insert(PathBuf::from(&path));
+    state.resolver.cache_manager().register(Path::new(&path), CacheKind::Preview, Some(&task_id));
+    Ok(path)
+}
+
+/// Convert `duration_seconds` of the input from `start_seconds` (the
+/// `sample` of an `encode_failed` error) with ffmpeg's verbose output in the
+/// task log, and return the sample and the log's path. Progress events and
+/// `cmd_cancel_conversion` work as for `cmd_convert_video`.
+#[tauri::command]
+async fn cmd_convert_sample(
+    input_path: String,
+    options: Option<ConversionOptions>,
+    start_seconds: f64,
+    duration_seconds: f64,
+    task_id: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<SampleOutcome, ConvertError> {
+    let cache_dir = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to find cache directory: {}", e))?;
+    let log = TaskLog::new(app.path().app_log_dir().ok().as_deref(), &task_id);
+    let cancel = state.start_task(&task_id);
+    let task_id_clone = task_id.clone();
+    let emitter = app.clone();
+
+    let outcome = convert_sample(
+        &state.resolver,
+        &input_path,
+        &options.unwrap_or_default(),
+        start_seconds,
+        duration_seconds,
+        &cache_dir.join(SAMPLE_DIR),
+        Some(&cache_dir),
+        &task_id,
+        &log,
+        &cancel,
+        move |progress| {
+            let _ = emitter.emit(&events::conversion_progress(&task_id_clone), progress);
+        },
+    )
+    .await;
+
+    state.finish_task(&task_id);
+    let outcome = outcome?;
+    if let Some(path) = &outcome.output_path {
+        state.produced_outputs.lock().unwrap().insert(PathBuf::from(path));
+        state.resolver.cache_manager().register(Path::new(path), CacheKind::Sample, Some(&task_id));
+    }
+    Ok(outcome)
+}
+
+/// How much the cache dir holds against its cap
+#[tauri::command]
+async fn cmd_get_cache_usage(state: State<'_, AppState>) -> Result<CacheUsage, ConvertError> {
+    Ok(state.resolver.cache_manager().usage())
+}
+
+/// Delete every cached file not in use by a running task; the bytes freed
+#[tauri::command]
+async fn cmd_clear_cache(state: State<'_, AppState>) -> Result<u64, ConvertError> {
+    Ok(state.resolver.cache_manager().clear(&state.running_task_ids()))
+}
+
+/// Set the most the cache dir may hold; None restores the default
+#[tauri::command]
+async fn cmd_set_cache_cap(bytes: Option<u64>, state: State<'_, AppState>) -> Result<(), ConvertError> {
+    if bytes == Some(0) {
+        return Err("The cache size cap must be above zero".into());
+    }
+    state.settings.update(|settings| settings.cache_cap_bytes = bytes)?;
+    state.resolver.cache_manager().set_cap(bytes.unwrap_or(DEFAULT_CACHE_CAP_BYTES));
+    Ok(())
+}
+
+/// Mark a cached file (a preview being played, a contact sheet being
+/// shown) as just used, so it is evicted last
+#[tauri::command]
+async fn cmd_touch_cache_file(path: String, state: State<'_, AppState>) -> Result<(), ConvertError> {
+    state.resolver.cache_manager().touch(Path::new(&path));
+    Ok(())
+}
+
+/// The output name a file would get; with `output_dir`, the full path it
+/// would be written to, folder template included
+#[tauri::command]
+async fn cmd_preview_output_name(
+    input_path: String,
+    template: Option<String>,
+    output_dir: Option<String>,
+    options: Option<ConversionOptions>,
+    state: State<'_, AppState>,
+) -> Result<String, ConvertError> {
+    let info = get_video_info(&state.resolver, &input_path).await?;
+    let mut options = options.unwrap_or_default();
+    let Some(output_dir) = output_dir else {
+        return Ok(output_file_name(&info, template.as_deref(), &options)?);
+    };
+    if template.is_some() {
+        options.output_template = template;
+    }
+    Ok(output_destination(&info, &output_dir, &options)?.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+async fn cmd_delete_file(
+    path: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), ConvertError> {
+    // Deletable: files we produced, plus anything the user picked (or that
+    // sits in a folder they picked) through the dialog plugin
+    let scope = app.fs_scope();
+    let is_allowed = |p: &std::path::Path| {
+        state.produced_outputs.lock().unwrap().contains(p) || scope.is_allowed(p)
+    };
+    delete_file(&path, is_allowed).await?;
+    state.produced_outputs.lock().unwrap().remove(&PathBuf::from(&path));
+    Ok(())
 }
 
 fn main() {
@@ -69,16 +1409,227 @@ fn main() {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
-        .manage(AppState {
-            conversions: Mutex::new(std::collections::HashMap::new()),
+        .plugin(tauri_plugin_notification::init())
+        .setup(|app| {
+            let config_dir = app.path().app_config_dir().ok();
+            let settings = SettingsStore::load(config_dir.as_deref());
+            let data_dir = app.path().app_data_dir().ok();
+            let cache_dir = app.path().app_cache_dir().ok();
+            let sweep_dir = cache_dir.clone();
+            std::thread::spawn(move || sweep_stale_task_dirs(sweep_dir.as_deref()));
+            let current = settings.get();
+            let audit = current
+                .audit_log_path
+                .clone()
+                .map(PathBuf::from)
+                .map_or_else(AuditLog::default, AuditLog::open);
+            let resolver = FfmpegResolver::new(current.ffmpeg_path, current.ffprobe_path)
+                .with_resource_dir(app.path().resource_dir().ok());
+            if let Some(seconds) = current.probe_timeout_secs {
+                resolver.set_probe_timeout(seconds);
+            }
+            if let Some(seconds) = current.max_probe_duration_secs {
+                resolver.set_max_probe_duration(seconds);
+            }
+            resolver.encoder_slots().set_limits(current.encoder_session_limits.clone());
+            if let Some(dir) = &cache_dir {
+                resolver.cache_manager().open(dir);
+            }
+            resolver
+                .cache_manager()
+                .set_cap(current.cache_cap_bytes.unwrap_or(DEFAULT_CACHE_CAP_BYTES));
+            let tray = match Tray::build(app.handle()) {
+                Ok(tray) => Some(tray),
+                Err(e) => {
+                    let log_dir = app.path().app_log_dir().ok();
+                    TaskLog::app(log_dir.as_deref())
+                        .line(&format!("Failed to create the tray icon: {}", e));
+                    None
+                }
+            };
+            app.manage(AppState {
+                conversions: Mutex::new(std::collections::HashMap::new()),
+                produced_outputs: Mutex::new(HashSet::new()),
+                resolver,
+                settings,
+                notifier: Notifier::default(),
+                progress: ProgressTracker::default(),
+                queue: QueueStore::load(data_dir.as_deref()),
+                history: HistoryStore::new(data_dir.as_deref()),
+                tray,
+                queue_paused: watch::Sender::new(false),
+                shutting_down: AtomicBool::new(false),
+                quit_confirmed: AtomicBool::new(false),
+                draining: AtomicBool::new(false),
+                idle: Notify::new(),
+                audit: Mutex::new(audit),
+                failed_tasks: Mutex::new(Vec::new()),
+            });
+            // Keeps the cache under its cap as files are added
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let state = handle.state::<AppState>();
+                let cache = state.resolver.cache_manager();
+                loop {
+                    tokio::select! {
+                        _ = cache.over_cap() => {}
+                        _ = tokio::time::sleep(CACHE_CHECK_INTERVAL) => {}
+                    }
+                    cache.evict(&state.running_task_ids());
+                }
+            });
+            // One sampler for every conversion, idle while none is running
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let state = handle.state::<AppState>();
+                let processes = state.resolver.processes();
+                loop {
+                    processes.wait_for_processes().await;
+                    let mut sampler = UsageSampler::default();
+                    while !processes.is_empty() {
+                        tokio::time::sleep(TELEMETRY_INTERVAL).await;
+                        let _ = handle.emit(events::CONVERSION_TELEMETRY, sampler.sample(processes));
+                    }
+                    let _ = handle.emit(events::CONVERSION_TELEMETRY, Vec::<TaskUsage>::new());
+                }
+            });
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                let state = window.state::<AppState>();
+                // Other windows keep the app and its conversions going on
+                // their own; only the last one hides to the tray
+                let last_window = window.app_handle().webview_windows().len() <= 1;
+                let running = state.running_tasks() > 0;
+                let to_tray = last_window
+                    && state.tray.is_some()
+                    && state.settings.get().keep_running_in_tray
+                    && running;
+                let quitting = state.quit_confirmed.load(Ordering::SeqCst)
+                    || state.draining.load(Ordering::SeqCst);
+                if to_tray {
+                    // Hidden, not closed, so the webview keeps receiving events
+                    api.prevent_close();
+                    let _ = window.hide();
+                } else if last_window && running && !quitting {
+                    api.prevent_close();
+                    shutdown::confirm_quit(window.app_handle());
+                }
+            }
         })
         .invoke_handler(tauri::generate_handler![
             cmd_check_ffmpeg,
+            cmd_get_ffmpeg_info,
+            cmd_set_ffmpeg_path,
+            cmd_set_ffprobe_path,
+            cmd_set_ffmpeg_download,
+            cmd_download_ffmpeg,
+            cmd_set_probe_timeout,
+            cmd_set_max_probe_duration,
+            cmd_set_encoder_session_limits,
+            cmd_set_preferred_languages,
+            cmd_get_settings,
+            cmd_set_post_hooks,
+            cmd_get_failed_tasks,
+            cmd_retry_failed,
+            cmd_set_output_dir_mode,
+            cmd_resolve_output_dir,
+            cmd_remembered_output_dir,
+            cmd_set_notify_on_completion,
+            cmd_set_keep_running_in_tray,
+            cmd_set_queue_paused,
+            cmd_get_progress,
+            cmd_snapshot_state,
+            cmd_request_graceful_shutdown,
             cmd_get_video_info,
+            cmd_get_raw_probe,
+            cmd_select_streams,
+            cmd_check_options,
+            cmd_set_strict_streaming,
+            cmd_set_device_profile,
+            cmd_set_progress_file_dir,
+            cmd_set_audit_log_path,
+            cmd_get_audit_log_tail,
+            cmd_get_statistics,
+            cmd_find_duplicate_conversions,
+            cmd_get_presets,
+            cmd_save_preset,
+            cmd_delete_preset,
+            cmd_export_presets,
+            cmd_import_presets,
+            cmd_optimize_faststart,
+            cmd_detect_crop,
+            cmd_detect_channel_balance,
+            cmd_detect_silence,
             cmd_convert_video,
             cmd_cancel_conversion,
+            cmd_mux_audio,
+            cmd_missing_inputs,
+            cmd_set_pending_queue,
+            cmd_get_restored_queue,
+            cmd_resume_restored_queue,
+            cmd_discard_restored_queue,
+            cmd_generate_contact_sheet,
+            cmd_compare_quality,
+            cmd_run_benchmark,
+            cmd_estimate_conversion_time,
+            cmd_preview_output_name,
+            cmd_preview_conversion,
+            cmd_convert_sample,
+            cmd_get_cache_usage,
+            cmd_clear_cache,
+            cmd_set_cache_cap,
+            cmd_touch_cache_file,
             cmd_delete_file,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // Closing the last window and quitting from the tray both end
+            // up here, so running tasks are cancelled the same way for both
+            if let tauri::RunEvent::ExitRequested { api, code, .. } = event {
+                if let Ok(cache_dir) = app.path().app_cache_dir() {
+                    clear_previews(&cache_dir.join(PREVIEW_DIR));
+                }
+                let state = app.state::<AppState>();
+                if state.running_tasks() == 0 || state.shutting_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                api.prevent_exit();
+                let draining = state.draining.load(Ordering::SeqCst);
+                // Any other way of quitting (the app menu, the OS) asks first
+                // too, as long as there is a window to ask in
+                if !draining
+                    && !state.quit_confirmed.load(Ordering::SeqCst)
+                    && !app.webview_windows().is_empty()
+                {
+                    shutdown::confirm_quit(app);
+                    return;
+                }
+                state.shutting_down.store(true, Ordering::SeqCst);
+                if !draining {
+                    state.cancel_all();
+                }
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    let state = app.state::<AppState>();
+                    if draining {
+                        let finished = tokio::time::timeout(
+                            shutdown::GRACEFUL_SHUTDOWN_TIMEOUT,
+                            state.all_tasks_done(),
+                        );
+                        if finished.await.is_err() {
+                            state.cancel_all();
+                        }
+                    }
+                    // Cancelled conversions remove their partial outputs
+                    let deadline = Instant::now() + SHUTDOWN_GRACE;
+                    while app.state::<AppState>().running_tasks() > 0 && Instant::now() < deadline {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    app.exit(code.unwrap_or(0));
+                });
+            }
+        });
 }
@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+/// Conversions finishing this close together share one notification
+const BATCH_WINDOW: Duration = Duration::from_secs(3);
+
+/// How one conversion ended, for the notification text
+pub enum Outcome {
+    /// File name and output size in bytes
+    Converted(String, Option<u64>),
+    /// File name and error message
+    Failed(String, String),
+}
+
+/// Posts "conversion finished" notifications, collecting the ones that
+/// arrive within `BATCH_WINDOW` of each other so a queue finishing doesn't
+/// produce one notification per file.
+///
+/// Clicking a notification brings the app forward through the OS default
+/// action; the desktop notification plugin has no click callback.
+#[derive(Default)]
+pub struct Notifier {
+    pending: Mutex<Vec<Outcome>>,
+}
+
+impl Notifier {
+    pub fn push(&self, app: &AppHandle, outcome: Outcome) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(outcome);
+        if pending.len() > 1 {
+            // The first outcome of this batch already scheduled the flush
+            return;
+        }
+
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(BATCH_WINDOW).await;
+            let notifier = app.state::<crate::AppState>();
+            let batch = std::mem::take(&mut *notifier.notifier.pending.lock().unwrap());
+            show(&app, &batch);
+        });
+    }
+}
+
+fn show(app: &AppHandle, batch: &[Outcome]) {
+    let (title, body) = match batch {
+        [] => return,
+        [Outcome::Converted(name, size)] => {
+            let title = match size {
+                Some(bytes) => format!("{} converted — {}", name, format_size(*bytes)),
+                None => format!("{} converted", name),
+            };
+            (title, String::new())
+        }
+        [Outcome::Failed(name, error)] => {
+            (format!("Conversion failed: {}", name), error.clone())
+        }
+        _ => {
+            let converted = batch
+                .iter()
+                .filter(|outcome| matches!(outcome, Outcome::Converted(..)))
+                .count();
+            let failed: Vec<&str> = batch
+                .iter()
+                .filter_map(|outcome| match outcome {
+                    Outcome::Failed(name, _) => Some(name.as_str()),
+                    Outcome::Converted(..) => None,
+                })
+                .collect();
+            let body = if failed.is_empty() {
+                String::new()
+            } else {
+                format!("Failed: {}", failed.join(", "))
+            };
+            (format!("{} of {} files converted", converted, batch.len()), body)
+        }
+    };
+
+    let mut builder = app.notification().builder().title(title);
+    if !body.is_empty() {
+        builder = builder.body(body);
+    }
+    let _ = builder.show();
+}
+
+fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    let mb = bytes as f64 / MB;
+    if mb >= 1024.0 {
+        format!("{:.1} GB", mb / 1024.0)
+    } else {
+        format!("{:.0} MB", mb.max(1.0))
+    }
+}
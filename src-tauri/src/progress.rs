@@ -0,0 +1,191 @@
+use mp4_converter_core::converter::{ConversionProgress, ConversionStatus};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::window::{ProgressBarState, ProgressBarStatus};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Summary of every task in the current batch, sent as
+/// `conversion-progress-overall`
+#[derive(Debug, Clone, Serialize)]
+pub struct OverallProgress {
+    /// Tasks encoding right now
+    pub active: usize,
+    /// Tasks submitted but not encoding yet (probing, preparing or copying
+    /// the input to local disk)
+    pub queued: usize,
+    /// Tasks of this batch that have ended, whatever the outcome
+    pub completed: usize,
+    /// Percent over the whole batch, each task weighted by its duration
+    pub percent: f64,
+}
+
+/// Everything needed to redraw the progress of the current batch, for a
+/// window that missed the events while it was hidden
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressSnapshot {
+    pub overall: OverallProgress,
+    /// The last event of each task in the batch that has reported one
+    pub tasks: Vec<ConversionProgress>,
+}
+
+struct Task {
+    /// Media duration in seconds, so long files count for more
+    weight: f64,
+    percent: f64,
+    started: bool,
+    done: bool,
+    latest: Option<ConversionProgress>,
+}
+
+/// Tracks every task since the app was last idle. Ended tasks stay in the
+/// batch at 100% until the last one ends, so the overall number never
+/// jumps backwards when a short file finishes first.
+#[derive(Default)]
+pub struct ProgressTracker {
+    tasks: Mutex<HashMap<String, Task>>,
+}
+
+impl ProgressTracker {
+    pub fn start(&self, app: &AppHandle, task_id: &str, duration: f64) {
+        let task = Task {
+            weight: duration.max(1.0),
+            percent: 0.0,
+            started: false,
+            done: false,
+            latest: None,
+        };
+        let overall = {
+            let mut tasks = self.tasks.lock().unwrap();
+            tasks.insert(task_id.to_string(), task);
+            summarize(&tasks)
+        };
+        publish(app, &overall);
+    }
+
+    pub fn update(&self, app: &AppHandle, progress: &ConversionProgress) {
+        self.apply(app, &progress.task_id, progress.status, progress.progress, Some(progress));
+    }
+
+    /// Mark a task ended, for failures that never reported a final status
+    pub fn finish(&self, app: &AppHandle, task_id: &str) {
+        self.apply(app, task_id, ConversionStatus::Completed, 100.0, None);
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let tasks = self.tasks.lock().unwrap();
+        ProgressSnapshot {
+            overall: summarize(&tasks),
+            tasks: tasks.values().filter_map(|task| task.latest.clone()).collect(),
+        }
+    }
+
+    /// Tasks that are encoding right now
+    pub fn encoding_tasks(&self) -> Vec<String> {
+        self.tasks_where(|task| task.started && !task.done)
+    }
+
+    /// Tasks submitted but not encoding yet
+    pub fn waiting_tasks(&self) -> Vec<String> {
+        self.tasks_where(|task| !task.started && !task.done)
+    }
+
+    fn tasks_where(&self, keep: impl Fn(&Task) -> bool) -> Vec<String> {
+        let tasks = self.tasks.lock().unwrap();
+        let mut ids: Vec<String> =
+            tasks.iter().filter(|(_, task)| keep(task)).map(|(id, _)| id.clone()).collect();
+        ids.sort();
+        ids
+    }
+
+    /// Rough seconds until every running task is done: the longest ETA,
+    /// since they share the machine. None while one has no ETA yet.
+    pub fn busy_seconds(&self) -> Option<f64> {
+        let tasks = self.tasks.lock().unwrap();
+        tasks
+            .values()
+            .filter(|task| task.started && !task.done)
+            .map(|task| task.latest.as_ref().and_then(|latest| latest.eta_seconds))
+            .try_fold(0.0, |longest: f64, eta| eta.map(|eta| longest.max(eta)))
+    }
+
+    /// Average encoding speed of the running tasks, as a multiple of realtime
+    pub fn measured_speed(&self) -> Option<f64> {
+        let tasks = self.tasks.lock().unwrap();
+        let speeds: Vec<f64> = tasks
+            .values()
+            .filter(|task| task.started && !task.done)
+            .filter_map(|task| task.latest.as_ref().and_then(|latest| latest.speed))
+            .filter(|speed| *speed > 0.0)
+            .collect();
+        (!speeds.is_empty()).then(|| speeds.iter().sum::<f64>() / speeds.len() as f64)
+    }
+
+    fn apply(
+        &self,
+        app: &AppHandle,
+        task_id: &str,
+        status: ConversionStatus,
+        percent: f64,
+        progress: Option<&ConversionProgress>,
+    ) {
+        let overall = {
+            let mut tasks = self.tasks.lock().unwrap();
+            let Some(task) = tasks.get_mut(task_id).filter(|task| !task.done) else {
+                return;
+            };
+            if let Some(progress) = progress {
+                task.latest = Some(progress.clone());
+            }
+            if status.is_terminal() {
+                task.done = true;
+                task.percent = 100.0;
+            } else if !matches!(
+                status,
+                ConversionStatus::Queued
+                    | ConversionStatus::Analyzing
+                    | ConversionStatus::Starting
+                    | ConversionStatus::WaitingForFile
+                    | ConversionStatus::WaitingForEncoder
+                    | ConversionStatus::Staging
+            ) {
+                task.started = true;
+                task.percent = percent;
+            }
+            let overall = summarize(&tasks);
+            if tasks.values().all(|task| task.done) {
+                tasks.clear();
+            }
+            overall
+        };
+        publish(app, &overall);
+    }
+}
+
+fn summarize(tasks: &HashMap<String, Task>) -> OverallProgress {
+    let total_weight: f64 = tasks.values().map(|task| task.weight).sum();
+    let done_weight: f64 = tasks.values().map(|task| task.weight * task.percent / 100.0).sum();
+    OverallProgress {
+        active: tasks.values().filter(|task| task.started && !task.done).count(),
+        queued: tasks.values().filter(|task| !task.started && !task.done).count(),
+        completed: tasks.values().filter(|task| task.done).count(),
+        percent: if total_weight > 0.0 { done_weight / total_weight * 100.0 } else { 100.0 },
+    }
+}
+
+/// Emit the summary and mirror it on the taskbar/dock icon
+fn publish(app: &AppHandle, overall: &OverallProgress) {
+    let _ = app.emit(crate::events::OVERALL_PROGRESS, overall);
+
+    let running = overall.active + overall.queued > 0;
+    let state = ProgressBarState {
+        status: Some(if running { ProgressBarStatus::Normal } else { ProgressBarStatus::None }),
+        progress: running.then_some(overall.percent.round() as u64),
+    };
+    // The indicator is app-wide on macOS and Linux, and not every platform
+    // has one
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.set_progress_bar(state);
+    }
+    crate::tray::show_progress(app, overall);
+}
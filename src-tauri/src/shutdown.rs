@@ -0,0 +1,87 @@
+//! Quitting while tasks run. The user chooses between letting the files
+//! that are encoding finish, cancelling everything, or not quitting; the
+//! exit handler in `main` carries out the first two.
+
+use mp4_converter_core::converter::{ConversionProgress, ConversionStatus};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind, MessageDialogResult};
+
+use crate::{events, AppState};
+
+/// How long a graceful quit waits for the running files before their
+/// ffmpeg processes are killed anyway
+pub const GRACEFUL_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const WAIT: &str = "Finish current";
+const CANCEL_ALL: &str = "Cancel all";
+const KEEP_RUNNING: &str = "Keep running";
+
+/// Ask how to quit with tasks running; quits straight away when there are
+/// none
+pub fn confirm_quit(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let running = state.running_tasks();
+    if running == 0 {
+        quit_now(app);
+        return;
+    }
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    let handle = app.clone();
+    app.dialog()
+        .message(format!(
+            "{} task(s) still running. Let the files being encoded finish and drop the rest \
+             of the queue, or cancel everything now?",
+            running
+        ))
+        .title(crate::tray::APP_NAME)
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::YesNoCancelCustom(
+            WAIT.into(),
+            CANCEL_ALL.into(),
+            KEEP_RUNNING.into(),
+        ))
+        .show_with_result(move |choice| match choice {
+            MessageDialogResult::Yes => finish_running_then_quit(&handle),
+            MessageDialogResult::Custom(label) if label == WAIT => {
+                finish_running_then_quit(&handle)
+            }
+            MessageDialogResult::No => quit_now(&handle),
+            MessageDialogResult::Custom(label) if label == CANCEL_ALL => quit_now(&handle),
+            _ => {}
+        });
+}
+
+/// Quit, cancelling whatever still runs
+pub fn quit_now(app: &AppHandle) {
+    app.state::<AppState>().quit_confirmed.store(true, Ordering::SeqCst);
+    app.exit(0);
+}
+
+/// Stop starting tasks, cancel the queued ones and everything that isn't
+/// encoding yet, and quit once the encodes that are left are done
+pub fn finish_running_then_quit(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    state.draining.store(true, Ordering::SeqCst);
+
+    for entry in state.queue.pending() {
+        let cancelled = ConversionProgress::update(&entry.task_id, 0.0, ConversionStatus::Cancelled);
+        let _ = app.emit(&events::conversion_progress(&entry.task_id), cancelled);
+    }
+    state.queue.set_pending(Vec::new());
+    {
+        let conversions = state.conversions.lock().unwrap();
+        for task_id in state.progress.waiting_tasks() {
+            if let Some(token) = conversions.get(&task_id) {
+                token.cancel();
+            }
+        }
+    }
+
+    let _ = app.emit(events::SHUTDOWN_FINISHING, state.progress.encoding_tasks());
+    app.exit(0);
+}
@@ -0,0 +1,52 @@
+use mp4_converter_core::devices::DeviceProfile;
+use mp4_converter_core::queue::QueueEntry;
+use serde::Serialize;
+use std::sync::atomic::Ordering;
+
+use crate::progress::ProgressSnapshot;
+use crate::AppState;
+
+/// The settings windows render from
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsDigest {
+    pub notify_on_completion: bool,
+    pub keep_running_in_tray: bool,
+    pub strict_streaming: bool,
+    pub device_profile: DeviceProfile,
+}
+
+/// Everything a window needs to draw itself on mount; from then on the
+/// events in `events` keep it current. Any window gets the same answer, so
+/// they all render alike.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppSnapshot {
+    /// The current batch with each task's latest progress
+    pub progress: ProgressSnapshot,
+    /// Ids of every running task, previews and downloads included
+    pub running_tasks: Vec<String>,
+    /// Entries waiting to be converted, in order
+    pub queue: Vec<QueueEntry>,
+    pub queue_paused: bool,
+    /// A graceful quit is waiting for the running files to finish
+    pub finishing_before_quit: bool,
+    pub settings: SettingsDigest,
+}
+
+pub fn snapshot(state: &AppState) -> AppSnapshot {
+    let settings = state.settings.get();
+    let mut running_tasks: Vec<String> = state.conversions.lock().unwrap().keys().cloned().collect();
+    running_tasks.sort();
+    AppSnapshot {
+        progress: state.progress.snapshot(),
+        running_tasks,
+        queue: state.queue.pending(),
+        queue_paused: *state.queue_paused.borrow(),
+        finishing_before_quit: state.draining.load(Ordering::SeqCst),
+        settings: SettingsDigest {
+            notify_on_completion: settings.notify_on_completion,
+            keep_running_in_tray: settings.keep_running_in_tray,
+            strict_streaming: settings.strict_streaming,
+            device_profile: settings.device_profile,
+        },
+    }
+}
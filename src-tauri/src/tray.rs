@@ -0,0 +1,91 @@
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Manager, Wry};
+
+use crate::progress::OverallProgress;
+use crate::AppState;
+
+const TRAY_ID: &str = "main";
+pub(crate) const APP_NAME: &str = "MP4 Converter";
+const SHOW_WINDOW: &str = "show_window";
+const PAUSE_QUEUE: &str = "pause_queue";
+const CANCEL_ALL: &str = "cancel_all";
+const QUIT: &str = "quit";
+
+/// The tray icon's menu items whose state follows the app
+pub struct Tray {
+    pause_queue: CheckMenuItem<Wry>,
+}
+
+impl Tray {
+    /// Add the tray icon. Fails where the desktop has no tray, in which case
+    /// closing the window quits as before.
+    pub fn build(app: &AppHandle) -> tauri::Result<Tray> {
+        let pause_queue =
+            CheckMenuItem::with_id(app, PAUSE_QUEUE, "Pause queue", true, false, None::<&str>)?;
+        let menu = Menu::with_items(
+            app,
+            &[
+                &MenuItem::with_id(app, SHOW_WINDOW, "Show window", true, None::<&str>)?,
+                &pause_queue,
+                &MenuItem::with_id(app, CANCEL_ALL, "Cancel all", true, None::<&str>)?,
+                &PredefinedMenuItem::separator(app)?,
+                &MenuItem::with_id(app, QUIT, "Quit", true, None::<&str>)?,
+            ],
+        )?;
+
+        let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+            .tooltip(APP_NAME)
+            .menu(&menu)
+            .on_menu_event(on_menu_event);
+        if let Some(icon) = app.default_window_icon() {
+            builder = builder.icon(icon.clone());
+        }
+        builder.build(app)?;
+        Ok(Tray { pause_queue })
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        let _ = self.pause_queue.set_checked(paused);
+    }
+}
+
+/// Mirror the batch progress in the tray tooltip
+pub fn show_progress(app: &AppHandle, overall: &OverallProgress) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    let tooltip = if overall.active + overall.queued > 0 {
+        format!(
+            "{} — {:.0}% ({} converting, {} queued)",
+            APP_NAME, overall.percent, overall.active, overall.queued
+        )
+    } else {
+        APP_NAME.to_string()
+    };
+    let _ = tray.set_tooltip(Some(tooltip));
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    let state = app.state::<AppState>();
+    match event.id().as_ref() {
+        SHOW_WINDOW => {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.show();
+                let _ = window.unminimize();
+                let _ = window.set_focus();
+            }
+        }
+        PAUSE_QUEUE => {
+            // The check mark has already flipped by the time the event arrives
+            let paused = state
+                .tray
+                .as_ref()
+                .is_some_and(|tray| tray.pause_queue.is_checked().unwrap_or(false));
+            state.set_queue_paused(app, paused);
+        }
+        CANCEL_ALL => state.cancel_all(),
+        QUIT => crate::shutdown::confirm_quit(app),
+        _ => {}
+    }
+}
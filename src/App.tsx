@@ -14,6 +14,9 @@ interface VideoInfo {
   height: number;
   bitrate: number;
   needs_conversion: boolean;
+  is_faststart: boolean;
+  has_alpha: boolean;
+  compatibility_warnings: string[];
 }
 
 interface FileItem extends VideoInfo {
@@ -23,21 +26,195 @@ interface FileItem extends VideoInfo {
   progress: number;
   outputPath?: string;
   error?: string;
+  finalizing?: boolean;
+  staging?: boolean;
+  /** Finished with many decoding errors; the output may be damaged */
+  needsReview?: boolean;
+  /** Cancelled with what was encoded so far kept */
+  partial?: boolean;
+  waitingForFile?: boolean;
+  waitingForEncoder?: boolean;
+  analyzing?: boolean;
+  queuePosition?: number;
+  startsInSeconds?: number;
+  fixingAudio?: boolean;
+  etaSeconds?: number;
+  bytesWritten?: number;
+  softwareFallback?: boolean;
+  warnings?: string[];
+  /** Options a retry runs with; none uses the defaults */
+  options?: unknown;
+  /** The failed task this item retries */
+  retryOf?: string;
+}
+
+type StreamAction =
+  | { action: "copied" }
+  | { action: "encoded"; encoder: string };
+
+type ConversionStatus =
+  | "queued"
+  | "analyzing"
+  | "starting"
+  | "waiting_for_file"
+  | "waiting_for_encoder"
+  | "staging"
+  | "converting"
+  | "fixing_audio"
+  | "finalizing"
+  | "completed"
+  | "completed_with_errors"
+  | "error"
+  | "input_unavailable"
+  | "output_too_large"
+  | "cancelled"
+  | "cancelled_partial";
+
+/** CPU and memory of one task's ffmpeg processes */
+interface TaskUsage {
+  task_id: string;
+  /** Share of one core; null on a process's first sample */
+  cpu_percent: number | null;
+  memory_bytes: number;
 }
 
 interface ConversionProgress {
   task_id: string;
   progress: number;
-  status: string;
+  status: ConversionStatus;
   output_path?: string;
   error?: string;
+  video_action?: StreamAction;
+  audio_action?: StreamAction;
+  indeterminate: boolean;
+  speed?: number;
+  eta_seconds?: number;
+  warnings: string[];
+  bytes_written?: number;
+  software_fallback: boolean;
+  queue_position?: number;
+  estimated_start_seconds?: number;
+}
+
+interface AppSnapshot {
+  progress: { tasks: ConversionProgress[] };
+  running_tasks: string[];
+  queue_paused: boolean;
+  finishing_before_quit: boolean;
+}
+
+interface OptionError {
+  code: string;
+  fields: string[];
+  message: string;
+}
+
+interface SampleRange {
+  start_seconds: number;
+  end_seconds: number;
+}
+
+interface CommandError {
+  kind: string;
+  message?: string | OptionError[] | { message: string; sample: SampleRange };
+}
+
+const errorMessage = (error: unknown) => {
+  if (typeof error === "object" && error !== null && "kind" in error) {
+    const { kind, message } = error as CommandError;
+    if (Array.isArray(message)) {
+      return message.map((e) => e.message).join("；");
+    }
+    if (kind === "encode_failed" && typeof message === "object") {
+      const { start_seconds, end_seconds } = message.sample;
+      return `${message.message}（可转换 ${start_seconds.toFixed(1)}s–${end_seconds.toFixed(1)}s 的片段来快速复现）`;
+    }
+    if (kind === "probe_timeout") {
+      return `Timed out reading ${message}. Is the drive connected and the file downloaded?`;
+    }
+    if (kind === "input_unavailable") {
+      return `找不到源文件 ${message}，请重新连接所在的磁盘或网络共享后重试`;
+    }
+    if (kind === "offline") {
+      return `无法连接下载服务器，请检查网络后重试（${message}）`;
+    }
+    if (kind === "checksum_mismatch") {
+      return `下载的文件校验失败，可能已损坏或被篡改（${message}）`;
+    }
+    if (kind === "not_writable") {
+      return `无法写入 ${message}`;
+    }
+    if (kind === "output_too_large") {
+      return `输出文件已超过源文件大小，转换已停止（${message}）`;
+    }
+    if (kind === "drm_protected") {
+      return `${message} 受版权保护（DRM），无法转换，请使用未加密的副本`;
+    }
+    if (kind === "verification_failed") {
+      return `输出文件校验失败，已保留以便检查：${message}`;
+    }
+    return typeof message === "string" ? message : kind;
+  }
+  return String(error);
+};
+
+interface DownloadProgress {
+  downloaded_bytes: number;
+  total_bytes?: number;
+}
+
+interface QueueEntry {
+  task_id: string;
+  input_path: string;
+  output_dir: string | null;
+  options: unknown;
+  retry_of?: string | null;
+}
+
+interface RetryReport {
+  entries: QueueEntry[];
+  /** Errors of the tasks that couldn't be retried, by task id */
+  failed: Record<string, CommandError>;
+}
+
+// How the backend picks a folder when none is chosen
+type OutputDirMode =
+  | { mode: "same_as_input" }
+  | { mode: "fixed"; path: string }
+  | { mode: "ask" };
+
+interface Settings {
+  output_dir_mode: OutputDirMode;
+}
+
+interface RestoredQueue {
+  entries: QueueEntry[];
+  unrecoverable: QueueEntry[];
+}
+
+interface ConversionResult {
+  output_path: string;
+  video_action: StreamAction;
+  audio_action: StreamAction;
+  output_bitrate?: number;
+  warnings: string[];
+  output_size?: [number, number];
+  input_bytes: number;
+  output_bytes: number;
 }
 
 function App() {
   const [files, setFiles] = useState<FileItem[]>([]);
   const [outputDir, setOutputDir] = useState<string>("");
+  const [outputDirMode, setOutputDirMode] = useState<OutputDirMode>({ mode: "ask" });
   const [ffmpegAvailable, setFfmpegAvailable] = useState<boolean | null>(null);
   const [isConverting, setIsConverting] = useState(false);
+  // Percent while ffmpeg downloads; null when no download is running
+  const [downloadPercent, setDownloadPercent] = useState<number | null>(null);
+  // Set while a graceful quit waits for the files being encoded
+  const [finishingBeforeQuit, setFinishingBeforeQuit] = useState(false);
+  // Latest CPU and memory sample of the running conversions, by task id
+  const [usage, setUsage] = useState<Record<string, TaskUsage>>({});
 
   // Check FFmpeg availability on mount
   useEffect(() => {
@@ -46,11 +223,137 @@ function App() {
       .catch(() => setFfmpegAvailable(false));
   }, []);
 
+  useEffect(() => {
+    invoke<Settings>("cmd_get_settings")
+      .then((settings) => setOutputDirMode(settings.output_dir_mode))
+      .catch((error) => console.error("Failed to load settings:", error));
+  }, []);
+
+  // Without a chosen folder the backend's default applies, unless it asks
+  const needsOutputDir = !outputDir && outputDirMode.mode === "ask";
+  const defaultOutputDirLabel =
+    outputDirMode.mode === "same_as_input"
+      ? "默认：与源文件相同"
+      : outputDirMode.mode === "fixed"
+        ? `默认：${outputDirMode.path}`
+        : "请选择输出目录";
+
+  // Offer back whatever was still queued when the app last quit
+  useEffect(() => {
+    const restore = async () => {
+      const restored = await invoke<RestoredQueue>("cmd_get_restored_queue");
+      const count = restored.entries.length + restored.unrecoverable.length;
+      if (count === 0) return;
+      if (!confirm(`上次退出时还有 ${count} 个文件未转换，是否恢复？`)) {
+        await invoke("cmd_discard_restored_queue");
+        return;
+      }
+      const resumed = await invoke<RestoredQueue>("cmd_resume_restored_queue");
+      if (resumed.unrecoverable.length > 0) {
+        const missing = resumed.unrecoverable.map((e) => e.input_path).join("\n");
+        alert(`以下文件已不存在，无法恢复：\n${missing}`);
+      }
+      for (const entry of resumed.entries) {
+        if (entry.output_dir) {
+          const dir = entry.output_dir;
+          setOutputDir((current) => current || dir);
+        }
+        await addFile(entry.input_path, entry.task_id);
+      }
+    };
+    restore().catch((error) => console.error("Failed to restore queue:", error));
+  }, []);
+
+  // Persist the not-yet-started files so a crash or quit doesn't lose them
+  useEffect(() => {
+    const entries: QueueEntry[] = files
+      .filter((f) => f.status === "pending")
+      .map((f) => ({
+        task_id: f.id,
+        input_path: f.path,
+        output_dir: outputDir || null,
+        options: f.options ?? null,
+        retry_of: f.retryOf ?? null,
+      }));
+    invoke("cmd_set_pending_queue", { entries }).catch((error) =>
+      console.error("Failed to save queue:", error)
+    );
+  }, [files, outputDir]);
+
+  // Catch up on progress when the window mounts or comes back from the tray
+  useEffect(() => {
+    const resync = async () => {
+      if (document.visibilityState !== "visible") return;
+      const snapshot = await invoke<AppSnapshot>("cmd_snapshot_state");
+      setFinishingBeforeQuit(snapshot.finishing_before_quit);
+      setFiles((prev) =>
+        prev.map((f) => {
+          const latest = snapshot.progress.tasks.find((t) => t.task_id === f.id);
+          return latest && f.status === "converting"
+            ? { ...f, progress: latest.progress, etaSeconds: latest.eta_seconds }
+            : f;
+        })
+      );
+    };
+    const onVisibilityChange = () => {
+      resync().catch((error) => console.error("Failed to refresh progress:", error));
+    };
+    onVisibilityChange();
+    document.addEventListener("visibilitychange", onVisibilityChange);
+    return () => document.removeEventListener("visibilitychange", onVisibilityChange);
+  }, []);
+
+  // Quitting was chosen to wait for the running files
+  useEffect(() => {
+    const unlisten = listen<string[]>("shutdown-finishing", () =>
+      setFinishingBeforeQuit(true)
+    );
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<TaskUsage[]>("conversion-telemetry", (event) =>
+      setUsage(Object.fromEntries(event.payload.map((task) => [task.task_id, task])))
+    );
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
   // Listen for conversion progress events
   useEffect(() => {
     const unlisteners: (() => void)[] = [];
 
     files.forEach((file) => {
+      // Queued entries only hear their place in line and start estimate
+      if (file.status === "pending") {
+        listen<ConversionProgress>(`conversion-progress-${file.id}`, (event) => {
+          const progress = event.payload;
+          // Dropped by a graceful quit
+          if (progress.status === "cancelled") {
+            setFiles((prev) =>
+              prev.map((f) =>
+                f.id === file.id ? { ...f, status: "error", error: "退出前已取消" } : f
+              )
+            );
+            return;
+          }
+          if (progress.status !== "queued") return;
+          setFiles((prev) =>
+            prev.map((f) =>
+              f.id === file.id
+                ? {
+                    ...f,
+                    queuePosition: progress.queue_position,
+                    startsInSeconds: progress.estimated_start_seconds,
+                  }
+                : f
+            )
+          );
+        }).then((unlisten) => unlisteners.push(unlisten));
+      }
       if (file.status === "converting") {
         listen<ConversionProgress>(
           `conversion-progress-${file.id}`,
@@ -62,14 +365,34 @@ function App() {
                   ? {
                       ...f,
                       progress: progress.progress,
+                      finalizing: progress.status === "finalizing",
+                      staging: progress.status === "staging",
+                      waitingForFile: progress.status === "waiting_for_file",
+                      waitingForEncoder:
+                        progress.status === "waiting_for_encoder",
+                      analyzing: progress.status === "analyzing",
+                      fixingAudio:
+                        f.fixingAudio || progress.status === "fixing_audio",
+                      etaSeconds: progress.eta_seconds,
+                      bytesWritten: progress.bytes_written,
+                      softwareFallback:
+                        f.softwareFallback || progress.software_fallback,
+                      needsReview:
+                        progress.status === "completed_with_errors",
+                      partial: progress.status === "cancelled_partial",
                       status:
-                        progress.status === "completed"
+                        progress.status === "completed" ||
+                        progress.status === "completed_with_errors" ||
+                        progress.status === "cancelled_partial"
                           ? "completed"
-                          : progress.status === "error"
+                          : progress.status === "error" ||
+                            progress.status === "input_unavailable" ||
+                            progress.status === "output_too_large"
                           ? "error"
                           : "converting",
                       outputPath: progress.output_path,
                       error: progress.error,
+                      warnings: progress.warnings,
                     }
                   : f
               )
@@ -84,6 +407,26 @@ function App() {
     };
   }, [files]);
 
+  const addFile = async (path: string, id: string = crypto.randomUUID()) => {
+    try {
+      const info = await invoke<VideoInfo>("cmd_get_video_info", { path });
+      const newFile: FileItem = {
+        ...info,
+        id,
+        selected: false,
+        status: "pending",
+        progress: 0,
+      };
+      setFiles((prev) => {
+        // Avoid duplicates
+        if (prev.some((f) => f.path === path)) return prev;
+        return [...prev, newFile];
+      });
+    } catch (error) {
+      console.error("Failed to get video info:", error);
+    }
+  };
+
   const handleSelectFiles = async () => {
     const selected = await open({
       multiple: true,
@@ -102,6 +445,9 @@ function App() {
             "mpeg",
             "mpg",
             "3gp",
+            "webp",
+            "png",
+            "apng",
           ],
         },
       ],
@@ -109,31 +455,23 @@ function App() {
 
     if (selected && Array.isArray(selected)) {
       for (const path of selected) {
-        try {
-          const info = await invoke<VideoInfo>("cmd_get_video_info", { path });
-          const newFile: FileItem = {
-            ...info,
-            id: crypto.randomUUID(),
-            selected: false,
-            status: "pending",
-            progress: 0,
-          };
-          setFiles((prev) => {
-            // Avoid duplicates
-            if (prev.some((f) => f.path === path)) return prev;
-            return [...prev, newFile];
-          });
-        } catch (error) {
-          console.error("Failed to get video info:", error);
-        }
+        await addFile(path);
       }
     }
   };
 
   const handleSelectOutputDir = async () => {
+    // Start where the last output of a source from the same drive went
+    const first = files[0];
+    const remembered = first
+      ? await invoke<string | null>("cmd_remembered_output_dir", {
+          inputPath: first.path,
+        }).catch(() => null)
+      : null;
     const selected = await open({
       directory: true,
       multiple: false,
+      defaultPath: outputDir || remembered || undefined,
     });
 
     if (selected && typeof selected === "string") {
@@ -153,7 +491,7 @@ function App() {
   };
 
   const convertSingleFile = async (file: FileItem) => {
-    if (!outputDir) {
+    if (needsOutputDir) {
       alert("请先选择输出目录");
       return;
     }
@@ -165,12 +503,51 @@ function App() {
     );
 
     try {
-      const outputPath = await invoke<string>("cmd_convert_video", {
+      const result = await invoke<ConversionResult>("cmd_convert_video", {
         inputPath: file.path,
-        outputDir,
+        outputDir: outputDir || null,
         taskId: file.id,
+        options: file.options ?? null,
+        retryOf: file.retryOf ?? null,
       });
 
+      setFiles((prev) =>
+        prev.map((f) =>
+          f.id === file.id
+            ? {
+                ...f,
+                status: "completed",
+                progress: 100,
+                outputPath: result.output_path,
+                warnings: result.warnings,
+              }
+            : f
+        )
+      );
+    } catch (error) {
+      setFiles((prev) =>
+        prev.map((f) =>
+          f.id === file.id
+            ? { ...f, status: "error", error: errorMessage(error) }
+            : f
+        )
+      );
+    }
+  };
+
+  // Remux only, for compatible files whose index sits at the end
+  const optimizeFile = async (file: FileItem) => {
+    setFiles((prev) =>
+      prev.map((f) =>
+        f.id === file.id ? { ...f, status: "converting", progress: 0 } : f
+      )
+    );
+    try {
+      const outputPath = await invoke<string>("cmd_optimize_faststart", {
+        inputPath: file.path,
+        outputDir,
+        taskId: file.id,
+      });
       setFiles((prev) =>
         prev.map((f) =>
           f.id === file.id
@@ -182,7 +559,7 @@ function App() {
       setFiles((prev) =>
         prev.map((f) =>
           f.id === file.id
-            ? { ...f, status: "error", error: String(error) }
+            ? { ...f, status: "error", error: errorMessage(error) }
             : f
         )
       );
@@ -197,15 +574,43 @@ function App() {
       alert("请选择要转换的文件");
       return;
     }
-    if (!outputDir) {
+    if (needsOutputDir) {
       alert("请先选择输出目录");
       return;
     }
 
+    await startConversions(selectedFiles);
+  };
+
+  // Files whose drive is gone fail up front instead of launching ffmpeg
+  const startConversions = async (batch: FileItem[]) => {
     setIsConverting(true);
 
+    const missing = new Set(
+      await invoke<string[]>("cmd_missing_inputs", {
+        paths: batch.map((f) => f.path),
+      }).catch(() => [] as string[])
+    );
+    if (missing.size > 0) {
+      setFiles((prev) =>
+        prev.map((f) =>
+          batch.some((b) => b.id === f.id) && missing.has(f.path)
+            ? {
+                ...f,
+                status: "error",
+                error: errorMessage({ kind: "input_unavailable", message: f.path }),
+              }
+            : f
+        )
+      );
+    }
+
     // Start all conversions in parallel
-    await Promise.all(selectedFiles.map((file) => convertSingleFile(file)));
+    await Promise.all(
+      batch
+        .filter((file) => !missing.has(file.path))
+        .map((file) => convertSingleFile(file))
+    );
 
     setIsConverting(false);
   };
@@ -216,16 +621,12 @@ function App() {
       alert("没有可转换的文件");
       return;
     }
-    if (!outputDir) {
+    if (needsOutputDir) {
       alert("请先选择输出目录");
       return;
     }
 
-    setIsConverting(true);
-
-    await Promise.all(pendingFiles.map((file) => convertSingleFile(file)));
-
-    setIsConverting(false);
+    await startConversions(pendingFiles);
   };
 
   const deleteFile = useCallback(async (file: FileItem) => {
@@ -247,12 +648,40 @@ function App() {
     }
   };
 
+  // Queue every failed conversion again; each item takes the new task id
+  const retryFailedFiles = async () => {
+    try {
+      const report = await invoke<RetryReport>("cmd_retry_failed", {});
+      setFiles((prev) =>
+        prev.map((f) => {
+          const entry = report.entries.find((e) => e.input_path === f.path);
+          if (entry) {
+            return {
+              ...f,
+              id: entry.task_id,
+              status: "pending",
+              progress: 0,
+              error: undefined,
+              options: entry.options,
+              retryOf: entry.retry_of ?? undefined,
+            };
+          }
+          const error = report.failed[f.id];
+          return error ? { ...f, error: errorMessage(error) } : f;
+        })
+      );
+    } catch (error) {
+      alert(errorMessage(error));
+    }
+  };
+
   const removeFromList = (id: string) => {
     setFiles((prev) => prev.filter((f) => f.id !== id));
   };
 
   const selectedCount = files.filter((f) => f.selected).length;
   const pendingCount = files.filter((f) => f.status === "pending").length;
+  const failedCount = files.filter((f) => f.status === "error").length;
 
   const formatDuration = (seconds: number) => {
     const mins = Math.floor(seconds / 60);
@@ -260,6 +689,37 @@ function App() {
     return `${mins}:${secs.toString().padStart(2, "0")}`;
   };
 
+  const formatWritten = (bytes?: number) =>
+    bytes ? ` · 已写入 ${Math.round(bytes / (1024 * 1024))} MB` : "";
+
+  const formatUsage = (task?: TaskUsage) => {
+    if (!task) return "";
+    const cpu = task.cpu_percent === null ? "" : ` · CPU ${Math.round(task.cpu_percent)}%`;
+    return `${cpu} · 内存 ${Math.round(task.memory_bytes / (1024 * 1024))} MB`;
+  };
+
+  const downloadFfmpeg = async () => {
+    const taskId = crypto.randomUUID();
+    setDownloadPercent(0);
+    const unlisten = await listen<DownloadProgress>(
+      `ffmpeg-download-progress-${taskId}`,
+      ({ payload }) => {
+        if (payload.total_bytes) {
+          setDownloadPercent(Math.floor((payload.downloaded_bytes / payload.total_bytes) * 100));
+        }
+      }
+    );
+    try {
+      const info = await invoke<{ ffmpeg?: unknown }>("cmd_download_ffmpeg", { taskId });
+      setFfmpegAvailable(Boolean(info.ffmpeg));
+    } catch (error) {
+      alert(`下载 FFmpeg 失败：${errorMessage(error)}`);
+    } finally {
+      unlisten();
+      setDownloadPercent(null);
+    }
+  };
+
   const formatResolution = (width: number, height: number) => {
     return `${width}x${height}`;
   };
@@ -275,9 +735,22 @@ function App() {
           <span>
             FFmpeg {ffmpegAvailable ? "就绪" : "未找到"}
           </span>
+          {ffmpegAvailable === false && (
+            <button
+              className="btn btn-secondary"
+              onClick={downloadFfmpeg}
+              disabled={downloadPercent !== null}
+            >
+              {downloadPercent === null ? "下载 FFmpeg" : `下载中 ${downloadPercent}%`}
+            </button>
+          )}
         </div>
       </header>
 
+      {finishingBeforeQuit && (
+        <div className="shutdown-banner">正在完成当前文件，完成后将自动退出</div>
+      )}
+
       <div className="toolbar">
         <button
           className="btn btn-primary"
@@ -297,7 +770,7 @@ function App() {
         <button
           className="btn btn-success"
           onClick={convertSelectedFiles}
-          disabled={!ffmpegAvailable || selectedCount === 0 || isConverting || !outputDir}
+          disabled={!ffmpegAvailable || selectedCount === 0 || isConverting || needsOutputDir}
         >
           <svg
             viewBox="0 0 24 24"
@@ -312,7 +785,7 @@ function App() {
         <button
           className="btn btn-success"
           onClick={convertAllFiles}
-          disabled={!ffmpegAvailable || pendingCount === 0 || isConverting || !outputDir}
+          disabled={!ffmpegAvailable || pendingCount === 0 || isConverting || needsOutputDir}
         >
           <svg
             viewBox="0 0 24 24"
@@ -324,6 +797,13 @@ function App() {
           </svg>
           转换全部 ({pendingCount})
         </button>
+        <button
+          className="btn btn-secondary"
+          onClick={retryFailedFiles}
+          disabled={failedCount === 0 || isConverting}
+        >
+          重试失败 ({failedCount})
+        </button>
         <button
           className="btn btn-danger"
           onClick={deleteSelectedFiles}
@@ -344,7 +824,7 @@ function App() {
       <div className="output-selector">
         <label>输出目录：</label>
         <span className="output-path">
-          {outputDir || "请选择输出目录"}
+          {outputDir || defaultOutputDirLabel}
         </span>
         <button className="btn btn-secondary" onClick={handleSelectOutputDir}>
           浏览
@@ -400,11 +880,39 @@ function App() {
                     <span>{file.container}</span>
                     <span>{formatResolution(file.width, file.height)}</span>
                     <span>{formatDuration(file.duration)}</span>
+                    {file.has_alpha && (
+                      <span className="badge badge-warning" title="MP4 不支持透明，将使用白色背景">
+                        透明
+                      </span>
+                    )}
                     {file.needs_conversion ? (
                       <span className="badge badge-warning">需要转换</span>
                     ) : (
                       <span className="badge badge-success">已兼容</span>
                     )}
+                    {file.status === "pending" && file.queuePosition && (
+                      <span title="粗略估计，队列变化时会更新">
+                        第 {file.queuePosition} 位
+                        {file.startsInSeconds != null &&
+                          ` · 约 ${Math.max(1, Math.round(file.startsInSeconds / 60))} 分钟后开始`}
+                      </span>
+                    )}
+                    {file.compatibility_warnings.length > 0 && (
+                      <span
+                        className="badge badge-warning"
+                        title={file.compatibility_warnings.join("\n")}
+                      >
+                        部分设备可能无法播放
+                      </span>
+                    )}
+                    {file.softwareFallback && (
+                      <span
+                        className="badge badge-warning"
+                        title="硬件编码器可能已退回软件编码，速度远低于测速结果；建议改用 libx264 的 CPU 编码设置"
+                      >
+                        编码偏慢
+                      </span>
+                    )}
                   </div>
                 </div>
 
@@ -422,9 +930,38 @@ function App() {
                         style={{ width: `${file.progress}%` }}
                       />
                     </div>
-                    <div className="progress-text">
-                      {file.status === "converting"
-                        ? `${Math.round(file.progress)}%`
+                    <div
+                      className="progress-text"
+                      title={file.warnings?.join("\n")}
+                    >
+                      {file.status === "converting" && file.finalizing
+                        ? "正在完成…"
+                        : file.status === "converting" && file.analyzing
+                        ? "正在分析…"
+                        : file.status === "converting" && file.waitingForFile
+                        ? "等待文件写入完成…"
+                        : file.status === "converting" && file.waitingForEncoder
+                        ? "等待硬件编码器空闲…"
+                        : file.status === "converting" && file.staging
+                        ? `复制到本地 ${Math.round(file.progress)}%`
+                        : file.status === "converting" && file.fixingAudio
+                        ? `修复音频 ${Math.round(file.progress)}%`
+                        : file.status === "converting" && file.etaSeconds
+                        ? `${Math.round(file.progress)}% · 剩余 ${formatDuration(
+                            file.etaSeconds
+                          )}${formatWritten(file.bytesWritten)}${formatUsage(usage[file.id])}`
+                        : file.status === "converting"
+                        ? `${Math.round(file.progress)}%${formatWritten(
+                            file.bytesWritten
+                          )}${formatUsage(usage[file.id])}`
+                        : file.status === "completed" && file.partial
+                        ? "已取消 · 保留了已转换的部分"
+                        : file.status === "completed" && file.needsReview
+                        ? "完成 · 源文件有损坏，请检查输出"
+                        : file.status === "completed" && file.warnings?.length
+                        ? "完成（有警告）"
+                        : file.status === "completed" && file.fixingAudio
+                        ? "完成 · 视频未改动"
                         : file.status === "completed"
                         ? "完成"
                         : file.status === "error"
@@ -435,11 +972,23 @@ function App() {
                 )}
 
                 <div className="file-item-actions">
+                  {file.status === "pending" &&
+                    !file.needs_conversion &&
+                    !file.is_faststart && (
+                      <button
+                        className="btn btn-small"
+                        onClick={() => optimizeFile(file)}
+                        disabled={isConverting || !outputDir}
+                        title="仅调整文件结构以便边下边播，不重新编码"
+                      >
+                        ⚡ 优化
+                      </button>
+                    )}
                   {file.status === "pending" && (
                     <button
                       className="btn btn-small btn-primary"
                       onClick={() => convertSingleFile(file)}
-                      disabled={isConverting || !outputDir}
+                      disabled={isConverting || needsOutputDir}
                     >
                       转换
                     </button>